@@ -5,6 +5,15 @@ struct pijul_repository;
 typedef struct pijul_repository* pijul_repository;
 int pijul_open_repository(const char*,pijul_repository*);
 void pijul_close_repository(pijul_repository);
+struct pijul_txn_t;
+typedef struct pijul_txn_t* pijul_txn_t;
+int pijul_txn_begin(pijul_repository,pijul_txn_t*);
+void pijul_txn_destroy(pijul_txn_t);
+int pijul_list_files(pijul_txn_t,char***,unsigned int*);
+struct pijul_branch_t;
+typedef struct pijul_branch_t* pijul_branch_t;
+int pijul_get_branch(pijul_txn_t,const char*,pijul_branch_t*);
+int pijul_retrieve_and_output(pijul_txn_t,pijul_branch_t,const char*,int);
 void pijul_add_file(pijul_repository,const char*,int);
 void pijul_move_file(pijul_repository,char*,char*,int);
 void pijul_remove_file(pijul_repository,char*);
@@ -15,7 +24,7 @@ struct pijul_changes_t;
 typedef struct pijul_changes_t* pijul_changes_t;
 struct pijul_updates_t;
 typedef struct pijul_updates_t* pijul_updates_t;
-int pijul_record(pijul_repository,const char*,pijul_changes_t*,pijul_updates_t*);
+int pijul_record(pijul_txn_t,const char*,const char*,pijul_changes_t*,pijul_updates_t*);
 void pijul_has_patch(pijul_repository,char*,char*,size_t);
 
 struct pijul_patch_t;
@@ -23,7 +23,7 @@ use libc::{c_char,c_int,c_void,c_uint};
 extern crate libpijul;
 use libpijul::*;
 use libpijul::backend::Branch;
-use libpijul::patch::{KEY_SIZE,LocalKey,Patch};
+use libpijul::patch::{KEY_SIZE,Patch};
 use std::ffi::CString;
 use std::path::{Path};
 use std::collections::{HashMap,HashSet};
@@ -76,6 +76,28 @@ pub unsafe extern "C" fn pijul_mut_txn_begin(repository:*const c_void, transacti
 }
 
 
+#[no_mangle]
+pub unsafe extern "C" fn pijul_txn_begin(repository:*const c_void, transaction:*mut *mut c_void) -> c_int {
+    let r:Box<Repository>=std::mem::transmute(repository);
+    let result = match r.txn_begin() {
+        Ok(t) => {
+            *transaction = std::mem::transmute(Box::new(t));
+            0
+        },
+        _ => {
+            -1
+        }
+    };
+    std::mem::forget(r);
+    result
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pijul_txn_destroy(transaction:*mut c_void) {
+    let r:Box<ReadTransaction> = std::mem::transmute(transaction);
+    std::mem::drop(r)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn pijul_mut_txn_destroy(transaction:*mut c_void) {
     let r:*mut *mut c_void = std::mem::transmute(transaction);
@@ -137,7 +159,7 @@ pub unsafe extern "C" fn pijul_add_file(transaction:*mut c_void,path:*const c_ch
 
 #[no_mangle]
 pub unsafe extern "C" fn pijul_list_files(transaction:*mut c_void, p_c_list:*mut *mut *mut c_char, c_len:*mut c_uint)->c_int {
-    let repository:Box<Transaction>=std::mem::transmute(transaction);
+    let repository:Box<ReadTransaction>=std::mem::transmute(transaction);
     let result = match repository.list_files() {
         Ok(list) => {
 
@@ -202,7 +224,7 @@ pub extern "C" fn pijul_remove_file(repository:*mut c_void,path:*const c_char)->
 #[no_mangle]
 pub unsafe extern "C" fn pijul_get_branch(repository:*mut c_void, c_branch:*const c_char, r:*mut *mut c_void) -> c_int {
     
-    let repository:Box<Transaction>=std::mem::transmute(repository);
+    let repository:Box<ReadTransaction>=std::mem::transmute(repository);
     let branch=std::str::from_utf8_unchecked(std::ffi::CStr::from_ptr(c_branch).to_bytes());
     let result = if let Ok(branch) = repository.db_nodes(branch) {
         *r = std::mem::transmute(Box::new(branch));
@@ -218,8 +240,8 @@ pub unsafe extern "C" fn pijul_get_branch(repository:*mut c_void, c_branch:*cons
 #[no_mangle]
 pub unsafe extern "C" fn pijul_retrieve_and_output(repository:*mut c_void, c_branch:*const c_void, c_key:*const c_char, output:c_int) -> c_int {
     
-    let repository:Box<Transaction>=std::mem::transmute(repository);
-    let branch:Box<Branch<_>>=std::mem::transmute(c_branch);
+    let repository:Box<ReadTransaction>=std::mem::transmute(repository);
+    let branch:Box<Branch<_,_>>=std::mem::transmute(c_branch);
     // let branch=std::str::from_utf8_unchecked(std::ffi::CStr::from_ptr(c_branch).to_bytes());
     let key=std::slice::from_raw_parts(c_key as *const u8, KEY_SIZE);
     let mut file = std::fs::File::from_raw_fd(output);
@@ -255,7 +277,7 @@ pub unsafe extern "C" fn pijul_apply_patches(repository:*mut c_void,
         } else {
             std::mem::transmute(c_local_patches)
         };
-    let result = if let Ok(()) = repository.apply_patches(branch, path, &remote_patches, &local_patches) {
+    let result = if let Ok(()) = repository.apply_patches(branch, path, &remote_patches, &local_patches, ConflictStyle::default()) {
         0
     } else {
         -1
@@ -281,15 +303,22 @@ pub unsafe extern "C" fn pijul_apply_local_patch(transaction:*mut c_void,
     let branch=std::str::from_utf8_unchecked(std::ffi::CStr::from_ptr(c_branch).to_bytes());
     let path=std::str::from_utf8_unchecked(std::ffi::CStr::from_ptr(c_path).to_bytes());
     let patch:Box<Patch> = std::mem::transmute(c_patch);
-    let inode_updates:Box<HashMap<LocalKey, Inode>> = std::mem::transmute(c_inode_updates);
+    let updates:Box<RecordUpdates> =
+        if c_inode_updates.is_null() {
+            Box::new(RecordUpdates { inodes: HashMap::new(), redundant: Vec::new() })
+        } else {
+            std::mem::transmute(c_inode_updates)
+        };
 
-    let result = if let Ok(()) = transaction.apply_local_patch(branch, path, *patch, &inode_updates) {
+    let result = if let Ok(()) = transaction.apply_local_patch(branch, path, *patch, &updates, None) {
         0
     } else {
         -1
     };
     std::mem::forget(transaction);
-    std::mem::forget(inode_updates);
+    if !c_inode_updates.is_null() {
+        std::mem::forget(updates);
+    }
     result
 }
 
@@ -301,12 +330,12 @@ pub unsafe extern "C" fn pijul_record(repository:*mut c_void,
                                       c_patch:*mut *mut c_void,
                                       c_inode_updates: *mut *mut c_void) -> c_int {
     
-    let mut repository:Box<Transaction>=std::mem::transmute(repository);
+    let repository:Box<ReadTransaction>=std::mem::transmute(repository);
     let branch=std::str::from_utf8_unchecked(std::ffi::CStr::from_ptr(c_branch).to_bytes());
     let path=std::str::from_utf8_unchecked(std::ffi::CStr::from_ptr(c_path).to_bytes());
-    let result = if let Ok((patch, inode_updates)) = repository.record(path, branch) {
+    let result = if let Ok((patch, updates)) = repository.record(branch, path) {
         *c_patch = std::mem::transmute(Box::new(patch));
-        *c_inode_updates = std::mem::transmute(Box::new(inode_updates));
+        *c_inode_updates = std::mem::transmute(Box::new(updates));
         0
     } else {
         -1
@@ -326,7 +355,7 @@ pub unsafe extern "C" fn pijul_output_repository(repository:*mut c_void,
     let pending:Box<Patch> = std::mem::transmute(c_pending);
     let branch=std::str::from_utf8_unchecked(std::ffi::CStr::from_ptr(c_branch).to_bytes());
     let working_copy=std::str::from_utf8_unchecked(std::ffi::CStr::from_ptr(c_working_copy).to_bytes());
-    let result = if let Ok(()) = repository.output_repository(branch, working_copy, &pending) {
+    let result = if let Ok(()) = repository.output_repository(branch, working_copy, &pending, ConflictStyle::default()) {
         0
     } else {
         -1
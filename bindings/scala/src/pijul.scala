@@ -15,15 +15,15 @@ class PijulRepository(path:String) {
   @native def cNewInternal(repository:Long,array:Array[Byte]): Unit
   def newInternal(x:Array[Byte]){cNewInternal(repository,x)}
 
-  @native def cRecord(repository:Long,x:String): (Long,Long)
+  @native def cRecord(repository:Long,branch:String,x:String): (Long,Long)
   class Changes(ch:Long) {
     val changes=ch
   }
   class Updates(up:Long) {
     val updates=up
   }
-  def record(path:String):(Changes,Updates) ={
-    val (a,b)=cRecord(repository,path)
+  def record(branch:String,path:String):(Changes,Updates) ={
+    val (a,b)=cRecord(repository,branch,path)
     return (new Changes(a), new Updates(b))
   }
 }
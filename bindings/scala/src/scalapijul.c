@@ -35,12 +35,17 @@ JNIEXPORT void JNICALL Java_PijulRepository_cRegisterHash(JNIEnv* jenv,jobject j
   (*jenv)->ReleaseByteArrayElements(jenv,external,e,0);
 }
 
-JNIEXPORT jobject JNICALL Java_PijulRepository_cRecord(JNIEnv* jenv,jobject jobj,jlong repository,jstring working_copy) {
+JNIEXPORT jobject JNICALL Java_PijulRepository_cRecord(JNIEnv* jenv,jobject jobj,jlong repository,jstring branch,jstring working_copy) {
+  const char* c_branch = (*jenv)->GetStringUTFChars(jenv,branch,0);
   const char* c_path = (*jenv)->GetStringUTFChars(jenv,working_copy,0);
   pijul_changes_t a;
   pijul_updates_t b;
-  pijul_record((pijul_repository)repository,c_path,&a,&b);
+  pijul_txn_t txn;
+  pijul_txn_begin((pijul_repository)repository,&txn);
+  pijul_record(txn,c_branch,c_path,&a,&b);
+  pijul_txn_destroy(txn);
   (*jenv)->ReleaseStringUTFChars(jenv,working_copy,c_path);
+  (*jenv)->ReleaseStringUTFChars(jenv,branch,c_branch);
 
   jclass tupclass = (*jenv)->FindClass(jenv,"scala/Tuple2");
   jmethodID tupcon = (*jenv)->GetMethodID(jenv,tupclass, "<init>", "(Ljava/lang/Object;Ljava/lang/Object;)V");
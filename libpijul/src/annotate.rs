@@ -0,0 +1,156 @@
+// Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+//
+// This file is part of Pijul.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+use super::backend::*;
+use super::error::Error;
+use super::patch::{Patch, Value, HASH_SIZE, external_hash, external_key};
+use super::graph::{LineBuffer, retrieve, output_file, CONFLICT_START, CONFLICT_NEXT, CONFLICT_END};
+use super::output::{follow_path, node_of_inode};
+use super::file_operations::is_directory;
+
+use std::path::Path;
+use std::collections::HashMap;
+
+/// A line of a file, with the patch that introduced it.
+#[derive(Debug)]
+pub struct AnnotatedLine {
+    /// External hash of the patch that introduced this line, or
+    /// `None` for conflict markers, which are not in any patch.
+    pub patch: Option<Vec<u8>>,
+    /// External key of the line, `None` for conflict markers.
+    pub key: Option<Vec<u8>>,
+    /// Names of the authors of `patch`. Only `annotate` reads the
+    /// patches, this is empty elsewhere and for conflict markers.
+    pub authors: Vec<String>,
+    /// Timestamp of `patch`, `None` when `authors` is not known.
+    pub timestamp: Option<String>,
+    pub contents: Vec<u8>,
+}
+
+struct Annotator<'a, 'b: 'a, T: 'a, R: 'a + RawTxn> {
+    db_external: &'a Db<'a, 'b, T, R>,
+    lines: Vec<AnnotatedLine>,
+}
+
+impl<'a, 'b, T, R: RawTxn> Annotator<'a, 'b, T, R> {
+    fn marker(&mut self, marker: &[u8]) -> Result<(), Error> {
+        self.lines.push(AnnotatedLine {
+            patch: None,
+            key: None,
+            authors: Vec::new(),
+            timestamp: None,
+            contents: marker.to_vec(),
+        });
+        Ok(())
+    }
+}
+
+impl<'a, 'env: 'a, T: 'a, R: 'a + RawTxn> LineBuffer<'a, 'env, T, R> for Annotator<'a, 'env, T, R> {
+    fn output_line(&mut self, key: &'a [u8], contents: Contents<'a, 'env, T, R>) -> Result<(), Error> {
+        let mut line = Vec::new();
+        for c in contents {
+            line.extend(c)
+        }
+        // The file node itself is output first, with empty contents.
+        if line.is_empty() || key.len() < HASH_SIZE {
+            return Ok(());
+        }
+        self.lines.push(AnnotatedLine {
+            patch: Some(external_hash(self.db_external, key).to_vec()),
+            key: Some(external_key(self.db_external, key)),
+            authors: Vec::new(),
+            timestamp: None,
+            contents: line,
+        });
+        Ok(())
+    }
+    fn begin_conflict(&mut self) -> Result<(), Error> {
+        self.marker(CONFLICT_START)
+    }
+    fn conflict_next(&mut self) -> Result<(), Error> {
+        self.marker(CONFLICT_NEXT)
+    }
+    fn end_conflict(&mut self) -> Result<(), Error> {
+        self.marker(CONFLICT_END)
+    }
+}
+
+/// Lines of file `path` (relative to the root of the repository) on
+/// branch `branch_name`, in the order they are output, each with the
+/// patch that introduced it. Since every node key starts with the
+/// internal hash of the patch that created the node, this is just a
+/// matter of outputting the file. The authors and timestamps are read
+/// from the patches in repository `location`, each patch being loaded
+/// once.
+pub fn annotate<T, R: RawTxn>(repository: &Transaction<T, R>,
+                              branch_name: &str,
+                              location: &Path,
+                              path: &Path)
+                              -> Result<Vec<AnnotatedLine>, Error> {
+    let components: Vec<&[u8]> = path.iter()
+        .map(|c| c.to_str().unwrap_or("").as_bytes())
+        .collect();
+    let inode = {
+        let db_tree = repository.db_tree();
+        match try!(follow_path(&db_tree, &components)) {
+            Some(inode) => inode,
+            None => return Err(Error::FileNotInRepo(path.to_path_buf())),
+        }
+    };
+    if is_directory(repository, &inode) {
+        return Err(Error::FileNotInRepo(path.to_path_buf()));
+    }
+    let node = {
+        let db_inodes = repository.db_inodes();
+        match node_of_inode(&db_inodes, inode.as_ref()) {
+            Some(node) => node,
+            // Added, but not recorded yet.
+            None => return Ok(Vec::new()),
+        }
+    };
+    let branch = try!(repository.db_nodes(branch_name));
+    let db_contents = repository.db_contents();
+    let db_external = repository.db_external();
+    let mut annotator = Annotator {
+        db_external: &db_external,
+        lines: Vec::new(),
+    };
+    let mut redundant_edges = Vec::new();
+    let graph = retrieve(&branch, &node[3..]);
+    try!(output_file(&branch, &db_contents, &mut annotator, graph, &mut redundant_edges));
+    let mut lines = annotator.lines;
+    let mut patches: HashMap<Vec<u8>, (Vec<String>, String)> = HashMap::new();
+    for line in lines.iter_mut() {
+        if let Some(ref hash) = line.patch {
+            if !patches.contains_key(hash) {
+                let patch = try!(Patch::from_repository(location, hash));
+                let authors = patch.authors
+                    .iter()
+                    .map(|a| match a.get("name") {
+                        Some(&Value::String(ref name)) => name.clone(),
+                        None => String::new(),
+                    })
+                    .collect();
+                patches.insert(hash.clone(), (authors, patch.timestamp));
+            }
+            let &(ref authors, ref timestamp) = patches.get(hash).unwrap();
+            line.authors = authors.clone();
+            line.timestamp = Some(timestamp.clone());
+        }
+    }
+    Ok(lines)
+}
@@ -19,12 +19,14 @@
 use super::backend::*;
 use super::error::Error;
 use super::patch::{Change, HASH_SIZE, KEY_SIZE, LINE_SIZE, ROOT_KEY, EDGE_SIZE, InternalKey,
-                   internal_hash, external_hash, Patch, LocalKey, new_internal, register_hash};
-use super::graph::{PSEUDO_EDGE, FOLDER_EDGE, PARENT_EDGE, DELETED_EDGE};
-use super::file_operations::Inode;
-use super::Len;
+                   internal_hash, external_hash, Patch, new_internal, register_hash};
+use super::graph::{PSEUDO_EDGE, FOLDER_EDGE, PARENT_EDGE, DELETED_EDGE, remove_redundant_edges};
+use super::output::{ConflictStyle, forget_removed_files};
+use super::signature::SigningKey;
+use super::record::RecordUpdates;
+use super::{Len, ReadTxn};
 
-use std::collections::{HashSet, HashMap};
+use std::collections::HashSet;
 use std::ptr::copy_nonoverlapping;
 use std::fs::File;
 use std::io::BufWriter;
@@ -32,7 +34,7 @@ use std::path::Path;
 use time;
 use std::sync::Arc;
 use std::thread;
-use super::fs_representation::patches_dir;
+use super::fs_representation::{patches_dir, repo_dir};
 use rustc_serialize::hex::ToHex;
 
 /// Test whether a node has edges unknown to the patch we're applying.
@@ -68,7 +70,7 @@ fn has_exclusive_edge<T>(branch: &Branch<T>,
 
 
 /// "intro" is the internal patch number of the patch that introduced this edge.
-fn internal_edge<T>(internal: &Db<T>,
+pub fn internal_edge<T>(internal: &Db<T>,
                     flag: u8,
                     to: &[u8],
                     intro: &InternalKey,
@@ -255,10 +257,17 @@ fn unsafe_apply<T>(db_internal: &Db<T>,
                                 // &mut children[i..(i+1+KEY_SIZE+HASH_SIZE)].to_hex());
                                 if &parents[(j + 1)..(j + 1 + KEY_SIZE)] !=
                                    &children[(i + 1)..(i + 1 + KEY_SIZE)] {
+                                    // Both sides of the pseudo-edge must have
+                                    // the same patch, or deleting it later
+                                    // (see `kill_obsolete_pseudo_edges`)
+                                    // leaves one side in the graph.
+                                    let mut child = [0; 1 + KEY_SIZE + HASH_SIZE];
+                                    child.clone_from_slice(&children[i..(i + 1 + KEY_SIZE + HASH_SIZE)]);
+                                    child[(1 + KEY_SIZE)..]
+                                        .clone_from_slice(&parents[(j + 1 + KEY_SIZE)..(j + 1 + KEY_SIZE + HASH_SIZE)]);
                                     try!(add_edge(branch,
                                                   &parents[j..(j + 1 + KEY_SIZE + HASH_SIZE)],
-                                                  &mut children[i..(i + 1 + KEY_SIZE +
-                                                                    HASH_SIZE)]));
+                                                  &child));
                                 }
                             }
                             j += 1 + KEY_SIZE + HASH_SIZE;
@@ -470,6 +479,36 @@ fn kill_obsolete_pseudo_edges<T>(branch: &mut Branch<T>, pv: &[u8]) -> Result<()
 }
 
 
+/// Key of the next sequence number in `db_applied`. No branch has
+/// an empty name.
+const APPLIED_COUNTER: &'static [u8] = b"";
+
+/// Big-endian bytes of sequence number `n` in `db_applied`.
+fn sequence_bytes(n: u64) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = (n >> (8 * (7 - i))) as u8
+    }
+    bytes
+}
+
+/// Append patch `internal` to the application order of branch
+/// `branch_name` (see `Transaction::db_applied`).
+fn register_applied<T>(db_applied: &mut Db<T>, branch_name: &str, internal: &[u8]) -> Result<(), Error> {
+    let next = match db_applied.get(APPLIED_COUNTER) {
+        Some(n) if n.len() == 8 => n.iter().fold(0, |n, &b| (n << 8) | b as u64),
+        _ => 0,
+    };
+    if next > 0 {
+        try!(db_applied.del(APPLIED_COUNTER, None));
+    }
+    try!(db_applied.put(APPLIED_COUNTER, &sequence_bytes(next + 1)));
+    let mut value = Vec::with_capacity(8 + HASH_SIZE);
+    value.extend(&sequence_bytes(next));
+    value.extend(internal);
+    db_applied.put(branch_name.as_bytes(), &value)
+}
+
 /// Applies a patch to a repository. "new_patches" are patches that
 /// just this repository has, and the remote repository doesn't have.
 pub fn apply<'b, T>(repository: &mut Transaction<T>,
@@ -491,6 +530,8 @@ pub fn apply<'b, T>(repository: &mut Transaction<T>,
                internal.as_slice().to_hex(),
                branch_name);
         try!(db_branches.put(branch_name.as_bytes(), internal.as_slice()));
+        let mut db_applied = repository.db_applied();
+        try!(register_applied(&mut db_applied, branch_name, internal.as_slice()));
         // repository.set_db_branches(db_branches);
         debug!("done");
         try!(unsafe_apply(&db_internal,
@@ -539,6 +580,7 @@ pub fn apply<'b, T>(repository: &mut Transaction<T>,
                                      },
                                      internal,
                                      new_patches,
+                                     &patch.dependencies,
                                      &mut relatives);
                 let mut i = 0;
                 while i < relatives.len() {
@@ -647,7 +689,18 @@ pub fn apply<'b, T>(repository: &mut Transaction<T>,
     let mut db_revdep = repository.db_revdep();
     for ref dep in patch.dependencies.iter() {
         let dep_internal = try!(internal_hash(&db_internal, &dep)).contents.to_vec();
-        try!(db_revdep.put(&dep_internal, &internal.contents));
+        // This dependency is already registered if the patch is
+        // applied on another branch.
+        let registered = {
+            let mut it = db_revdep.iter(&dep_internal, Some(&internal.contents));
+            match it.next() {
+                Some((k, v)) => k == &dep_internal[..] && v == &internal.contents[..],
+                None => false,
+            }
+        };
+        if !registered {
+            try!(db_revdep.put(&dep_internal, &internal.contents));
+        }
     }
 
     try!(db_nodes.commit_branch(branch_name));
@@ -657,12 +710,20 @@ pub fn apply<'b, T>(repository: &mut Transaction<T>,
 }
 
 
+/// Find the edges to add around `a`, a context of `patch_id` that
+/// was deleted by patches unknown to the author of `patch_id`: these
+/// are the newly applied patches (`new_patches`), and the patches
+/// that deleted `a` and are not in `dependencies`. The deleted
+/// relatives of `a` are followed through the edges deleted by these
+/// patches only, and the zombie edges to them are pushed to
+/// `relatives`.
 fn find_alive_relatives<T>(repository: &Transaction<T>,
                            branch: &Branch<T>,
                            a: &[u8],
                            direction: u8,
                            patch_id: &InternalKey,
                            new_patches: &HashSet<&[u8]>,
+                           dependencies: &HashSet<Vec<u8>>,
                            relatives: &mut Vec<u8>) {
     // let cursor= unsafe { &mut * self.txn.unsafe_cursor(self.dbi_nodes).unwrap() };
     fn connect<T>(repository: &Transaction<T>,
@@ -672,19 +733,31 @@ fn find_alive_relatives<T>(repository: &Transaction<T>,
                   result: &mut Vec<u8>,
                   // buffer:&mut Vec<u8>,
                   patch_id: &InternalKey,
-                  new_patches: &HashSet<&[u8]>) {
+                  new_patches: &HashSet<&[u8]>,
+                  dependencies: &HashSet<Vec<u8>>,
+                  deleters: &mut HashSet<Vec<u8>>,
+                  is_context: bool) {
         // different from root
         if ROOT_KEY != a {
             let db_external = repository.db_external();
             let mut i = result.len();
             for (k, neighbor) in branch.iter(a, Some(&[direction][..])) {
                 if k == a && neighbor[0] <= direction | PSEUDO_EDGE {
-                    // Is this neighbor from one of the newly applied patches?
-                    let is_new = if &neighbor[(1 + KEY_SIZE)..] == patch_id.as_slice() {
+                    // Is this neighbor from a patch unknown to the
+                    // author of `patch_id`?
+                    let deleter = &neighbor[(1 + KEY_SIZE)..];
+                    let is_new = if deleter == patch_id.as_slice() {
                         false
                     } else {
-                        let ext = external_hash(&db_external, &neighbor[(1 + KEY_SIZE)..]);
-                        new_patches.contains(ext)
+                        let ext = external_hash(&db_external, deleter);
+                        if new_patches.contains(ext) || deleters.contains(deleter) {
+                            true
+                        } else if is_context && !dependencies.contains(ext) {
+                            deleters.insert(deleter.to_vec());
+                            true
+                        } else {
+                            false
+                        }
                     };
                     if is_new {
                         result.push((neighbor[0] ^ PARENT_EDGE) ^ DELETED_EDGE);
@@ -718,20 +791,27 @@ fn find_alive_relatives<T>(repository: &Transaction<T>,
                         result,
                         // buffer,
                         patch_id,
-                        new_patches);
+                        new_patches,
+                        dependencies,
+                        deleters,
+                        false);
                 i += 2 * (1 + KEY_SIZE + HASH_SIZE)
             }
             // buffer.truncate(i0)
         }
     }
     // let mut buf=Vec::with_capacity(4*KEY_SIZE);
+    let mut deleters = HashSet::new();
     connect(repository,
             branch,
             a,
             direction,
             relatives,
             patch_id,
-            new_patches);
+            new_patches,
+            dependencies,
+            &mut deleters,
+            true);
     // unsafe { lmdb::mdb_cursor_close(cursor); }
 }
 
@@ -791,15 +871,71 @@ fn reconnect_zombie_folder<T>(branch: &mut Branch<T>,
     Ok(())
 }
 
+/// Apply patch `patch_hash` from the patches directory of
+/// `repo_root` on branch `branch_name`, after its dependencies, unless
+/// it is already there. Sets `patches_were_applied` if anything was
+/// applied.
+pub fn apply_with_dependencies<'a, T>(repository: &mut Transaction<'a, T>,
+                                      branch_name: &str,
+                                      repo_root: &Path,
+                                      patch_hash: &[u8],
+                                      patches_were_applied: &mut bool,
+                                      only_local: &HashSet<&[u8]>)
+                                      -> Result<(), Error> {
+    if !try!(has_patch(repository, branch_name, patch_hash)) {
+        let patch = try!(Patch::from_repository(repo_root, patch_hash));
+        debug!("Applying patch {:?}", patch_hash.to_hex());
+        for dep in patch.dependencies.iter() {
+            debug!("Applying dependency {:?}", dep.to_hex());
+            try!(apply_with_dependencies(repository,
+                                         branch_name,
+                                         repo_root,
+                                         &dep,
+                                         patches_were_applied,
+                                         only_local))
+        }
+        debug!("Now applying patch {:?}", patch);
+        // If this patch is already applied on another branch,
+        // reuse its internal id, since node contents are shared
+        // between branches.
+        let known = {
+            let db_internal = repository.db_internal();
+            match internal_hash(&db_internal, patch_hash) {
+                Ok(internal) => Some(InternalKey { contents: internal.contents }),
+                Err(Error::InternalHashNotFound(_)) => None,
+                Err(e) => return Err(e),
+            }
+        };
+        let is_new = known.is_none();
+        let internal = match known {
+            Some(internal) => internal,
+            None => new_internal(repository),
+        };
+        // println!("pulling and applying patch {}",to_hex(patch_hash));
+        try!(apply(repository, branch_name, &patch, &internal, only_local));
+        *patches_were_applied = true;
+        // This is not necessary anymore, output_files does this.
+        // sync_file_additions(repository, &patch.changes[..],&HashMap::new(), &internal);
+        if is_new {
+            try!(register_hash(repository, &internal, patch_hash));
+        }
+        Ok(())
+    } else {
+        debug!("Patch {:?} has already been applied", patch_hash);
+        Ok(())
+    }
+}
+
 /// Assumes all patches have been downloaded. The third argument
 /// `remote_patches` needs to contain at least all the patches we want
 /// to apply, and the fourth one `local_patches` at least all the patches the other
-/// party doesn't have.
+/// party doesn't have. Conflicts are output according to `style`.
 pub fn apply_patches<T>(repository: &mut Transaction<T>,
                         branch_name: &str,
                         r: &Path,
                         remote_patches: &HashSet<Vec<u8>>,
-                        local_patches: &HashSet<Vec<u8>>)
+                        local_patches: &HashSet<Vec<u8>>,
+                        style: ConflictStyle)
                         -> Result<(), Error> {
     debug!("local {}, remote {}",
            local_patches.len(),
@@ -812,39 +948,6 @@ pub fn apply_patches<T>(repository: &mut Transaction<T>,
         }
         only_local
     };
-    fn apply_patches<'a, T>(repository: &mut Transaction<'a, T>,
-                            branch_name: &str,
-                            repo_root: &Path,
-                            patch_hash: &[u8],
-                            patches_were_applied: &mut bool,
-                            only_local: &HashSet<&[u8]>)
-                            -> Result<(), Error> {
-        if !try!(has_patch(repository, branch_name, patch_hash)) {
-            let patch = try!(Patch::from_repository(repo_root, patch_hash));
-            debug!("Applying patch {:?}", patch_hash.to_hex());
-            for dep in patch.dependencies.iter() {
-                debug!("Applying dependency {:?}", dep.to_hex());
-                try!(apply_patches(repository,
-                                   branch_name,
-                                   repo_root,
-                                   &dep,
-                                   patches_were_applied,
-                                   only_local))
-            }
-            debug!("Now applying patch {:?}", patch);
-            let internal = new_internal(repository);
-            // println!("pulling and applying patch {}",to_hex(patch_hash));
-            try!(apply(repository, branch_name, &patch, &internal, only_local));
-            *patches_were_applied = true;
-            // This is not necessary anymore, output_files does this.
-            // sync_file_additions(repository, &patch.changes[..],&HashMap::new(), &internal);
-            try!(register_hash(repository, &internal, patch_hash));
-            Ok(())
-        } else {
-            debug!("Patch {:?} has already been applied", patch_hash);
-            Ok(())
-        }
-    }
     // let current_branch=self.get_current_branch().to_vec();
     // let branch = repository.db_nodes(branch_name);
     let pending = {
@@ -855,38 +958,40 @@ pub fn apply_patches<T>(repository: &mut Transaction<T>,
     };
     let mut patches_were_applied = false;
     for p in pullable {
-        try!(apply_patches(repository,
-                           branch_name,
-                           &r,
-                           p,
-                           &mut patches_were_applied,
-                           &only_local))
+        try!(apply_with_dependencies(repository,
+                                     branch_name,
+                                     &r,
+                                     p,
+                                     &mut patches_were_applied,
+                                     &only_local))
     }
     debug!("patches applied? {}", patches_were_applied);
     if cfg!(debug_assertions) {
         debug!("debugging");
-        let mut buffer = BufWriter::new(File::create(r.join("debug_")).unwrap());
+        let mut buffer = BufWriter::new(File::create(repo_dir(r).join("debug_")).unwrap());
         repository.debug(branch_name, &mut buffer);
         debug!("/debugging");
     }
     if patches_were_applied {
         try!(repository.write_changes_file(branch_name, r));
         debug!("output_repository");
-        try!(super::output::output_repository(repository, branch_name, &r, &pending));
+        try!(super::output::output_repository(repository, branch_name, &r, &pending, style));
         debug!("done outputting_repository");
     }
-    let mut f = File::create(r.join("dump")).unwrap();
+    let mut f = File::create(repo_dir(r).join("dump")).unwrap();
     try!(repository.dump(&mut f));
     debug!("finished apply_patches");
     Ok(())
 }
 
-/// Apply a patch from a local record: register it, give it a hash, and then apply.
+/// Apply a patch from a local record: register it, give it a hash
+/// (signing it with `key`, if any), and then apply.
 pub fn apply_local_patch<T>(repository: &mut Transaction<T>,
                             branch_name: &str,
                             location: &Path,
                             patch: Patch,
-                            inode_updates: &HashMap<LocalKey, Inode>)
+                            updates: &RecordUpdates,
+                            key: Option<&SigningKey>)
                             -> Result<(), Error> {
     info!("registering a patch with {} changes: {:?}",
           patch.changes.len(),
@@ -894,9 +999,10 @@ pub fn apply_local_patch<T>(repository: &mut Transaction<T>,
     let patch = Arc::new(patch);
     let child_patch = patch.clone();
     let patches_dir = patches_dir(location);
+    let key = key.cloned();
     let hash_child = thread::spawn(move || {
         let t0 = time::precise_time_s();
-        let hash = child_patch.save(&patches_dir);
+        let hash = child_patch.save(&patches_dir, key.as_ref());
         let t1 = time::precise_time_s();
         info!("hashed patch in {}s", t1 - t0);
         hash
@@ -905,9 +1011,17 @@ pub fn apply_local_patch<T>(repository: &mut Transaction<T>,
 
     let t0 = time::precise_time_s();
     let internal: &InternalKey = &new_internal(repository);// InternalKey::new( &internal );
+    {
+        // `record` cannot remove the redundant pseudo-edges it found,
+        // since it may run in a read-only transaction.
+        let mut branch = try!(repository.db_nodes(branch_name));
+        let mut redundant = updates.redundant.clone();
+        try!(remove_redundant_edges(&mut branch, &mut redundant));
+        try!(branch.commit_branch(branch_name));
+    }
     debug!("applying patch");
     try!(apply(repository, branch_name, &patch, internal, &HashSet::new()));
-    debug!("synchronizing tree: {:?}", inode_updates);
+    debug!("synchronizing tree: {:?}", updates.inodes);
     {
         let branch = try!(repository.db_nodes(branch_name));
         let mut db_inodes = repository.db_inodes();
@@ -919,7 +1033,7 @@ pub fn apply_local_patch<T>(repository: &mut Transaction<T>,
                                     key.as_mut_ptr().offset(3),
                                     HASH_SIZE)
             }
-            for (local_key, inode) in inode_updates.iter() {
+            for (local_key, inode) in updates.inodes.iter() {
                 unsafe {
                     copy_nonoverlapping(local_key.as_ptr().offset(2),
                                         key.as_mut_ptr().offset(3 + HASH_SIZE as isize),
@@ -939,14 +1053,15 @@ pub fn apply_local_patch<T>(repository: &mut Transaction<T>,
         try!(branch.commit_branch(branch_name));
         if cfg!(debug_assertions) {
             debug!("debugging");
-            let mut buffer = BufWriter::new(File::create(location.join("debug_")).unwrap());
+            let mut buffer = BufWriter::new(File::create(repo_dir(location).join("debug_")).unwrap());
             repository.debug(branch_name, &mut buffer);
 
-            let mut f = File::create(location.join("dump")).unwrap();
+            let mut f = File::create(repo_dir(location).join("dump")).unwrap();
             try!(repository.dump(&mut f));
             debug!("/debugging");
         }
     }
+    try!(forget_removed_files(repository, branch_name));
     let t2 = time::precise_time_s();
     info!("applied patch in {}s", t2 - t0);
     match join {
@@ -0,0 +1,343 @@
+// Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+//
+// This file is part of Pijul.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+use super::backend::*;
+use super::error::Error;
+use super::patch::{KEY_SIZE, read_changes_from_file, external_hash, patch_hash};
+use super::graph::PARENT_EDGE;
+use super::file_operations::INODE_SIZE;
+use super::fs_representation::{patch_path, branch_changes_file};
+use super::ReadTxn;
+
+use rustc_serialize::hex::ToHex;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::{Path, MAIN_SEPARATOR};
+
+/// A violation of one of the invariants of a repository. Keys,
+/// inodes and hashes are given in hexadecimal when displayed.
+#[derive(Debug)]
+pub enum Inconsistency {
+    /// `db_tree` has this binding, but `db_revtree` lacks its inverse.
+    TreeWithoutRevtree { parent: Vec<u8>, name: Vec<u8>, inode: Vec<u8> },
+    /// `db_revtree` has this binding, but `db_tree` lacks its inverse.
+    RevtreeWithoutTree { inode: Vec<u8>, parent: Vec<u8>, name: Vec<u8> },
+    /// `db_inodes` has this binding, but `db_revinodes` lacks its inverse.
+    InodeWithoutRevinode { inode: Vec<u8>, key: Vec<u8> },
+    /// `db_revinodes` has this binding, but `db_inodes` lacks its inverse.
+    RevinodeWithoutInode { key: Vec<u8>, inode: Vec<u8> },
+    /// An edge of a branch without the edge in the other direction.
+    MissingReverseEdge { branch: String, from: Vec<u8>, to: Vec<u8>, flag: u8 },
+    /// A value of the wrong size in the graph of a branch.
+    MalformedEdge { branch: String, from: Vec<u8>, value: Vec<u8> },
+    /// A key or value too short for table `table`.
+    MalformedEntry { table: &'static str, key: Vec<u8>, value: Vec<u8> },
+    /// `db_external` has this binding, but `db_internal` lacks its inverse.
+    ExternalWithoutInternal { internal: Vec<u8>, external: Vec<u8> },
+    /// `db_internal` has this binding, but `db_external` lacks its inverse.
+    InternalWithoutExternal { external: Vec<u8>, internal: Vec<u8> },
+    /// A known patch without a file in the patches directory.
+    MissingPatchFile { hash: Vec<u8> },
+    /// A patch file whose hash is not its name.
+    WrongPatchHash { hash: Vec<u8>, actual: Vec<u8> },
+    /// A patch file that cannot be decoded.
+    UnreadablePatchFile { hash: Vec<u8> },
+    /// A patch applied on a branch, but not in its changes file.
+    NotInChangesFile { branch: String, hash: Vec<u8> },
+    /// A patch in the changes file of a branch, but not applied on it.
+    NotOnBranch { branch: String, hash: Vec<u8> },
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Inconsistency::TreeWithoutRevtree { ref parent, ref name, ref inode } => {
+                write!(f, "tree maps {}/{:?} to inode {}, but revtree does not map it back",
+                       parent.to_hex(), String::from_utf8_lossy(name), inode.to_hex())
+            }
+            Inconsistency::RevtreeWithoutTree { ref inode, ref parent, ref name } => {
+                write!(f, "revtree maps inode {} to {}/{:?}, but tree does not map it back",
+                       inode.to_hex(), parent.to_hex(), String::from_utf8_lossy(name))
+            }
+            Inconsistency::InodeWithoutRevinode { ref inode, ref key } => {
+                write!(f, "inodes maps inode {} to key {}, but revinodes does not map it back",
+                       inode.to_hex(), key.to_hex())
+            }
+            Inconsistency::RevinodeWithoutInode { ref key, ref inode } => {
+                write!(f, "revinodes maps key {} to inode {}, but inodes does not map it back",
+                       key.to_hex(), inode.to_hex())
+            }
+            Inconsistency::MissingReverseEdge { ref branch, ref from, ref to, flag } => {
+                write!(f, "branch {}: edge {} -> {} (flag {}) has no reverse",
+                       branch, from.to_hex(), to.to_hex(), flag)
+            }
+            Inconsistency::MalformedEdge { ref branch, ref from, ref value } => {
+                write!(f, "branch {}: malformed edge from {}: {}", branch, from.to_hex(), value.to_hex())
+            }
+            Inconsistency::MalformedEntry { table, ref key, ref value } => {
+                write!(f, "{}: malformed entry {} -> {}", table, key.to_hex(), value.to_hex())
+            }
+            Inconsistency::ExternalWithoutInternal { ref internal, ref external } => {
+                write!(f, "internal id {} is patch {}, but that patch has no internal id",
+                       internal.to_hex(), external.to_hex())
+            }
+            Inconsistency::InternalWithoutExternal { ref external, ref internal } => {
+                write!(f, "patch {} has internal id {}, but that id is not mapped back",
+                       external.to_hex(), internal.to_hex())
+            }
+            Inconsistency::MissingPatchFile { ref hash } => write!(f, "patch {} has no file", hash.to_hex()),
+            Inconsistency::WrongPatchHash { ref hash, ref actual } => {
+                write!(f, "the file of patch {} has hash {}", hash.to_hex(), actual.to_hex())
+            }
+            Inconsistency::UnreadablePatchFile { ref hash } => {
+                write!(f, "the file of patch {} cannot be decoded", hash.to_hex())
+            }
+            Inconsistency::NotInChangesFile { ref branch, ref hash } => {
+                write!(f, "patch {} is applied on branch {}, but not in its changes file",
+                       hash.to_hex(), branch)
+            }
+            Inconsistency::NotOnBranch { ref branch, ref hash } => {
+                write!(f, "patch {} is in the changes file of branch {}, but not applied on it",
+                       hash.to_hex(), branch)
+            }
+        }
+    }
+}
+
+/// Does `db` bind `k` to `v`?
+fn has_binding<T, R: RawTxn>(db: &Db<T, R>, k: &[u8], v: &[u8]) -> bool {
+    db.iter(k, Some(v)).next().map_or(false, |(k_, v_)| k_ == k && v_ == v)
+}
+
+fn check_tree<T, R: RawTxn>(repository: &Transaction<T, R>, result: &mut Vec<Inconsistency>) {
+    let db_tree = repository.db_tree();
+    let db_revtree = repository.db_revtree();
+    for (k, v) in db_tree.iter(b"", None) {
+        // Directories are also bound to the empty value.
+        if v.is_empty() {
+            continue;
+        }
+        if k.len() < INODE_SIZE {
+            result.push(Inconsistency::MalformedEntry {
+                table: "tree",
+                key: k.to_vec(),
+                value: v.to_vec(),
+            });
+            continue;
+        }
+        if !has_binding(&db_revtree, v, k) {
+            result.push(Inconsistency::TreeWithoutRevtree {
+                parent: k[0..INODE_SIZE].to_vec(),
+                name: k[INODE_SIZE..].to_vec(),
+                inode: v.to_vec(),
+            })
+        }
+    }
+    for (k, v) in db_revtree.iter(b"", None) {
+        if v.len() < INODE_SIZE {
+            result.push(Inconsistency::MalformedEntry {
+                table: "revtree",
+                key: k.to_vec(),
+                value: v.to_vec(),
+            });
+            continue;
+        }
+        if !has_binding(&db_tree, v, k) {
+            result.push(Inconsistency::RevtreeWithoutTree {
+                inode: k.to_vec(),
+                parent: v[0..INODE_SIZE].to_vec(),
+                name: v[INODE_SIZE..].to_vec(),
+            })
+        }
+    }
+}
+
+fn check_inodes<T, R: RawTxn>(repository: &Transaction<T, R>, result: &mut Vec<Inconsistency>) {
+    let db_inodes = repository.db_inodes();
+    let db_revinodes = repository.db_revinodes();
+    for (k, v) in db_inodes.iter(b"", None) {
+        // Values start with the status and permissions of the file.
+        if v.len() < 3 {
+            result.push(Inconsistency::MalformedEntry {
+                table: "inodes",
+                key: k.to_vec(),
+                value: v.to_vec(),
+            });
+            continue;
+        }
+        if !has_binding(&db_revinodes, &v[3..], k) {
+            result.push(Inconsistency::InodeWithoutRevinode {
+                inode: k.to_vec(),
+                key: v[3..].to_vec(),
+            })
+        }
+    }
+    for (k, v) in db_revinodes.iter(b"", None) {
+        let found = match db_inodes.get(v) {
+            Some(node) => node.len() >= 3 && &node[3..] == k,
+            None => false,
+        };
+        if !found {
+            result.push(Inconsistency::RevinodeWithoutInode {
+                key: k.to_vec(),
+                inode: v.to_vec(),
+            })
+        }
+    }
+}
+
+fn check_edges<T, R: RawTxn>(repository: &Transaction<T, R>,
+                             branch_name: &str,
+                             result: &mut Vec<Inconsistency>)
+                             -> Result<(), Error> {
+    let branch = try!(repository.db_nodes(branch_name));
+    let mut reverse = Vec::new();
+    for (k, v) in branch.iter(b"", None) {
+        if v.len() < 1 + KEY_SIZE {
+            result.push(Inconsistency::MalformedEdge {
+                branch: branch_name.to_string(),
+                from: k.to_vec(),
+                value: v.to_vec(),
+            });
+            continue;
+        }
+        let to = &v[1..(1 + KEY_SIZE)];
+        reverse.clear();
+        reverse.push(v[0] ^ PARENT_EDGE);
+        reverse.extend(k);
+        reverse.extend(&v[(1 + KEY_SIZE)..]);
+        let found = branch.iter(to, Some(&reverse))
+            .next()
+            .map(|(k_, v_)| k_ == to && v_ == &reverse[..])
+            .unwrap_or(false);
+        if !found {
+            result.push(Inconsistency::MissingReverseEdge {
+                branch: branch_name.to_string(),
+                from: k.to_vec(),
+                to: to.to_vec(),
+                flag: v[0],
+            })
+        }
+    }
+    Ok(())
+}
+
+fn check_patches<T, R: RawTxn>(repository: &Transaction<T, R>,
+                               location: &Path,
+                               result: &mut Vec<Inconsistency>)
+                               -> Result<(), Error> {
+    let db_external = repository.db_external();
+    let db_internal = repository.db_internal();
+    for (k, v) in db_external.iter(b"", None) {
+        if !has_binding(&db_internal, v, k) {
+            result.push(Inconsistency::ExternalWithoutInternal {
+                internal: k.to_vec(),
+                external: v.to_vec(),
+            })
+        }
+    }
+    for (k, v) in db_internal.iter(b"", None) {
+        if !has_binding(&db_external, v, k) {
+            result.push(Inconsistency::InternalWithoutExternal {
+                external: k.to_vec(),
+                internal: v.to_vec(),
+            })
+        }
+        let path = location.join(patch_path(k, MAIN_SEPARATOR));
+        match patch_hash(&path) {
+            Ok((_, actual)) => {
+                if &actual[..] != k {
+                    result.push(Inconsistency::WrongPatchHash {
+                        hash: k.to_vec(),
+                        actual: actual,
+                    })
+                }
+            }
+            Err(Error::IO(ref e)) if e.kind() == ErrorKind::NotFound => {
+                result.push(Inconsistency::MissingPatchFile { hash: k.to_vec() })
+            }
+            Err(Error::InvalidPatch(_, _)) |
+            Err(Error::UnknownPatchVersion(_, _)) => {
+                result.push(Inconsistency::UnreadablePatchFile { hash: k.to_vec() })
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn check_changes_file<T, R: RawTxn>(repository: &Transaction<T, R>,
+                                    location: &Path,
+                                    branch_name: &str,
+                                    result: &mut Vec<Inconsistency>)
+                                    -> Result<(), Error> {
+    let db_external = repository.db_external();
+    let db_branches = repository.db_branches();
+    let mut applied = HashSet::new();
+    for (k, v) in db_branches.iter(branch_name.as_bytes(), None) {
+        if k != branch_name.as_bytes() {
+            break;
+        }
+        applied.insert(external_hash(&db_external, v).to_vec());
+    }
+    let changes_file = branch_changes_file(location, branch_name.as_bytes());
+    let in_file = match read_changes_from_file(&changes_file) {
+        Ok(changes) => changes,
+        // Branches where nothing was ever applied have no changes file.
+        Err(Error::IO(ref e)) if e.kind() == ErrorKind::NotFound => HashSet::new(),
+        Err(e) => return Err(e),
+    };
+    let mut missing: Vec<&Vec<u8>> = applied.difference(&in_file).collect();
+    missing.sort();
+    for hash in missing {
+        result.push(Inconsistency::NotInChangesFile {
+            branch: branch_name.to_string(),
+            hash: hash.clone(),
+        })
+    }
+    let mut extra: Vec<&Vec<u8>> = in_file.difference(&applied).collect();
+    extra.sort();
+    for hash in extra {
+        result.push(Inconsistency::NotOnBranch {
+            branch: branch_name.to_string(),
+            hash: hash.clone(),
+        })
+    }
+    Ok(())
+}
+
+/// Check the invariants of the repository at `location`: inverse
+/// tables must be inverses of each other, every edge must have its
+/// reverse, every known patch must have a readable file with the
+/// right hash (see `patch_hash`), and the changes file of each
+/// branch must list exactly the patches applied on that branch.
+pub fn check<T, R: RawTxn>(repository: &Transaction<T, R>,
+                           location: &Path)
+                           -> Result<Vec<Inconsistency>, Error> {
+    let mut result = Vec::new();
+    check_tree(repository, &mut result);
+    check_inodes(repository, &mut result);
+    for branch in repository.branches() {
+        if repository.has_branch(&branch) {
+            try!(check_edges(repository, &branch, &mut result))
+        }
+        try!(check_changes_file(repository, location, &branch, &mut result))
+    }
+    try!(check_patches(repository, location, &mut result));
+    Ok(result)
+}
@@ -0,0 +1,211 @@
+// Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+//
+// This file is part of Pijul.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+use super::backend::*;
+use super::error::Error;
+use super::patch::{KEY_SIZE, HASH_SIZE, ROOT_KEY, external_hash, external_key};
+use super::graph::{LineBuffer, retrieve, output_file, PSEUDO_EDGE, FOLDER_EDGE, DIRECTORY_FLAG};
+use super::annotate::AnnotatedLine;
+
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+/// One of the competing versions in a conflict.
+#[derive(Debug)]
+pub struct ConflictSide {
+    pub lines: Vec<AnnotatedLine>,
+}
+
+impl ConflictSide {
+    /// Patches that introduced the lines of this side, in the order
+    /// of their first line.
+    pub fn patches(&self) -> Vec<Vec<u8>> {
+        let mut patches: Vec<Vec<u8>> = Vec::new();
+        for line in self.lines.iter() {
+            if let Some(ref p) = line.patch {
+                if !patches.contains(p) {
+                    patches.push(p.clone())
+                }
+            }
+        }
+        patches
+    }
+}
+
+#[derive(Debug)]
+pub enum Conflict {
+    /// Several orderings of the lines of a file, between the same
+    /// two unconflicting lines.
+    Lines { path: PathBuf, sides: Vec<ConflictSide> },
+    /// A file with several names.
+    Names { paths: Vec<PathBuf> },
+    /// Several files with the same name. When outputting them, all
+    /// but the first one are renamed, see
+    /// `output::create_conflicting_copy`.
+    Files { path: PathBuf, count: usize },
+}
+
+/// Line buffer keeping only the sides of conflicts.
+struct ConflictBuffer<'a, 'b: 'a, T: 'a, R: 'a + RawTxn> {
+    db_external: &'a Db<'a, 'b, T, R>,
+    current: Option<Vec<ConflictSide>>,
+    conflicts: Vec<Vec<ConflictSide>>,
+}
+
+impl<'a, 'env: 'a, T: 'a, R: 'a + RawTxn> LineBuffer<'a, 'env, T, R> for ConflictBuffer<'a, 'env, T, R> {
+    fn output_line(&mut self, key: &'a [u8], contents: Contents<'a, 'env, T, R>) -> Result<(), Error> {
+        if let Some(ref mut sides) = self.current {
+            let mut line = Vec::new();
+            for c in contents {
+                line.extend(c)
+            }
+            if !line.is_empty() && key.len() >= HASH_SIZE {
+                let side = sides.last_mut().unwrap();
+                side.lines.push(AnnotatedLine {
+                    patch: Some(external_hash(self.db_external, key).to_vec()),
+                    key: Some(external_key(self.db_external, key)),
+                    authors: Vec::new(),
+                    timestamp: None,
+                    contents: line,
+                })
+            }
+        }
+        Ok(())
+    }
+    fn begin_conflict(&mut self) -> Result<(), Error> {
+        self.current = Some(vec![ConflictSide { lines: Vec::new() }]);
+        Ok(())
+    }
+    fn conflict_next(&mut self) -> Result<(), Error> {
+        if let Some(ref mut sides) = self.current {
+            sides.push(ConflictSide { lines: Vec::new() })
+        }
+        Ok(())
+    }
+    fn end_conflict(&mut self) -> Result<(), Error> {
+        if let Some(sides) = self.current.take() {
+            self.conflicts.push(sides)
+        }
+        Ok(())
+    }
+}
+
+/// Collect the names of the files below directory node `key`, the
+/// same way `output::output_aux` does, but without touching the
+/// working copy, along with their nodes and permissions. `visited`
+/// maps each file node to its paths.
+pub fn collect_files<T, R: RawTxn>(branch: &Branch<T, R>,
+                                   db_contents: &Db<T, R>,
+                                   key: &[u8],
+                                   path: &Path,
+                                   visited: &mut HashMap<Vec<u8>, Vec<PathBuf>>,
+                                   files: &mut Vec<(PathBuf, Vec<u8>, usize)>)
+                                   -> Result<(), Error> {
+    let mut directories = Vec::new();
+    for (_, b) in branch.iter(key, Some(&[FOLDER_EDGE][..]))
+        .take_while(|&(k, b)| k == key && b[0] <= FOLDER_EDGE | PSEUDO_EDGE) {
+        let mut name = Vec::new();
+        if let Some(cont_b) = db_contents.contents(&b[1..(1 + KEY_SIZE)]) {
+            for c in cont_b {
+                name.extend(c)
+            }
+        }
+        if name.len() < 2 {
+            continue;
+        }
+        let perms = ((name[0] as usize) << 8) | (name[1] as usize);
+        let file_path = path.join(String::from_utf8_lossy(&name[2..]).as_ref());
+        for (_, c) in branch.iter(&b[1..(1 + KEY_SIZE)], Some(&[FOLDER_EDGE][..]))
+            .take_while(|&(k, c)| k == &b[1..(1 + KEY_SIZE)] && c[0] <= FOLDER_EDGE | PSEUDO_EDGE) {
+            let cv = &c[1..(1 + KEY_SIZE)];
+            match visited.entry(cv.to_vec()) {
+                Entry::Occupied(mut e) => e.get_mut().push(file_path.clone()),
+                Entry::Vacant(e) => {
+                    e.insert(vec![file_path.clone()]);
+                    if perms & DIRECTORY_FLAG != 0 {
+                        directories.push((file_path.clone(), cv.to_vec()))
+                    } else {
+                        files.push((file_path.clone(), cv.to_vec(), perms))
+                    }
+                }
+            }
+        }
+    }
+    for (dir_path, dir_key) in directories {
+        try!(collect_files(branch, db_contents, &dir_key, &dir_path, visited, files))
+    }
+    Ok(())
+}
+
+/// All conflicts on branch `branch_name`, with paths relative to the
+/// root of the repository. Name conflicts come first, then paths
+/// shared by several files, then conflicts inside files, in the order
+/// of the files' paths.
+pub fn conflicts<T, R: RawTxn>(repository: &Transaction<T, R>,
+                               branch_name: &str)
+                               -> Result<Vec<Conflict>, Error> {
+    let branch = try!(repository.db_nodes(branch_name));
+    let db_contents = repository.db_contents();
+    let db_external = repository.db_external();
+    let mut visited = HashMap::new();
+    let mut files = Vec::new();
+    try!(collect_files(&branch, &db_contents, ROOT_KEY, Path::new(""), &mut visited, &mut files));
+
+    let mut result = Vec::new();
+    let mut nodes: HashMap<PathBuf, Vec<Vec<u8>>> = HashMap::new();
+    for (key, paths) in visited.iter() {
+        for path in paths.iter() {
+            nodes.entry(path.clone()).or_insert(Vec::new()).push(key.clone())
+        }
+    }
+    let mut names: Vec<Vec<PathBuf>> = visited.into_iter()
+        .map(|(_, paths)| paths)
+        .filter(|paths| paths.len() > 1)
+        .collect();
+    names.sort();
+    for paths in names {
+        result.push(Conflict::Names { paths: paths })
+    }
+    let mut same_path: Vec<(PathBuf, usize)> = nodes.into_iter()
+        .filter(|&(_, ref keys)| keys.len() > 1)
+        .map(|(path, keys)| (path, keys.len()))
+        .collect();
+    same_path.sort();
+    for (path, count) in same_path {
+        result.push(Conflict::Files { path: path, count: count })
+    }
+
+    files.sort();
+    for (path, key, _) in files {
+        let mut buf = ConflictBuffer {
+            db_external: &db_external,
+            current: None,
+            conflicts: Vec::new(),
+        };
+        let mut redundant_edges = Vec::new();
+        let graph = retrieve(&branch, &key);
+        try!(output_file(&branch, &db_contents, &mut buf, graph, &mut redundant_edges));
+        for sides in buf.conflicts {
+            result.push(Conflict::Lines {
+                path: path.clone(),
+                sides: sides,
+            })
+        }
+    }
+    Ok(result)
+}
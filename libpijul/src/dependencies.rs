@@ -0,0 +1,140 @@
+// Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+//
+// This file is part of Pijul.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+use super::backend::*;
+use super::error::Error;
+use super::patch::{Patch, internal_hash, external_hash};
+use super::ReadTxn;
+
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Is the patch with internal id `internal` applied on branch `branch_name`?
+fn is_applied<T, R: RawTxn>(db_branches: &Db<T, R>, branch_name: &str, internal: &[u8]) -> bool {
+    db_branches.iter(branch_name.as_bytes(), Some(internal))
+        .next()
+        .map_or(false, |(k, v)| k == branch_name.as_bytes() && v == internal)
+}
+
+/// Internal id of patch `hash`, which must be applied on branch
+/// `branch_name`.
+fn applied_internal<T, R: RawTxn>(repository: &Transaction<T, R>,
+                                  branch_name: &str,
+                                  hash: &[u8])
+                                  -> Result<Vec<u8>, Error> {
+    let internal = {
+        let db_internal = repository.db_internal();
+        match internal_hash(&db_internal, hash) {
+            Ok(internal) => internal.contents.to_vec(),
+            Err(Error::InternalHashNotFound(_)) => return Err(Error::PatchNotOnBranch(hash.to_vec())),
+            Err(e) => return Err(e),
+        }
+    };
+    if is_applied(&repository.db_branches(), branch_name, &internal) {
+        Ok(internal)
+    } else {
+        Err(Error::PatchNotOnBranch(hash.to_vec()))
+    }
+}
+
+/// Hashes of the patches applied on branch `branch_name` that depend
+/// directly on patch `hash`.
+pub fn direct_dependents<T, R: RawTxn>(repository: &Transaction<T, R>,
+                                       branch_name: &str,
+                                       hash: &[u8])
+                                       -> Result<Vec<Vec<u8>>, Error> {
+    let internal = try!(applied_internal(repository, branch_name, hash));
+    let db_revdep = repository.db_revdep();
+    let db_branches = repository.db_branches();
+    let db_external = repository.db_external();
+    let mut result = Vec::new();
+    for (k, v) in db_revdep.iter(&internal, None) {
+        if k != &internal[..] {
+            break;
+        }
+        if is_applied(&db_branches, branch_name, v) {
+            result.push(external_hash(&db_external, v).to_vec())
+        }
+    }
+    Ok(result)
+}
+
+/// Hashes of the patches applied on branch `branch_name` that patch
+/// `hash` depends on directly. db_revdep is indexed by dependency, so
+/// these are read from the patch file, in the patches directory of
+/// `location`.
+pub fn direct_dependencies<T, R: RawTxn>(repository: &Transaction<T, R>,
+                                         branch_name: &str,
+                                         location: &Path,
+                                         hash: &[u8])
+                                         -> Result<Vec<Vec<u8>>, Error> {
+    let patch = try!(Patch::from_repository(location, hash));
+    Ok(patch.dependencies
+        .into_iter()
+        .filter(|dep| applied_internal(repository, branch_name, dep).is_ok())
+        .collect())
+}
+
+/// Patches of branch `branch_name` related to patch `hash` by
+/// `neighbours` (`direct_dependents` or `direct_dependencies`),
+/// sorted by hash. If `transitive` is true, this follows these
+/// relations through other patches.
+pub fn related_patches<T, R, F>(repository: &Transaction<T, R>,
+                                branch_name: &str,
+                                hash: &[u8],
+                                transitive: bool,
+                                mut neighbours: F)
+                                -> Result<Vec<Vec<u8>>, Error>
+    where R: RawTxn,
+          F: FnMut(&[u8]) -> Result<Vec<Vec<u8>>, Error>
+{
+    try!(applied_internal(repository, branch_name, hash));
+    let mut seen = HashSet::new();
+    let mut stack = vec![hash.to_vec()];
+    while let Some(patch) = stack.pop() {
+        for next in try!(neighbours(&patch)) {
+            if seen.insert(next.clone()) && transitive {
+                stack.push(next)
+            }
+        }
+    }
+    let mut result: Vec<Vec<u8>> = seen.into_iter().collect();
+    result.sort();
+    Ok(result)
+}
+
+/// Direct dependencies between the patches of branch `branch_name`,
+/// as pairs `(dependent, dependency)` of hashes, sorted. The
+/// dependencies are read from the patch files, in the patches
+/// directory of `location`.
+pub fn dependency_graph<T, R: RawTxn>(repository: &Transaction<T, R>,
+                                      branch_name: &str,
+                                      location: &Path)
+                                      -> Result<Vec<(Vec<u8>, Vec<u8>)>, Error> {
+    let applied: HashSet<Vec<u8>> = try!(repository.applied_patches(branch_name)).into_iter().collect();
+    let mut edges = Vec::new();
+    for hash in applied.iter() {
+        let patch = try!(Patch::from_repository(location, hash));
+        for dep in patch.dependencies {
+            if applied.contains(&dep) {
+                edges.push((hash.clone(), dep))
+            }
+        }
+    }
+    edges.sort();
+    Ok(edges)
+}
@@ -24,6 +24,7 @@ extern crate cbor;
 extern crate rustc_serialize;
 use self::rustc_serialize::hex::ToHex;
 use sanakirja;
+use signature::Signature;
 
 #[derive(Debug)]
 pub enum Error {
@@ -38,6 +39,17 @@ pub enum Error {
     PatchNotFound(PathBuf, String),
     GPG(i32, String),
     Utf8(std::str::Utf8Error),
+    PatchNotOnBranch(Vec<u8>),
+    PatchIsDependedOn(Vec<u8>, Vec<u8>),
+    BranchNotFound(String),
+    BranchAlreadyExists(String),
+    TagNotFound(String),
+    TagAlreadyExists(String),
+    UntrustedPatch(Vec<u8>, Signature),
+    InvalidSigningKey(PathBuf),
+    InvalidPatch(Option<PathBuf>, String),
+    UnknownPatchVersion(Option<PathBuf>, u32),
+    PristineNotInitialized,
 }
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -57,6 +69,27 @@ impl fmt::Display for Error {
             }
             Error::GPG(ref code, ref s) => write!(f, "GPG returned code {:?}, {:?}", code, s),
             Error::Utf8(ref e) => write!(f, "Utf8 Error {:?}", e),
+            Error::PatchNotOnBranch(ref hash) => {
+                write!(f, "Patch {} is not applied on this branch", hash.to_hex())
+            }
+            Error::PatchIsDependedOn(ref hash, ref dep) => {
+                write!(f, "Patch {} is depended on by patch {}", hash.to_hex(), dep.to_hex())
+            }
+            Error::BranchNotFound(ref name) => write!(f, "Branch {:?} not found", name),
+            Error::BranchAlreadyExists(ref name) => write!(f, "Branch {:?} already exists", name),
+            Error::TagNotFound(ref name) => write!(f, "Tag {:?} not found", name),
+            Error::TagAlreadyExists(ref name) => write!(f, "Tag {:?} already exists", name),
+            Error::UntrustedPatch(ref hash, ref sig) => {
+                write!(f, "Patch {} is not signed by a trusted key ({})", hash.to_hex(), sig)
+            }
+            Error::InvalidSigningKey(ref path) => write!(f, "Invalid signing key in {}", path.display()),
+            Error::InvalidPatch(ref path, ref msg) => write!(f, "Invalid patch {:?}: {}", path, msg),
+            Error::UnknownPatchVersion(ref path, v) => {
+                write!(f, "Patch {:?} has format version {}, which this version of Pijul cannot read", path, v)
+            }
+            Error::PristineNotInitialized => {
+                write!(f, "The pristine lacks some tables, \"pijul check --repair\" rebuilds it")
+            }
         }
     }
 }
@@ -75,6 +108,17 @@ impl std::error::Error for Error {
             Error::PatchNotFound(_, _) => "Patch not found",
             Error::GPG(_, _) => "GPG was unsuccessful",
             Error::Utf8(ref e) => e.description(),
+            Error::PatchNotOnBranch(_) => "Patch not applied on this branch",
+            Error::PatchIsDependedOn(_, _) => "Patch is depended on by another patch",
+            Error::BranchNotFound(_) => "Branch not found",
+            Error::BranchAlreadyExists(_) => "Branch already exists",
+            Error::TagNotFound(_) => "Tag not found",
+            Error::TagAlreadyExists(_) => "Tag already exists",
+            Error::UntrustedPatch(_, _) => "Patch not signed by a trusted key",
+            Error::InvalidSigningKey(_) => "Invalid signing key",
+            Error::InvalidPatch(_, _) => "Invalid patch",
+            Error::UnknownPatchVersion(_, _) => "Unknown patch format version",
+            Error::PristineNotInitialized => "Pristine not initialized",
         }
     }
 
@@ -91,6 +135,17 @@ impl std::error::Error for Error {
             Error::PatchNotFound(_, _) => None,
             Error::GPG(_, _) => None,
             Error::Utf8(ref e) => Some(e),
+            Error::PatchNotOnBranch(_) => None,
+            Error::PatchIsDependedOn(_, _) => None,
+            Error::BranchNotFound(_) => None,
+            Error::BranchAlreadyExists(_) => None,
+            Error::TagNotFound(_) => None,
+            Error::TagAlreadyExists(_) => None,
+            Error::UntrustedPatch(_, _) => None,
+            Error::InvalidSigningKey(_) => None,
+            Error::InvalidPatch(_, _) => None,
+            Error::UnknownPatchVersion(_, _) => None,
+            Error::PristineNotInitialized => None,
         }
     }
 }
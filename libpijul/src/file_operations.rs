@@ -267,8 +267,22 @@ pub fn remove_file<T>(repository:&mut Transaction<T>, path:&std::path::Path) ->
     Ok(())
 }
 
-pub fn list_files<T>(repository:&Transaction<T>)->Result<Vec<PathBuf>, Error> {
-    fn collect<T>(repo:&Transaction<T>,key:&[u8],pb:&Path, basename:&[u8],files:&mut Vec<PathBuf>)->Result<(),Error> {
+/// The target of symbolic link `path`, which is what gets recorded
+/// as the link's contents.
+#[cfg(not(windows))]
+pub fn read_link_target(path:&Path) -> std::io::Result<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+    let target = try!(std::fs::read_link(path));
+    Ok(target.as_os_str().as_bytes().to_vec())
+}
+#[cfg(windows)]
+pub fn read_link_target(path:&Path) -> std::io::Result<Vec<u8>> {
+    let target = try!(std::fs::read_link(path));
+    Ok(target.to_string_lossy().into_owned().into_bytes())
+}
+
+pub fn list_files<T,R:RawTxn>(repository:&Transaction<T,R>)->Result<Vec<PathBuf>, Error> {
+    fn collect<T,R:RawTxn>(repo:&Transaction<T,R>,key:&[u8],pb:&Path, basename:&[u8],files:&mut Vec<PathBuf>)->Result<(),Error> {
         debug!("collecting {:?},{:?}",key,std::str::from_utf8(basename));
         let db_inodes = repo.db_inodes();
         let add= match db_inodes.get(key) {
@@ -304,8 +318,8 @@ pub fn list_files<T>(repository:&Transaction<T>)->Result<Vec<PathBuf>, Error> {
 
 
 // Returns internal keys -- we have no type for that yet.
-pub fn list_files_in_dir<T>(repository:&Transaction<T>, inode:&Inode)
-                            ->Result<Vec<(String, Option<Vec<u8>>, Inode)>, Error> {
+pub fn list_files_in_dir<T,R:RawTxn>(repository:&Transaction<T,R>, inode:&Inode)
+                                     ->Result<Vec<(String, Option<Vec<u8>>, Inode)>, Error> {
     let mut result = Vec::new();
     let db_tree = repository.db_tree();
     let db_inodes = repository.db_inodes();
@@ -324,7 +338,7 @@ pub fn list_files_in_dir<T>(repository:&Transaction<T>, inode:&Inode)
     Ok(result)
 }
 
-pub fn is_directory<T>(repository:&Transaction<T>, inode:&Inode) -> bool {
+pub fn is_directory<T,R:RawTxn>(repository:&Transaction<T,R>, inode:&Inode) -> bool {
     let db_tree = repository.db_tree();
     inode == &ROOT_INODE || db_tree.get(inode.as_ref()).is_some()
 }
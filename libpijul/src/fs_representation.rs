@@ -23,7 +23,10 @@
 //! used for instance for downloading files from remote repositories.
 
 use std::path::{Path,PathBuf};
-use std::fs::{metadata,create_dir_all};
+use std::fs::{metadata,create_dir_all,File};
+use std::io::{Read,Write};
+use backend::{DEFAULT_BRANCH,Repository};
+use error::Error;
 extern crate rustc_serialize;
 use self::rustc_serialize::hex::ToHex;
 use std;
@@ -44,6 +47,18 @@ pub fn patches_dir<P:AsRef<Path>>(p : P) -> PathBuf {
     return p.as_ref().join(PIJUL_DIR_NAME).join(PATCHES_DIR_NAME)
 }
 
+pub const TAGS_DIR_NAME:&'static str="tags";
+
+pub fn tags_dir<P:AsRef<Path>>(p : P) -> PathBuf {
+    return p.as_ref().join(PIJUL_DIR_NAME).join(TAGS_DIR_NAME)
+}
+
+/// File holding the patches of tag `name`, in the format of changes
+/// files. Names are hex-encoded, so that any name is a valid file name.
+pub fn tag_file(p : &Path, name: &str) -> PathBuf {
+    tags_dir(p).join(name.as_bytes().to_hex())
+}
+
 pub fn branch_changes_base_path(b:&[u8])->String {
     "changes.".to_string() + &b.to_hex()
 }
@@ -56,6 +71,37 @@ pub fn meta_file(p : &Path) -> PathBuf {
     p.join(PIJUL_DIR_NAME).join("meta.toml")
 }
 
+/// File listing the copies of binary files written for the sides of
+/// their conflicts, relative to the working copy, one per line.
+pub fn conflict_copies_file(p : &Path) -> PathBuf {
+    p.join(PIJUL_DIR_NAME).join("conflict_copies")
+}
+
+pub fn current_branch_file(p : &Path) -> PathBuf {
+    p.join(PIJUL_DIR_NAME).join("current_branch")
+}
+
+/// Name of the branch the working copy tracks, defaulting to
+/// `DEFAULT_BRANCH` if it was never set.
+pub fn read_current_branch(p : &Path) -> String {
+    let mut name = String::new();
+    let read = match File::open(current_branch_file(p)) {
+        Ok(mut f) => f.read_to_string(&mut name).is_ok(),
+        Err(_) => false
+    };
+    if read && name.trim().len() > 0 {
+        name.trim().to_string()
+    } else {
+        DEFAULT_BRANCH.to_string()
+    }
+}
+
+pub fn set_current_branch(p : &Path, branch_name : &str) -> std::io::Result<()> {
+    let mut f = try!(File::create(current_branch_file(p)));
+    try!(f.write_all(branch_name.as_bytes()));
+    f.write_all(b"\n")
+}
+
 pub fn find_repo_root<'a>(dir : &'a Path) -> Option<PathBuf> {
     let c:Vec<&std::ffi::OsStr>=dir.iter().collect();
     let mut i=c.len();
@@ -79,14 +125,19 @@ pub fn find_repo_root<'a>(dir : &'a Path) -> Option<PathBuf> {
     None
 }
 
-pub fn create(dir : &Path) -> std::io::Result<()> {
+/// Create a repository at `dir`, with an empty default branch.
+pub fn create(dir : &Path) -> Result<(),Error> {
     let mut repo_dir = repo_dir(dir);
     try!(create_dir_all(&repo_dir));
     repo_dir.push("pristine");
     try!(create_dir_all(&repo_dir));
+    try!(Repository::create(&repo_dir));
     repo_dir.pop();
     repo_dir.push("patches");
     try!(create_dir_all(&repo_dir));
+    repo_dir.pop();
+    repo_dir.push(TAGS_DIR_NAME);
+    try!(create_dir_all(&repo_dir));
     Ok(())
 }
 
@@ -100,3 +151,25 @@ pub fn patch_path(h:&[u8],sep:char)->String {
     p.push_str(".cbor.gz");
     p
 }
+
+/// Path of the detached signature of patch `h`, next to the patch.
+pub fn signature_path(h:&[u8],sep:char)->String {
+    let mut p=PIJUL_DIR_NAME.to_string();
+    p.push(sep);
+    p.push_str(PATCHES_DIR_NAME);
+    p.push(sep);
+    p.push_str(&h.to_hex());
+    p.push_str(".cbor.sig");
+    p
+}
+
+/// Path of the file of tag `name`, relative to the root of the
+/// repository, as in `tag_file`.
+pub fn tag_path(name:&str,sep:char)->String {
+    let mut p=PIJUL_DIR_NAME.to_string();
+    p.push(sep);
+    p.push_str(TAGS_DIR_NAME);
+    p.push(sep);
+    p.push_str(&name.as_bytes().to_hex());
+    p
+}
@@ -45,6 +45,7 @@ use patch::{HASH_SIZE, KEY_SIZE};
 use std;
 
 pub const DIRECTORY_FLAG:usize = 0x200;
+pub const SYMLINK_FLAG:usize = 0x400;
 
 bitflags! {
     pub flags Flags: u8 {
@@ -91,20 +92,75 @@ pub struct Graph<'a> {
     pub children:Vec<(Option<&'a[u8]>,usize)>
 }
 
-pub trait LineBuffer<'a,'env:'a,T:'a> {
+pub const CONFLICT_START:&'static [u8] = b">>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>\n";
+pub const CONFLICT_NEXT:&'static [u8] = b"================================\n";
+pub const CONFLICT_END:&'static [u8] = b"<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<\n";
+/// Start of the original version of a conflict, in the diff3 layout.
+pub const CONFLICT_BASE:&'static [u8] = b"||||||||||||||||||||||||||||||||\n";
 
-    fn output_line(&mut self, key:&'a [u8], contents: Contents<'a,'env,T>) -> Result<(),Error>;
+/// Is `line` the conflict marker `marker`, possibly followed by a label?
+fn is_marker(line:&[u8], marker:&[u8]) -> bool {
+    let n = marker.len() - 1;
+    line == marker || (line.len() > n && line.starts_with(&marker[..n]) && line[n] == b' ')
+}
+
+/// Is `line` the start of the original version of a diff3-style
+/// conflict, as written by `output`?
+fn is_base_marker(line:&[u8]) -> bool {
+    let n = CONFLICT_BASE.len() - 1;
+    line.starts_with(&CONFLICT_BASE[..n]) && &line[n..] == &b" base\n"[..]
+}
+
+/// Replace labelled conflict markers by the plain ones, and remove
+/// the original versions of diff3-style conflicts, so that files
+/// output with any conflict style compare equal to their graph.
+/// `markers` are the markers of the graph, in order: only lines
+/// matching the next of them are taken for markers, and other lines
+/// are kept as they are, even if they look like markers.
+pub fn strip_conflict_labels<'a>(lines:&[&'a [u8]], markers:&[&'static [u8]]) -> Vec<&'a [u8]> {
+    let mut result = Vec::with_capacity(lines.len());
+    let mut markers = markers.iter().cloned().peekable();
+    let mut first_side = false;
+    let mut in_base = false;
+    for &line in lines {
+        let next = markers.peek().cloned();
+        if in_base {
+            if next.map_or(false, |m| is_marker(line, m)) {
+                in_base = false
+            } else {
+                continue
+            }
+        }
+        match next {
+            Some(m) if is_marker(line, m) => {
+                result.push(m);
+                markers.next();
+                first_side = m == CONFLICT_START
+            },
+            Some(m) if first_side && m == CONFLICT_NEXT && is_base_marker(line) => {
+                in_base = true;
+                first_side = false
+            },
+            _ => result.push(line)
+        }
+    }
+    result
+}
+
+pub trait LineBuffer<'a,'env:'a,T:'a,R:'a+RawTxn> {
+
+    fn output_line(&mut self, key:&'a [u8], contents: Contents<'a,'env,T,R>) -> Result<(),Error>;
 
     fn begin_conflict(&mut self) -> Result<(),Error> {
-        let l = Contents::from_slice(b">>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>\n");
+        let l = Contents::from_slice(CONFLICT_START);
         self.output_line(&[], l)
     }
     fn conflict_next(&mut self) -> Result<(),Error> {
-        let l = Contents::from_slice(b"================================\n");
+        let l = Contents::from_slice(CONFLICT_NEXT);
         self.output_line(&[], l)
     }
     fn end_conflict(&mut self) -> Result<(),Error> {
-        let l = Contents::from_slice(b"<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<\n");
+        let l = Contents::from_slice(CONFLICT_END);
         self.output_line(&[], l)
     }
 }
@@ -113,19 +169,21 @@ pub trait LineBuffer<'a,'env:'a,T:'a> {
 /// This function constructs a graph by reading the branch from the
 /// input key. It guarantees that all nodes but the first one (index
 /// 0) have a common descendant, which is index 0.
-pub fn retrieve<'a,'b,'name,T>(branch:&'a Branch<'name,'a,'b,T>, key:&'a [u8])->Graph<'a>{
+pub fn retrieve<'a,'b,'name,T,R:RawTxn>(branch:&'a Branch<'name,'a,'b,T,R>, key:&'a [u8])->Graph<'a>{
 
     // In order to identify "merging paths" of the graph correctly, we
     // maintain a cache of visited lines (mapped to their index in the graph).
-    fn retr<'a,'b,'name,T>(
-        db_nodes: &'a Branch<'name,'a,'b,T>,
+    // Returns the index of `key`, and whether it was just added (and
+    // its children still need to be visited).
+    fn retr<'a,'b,'name,T,R:RawTxn>(
+        db_nodes: &'a Branch<'name,'a,'b,T,R>,
         cache: &mut HashMap<&'a [u8],usize>,
         lines: &mut Vec<Line<'a>>,
         children: &mut Vec<(Option<&'a[u8]>,usize)>,
-        key: &'a [u8])->usize {
+        key: &'a [u8])->(usize,bool) {
 
         match cache.entry(key) {
-            Entry::Occupied(e) => return *(e.get()),
+            Entry::Occupied(e) => return (*(e.get()),false),
             Entry::Vacant(e) => {
                 let idx=lines.len();
                 e.insert(idx);
@@ -166,24 +224,15 @@ pub fn retrieve<'a,'b,'name,T>(branch:&'a Branch<'name,'a,'b,T>, key:&'a [u8])->
                         break
                     }
                 }
-                lines.push(l)
-            }
-        }
-        let idx=lines.len()-1;
-        let l_children=lines[idx].children;
-        let n_children=lines[idx].n_children;
-        debug!("n_children: {}",n_children);
-        for i in 0..n_children {
-            let (a,_)=children[l_children+i];
-            if let Some(a)=a {
-                children[l_children+i] = (Some(a), retr(db_nodes,cache,lines,children, &a[1..(1+KEY_SIZE)]))
+                debug!("n_children: {}",l.n_children);
+                if l.n_children==0 {
+                    children.push((None,0));
+                    l.n_children=1;
+                }
+                lines.push(l);
+                (idx,true)
             }
         }
-        if n_children==0 {
-            children.push((None,0));
-            lines[idx].n_children=1;
-        }
-        idx
     }
     let mut cache=HashMap::new();
     let mut lines=Vec::new();
@@ -193,14 +242,28 @@ pub fn retrieve<'a,'b,'name,T>(branch:&'a Branch<'name,'a,'b,T>, key:&'a [u8])->
     });
     cache.insert(&b""[..],0);
     let mut children=Vec::new();
-    retr(&branch, &mut cache, &mut lines, &mut children, key);
+    // Depth-first search, with an explicit stack of (line, next
+    // child) so that long files do not overflow the call stack.
+    let (root,_)=retr(&branch, &mut cache, &mut lines, &mut children, key);
+    let mut stack=vec!((root,0));
+    while let Some((idx,i))=stack.pop() {
+        if i < lines[idx].n_children {
+            stack.push((idx,i+1));
+            let c=lines[idx].children+i;
+            if let (Some(a),_)=children[c] {
+                let (child,added)=retr(&branch, &mut cache, &mut lines, &mut children, &a[1..(1+KEY_SIZE)]);
+                children[c]=(Some(a),child);
+                if added {
+                    stack.push((child,0))
+                }
+            }
+        }
+    }
     Graph { lines:lines, children:children }
 }
 
 fn tarjan(line:&mut Graph)->Vec<Vec<usize>> {
-    fn dfs<'a>(scc:&mut Vec<Vec<usize>>,
-               stack:&mut Vec<usize>,
-               index:&mut usize, g:&mut Graph<'a>, n_l:usize){
+    fn visit<'a>(stack:&mut Vec<usize>, index:&mut usize, g:&mut Graph<'a>, n_l:usize) {
         {
             let mut l=&mut (g.lines[n_l]);
             debug!("tarjan: {:?}", l.key.to_hex());
@@ -208,42 +271,51 @@ fn tarjan(line:&mut Graph)->Vec<Vec<usize>> {
             (*l).lowlink = *index;
             (*l).flags = (*l).flags | LINE_ONSTACK | LINE_VISITED;
             debug!("{} {} chi",(*l).key.to_hex(),(*l).n_children);
-            //unsafe {println!("contents: {}",std::str::from_utf8_unchecked(repo.contents((*l).key))); }
         }
         stack.push(n_l);
         *index = *index + 1;
-        for i in 0..g.lines[n_l].n_children {
-            //let mut l=&mut (g.lines[n_l]);
-
-            let (_,n_child) = g.children[g.lines[n_l].children + i];
-            //println!("children: {}",to_hex(g.lines[n_child].key));
-
-            if ! g.lines[n_child].flags.contains(LINE_VISITED) {
-                dfs(scc,stack,index,g,n_child);
-                g.lines[n_l].lowlink=std::cmp::min(g.lines[n_l].lowlink, g.lines[n_child].lowlink);
-            } else {
-                if g.lines[n_child].flags.contains(LINE_ONSTACK) {
-                    g.lines[n_l].lowlink=std::cmp::min(g.lines[n_l].lowlink, g.lines[n_child].index)
+    }
+    fn dfs<'a>(scc:&mut Vec<Vec<usize>>,
+               stack:&mut Vec<usize>,
+               index:&mut usize, g:&mut Graph<'a>, n_l:usize){
+        // Explicit stack of (line, next child), the lines of a file
+        // can be too many to recurse on.
+        let mut calls=vec!((n_l,0));
+        visit(stack,index,g,n_l);
+        while let Some((n_l,i))=calls.pop() {
+            if i < g.lines[n_l].n_children {
+                calls.push((n_l,i+1));
+                let (_,n_child) = g.children[g.lines[n_l].children + i];
+                if ! g.lines[n_child].flags.contains(LINE_VISITED) {
+                    visit(stack,index,g,n_child);
+                    calls.push((n_child,0));
+                } else {
+                    if g.lines[n_child].flags.contains(LINE_ONSTACK) {
+                        g.lines[n_l].lowlink=std::cmp::min(g.lines[n_l].lowlink, g.lines[n_child].index)
+                    }
                 }
+                continue
             }
-        }
-
-        if g.lines[n_l].index == g.lines[n_l].lowlink {
-            //println!("SCC: {:?}",slice::from_raw_parts((*l).key,KEY_SIZE));
-            let mut v=Vec::new();
-            loop {
-                match stack.pop() {
-                    None=>break,
-                    Some(n_p)=>{
-                        g.lines[n_p].scc= scc.len();
-                        g.lines[n_p].flags = g.lines[n_p].flags ^ LINE_ONSTACK;
-                        v.push(n_p);
-                        if n_p == n_l { break }
+            if g.lines[n_l].index == g.lines[n_l].lowlink {
+                //println!("SCC: {:?}",slice::from_raw_parts((*l).key,KEY_SIZE));
+                let mut v=Vec::new();
+                loop {
+                    match stack.pop() {
+                        None=>break,
+                        Some(n_p)=>{
+                            g.lines[n_p].scc= scc.len();
+                            g.lines[n_p].flags = g.lines[n_p].flags ^ LINE_ONSTACK;
+                            v.push(n_p);
+                            if n_p == n_l { break }
+                        }
                     }
                 }
+                scc.push(v);
+            }
+            // All the children of `n_l` are visited, update its parent.
+            if let Some(&(n_parent,_))=calls.last() {
+                g.lines[n_parent].lowlink=std::cmp::min(g.lines[n_parent].lowlink, g.lines[n_l].lowlink);
             }
-            scc.push(v);
-            //*scc+=1
         }
     }
     let mut scc=Vec::with_capacity(line.lines.len());
@@ -263,7 +335,57 @@ fn tarjan(line:&mut Graph)->Vec<Vec<usize>> {
 
 
 
-pub fn output_file<'a,'b,'name,T,B:LineBuffer<'a,'b,T>>(branch:&'a Branch<'name,'a,'b,T>, db_contents:&'a Db<'a,'b,T>, buf:&mut B,mut graph:Graph<'a>,forward:&mut Vec<u8>) -> Result<(),Error> {
+/// Which strongly connected components of `graph` are comparable
+/// with all the others, i.e. come after all the components before
+/// them and before all the components after them. The lines of the
+/// other components are in conflict. `scc` is in reverse
+/// topological order, as returned by `tarjan`.
+///
+/// Component `k` has this property if, among the components
+/// `k..scc.len()`, it is the only one with no child in this range,
+/// and among the components `0..k+1`, it is the only one with no
+/// parent in this range.
+fn totally_ordered(graph:&Graph, scc:&[Vec<usize>])->Vec<bool> {
+    let n=scc.len();
+    // Number of components with no child among k..n (resp. no
+    // parent among 0..k+1), as differences between k-1 and k.
+    let mut sinks=vec![0isize;n+1];
+    let mut sources=vec![0isize;n+1];
+    // Smallest parent of each component.
+    let mut last_parent=vec![n;n];
+    for u in 0..n {
+        let mut first_child=None;
+        for line in scc[u].iter() {
+            let line=&graph.lines[*line];
+            for c in 0 .. line.n_children {
+                let (_,n_child)=graph.children[line.children + c];
+                let v=graph.lines[n_child].scc;
+                if v < u {
+                    first_child=std::cmp::max(first_child, Some(v));
+                    last_parent[v]=std::cmp::min(last_parent[v], u)
+                }
+            }
+        }
+        // `u` has no child among k..n for k in (first_child, u].
+        sinks[first_child.map(|v| v+1).unwrap_or(0)] += 1;
+        sinks[u+1] -= 1;
+    }
+    for v in 0..n {
+        // `v` has no parent among 0..k+1 for k in [v, last_parent).
+        sources[v] += 1;
+        sources[last_parent[v]] -= 1;
+    }
+    let mut ordered=Vec::with_capacity(n);
+    let (mut n_sinks, mut n_sources)=(0,0);
+    for k in 0..n {
+        n_sinks += sinks[k];
+        n_sources += sources[k];
+        ordered.push(n_sinks==1 && n_sources==1)
+    }
+    ordered
+}
+
+pub fn output_file<'a,'b,'name,T,R:RawTxn,B:LineBuffer<'a,'b,T,R>>(branch:&'a Branch<'name,'a,'b,T,R>, db_contents:&'a Db<'a,'b,T,R>, buf:&mut B,mut graph:Graph<'a>,forward:&mut Vec<u8>) -> Result<(),Error> {
     debug!("output_file");
 
     //let t0=time::precise_time_s();
@@ -272,21 +394,25 @@ pub fn output_file<'a,'b,'name,T,B:LineBuffer<'a,'b,T>>(branch:&'a Branch<'name,
     //info!("tarjan took {}s",t1-t0);
     info!("There are {} SCC",scc.len());
     //let mut levels=vec![0;scc];
-    let mut last_visit=vec![0;scc.len()];
     let mut first_visit=vec![0;scc.len()];
     let mut step=1;
-    fn dfs<'a>(
-        graph:&mut Graph<'a>,
+    /// A strongly connected component being visited, after the
+    /// chain of components with a single child that starts at it.
+    struct Visit {
+        n_scc:usize,
+        // Child components, in the order in which they are visited.
+        children:Vec<usize>,
+        next:usize,
+        forward_scc:HashSet<usize>
+    }
+    fn enter<'a>(
+        graph:&Graph<'a>,
         first_visit:&mut[usize],
-        last_visit:&mut[usize],
-        forward:&mut Vec<u8>,
-        zero:&[u8],
         step:&mut usize,
         scc:&[Vec<usize>],
-        mut n_scc:usize)->Result<(),Error> {
+        mut n_scc:usize)->Visit {
 
         let mut child_components=BTreeSet::new();
-        let mut skipped=vec!(n_scc);
         loop {
             first_visit[n_scc] = *step;
             debug!("step={} scc={}",*step,n_scc);
@@ -307,25 +433,24 @@ pub fn output_file<'a,'b,'name,T,B:LineBuffer<'a,'b,T>>(branch:&'a Branch<'name,
             }
             if child_components.len() != 1 { break } else {
                 n_scc=next_scc;
-                skipped.push(next_scc);
-            }
-        }
-        let mut forward_scc=HashSet::new();
-        for component in child_components.iter().rev() {
-            if first_visit[*component] > first_visit[n_scc] { // forward edge
-                debug!("forward ! {} {}",n_scc,*component);
-                forward_scc.insert(*component);
-            } else {
-                debug!("visiting scc {} {}",*component,graph.lines[scc[*component][0]].key.to_hex());
-                try!(dfs(graph,first_visit,last_visit,forward,zero,step,scc,*component))
             }
         }
+        Visit { n_scc:n_scc, children:child_components.iter().rev().cloned().collect(),
+                next:0, forward_scc:HashSet::new() }
+    }
+    fn leave<'a>(
+        graph:&mut Graph<'a>,
+        forward:&mut Vec<u8>,
+        zero:&[u8],
+        scc:&[Vec<usize>],
+        visit:Visit) {
+        let n_scc=visit.n_scc;
         for cousin in scc[n_scc].iter() {
             let n=graph.lines[*cousin].n_children;
             for i in 0 .. n {
                 let (flag_child,n_child) = graph.children[graph.lines[*cousin].children + i];
                 let child_component=graph.lines[n_child].scc;
-                let is_forward=forward_scc.contains(&child_component);
+                let is_forward=visit.forward_scc.contains(&child_component);
                 if is_forward {
                     if let Some(flag_child)=flag_child {
                         if flag_child[0] & PSEUDO_EDGE != 0 {
@@ -342,29 +467,46 @@ pub fn output_file<'a,'b,'name,T,B:LineBuffer<'a,'b,T>>(branch:&'a Branch<'name,
                 }
             }
         }
-        for i in skipped.iter().rev() {
-            last_visit[*i] = *step;
-            *step+=1;
-        }
-        Ok(())
     }
     let zero=[0;HASH_SIZE];
-    try!(dfs(&mut graph,&mut first_visit,&mut last_visit,forward,&zero[..],&mut step,&scc,scc.len()-1));
+    // Depth-first search of the components, with an explicit stack
+    // so that long files do not overflow the call stack.
+    let mut visits=vec!(enter(&graph,&mut first_visit,&mut step,&scc,scc.len()-1));
+    while let Some(mut visit)=visits.pop() {
+        if visit.next < visit.children.len() {
+            let component=visit.children[visit.next];
+            visit.next += 1;
+            if first_visit[component] > first_visit[visit.n_scc] { // forward edge
+                debug!("forward ! {} {}",visit.n_scc,component);
+                visit.forward_scc.insert(component);
+                visits.push(visit);
+            } else {
+                debug!("visiting scc {} {}",component,graph.lines[scc[component][0]].key.to_hex());
+                visits.push(visit);
+                let child=enter(&graph,&mut first_visit,&mut step,&scc,component);
+                visits.push(child)
+            }
+        } else {
+            leave(&mut graph,forward,&zero[..],&scc,visit)
+        }
+    }
     debug!("dfs done");
+    let ordered=totally_ordered(&graph,&scc);
     // assumes no conflict for now.
     let mut i=scc.len()-1;
     let mut nodes=vec!();
     let mut selected_zombies=HashMap::new();
+    // Last line output outside of a conflict.
+    let mut last=None;
     // let cursor= unsafe { &mut *self.txn.unsafe_cursor(self.dbi_nodes).unwrap() };
     loop {
         // test for conflict
         // scc[i] has at least one element (from tarjan).
-        debug!("test: {:?} {:?} {:?} {:?} {:?} {:?}",
+        debug!("test: {:?} {:?} {:?}",
                scc[i].len(),
-               first_visit[i], first_visit[0],
-               last_visit[i], last_visit[0],
+               ordered[i],
                graph.lines[scc[i][0]].flags);
-        if scc[i].len() == 1 && first_visit[i] <= first_visit[0] && last_visit[i] >= last_visit[0] && ! graph.lines[scc[i][0]].flags.contains(LINE_HALF_DELETED) {
+        if scc[i].len() == 1 && ordered[i] && ! graph.lines[scc[i][0]].flags.contains(LINE_HALF_DELETED) {
             debug!("true");
             //debug!("/flag = {} {}",graph.lines[scc[i][0]].flags,LINE_HALF_DELETED);
             let key=graph.lines[scc[i][0]].key;
@@ -374,6 +516,7 @@ pub fn output_file<'a,'b,'name,T,B:LineBuffer<'a,'b,T>>(branch:&'a Branch<'name,
                     try!(buf.output_line(key, cont))
                 }
             }
+            last=Some(i);
             if i==0 { break } else { i-=1 }
         } else {
             debug!("false");
@@ -381,12 +524,11 @@ pub fn output_file<'a,'b,'name,T,B:LineBuffer<'a,'b,T>>(branch:&'a Branch<'name,
             let key=graph.lines[scc[i][0]].key;
             debug!("key = {}",key.to_hex());
 
-            fn get_conflict<'name,'a,'b,T, B:LineBuffer<'a,'b,T>>(
-                branch:&'a Branch<'name,'a,'b,T>,
-                db_contents:&'a Db<'a,'b,T>,
+            fn get_conflict<'name,'a,'b,T,R:RawTxn,B:LineBuffer<'a,'b,T,R>>(
+                branch:&'a Branch<'name,'a,'b,T,R>,
+                db_contents:&'a Db<'a,'b,T,R>,
                 graph:&Graph<'a>,
-                first_visit: &[usize],
-                last_visit: &[usize],
+                ordered: &[bool],
                 scc:&mut Vec<Vec<usize>>,
                 nodes:&mut Vec<&'a [u8]>,
                 b:&mut B,
@@ -396,7 +538,7 @@ pub fn output_file<'a,'b,'name,T,B:LineBuffer<'a,'b,T>>(branch:&'a Branch<'name,
                 i:usize) -> Result<(),Error> {
                 // x.scc[i] has at least one element (from tarjan).
 
-                if scc[i].len() == 1 && first_visit[i] <= first_visit[0] && last_visit[i] >= last_visit[0] && ! graph.lines[scc[i][0]].flags.contains(LINE_HALF_DELETED) {
+                if scc[i].len() == 1 && ordered[i] && ! graph.lines[scc[i][0]].flags.contains(LINE_HALF_DELETED) {
                     // End of conflict (or no conflict).
                     debug!("end of conflict");
                     let mut first=false; // Detect the first line
@@ -418,12 +560,11 @@ pub fn output_file<'a,'b,'name,T,B:LineBuffer<'a,'b,T>>(branch:&'a Branch<'name,
                     *next=i;
                 } else {
                     // Pour chaque permutation de la SCC, ajouter tous les sommets sur la pile, et appel recursif de chaque arete non-forward.
-                    fn permutations<'name,'a, 'b,T,B:LineBuffer<'a,'b,T>>(
-                        branch:&'a Branch<'name,'a,'b,T>,
-                        db_contents:&'a Db<'a,'b,T>,
+                    fn permutations<'name,'a, 'b,T,R:RawTxn,B:LineBuffer<'a,'b,T,R>>(
+                        branch:&'a Branch<'name,'a,'b,T,R>,
+                        db_contents:&'a Db<'a,'b,T,R>,
                         graph:&Graph<'a>,
-                        first_visit: &[usize],
-                        last_visit: &[usize],
+                        ordered: &[bool],
                         scc:&mut Vec<Vec<usize>>,
                         nodes:&mut Vec<&'a[u8]>,
                         b:&mut B,
@@ -484,7 +625,7 @@ pub fn output_file<'a,'b,'name,T,B:LineBuffer<'a,'b,T>>(branch:&'a Branch<'name,
                                         key_is_present = is_forced
                                     }
                                     if !is_forced {
-                                        try!(permutations(branch,db_contents, graph,first_visit,last_visit,
+                                        try!(permutations(branch,db_contents, graph,ordered,
                                                           scc,nodes,b,is_first,selected_zombies,next,
                                                           i,j+1,next_vertices))
                                     }
@@ -496,7 +637,7 @@ pub fn output_file<'a,'b,'name,T,B:LineBuffer<'a,'b,T>>(branch:&'a Branch<'name,
                                 }
                                 if key_is_present {
                                     nodes.push(key);
-                                    try!(permutations(branch,db_contents, graph,first_visit,last_visit,
+                                    try!(permutations(branch,db_contents, graph,ordered,
                                                       scc,nodes,b,is_first,selected_zombies,next,
                                                       i,j+1,next_vertices));
                                     nodes.pop();
@@ -513,14 +654,14 @@ pub fn output_file<'a,'b,'name,T,B:LineBuffer<'a,'b,T>>(branch:&'a Branch<'name,
                             debug!("next? {}",next_vertices.len());
                             for chi in next_vertices.iter() {
                                 debug!("rec: get_conflict {}",*chi);
-                                try!(get_conflict(branch,db_contents,graph,first_visit,last_visit,scc,nodes,b,is_first,selected_zombies,next,*chi));
+                                try!(get_conflict(branch,db_contents,graph,ordered,scc,nodes,b,is_first,selected_zombies,next,*chi));
                             };
                             Ok(())
                         }
                     }
                     let mut next_vertices=HashSet::new();
                     debug!("permutations");
-                    try!(permutations(branch,db_contents, graph,first_visit,last_visit,scc,nodes,b,is_first,selected_zombies,next,i,0,&mut next_vertices));
+                    try!(permutations(branch,db_contents, graph,ordered,scc,nodes,b,is_first,selected_zombies,next,i,0,&mut next_vertices));
                 }
                 Ok(())
             }
@@ -528,11 +669,30 @@ pub fn output_file<'a,'b,'name,T,B:LineBuffer<'a,'b,T>>(branch:&'a Branch<'name,
             let (next,is_first)={
                 let mut is_first = true;
                 let mut next = 0;
-                try!(get_conflict(branch,db_contents,&graph,&first_visit[..],&last_visit[..],&mut scc, &mut nodes,
-                                  buf,
-                                  &mut is_first,
-                                  &mut selected_zombies,
-                                  &mut next, i));
+                // The sides of the conflict start at the children of
+                // the last line output, not only at `scc[i]`.
+                let mut starts=vec!();
+                if let Some(last)=last {
+                    let line=&graph.lines[scc[last][0]];
+                    for c in 0 .. line.n_children {
+                        let (edge_child,n_child) = graph.children[line.children + c];
+                        if n_child != 0 || edge_child.is_none() {
+                            let child_component=graph.lines[n_child].scc;
+                            if !starts.contains(&child_component) {
+                                starts.push(child_component)
+                            }
+                        }
+                    }
+                } else {
+                    starts.push(i)
+                }
+                for start in starts {
+                    try!(get_conflict(branch,db_contents,&graph,&ordered[..],&mut scc, &mut nodes,
+                                      buf,
+                                      &mut is_first,
+                                      &mut selected_zombies,
+                                      &mut next, start));
+                }
                 (next,is_first)
             };
             if !is_first { try!(buf.end_conflict()) }
@@ -19,7 +19,7 @@
 //! This crate implements operations on Pijul repositories.
 
 use std::path::{Path,PathBuf};
-use std::collections::{HashSet,HashMap};
+use std::collections::HashSet;
 #[macro_use]
 extern crate log;
 extern crate time;
@@ -46,6 +46,7 @@ extern crate rand;
 
 pub mod fs_representation;
 pub mod patch;
+pub mod patch_text;
 
 mod sanakirja_backend;
 pub use sanakirja_backend::backend;
@@ -58,8 +59,8 @@ pub mod graph;
 mod optimal_diff;
 pub use optimal_diff::diff;
 
-impl <'a,'env:'a,T:'a,W> graph::LineBuffer<'a,'env,T> for W where W:std::io::Write {
-    fn output_line(&mut self,_:&[u8],c:backend::Contents<'a,'env,T>) -> Result<(),Error> {
+impl <'a,'env:'a,T:'a,R:'a+backend::RawTxn,W> graph::LineBuffer<'a,'env,T,R> for W where W:std::io::Write {
+    fn output_line(&mut self,_:&[u8],c:backend::Contents<'a,'env,T,R>) -> Result<(),Error> {
         for i in c {
             try!(self.write(i)); // .expect("output_line: could not write");
         }
@@ -69,12 +70,25 @@ impl <'a,'env:'a,T:'a,W> graph::LineBuffer<'a,'env,T> for W where W:std::io::Wri
 
 
 mod record;
+pub use record::RecordUpdates;
 mod output;
+pub use output::{ConflictStyle, OutputFile};
 mod apply;
+mod unrecord;
+mod annotate;
+pub use annotate::AnnotatedLine;
+mod conflicts;
+pub use conflicts::{Conflict, ConflictSide};
+mod check;
+pub use check::Inconsistency;
+mod repair;
+mod dependencies;
+pub mod signature;
+pub mod tag;
 
 pub type Transaction<'env> = backend::Transaction<'env,()>;
 
-pub use backend::{Repository,DEFAULT_BRANCH};
+pub use backend::{Repository,ReadTransaction,DEFAULT_BRANCH};
 
 pub use patch::internal_hash;
 
@@ -92,75 +106,308 @@ impl<'env,T> backend::Transaction<'env,T> {
         file_operations::move_file(self, path.as_ref(), path_.as_ref(), is_dir)
     }
     fn write_changes_file<P:AsRef<Path>>(&self, branch_name:&str, path:P)->Result<(),Error> {
-        let db_external = self.db_external();
-        let patches = try!(self.branch_patches(&db_external, branch_name));
+        let patches = try!(self.applied_patches(branch_name));
+        let patches: Vec<&[u8]> = patches.iter().map(|p| &p[..]).collect();
         debug!("write_changes_file, patches = {:?}", patches);
         let changes_file = fs_representation::branch_changes_file(path.as_ref(), branch_name.as_bytes());
         try!(patch::write_changes(&patches,&changes_file));
         Ok(())
     }
-    pub fn apply_patches<P:AsRef<Path>>(&mut self, branch_name:&str, r:P, remote_patches:&HashSet<Vec<u8>>, local_patches:&HashSet<Vec<u8>>) -> Result<(),Error> {
+    pub fn apply_patches<P:AsRef<Path>>(&mut self, branch_name:&str, r:P, remote_patches:&HashSet<Vec<u8>>, local_patches:&HashSet<Vec<u8>>, style:ConflictStyle) -> Result<(),Error> {
 
         debug!("apply_patches");
-        let result = try!(apply::apply_patches(self, branch_name, r.as_ref(), remote_patches, local_patches));
+        let result = try!(apply::apply_patches(self, branch_name, r.as_ref(), remote_patches, local_patches, style));
         debug!("/apply_patches");
         try!(self.write_changes_file(branch_name, r));
         Ok(result)
     }
-    pub fn apply_local_patch<P:AsRef<Path>>(&mut self, branch_name:&str, location:P, patch: patch::Patch, inode_updates:&HashMap<patch::LocalKey,file_operations::Inode>) -> Result<(), Error>{
+    pub fn apply_local_patch<P:AsRef<Path>>(&mut self, branch_name:&str, location:P, patch: patch::Patch, updates:&RecordUpdates, key:Option<&signature::SigningKey>) -> Result<(), Error>{
 
         debug!("apply_local_patch");
-        let result = try!(apply::apply_local_patch(self,branch_name,location.as_ref(),patch,inode_updates));
+        let result = try!(apply::apply_local_patch(self,branch_name,location.as_ref(),patch,updates,key));
         debug!("/apply_local_patch");
         try!(self.write_changes_file(branch_name, location));
         Ok(result)
     }
-    pub fn output_repository<P:AsRef<Path>>(&mut self, branch_name:&str, working_copy:P, pending:&patch::Patch) -> Result<(),Error>{
+    /// Create branch `new_name`, with the same graph and patches as branch `from`.
+    pub fn fork_branch<P:AsRef<Path>>(&mut self, location:P, from:&str, new_name:&str) -> Result<(), Error> {
+        if !self.branch_exists(from) {
+            return Err(Error::BranchNotFound(from.to_string()))
+        }
+        if self.branch_exists(new_name) {
+            return Err(Error::BranchAlreadyExists(new_name.to_string()))
+        }
+        debug!("forking branch {:?} into {:?}", from, new_name);
+        {
+            let source = try!(self.db_nodes(from));
+            let mut edges = Vec::new();
+            for (k,v) in source.iter(b"", None) {
+                edges.push((k.to_vec(), v.to_vec()))
+            }
+            let mut branch = try!(self.db_nodes(new_name));
+            for &(ref k, ref v) in edges.iter() {
+                try!(branch.put(k, v))
+            }
+            try!(branch.commit_branch(new_name));
+        }
+        {
+            let mut db_branches = self.db_branches();
+            let mut patches = Vec::new();
+            for (k,v) in db_branches.iter(from.as_bytes(), None) {
+                if k != from.as_bytes() {
+                    break
+                }
+                patches.push(v.to_vec())
+            }
+            for p in patches.iter() {
+                try!(db_branches.put(new_name.as_bytes(), p))
+            }
+            let mut db_applied = self.db_applied();
+            patches.clear();
+            for (k,v) in db_applied.iter(from.as_bytes(), None) {
+                if k != from.as_bytes() {
+                    break
+                }
+                patches.push(v.to_vec())
+            }
+            for p in patches.iter() {
+                try!(db_applied.put(new_name.as_bytes(), p))
+            }
+        }
+        self.write_changes_file(new_name, location)
+    }
+    /// Delete branch `name`, along with its changes file. Patches and
+    /// their contents are kept, since other branches might use them.
+    pub fn delete_branch<P:AsRef<Path>>(&mut self, location:P, name:&str) -> Result<(), Error> {
+        if !self.branch_exists(name) {
+            return Err(Error::BranchNotFound(name.to_string()))
+        }
+        debug!("deleting branch {:?}", name);
+        if self.has_branch(name) {
+            try!(self.drop_branch(name))
+        }
+        {
+            let mut db_branches = self.db_branches();
+            let mut patches = Vec::new();
+            for (k,v) in db_branches.iter(name.as_bytes(), None) {
+                if k != name.as_bytes() {
+                    break
+                }
+                patches.push(v.to_vec())
+            }
+            for p in patches.iter() {
+                try!(db_branches.del(name.as_bytes(), Some(p)))
+            }
+            let mut db_applied = self.db_applied();
+            patches.clear();
+            for (k,v) in db_applied.iter(name.as_bytes(), None) {
+                if k != name.as_bytes() {
+                    break
+                }
+                patches.push(v.to_vec())
+            }
+            for p in patches.iter() {
+                try!(db_applied.del(name.as_bytes(), Some(p)))
+            }
+        }
+        let changes_file = fs_representation::branch_changes_file(location.as_ref(), name.as_bytes());
+        match std::fs::remove_file(&changes_file) {
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::IO(e)),
+            Ok(()) => Ok(())
+        }
+    }
+    pub fn rename_branch<P:AsRef<Path>>(&mut self, location:P, name:&str, new_name:&str) -> Result<(), Error> {
+        try!(self.fork_branch(location.as_ref(), name, new_name));
+        self.delete_branch(location, name)
+    }
+    /// Make the working copy track branch `branch_name`: output that
+    /// branch, and remove the files that were tracked before, but are
+    /// not in `branch_name`. Unrecorded changes are not preserved.
+    pub fn checkout<P:AsRef<Path>>(&mut self, branch_name:&str, working_copy:P, style:ConflictStyle) -> Result<(), Error> {
+        if !self.branch_exists(branch_name) {
+            return Err(Error::BranchNotFound(branch_name.to_string()))
+        }
+        let working_copy = working_copy.as_ref();
+        let before = try!(self.list_files());
+        try!(self.output_repository(branch_name, working_copy, &patch::Patch::empty(), style));
+        let after:HashSet<PathBuf> = try!(self.list_files()).into_iter().collect();
+        let mut removed:Vec<PathBuf> = before.into_iter().filter(|f| !after.contains(f)).collect();
+        // Remove the contents of directories before the directories themselves.
+        removed.sort();
+        for f in removed.iter().rev() {
+            let path = working_copy.join(f);
+            debug!("checkout: removing {:?}", path);
+            let result = match std::fs::symlink_metadata(&path) {
+                Ok(ref attr) if attr.is_dir() => std::fs::remove_dir(&path),
+                Ok(_) => std::fs::remove_file(&path),
+                Err(_) => Ok(())
+            };
+            // Directories with untracked files in them are kept.
+            if let Err(e) = result {
+                debug!("checkout: could not remove {:?}: {:?}", path, e)
+            }
+        }
+        Ok(())
+    }
+    /// Remove a patch from a branch. See `unrecord::unrecord`.
+    pub fn unrecord<P:AsRef<Path>>(&mut self, branch_name:&str, location:P, hash:&[u8], cascade:bool) -> Result<(), Error> {
+        debug!("unrecord");
+        try!(unrecord::unrecord(self, branch_name, location.as_ref(), hash, cascade));
+        debug!("/unrecord");
+        self.write_changes_file(branch_name, location)
+    }
+    /// Replay all the patches listed in the changes files of
+    /// `location` on this (empty) repository, and derive the inodes
+    /// from the working copy and branch `current_branch`. See
+    /// `repair::rebuild`.
+    pub fn rebuild<P:AsRef<Path>>(&mut self, location:P, current_branch:&str) -> Result<(), Error> {
+        debug!("rebuild");
+        let result = try!(repair::rebuild(self, location.as_ref(), current_branch));
+        debug!("/rebuild");
+        Ok(result)
+    }
+    /// Apply patch `hash` from the patches directory of `location`,
+    /// and its dependencies, to branch `branch_name`, leaving the
+    /// working copy and the changes file alone. Use `output_files` to
+    /// see the result.
+    pub fn apply_to_pristine<P:AsRef<Path>>(&mut self, branch_name:&str, location:P, hash:&[u8]) -> Result<(), Error> {
+        let mut patches_were_applied = false;
+        apply::apply_with_dependencies(self, branch_name, location.as_ref(), hash, &mut patches_were_applied, &HashSet::new())
+    }
+    /// A name, starting with `prefix`, of a branch that does not
+    /// exist, to replay patches with `apply_to_pristine` in a
+    /// transaction that is not committed.
+    pub fn scratch_branch(&self, prefix:&str) -> String {
+        let mut name = prefix.to_string();
+        while self.branch_exists(&name) {
+            name.push('\'')
+        }
+        name
+    }
+    pub fn output_repository<P:AsRef<Path>>(&mut self, branch_name:&str, working_copy:P, pending:&patch::Patch, style:ConflictStyle) -> Result<(),Error>{
         debug!("outputting repository");
-        let result = output::output_repository(self,branch_name,working_copy.as_ref(),pending);
+        let result = output::output_repository(self,branch_name,working_copy.as_ref(),pending,style);
         debug!("/outputting repository");
         result
     }
 
+}
 
 
-    // immutable part, should be separated into a trait at some point
-    // (would need mutable + immutable transactions from the backend
-    // module).
+/// Queries on a repository. These only read the repository, and are
+/// available on both read-only and mutable transactions.
+pub trait ReadTxn<'env> {
+    type T;
+    type R:backend::RawTxn;
+    /// Names of all branches. A new repository has an empty default
+    /// branch.
+    fn branches(&self) -> Vec<String>;
+    fn branch_exists(&self, name:&str) -> bool;
+    fn follow_path(&self, path:&[&[u8]])->Result<Option<Inode>,Error>;
+    fn node_of_inode(&self, inode:&[u8])->Option<Vec<u8>>;
+    fn list_files(&self) -> Result<Vec<PathBuf>, Error>;
+    fn list_files_in_dir(&self, dir_inode:&Inode) -> Result<Vec<(String, Option<Vec<u8>>, Inode)>, Error>;
+    fn is_directory(&self, inode:&Inode) -> bool;
+    fn retrieve_paths(&self,branch_name:&str,key:&[u8], forward:bool) -> Result<Vec<(Vec<u8>, Vec<u8>)>,Error>;
+    /// Path of the file containing node `key` (an internal key), if any.
+    fn file_of_key(&self,branch_name:&str,key:&[u8]) -> Option<PathBuf>;
+    fn retrieve_and_output<'a,'name,W:std::io::Write>(&self,branch:&backend::Branch<'name,'a,'env,Self::T,Self::R>,key:&[u8],l:&mut W) -> Result<(),Error>;
+    fn branch_patches<'a>(&'a self,db_external:&'a backend::Db<'a,'env,Self::T,Self::R>, branch_name:&str)->Result<HashSet<&'a[u8]>,Error>;
+    /// External hashes of the patches on branch `branch_name`, in the
+    /// order in which they were applied. Patches applied before this
+    /// order was recorded come first, sorted by internal hash.
+    fn applied_patches(&self, branch_name:&str)->Result<Vec<Vec<u8>>,Error>;
+    /// Lines of file `path` on branch `branch_name`, with the patch
+    /// that introduced each of them, its authors and its timestamp.
+    /// The patches are read from repository `location`.
+    fn annotate<P:AsRef<Path>, Q:AsRef<Path>>(&self, branch_name:&str, location:P, path:Q)->Result<Vec<AnnotatedLine>,Error>;
+    /// Contents of the node with external key `key`.
+    fn node_contents(&self, key:&[u8])->Result<Vec<u8>,Error>;
+    /// Conflicts on branch `branch_name`, both between names and
+    /// inside files.
+    fn conflicts(&self, branch_name:&str)->Result<Vec<Conflict>,Error>;
+    /// Files of branch `branch_name` with their contents, output in
+    /// memory rather than to a working copy.
+    fn output_files(&self, branch_name:&str)->Result<Vec<OutputFile>,Error>;
+    /// Same as `output_files`, for the files at `paths` or below.
+    fn output_files_in(&self, branch_name:&str, paths:&[PathBuf])->Result<Vec<OutputFile>,Error>;
+    /// Paths of the files and directories touched by patch `hash`,
+    /// applied on branch `branch_name`, including the ones it removes.
+    fn patch_paths(&self, branch_name:&str, hash:&[u8], patch:&patch::Patch)->Result<Vec<PathBuf>,Error>;
+    /// Patches of branch `branch_name` that patch `hash` depends on,
+    /// sorted by hash. If `transitive` is true, the dependencies of
+    /// these patches are included, and so on. Dependencies are read
+    /// from the patch files of the repository at `location`.
+    fn dependencies<P:AsRef<Path>>(&self, branch_name:&str, location:P, hash:&[u8], transitive:bool)->Result<Vec<Vec<u8>>,Error>;
+    /// Patches of branch `branch_name` that depend on patch `hash`,
+    /// sorted by hash. These are the patches that unrecording `hash`
+    /// with `cascade` would unrecord if `transitive` is true.
+    fn dependents(&self, branch_name:&str, hash:&[u8], transitive:bool)->Result<Vec<Vec<u8>>,Error>;
+    /// Direct dependencies between the patches of branch
+    /// `branch_name`, as sorted pairs `(dependent, dependency)`, read
+    /// from the patch files of the repository at `location`.
+    fn dependency_graph<P:AsRef<Path>>(&self, branch_name:&str, location:P)->Result<Vec<(Vec<u8>,Vec<u8>)>,Error>;
+    /// Violations of the invariants of the repository at `location`.
+    fn check<P:AsRef<Path>>(&self, location:P)->Result<Vec<Inconsistency>,Error>;
+    /// Changes between branch `branch_name` and the working copy.
+    fn record<P:AsRef<Path>>(&self,branch_name:&str, working_copy:P)->Result<(Vec<patch::Change>,RecordUpdates),Error>;
+    /// Same as `record`, with the path of the file each change comes from.
+    fn record_with_paths<P:AsRef<Path>>(&self,branch_name:&str, working_copy:P)->Result<(Vec<(PathBuf,patch::Change)>,RecordUpdates),Error>;
+    fn debug<W>(&self,branch_name:&str, w:&mut W) where W:std::io::Write;
+}
 
-    pub fn follow_path(&self, path:&[&[u8]])->Result<Option<Inode>,Error> {
+impl<'env,T,R:backend::RawTxn> ReadTxn<'env> for backend::Transaction<'env,T,R> {
+    type T = T;
+    type R = R;
+    fn branches(&self) -> Vec<String> {
+        self.branch_names()
+    }
+    fn branch_exists(&self, name:&str) -> bool {
+        self.has_branch(name)
+    }
+    fn follow_path(&self, path:&[&[u8]])->Result<Option<Inode>,Error> {
         let db_tree = self.db_tree();
         output::follow_path(&db_tree, path)
     }
-    pub fn node_of_inode<'a>(&'a self, inode:&[u8])->Option<Vec<u8>> {
+    fn node_of_inode(&self, inode:&[u8])->Option<Vec<u8>> {
         let db_inodes = self.db_inodes();
         output::node_of_inode(&db_inodes, inode)
     }
-    pub fn list_files(&self) -> Result<Vec<PathBuf>, Error> {
+    fn list_files(&self) -> Result<Vec<PathBuf>, Error> {
         file_operations::list_files(self)
     }
-    pub fn list_files_in_dir(&self, dir_inode:&Inode) -> Result<Vec<(String, Option<Vec<u8>>, Inode)>, Error> {
+    fn list_files_in_dir(&self, dir_inode:&Inode) -> Result<Vec<(String, Option<Vec<u8>>, Inode)>, Error> {
         file_operations::list_files_in_dir(self, dir_inode)
     }
-    pub fn is_directory(&self, inode:&Inode) -> bool {
+    fn is_directory(&self, inode:&Inode) -> bool {
         file_operations::is_directory(self, inode)
     }
 
-    pub fn retrieve_paths(&self,branch_name:&str,key:&[u8], forward:bool) -> Vec<(Vec<u8>, Vec<u8>)> {
-        let db_nodes = self.db_nodes(branch_name).unwrap();
+    fn retrieve_paths(&self,branch_name:&str,key:&[u8], forward:bool) -> Result<Vec<(Vec<u8>, Vec<u8>)>,Error> {
+        let db_nodes = try!(self.db_nodes(branch_name));
         let db_contents = self.db_contents();
-        output::retrieve_paths(&db_nodes, &db_contents, key, if forward { graph::FOLDER_EDGE }
-                               else { graph::FOLDER_EDGE|graph::PARENT_EDGE })
+        Ok(output::retrieve_paths(&db_nodes, &db_contents, key, if forward { graph::FOLDER_EDGE }
+                                  else { graph::FOLDER_EDGE|graph::PARENT_EDGE }))
     }
     
-    pub fn retrieve_and_output<'a,'name,W:std::io::Write>(&self,branch:&backend::Branch<'name,'a,'env,T>,key:&[u8],l:&mut W) -> Result<(),Error> {
+    fn file_of_key(&self,branch_name:&str,key:&[u8]) -> Option<PathBuf> {
+        let db_nodes = match self.db_nodes(branch_name) {
+            Ok(db_nodes) => db_nodes,
+            Err(_) => return None
+        };
+        let db_contents = self.db_contents();
+        output::file_of_key(&db_nodes, &db_contents, key)
+    }
+
+    fn retrieve_and_output<'a,'name,W:std::io::Write>(&self,branch:&backend::Branch<'name,'a,'env,T,R>,key:&[u8],l:&mut W) -> Result<(),Error> {
         let db_contents = self.db_contents();
         let mut redundant_edges = Vec::new();
         let graph = graph::retrieve(branch,key);
         graph::output_file(branch, &db_contents, l, graph,&mut redundant_edges)
     }
 
-    pub fn branch_patches<'a>(&'a self,db_external:&'a backend::Db<'a,'env,T>, branch_name:&str)->Result<HashSet<&'a[u8]>,Error> {
+    fn branch_patches<'a>(&'a self,db_external:&'a backend::Db<'a,'env,T,R>, branch_name:&str)->Result<HashSet<&'a[u8]>,Error> {
         let mut patches = HashSet::new();
         let db_patches = self.db_branches();
         for (br_name,patch_hash) in db_patches.iter(branch_name.as_bytes(), None) {
@@ -176,10 +423,86 @@ impl<'env,T> backend::Transaction<'env,T> {
         }
         Ok(patches)
     }
-    pub fn record<P:AsRef<Path>>(&self,branch_name:&str, working_copy:P)->Result<(Vec<patch::Change>,HashMap<patch::LocalKey,file_operations::Inode>),Error>{
+    fn applied_patches(&self, branch_name:&str)->Result<Vec<Vec<u8>>,Error> {
+        let db_external = self.db_external();
+        let db_applied = self.db_applied();
+        let applied: Vec<&[u8]> = db_applied.iter(branch_name.as_bytes(), None)
+            .take_while(|&(k,_)| k == branch_name.as_bytes())
+            .map(|(_,v)| &v[8..])
+            .collect();
+        let seen: HashSet<&[u8]> = applied.iter().cloned().collect();
+        let db_branches = self.db_branches();
+        let mut patches = Vec::new();
+        for (k,v) in db_branches.iter(branch_name.as_bytes(), None) {
+            if k != branch_name.as_bytes() {
+                break
+            }
+            if !seen.contains(v) {
+                patches.push(patch::external_hash(&db_external, v).to_vec())
+            }
+        }
+        for v in applied {
+            patches.push(patch::external_hash(&db_external, v).to_vec())
+        }
+        Ok(patches)
+    }
+
+    fn annotate<P:AsRef<Path>, Q:AsRef<Path>>(&self, branch_name:&str, location:P, path:Q)->Result<Vec<AnnotatedLine>,Error> {
+        annotate::annotate(self, branch_name, location.as_ref(), path.as_ref())
+    }
+    fn node_contents(&self, key:&[u8])->Result<Vec<u8>,Error> {
+        if key.len() < patch::LINE_SIZE {
+            return Err(Error::InternalHashNotFound(key.to_vec()))
+        }
+        let (hash, line) = key.split_at(key.len() - patch::LINE_SIZE);
+        let db_internal = self.db_internal();
+        let mut internal = try!(patch::internal_hash(&db_internal, hash)).contents.to_vec();
+        internal.extend(line);
+        let db_contents = self.db_contents();
+        let mut contents = Vec::new();
+        if let Some(c) = db_contents.contents(&internal) {
+            for chunk in c {
+                contents.extend(chunk)
+            }
+        }
+        Ok(contents)
+    }
+    fn conflicts(&self, branch_name:&str)->Result<Vec<Conflict>,Error> {
+        conflicts::conflicts(self, branch_name)
+    }
+    fn output_files(&self, branch_name:&str)->Result<Vec<OutputFile>,Error> {
+        output::output_files(self, branch_name, None)
+    }
+    fn output_files_in(&self, branch_name:&str, paths:&[PathBuf])->Result<Vec<OutputFile>,Error> {
+        output::output_files(self, branch_name, Some(paths))
+    }
+    fn patch_paths(&self, branch_name:&str, hash:&[u8], patch:&patch::Patch)->Result<Vec<PathBuf>,Error> {
+        output::patch_paths(self, branch_name, hash, patch)
+    }
+    fn dependencies<P:AsRef<Path>>(&self, branch_name:&str, location:P, hash:&[u8], transitive:bool)->Result<Vec<Vec<u8>>,Error> {
+        dependencies::related_patches(self, branch_name, hash, transitive, |h| {
+            dependencies::direct_dependencies(self, branch_name, location.as_ref(), h)
+        })
+    }
+    fn dependents(&self, branch_name:&str, hash:&[u8], transitive:bool)->Result<Vec<Vec<u8>>,Error> {
+        dependencies::related_patches(self, branch_name, hash, transitive, |h| {
+            dependencies::direct_dependents(self, branch_name, h)
+        })
+    }
+    fn dependency_graph<P:AsRef<Path>>(&self, branch_name:&str, location:P)->Result<Vec<(Vec<u8>,Vec<u8>)>,Error> {
+        dependencies::dependency_graph(self, branch_name, location.as_ref())
+    }
+    fn check<P:AsRef<Path>>(&self, location:P)->Result<Vec<Inconsistency>,Error> {
+        check::check(self, location.as_ref())
+    }
+
+    fn record<P:AsRef<Path>>(&self,branch_name:&str, working_copy:P)->Result<(Vec<patch::Change>,RecordUpdates),Error>{
         record::record(self,branch_name,working_copy.as_ref())
     }
-    pub fn debug<W>(&self,branch_name:&str, w:&mut W) where W:std::io::Write {
+    fn record_with_paths<P:AsRef<Path>>(&self,branch_name:&str, working_copy:P)->Result<(Vec<(PathBuf,patch::Change)>,RecordUpdates),Error>{
+        record::record_with_paths(self,branch_name,working_copy.as_ref())
+    }
+    fn debug<W>(&self,branch_name:&str, w:&mut W) where W:std::io::Write {
         debug!("debugging branch {:?}", branch_name);
         let mut styles=Vec::with_capacity(16);
         for i in 0..16 {
@@ -211,7 +534,6 @@ impl<'env,T> backend::Transaction<'env,T> {
         }
         w.write(b"}\n").unwrap();
     }
-
 }
 
 
@@ -226,6 +548,13 @@ impl<'a> Len for &'a[u8] {
 }
 
 
+/// Binary files are stored as a single node, and replaced as a whole
+/// when they change. As in Git, a file is considered binary if its
+/// first 8000 bytes contain a zero byte.
+pub fn is_binary(contents:&[u8]) -> bool {
+    contents.iter().take(8000).any(|&b| b == 0)
+}
+
 pub fn eq<'a,'b,C:Iterator<Item=&'a[u8]>+Len, D:Iterator<Item=&'b[u8]>+Len>(c:&mut C, d:&mut D) -> bool {
 
     fn eq_rec<'a,'b,I:Iterator<Item=&'a [u8]>, J:Iterator<Item=&'b [u8]>>(sc:&[u8], c:&mut I, sd:&[u8], d:&mut J) -> bool {
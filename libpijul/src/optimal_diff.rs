@@ -25,12 +25,13 @@ pub mod diff {
 
     use super::super::backend::*;
     use super::super::graph::{Graph, PSEUDO_EDGE, FOLDER_EDGE, PARENT_EDGE, DELETED_EDGE};
+    use super::super::file_operations::read_link_target;
     use std;
     use std::path::Path;
     use std::io::Read;
     use rustc_serialize::hex::ToHex;
 
-    fn delete_edges<T>(repository:&Transaction<T>, branch:&Branch<T>, edges:&mut Vec<Edge>, key:&[u8],flag:u8) {
+    fn delete_edges<T,R:RawTxn>(repository:&Transaction<T,R>, branch:&Branch<T,R>, edges:&mut Vec<Edge>, key:&[u8],flag:u8) {
         debug!("deleting edges");
         if key.len() > 0 {
             let ext = repository.db_external();
@@ -52,9 +53,9 @@ pub mod diff {
         }
     }
 
-    fn add_lines<T>(repository:&Transaction<T>, line_num:&mut usize, up_context:&[u8],
-                    down_context:&[&[u8]], lines:&[&[u8]])
-                    -> patch::Change
+    fn add_lines<T,R:RawTxn>(repository:&Transaction<T,R>, line_num:&mut usize, up_context:&[u8],
+                             down_context:&[&[u8]], lines:&[&[u8]])
+                             -> patch::Change
     {
         debug!("adding lines {}",lines.len());
         let ext = repository.db_external();
@@ -70,7 +71,7 @@ pub mod diff {
     }
 
 
-    fn delete_lines<T>(repository:&Transaction<T>, branch:&Branch<T>, lines:&[&[u8]]) -> Change
+    fn delete_lines<T,R:RawTxn>(repository:&Transaction<T,R>, branch:&Branch<T,R>, lines:&[&[u8]]) -> Change
     {
         debug!("delete_lines: {:?}", lines.len());
         let mut edges=Vec::with_capacity(lines.len());
@@ -81,169 +82,211 @@ pub mod diff {
         Change::Edges{edges:edges, flag:PARENT_EDGE|DELETED_EDGE}
     }
 
-    fn local_diff<T>(repository:&Transaction<T>, branch:&Branch<T>, actions:&mut Vec<Change>,
-                     line_num:&mut usize, lines_a:&[&[u8]], contents_a:&[Contents<T>], b:&[&[u8]])
-    {
-        debug!("local_diff {} {}",contents_a.len(),b.len());
-        let mut opt=vec![vec![0;b.len()+1];contents_a.len()+1];
-        if contents_a.len()>0 {
-            let mut i=contents_a.len() - 1;
-            loop {
-                opt[i]=vec![0;b.len()+1];
-                if b.len()>0 {
-                    let mut j=b.len()-1;
-                    loop {
-                        /*{
-                            let contents_a_i = contents_a[i].clone();
-                            for chunk in contents_a_i {
-                                debug!("chunk: {:?}", std::str::from_utf8(chunk).unwrap());
-                            }
-                            debug!("b[j] = {:?}", std::str::from_utf8(b[j]).unwrap());
-                        }
-                        {
-                            let mut contents_a_i = contents_a[i].clone();
-                            debug!("eq: {:?}", super::super::eq(&mut contents_a_i, &mut Contents::from_slice(&b[j])))
-                        }*/
-                        let mut contents_a_i = contents_a[i].clone();
-                        let mut contents_b_j:Contents<T> = Contents::from_slice(&b[j]);
-                        opt[i][j]=
-                            if super::super::eq(&mut contents_a_i, &mut contents_b_j) {
-                                opt[i+1][j+1]+1
-                            } else {
-                                std::cmp::max(opt[i+1][j], opt[i][j+1])
-                            };
-                        //debug!("opt[{}][{}] = {}",i,j,opt[i][j]);
-                        if j>0 { j-=1 } else { break }
-                    }
+    /// Find a middle snake of the shortest edit script between `a` and
+    /// `b`, i.e. a diagonal run of equal lines crossed by an optimal
+    /// path from both ends. Returns its start and end points.
+    fn middle_snake(a:&[&[u8]], b:&[&[u8]]) -> (usize, usize, usize, usize) {
+        let n = a.len() as isize;
+        let m = b.len() as isize;
+        let delta = n - m;
+        let odd = delta & 1 != 0;
+        let max = (n + m + 1) / 2;
+        let off = max + 1;
+        // Furthest reaching x on each diagonal, forward and backward
+        // (backward paths are computed on the reversed sequences).
+        let mut vf = vec![0isize; (2 * off + 1) as usize];
+        let mut vb = vec![0isize; (2 * off + 1) as usize];
+        for d in 0..(max + 1) {
+            let mut k = -d;
+            while k <= d {
+                let mut x =
+                    if k == -d || (k != d && vf[(off + k - 1) as usize] < vf[(off + k + 1) as usize]) {
+                        vf[(off + k + 1) as usize]
+                    } else {
+                        vf[(off + k - 1) as usize] + 1
+                    };
+                let mut y = x - k;
+                let (x0, y0) = (x, y);
+                while x < n && y < m && a[x as usize] == b[y as usize] {
+                    x += 1;
+                    y += 1
                 }
-                if i>0 { i-=1 } else { break }
+                vf[(off + k) as usize] = x;
+                let c = delta - k;
+                if odd && c >= -(d - 1) && c <= d - 1 && n - vb[(off + c) as usize] <= x {
+                    return (x0 as usize, y0 as usize, x as usize, y as usize)
+                }
+                k += 2
             }
-        }
-        let mut i=1;
-        let mut j=0;
-        let mut oi=None;
-        let mut oj=None;
-        let mut last_alive_context=0;
-        while i<contents_a.len() && j<b.len() {
-            debug!("i={}, j={}",i,j);
-            let mut contents_a_i = contents_a[i].clone();
-            let mut contents_b_j:Contents<T> = Contents::from_slice(&b[j]);
-            if super::super::eq(&mut contents_a_i, &mut contents_b_j ) {
-                debug!("eq: {:?} {:?}", i, j);
-                if let Some(i0)=oi {
-                    debug!("deleting from {} to {} / {}",i0,i,lines_a.len());
-                    let dels = delete_lines(repository, branch, &lines_a[i0..i]);
-                    actions.push(dels);
-                    oi=None
-                } else if let Some(j0)=oj {
-                    debug!("adding from {} to {} / {}",j0,j,b.len());
-                    let adds = add_lines(repository, line_num,
-                                         lines_a[last_alive_context], // up context
-                                         &lines_a[i..i+1], // down context
-                                         &b[j0..j]);
-                    actions.push(adds);
-                    oj=None
+            let mut k = -d;
+            while k <= d {
+                let mut x =
+                    if k == -d || (k != d && vb[(off + k - 1) as usize] < vb[(off + k + 1) as usize]) {
+                        vb[(off + k + 1) as usize]
+                    } else {
+                        vb[(off + k - 1) as usize] + 1
+                    };
+                let mut y = x - k;
+                let (x0, y0) = (x, y);
+                while x < n && y < m && a[(n - 1 - x) as usize] == b[(m - 1 - y) as usize] {
+                    x += 1;
+                    y += 1
                 }
-                last_alive_context=i;
-                i+=1; j+=1;
-            } else {
-                debug!("not eq");
-                if opt[i+1][j] >= opt[i][j+1] {
-                    // we will delete things starting from i (included).
-                    if let Some(j0)=oj {
-                        let adds = add_lines(repository,
-                                             line_num,
-                                             lines_a[last_alive_context], // up context
-                                             &lines_a[i..i+1], // down context
-                                             &b[j0..j]);
-                        actions.push(adds);
-                        oj=None
-                    }
-                    if oi.is_none() {
-                        oi=Some(i)
-                    }
-                    i+=1
-                } else {
-                    // We will add things starting from j.
-                    if let Some(i0)=oi {
-                        let dels = delete_lines(repository, branch, &lines_a[i0..i]);
-                        actions.push(dels);
-                        last_alive_context=i0-1;
-                        oi=None
-                    }
-                    if oj.is_none() {
-                        oj=Some(j)
-                    }
-                    j+=1
+                vb[(off + k) as usize] = x;
+                let c = delta - k;
+                if !odd && c >= -d && c <= d && vf[(off + c) as usize] >= n - x {
+                    return ((n - x) as usize, (m - y) as usize, (n - x0) as usize, (m - y0) as usize)
                 }
+                k += 2
+            }
+        }
+        unreachable!()
+    }
+
+    /// Push to `matches` the pairs of indices of a longest common
+    /// subsequence of `a` and `b`, in increasing order, shifted by
+    /// `off_a` and `off_b`. This is Myers' O((N+M)D) algorithm, in
+    /// linear space, after trimming the common prefix and suffix.
+    fn lcs(a:&[&[u8]], b:&[&[u8]], off_a:usize, off_b:usize, matches:&mut Vec<(usize, usize)>) {
+        let mut prefix = 0;
+        while prefix < a.len() && prefix < b.len() && a[prefix] == b[prefix] {
+            matches.push((off_a + prefix, off_b + prefix));
+            prefix += 1
+        }
+        let mut suffix = 0;
+        while suffix < a.len() - prefix && suffix < b.len() - prefix
+            && a[a.len() - 1 - suffix] == b[b.len() - 1 - suffix] {
+            suffix += 1
+        }
+        let a_mid = &a[prefix..(a.len() - suffix)];
+        let b_mid = &b[prefix..(b.len() - suffix)];
+        // Once the prefix and suffix are trimmed, if both sides are
+        // non-empty, they are at distance at least 2, and the middle
+        // snake splits the problem into two strictly smaller ones.
+        if !a_mid.is_empty() && !b_mid.is_empty() {
+            let (x0, y0, x1, y1) = middle_snake(a_mid, b_mid);
+            lcs(&a_mid[..x0], &b_mid[..y0], off_a + prefix, off_b + prefix, matches);
+            for i in 0..(x1 - x0) {
+                matches.push((off_a + prefix + x0 + i, off_b + prefix + y0 + i))
             }
+            lcs(&a_mid[x1..], &b_mid[y1..], off_a + prefix + x1, off_b + prefix + y1, matches);
         }
-        if i < lines_a.len() {
-            if let Some(j0)=oj {
+        for i in 0..suffix {
+            matches.push((off_a + a.len() - suffix + i, off_b + b.len() - suffix + i))
+        }
+    }
+
+    fn local_diff<T,R:RawTxn>(repository:&Transaction<T,R>, branch:&Branch<T,R>, actions:&mut Vec<Change>,
+                              line_num:&mut usize, lines_a:&[&[u8]], contents_a:&[Contents<T,R>], b:&[&[u8]])
+    {
+        debug!("local_diff {} {}",contents_a.len(),b.len());
+        // Line 0 of a is the file's node, which is never deleted.
+        let a:Vec<Vec<u8>> = contents_a.iter().skip(1).map(|c| {
+            let mut line = Vec::new();
+            for chunk in c.clone() {
+                line.extend(chunk)
+            }
+            line
+        }).collect();
+        let a:Vec<&[u8]> = a.iter().map(|x| &x[..]).collect();
+        let mut matches = Vec::new();
+        lcs(&a, b, 1, 0, &mut matches);
+        // Both ends match, to flush the last changes.
+        matches.push((lines_a.len(), b.len()));
+
+        let mut last_alive_context=0;
+        let mut i=1;
+        let mut j=0;
+        for &(i1, j1) in matches.iter() {
+            debug!("match {} {}", i1, j1);
+            if i < i1 {
+                debug!("deleting from {} to {} / {}",i,i1,lines_a.len());
+                let dels = delete_lines(repository, branch, &lines_a[i..i1]);
+                actions.push(dels)
+            }
+            if j < j1 {
+                debug!("adding from {} to {} / {}",j,j1,b.len());
+                let down_context = if i1 < lines_a.len() { &lines_a[i1..i1+1] } else { &[][..] };
                 let adds = add_lines(repository, line_num,
-                                     lines_a[i-1], // up context
-                                     &lines_a[i..i+1], // down context
-                                     &b[j0..j]);
+                                     lines_a[last_alive_context],
+                                     down_context,
+                                     &b[j..j1]);
                 actions.push(adds)
-                    
-            }
-            let dels = delete_lines(repository, branch, &lines_a[i..lines_a.len()]);
-            actions.push(dels)
-        } else if j < b.len() {
-            if let Some(i0)=oi {
-                let dels = delete_lines(repository, branch, &lines_a[i0..i]);
-                actions.push(dels);
-                let adds =
-                    add_lines(repository, line_num, lines_a[i0-1], &[], &b[j..b.len()]);
-                actions.push(adds);
-            } else {
-                let adds = add_lines(repository, line_num, lines_a[i-1], &[], &b[j..b.len()]);
-                actions.push(adds);
             }
+            last_alive_context=i1;
+            i=i1+1;
+            j=j1+1;
         }
     }
     
 
-    struct Diff<'a,'env:'a,T:'a> {
+    struct Diff<'a,'env:'a,T:'a,R:'a+RawTxn> {
         lines_a:Vec<&'a[u8]>,
-        contents_a:Vec<Contents<'a,'env,T>>
+        contents_a:Vec<Contents<'a,'env,T,R>>,
+        markers:Vec<&'static [u8]>
     }
 
-    impl <'a,'env:'a,T:'a> graph::LineBuffer<'a,'env,T> for Diff<'a,'env,T> {
-        fn output_line(&mut self,k:&'a[u8],c:Contents<'a,'env,T>)->Result<(),super::super::error::Error> {
+    impl <'a,'env:'a,T:'a,R:'a+RawTxn> graph::LineBuffer<'a,'env,T,R> for Diff<'a,'env,T,R> {
+        fn output_line(&mut self,k:&'a[u8],c:Contents<'a,'env,T,R>)->Result<(),super::super::error::Error> {
             //println!("outputting {:?} {}",k,unsafe {std::str::from_utf8_unchecked(c)});
             self.lines_a.push(k);
             self.contents_a.push(c);
             Ok(())
         }
+        fn begin_conflict(&mut self)->Result<(),super::super::error::Error> {
+            self.markers.push(graph::CONFLICT_START);
+            self.output_line(&[], Contents::from_slice(graph::CONFLICT_START))
+        }
+        fn conflict_next(&mut self)->Result<(),super::super::error::Error> {
+            self.markers.push(graph::CONFLICT_NEXT);
+            self.output_line(&[], Contents::from_slice(graph::CONFLICT_NEXT))
+        }
+        fn end_conflict(&mut self)->Result<(),super::super::error::Error> {
+            self.markers.push(graph::CONFLICT_END);
+            self.output_line(&[], Contents::from_slice(graph::CONFLICT_END))
+        }
     }
 
-    pub fn diff<'a,'b,'name,T>(repository:&Transaction<'b,T>,branch:&Branch<'name,'a,'b,T>,line_num:&mut usize, actions:&mut Vec<Change>,
+    pub fn diff<'a,'b,'name,T,R:RawTxn>(repository:&Transaction<'b,T,R>,branch:&Branch<'name,'a,'b,T,R>,line_num:&mut usize, actions:&mut Vec<Change>,
                          redundant:&mut Vec<u8>,
                          a:Graph<'a>, b:&Path)->Result<(),super::super::error::Error> {
         
         let mut buf_b=Vec::new();
         let mut lines_b=Vec::new();
-        let f = std::fs::File::open(b);
-        let mut f = std::io::BufReader::new(f.unwrap());
-        try!(f.read_to_end(&mut buf_b));
-        let mut i=0;
-        let mut j=0;
+        let is_link = try!(std::fs::symlink_metadata(b)).file_type().is_symlink();
+        if is_link {
+            buf_b = try!(read_link_target(b))
+        } else {
+            let f = std::fs::File::open(b);
+            let mut f = std::io::BufReader::new(f.unwrap());
+            try!(f.read_to_end(&mut buf_b));
+        }
+        if is_link || super::super::is_binary(&buf_b) {
+            // Links and binary files are a single node, replaced as a whole.
+            if buf_b.len()>0 { lines_b.push(&buf_b[..]) }
+        } else {
+            let mut i=0;
+            let mut j=0;
 
-        while j<buf_b.len() {
-            if buf_b[j]==0xa {
-                lines_b.push(&buf_b[i..j+1]);
-                i=j+1
+            while j<buf_b.len() {
+                if buf_b[j]==0xa {
+                    lines_b.push(&buf_b[i..j+1]);
+                    i=j+1
+                }
+                j+=1;
             }
-            j+=1;
+            if i<j { lines_b.push(&buf_b[i..j]) }
         }
-        if i<j { lines_b.push(&buf_b[i..j]) }
 
 
         //let t0=time::precise_time_s();
         let db_contents = repository.db_contents();
-        let mut d = Diff { lines_a:Vec::new(), contents_a:Vec::new() };
+        let mut d = Diff { lines_a:Vec::new(), contents_a:Vec::new(), markers:Vec::new() };
         try!(graph::output_file(branch, &db_contents, &mut d,a,redundant));
+        // Conflict markers might have been output with labels.
+        if !d.markers.is_empty() {
+            lines_b = graph::strip_conflict_labels(&lines_b, &d.markers)
+        }
         //let t1=time::precise_time_s();
         //info!("output_file took {}s",t1-t0);
         local_diff(repository, branch, actions, line_num,
@@ -17,20 +17,24 @@
 //
 
 use super::backend::*;
-use super::patch::{Patch, KEY_SIZE, ROOT_KEY, HASH_SIZE, new_internal};
-use super::graph::{PSEUDO_EDGE, FOLDER_EDGE, PARENT_EDGE, DIRECTORY_FLAG, retrieve, output_file};
-use super::file_operations::{Inode, INODE_SIZE, ROOT_INODE, create_new_inode};
+use super::patch::{Patch, Change, KEY_SIZE, LINE_SIZE, ROOT_KEY, HASH_SIZE, new_internal, internal_hash};
+use super::graph::{PSEUDO_EDGE, FOLDER_EDGE, PARENT_EDGE, DELETED_EDGE, DIRECTORY_FLAG, SYMLINK_FLAG, CONFLICT_START, CONFLICT_NEXT,
+                   CONFLICT_END, CONFLICT_BASE, LineBuffer, retrieve, output_file};
+use super::file_operations::{Inode, INODE_SIZE, ROOT_INODE, create_new_inode, list_files};
 
 use super::error::Error;
+use super::fs_representation::conflict_copies_file;
 use super::apply::{apply, has_edge};
-use super::Len;
+use super::conflicts::collect_files;
+use super::{Len, is_binary};
 
 use rustc_serialize::hex::ToHex;
 use std::path::{Path, PathBuf};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::collections::hash_map::Entry;
 use std;
 use std::fs;
+use std::io::{Read, Write};
 use std::ptr::copy_nonoverlapping;
 
 
@@ -72,11 +76,11 @@ fn filename_of_inode<T>(db_revtree: &Db<T>, inode: &Inode, working_copy: &mut Pa
     true
 }
 
-pub fn retrieve_paths<'name, 'a, 'b, T>(branch: &'a Branch<'name, 'a, 'b, T>,
-                                        db_contents: &'a Db<'a, 'b, T>,
-                                        key: &[u8],
-                                        flag: u8)
-                                        -> Vec<(Vec<u8>, Vec<u8>)> {
+pub fn retrieve_paths<'name, 'a, 'b, T, R: RawTxn>(branch: &'a Branch<'name, 'a, 'b, T, R>,
+                                                   db_contents: &'a Db<'a, 'b, T, R>,
+                                                   key: &[u8],
+                                                   flag: u8)
+                                                   -> Vec<(Vec<u8>, Vec<u8>)> {
     let mut result = Vec::new();
     for (k, b) in branch.iter(key, Some(&[flag][..])) {
         if k == key && b[0] <= flag | PSEUDO_EDGE {
@@ -109,7 +113,7 @@ pub fn retrieve_paths<'name, 'a, 'b, T>(branch: &'a Branch<'name, 'a, 'b, T>,
 }
 
 /// Returns the path's inode
-pub fn follow_path<T>(db_tree:&Db<T>, path:&[&[u8]])->Result<Option<Inode>,Error> {
+pub fn follow_path<T, R: RawTxn>(db_tree:&Db<T, R>, path:&[&[u8]])->Result<Option<Inode>,Error> {
     // follow in tree, return inode
     let mut buf = vec![0;INODE_SIZE];
     for p in path {
@@ -132,7 +136,7 @@ pub fn follow_path<T>(db_tree:&Db<T>, path:&[&[u8]])->Result<Option<Inode>,Error
 }
 
 /// Returns the node's properties
-pub fn node_of_inode<'a, 'b, T>(db_inodes: &'a Db<'a, 'b, T>, inode: &[u8]) -> Option<Vec<u8>> {
+pub fn node_of_inode<'a, 'b, T, R: RawTxn>(db_inodes: &'a Db<'a, 'b, T, R>, inode: &[u8]) -> Option<Vec<u8>> {
     // follow in tree, return inode
     if inode == ROOT_INODE.as_ref() {
         Some(ROOT_KEY.to_vec())
@@ -142,6 +146,66 @@ pub fn node_of_inode<'a, 'b, T>(db_inodes: &'a Db<'a, 'b, T>, inode: &[u8]) -> O
     }
 }
 
+/// Parent of `key` through an edge with flag `flag`, possibly
+/// pseudo or deleted. Alive edges come first, but deleted ones are
+/// still followed, so that removed lines and files can be located.
+fn parent_with_flag<T, R: RawTxn>(branch: &Branch<T, R>, key: &[u8], flag: u8) -> Option<Vec<u8>> {
+    let mut deleted = None;
+    for (k, v) in branch.iter(key, Some(&[flag][..])) {
+        if k != key {
+            break;
+        }
+        if v[0] & !(PSEUDO_EDGE | DELETED_EDGE) == flag {
+            if v[0] & DELETED_EDGE == 0 {
+                return Some(v[1..(1 + KEY_SIZE)].to_vec());
+            } else if deleted.is_none() {
+                deleted = Some(v[1..(1 + KEY_SIZE)].to_vec())
+            }
+        }
+    }
+    deleted
+}
+
+/// Path of the file containing node `key` (an internal key) on
+/// `branch`: climb up the lines of the file to the file's node, and
+/// then up the names of the directories containing it.
+pub fn file_of_key<T, R: RawTxn>(branch: &Branch<T, R>,
+                                 db_contents: &Db<T, R>,
+                                 key: &[u8])
+                                 -> Option<PathBuf> {
+    let mut components = Vec::new();
+    let mut visited = HashSet::new();
+    let mut current = key.to_vec();
+    while &current[..] != ROOT_KEY {
+        if !visited.insert(current.clone()) {
+            return None;
+        }
+        if let Some(parent) = parent_with_flag(branch, &current, FOLDER_EDGE | PARENT_EDGE) {
+            // Name nodes start with two bytes of permissions, file
+            // nodes are empty.
+            if let Some(contents) = db_contents.contents(&current) {
+                let mut name = Vec::new();
+                for c in contents {
+                    name.extend(c)
+                }
+                if name.len() > 2 {
+                    components.push(String::from_utf8_lossy(&name[2..]).into_owned())
+                }
+            }
+            current = parent
+        } else if let Some(parent) = parent_with_flag(branch, &current, PARENT_EDGE) {
+            current = parent
+        } else {
+            return None;
+        }
+    }
+    if components.is_empty() {
+        None
+    } else {
+        Some(components.iter().rev().collect())
+    }
+}
+
 struct OutputState<'a, 'b, 'c, T>
     where 'a: 'c,
           'b: 'c,
@@ -151,8 +215,84 @@ struct OutputState<'a, 'b, 'c, T>
     db_revinodes: &'c mut Db<'b, 'a, T>,
     db_tree: &'c mut Db<'b, 'a, T>,
     db_revtree: &'c mut Db<'b, 'a, T>,
+    db_external: &'c Db<'b, 'a, T>,
     visited: &'c mut HashMap<Vec<u8>, Vec<PathBuf>>,
     moves: &'c mut Vec<Tree>,
+    style: ConflictStyle,
+    copies: &'c mut ConflictCopies,
+    /// Paths of the files of the directory being output.
+    siblings: HashSet<PathBuf>,
+}
+
+/// Copies of binary files written for the sides of their conflicts.
+#[derive(Default)]
+struct ConflictCopies {
+    /// Copies written by the previous output, which this one can
+    /// overwrite, and removes if it does not need them anymore.
+    old: HashSet<PathBuf>,
+    /// Copies written by this output.
+    new: HashSet<PathBuf>,
+}
+
+impl ConflictCopies {
+    /// Read the copies written by the last output of `working_copy`.
+    fn load(working_copy: &Path) -> Result<ConflictCopies, Error> {
+        let mut copies = ConflictCopies::default();
+        let mut text = String::new();
+        match fs::File::open(conflict_copies_file(working_copy)) {
+            Ok(mut f) => { try!(f.read_to_string(&mut text)); }
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::IO(e)),
+        }
+        copies.old = text.lines().map(|l| working_copy.join(l)).collect();
+        Ok(copies)
+    }
+
+    /// A path for a new copy of `path`, named `path~n`, that no other
+    /// file uses: neither the files in `siblings`, nor the other
+    /// copies, nor the files in the working copy that are not old
+    /// copies.
+    fn new_copy(&mut self, path: &Path, siblings: &HashSet<PathBuf>) -> PathBuf {
+        let filename = path.file_name().unwrap().to_string_lossy().into_owned();
+        let mut n = 1;
+        loop {
+            let copy = path.with_file_name(format!("{}~{}", filename, n));
+            if !siblings.contains(&copy) && !self.new.contains(&copy) &&
+               (self.old.contains(&copy) || fs::symlink_metadata(&copy).is_err()) {
+                self.new.insert(copy.clone());
+                return copy;
+            }
+            n += 1
+        }
+    }
+
+    /// Remove the old copies this output did not write again, unless
+    /// they are now files of the repository, and save the list of
+    /// the new ones.
+    fn finish<T>(self, repository: &Transaction<T>, working_copy: &Path) -> Result<(), Error> {
+        if self.old.is_empty() && self.new.is_empty() {
+            return Ok(());
+        }
+        let files: HashSet<PathBuf> = try!(list_files(repository)).into_iter().collect();
+        for copy in self.old.iter() {
+            if !self.new.contains(copy) &&
+               !files.contains(copy.strip_prefix(working_copy).unwrap_or(copy)) {
+                debug!("removing stale copy {:?}", copy);
+                if let Err(e) = fs::remove_file(copy) {
+                    debug!("could not remove {:?}: {:?}", copy, e)
+                }
+            }
+        }
+        let mut new: Vec<&PathBuf> = self.new.iter().collect();
+        new.sort();
+        let mut f = try!(fs::File::create(conflict_copies_file(working_copy)));
+        for copy in new {
+            if let Ok(copy) = copy.strip_prefix(working_copy) {
+                try!(writeln!(f, "{}", copy.display()))
+            }
+        }
+        Ok(())
+    }
 }
 
 fn create_inode_if_needed<'a, T>(file_start: &'a [u8],
@@ -161,7 +301,7 @@ fn create_inode_if_needed<'a, T>(file_start: &'a [u8],
                                  db_revtree: &mut Db<T>,
                                  new_inodes: &mut HashMap<Vec<u8>, (usize, &'a [u8])>)
                                  -> Inode {
-    match db_revinodes.get(file_start) {
+    match db_revinodes.get(&file_start[1..(1 + KEY_SIZE)]) {
         Some(c_inode) => Inode::from_slice(c_inode),
         None => {
             let mut v = vec![0;INODE_SIZE];
@@ -177,7 +317,9 @@ fn create_inode_if_needed<'a, T>(file_start: &'a [u8],
     }
 }
 
-fn create_conflicting_copy(buf: &Path, file_path: &mut PathBuf) {
+/// Move `buf` to `file_path~i`, for the first `i` such that no file
+/// is at that path, in the working copy or in `siblings`.
+fn create_conflicting_copy(buf: &Path, file_path: &mut PathBuf, siblings: &HashSet<PathBuf>) {
     let mut filename = file_path.file_name().unwrap().to_str().unwrap().to_string();
     let l = filename.len();
     let mut i = 0;
@@ -185,26 +327,457 @@ fn create_conflicting_copy(buf: &Path, file_path: &mut PathBuf) {
         filename.truncate(l);
         filename = filename + &format!("~{}", i);
         file_path.set_file_name(&filename);
-        if fs::rename(buf, &file_path).is_ok() {
+        if !siblings.contains(file_path) && fs::symlink_metadata(&file_path).is_err() &&
+           fs::rename(buf, &file_path).is_ok() {
             break;
         }
         i += 1
     }
 }
 
-fn output_visit_file<'a, 'b, 'name, T>(st: &mut OutputState<T>,
-                                       c: &[u8],
-                                       file_path: &Path,
-                                       perms: usize,
-                                       file_inode: &Inode,
-                                       fileref: &[u8],
-                                       working_copy: &Path,
-                                       db_contents: &Db<'b, 'a, T>,
-                                       branch: &Branch<'name, 'b, 'a, T>,
-                                       do_output: bool,
-                                       recursive_calls: &mut Vec<(String, Vec<u8>, Inode)>,
-                                       basename: &str)
-                                       -> Result<(), Error> {
+/// Write `contents` to `path`, unless the file already has exactly
+/// these contents, in which case it is left untouched.
+fn write_if_changed(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    // Do not write through a link that used to be at this path.
+    if let Ok(attr) = fs::symlink_metadata(path) {
+        if attr.file_type().is_symlink() {
+            try!(fs::remove_file(path))
+        }
+    }
+    if let Ok(mut f) = fs::File::open(path) {
+        let mut current = Vec::new();
+        if f.read_to_end(&mut current).is_ok() && &current[..] == contents {
+            debug!("unchanged file {:?}", path);
+            return Ok(());
+        }
+    }
+    debug!("creating file {:?}", path);
+    let mut f = try!(fs::File::create(path));
+    try!(f.write_all(contents));
+    Ok(())
+}
+
+/// Contents of a file being output. Binary files cannot have inline
+/// conflict markers, so the sides of their conflicts are also kept
+/// as separate versions of the file.
+struct FileBuffer {
+    inline: Vec<u8>,
+    common: Vec<u8>,
+    versions: Vec<Vec<u8>>,
+    side: Option<usize>,
+    is_binary: bool,
+}
+
+impl FileBuffer {
+    fn new() -> Self {
+        FileBuffer {
+            inline: Vec::new(),
+            common: Vec::new(),
+            versions: Vec::new(),
+            side: None,
+            is_binary: false,
+        }
+    }
+    fn push(&mut self, line: &[u8]) {
+        self.inline.extend(line);
+        if let Some(side) = self.side {
+            self.versions[side].extend(line)
+        } else {
+            self.common.extend(line);
+            for v in self.versions.iter_mut() {
+                v.extend(line)
+            }
+        }
+    }
+    fn next_side(&mut self, side: usize) {
+        if self.versions.len() <= side {
+            let v = self.common.clone();
+            self.versions.push(v)
+        }
+        self.side = Some(side)
+    }
+}
+
+impl<'a, 'env: 'a, T: 'a, R: 'a + RawTxn> LineBuffer<'a, 'env, T, R> for FileBuffer {
+    fn output_line(&mut self, _: &'a [u8], contents: Contents<'a, 'env, T, R>) -> Result<(), Error> {
+        let mut line = Vec::new();
+        for c in contents {
+            line.extend(c)
+        }
+        self.is_binary = self.is_binary || is_binary(&line);
+        self.push(&line);
+        Ok(())
+    }
+    fn begin_conflict(&mut self) -> Result<(), Error> {
+        self.inline.extend(CONFLICT_START);
+        self.next_side(0);
+        Ok(())
+    }
+    fn conflict_next(&mut self) -> Result<(), Error> {
+        self.inline.extend(CONFLICT_NEXT);
+        let side = self.side.map(|s| s + 1).unwrap_or(0);
+        self.next_side(side);
+        Ok(())
+    }
+    fn end_conflict(&mut self) -> Result<(), Error> {
+        self.inline.extend(CONFLICT_END);
+        self.side = None;
+        Ok(())
+    }
+}
+
+/// A file of a branch, output in memory by `output_files`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputFile {
+    /// Path relative to the root of the repository.
+    pub path: PathBuf,
+    pub symlink: bool,
+    pub executable: bool,
+    /// The contents of the file, or the target of the link, with
+    /// plain conflict markers.
+    pub contents: Vec<u8>,
+}
+
+impl OutputFile {
+    /// Write this file below `directory`, creating its parent
+    /// directories if needed.
+    pub fn write(&self, directory: &Path) -> Result<(), Error> {
+        let path = directory.join(&self.path);
+        if let Some(parent) = path.parent() {
+            try!(fs::create_dir_all(parent))
+        }
+        if self.symlink {
+            write_symlink(&path, &self.contents)
+        } else {
+            write_if_changed(&path, &self.contents)
+        }
+    }
+}
+
+/// Files of branch `branch_name`, sorted by path, as they would be
+/// output to a working copy with `ConflictStyle::Plain`, but in
+/// memory. Directories are not included, and only the first name of
+/// a file with conflicting names is. If `paths` is given, only the
+/// files at these paths or below them are output.
+pub fn output_files<T, R: RawTxn>(repository: &Transaction<T, R>,
+                                  branch_name: &str,
+                                  paths: Option<&[PathBuf]>)
+                                  -> Result<Vec<OutputFile>, Error> {
+    let branch = try!(repository.db_nodes(branch_name));
+    let db_contents = repository.db_contents();
+    let mut visited = HashMap::new();
+    let mut files = Vec::new();
+    try!(collect_files(&branch, &db_contents, ROOT_KEY, Path::new(""), &mut visited, &mut files));
+    if let Some(paths) = paths {
+        files.retain(|&(ref path, _, _)| paths.iter().any(|p| path.starts_with(p)))
+    }
+    files.sort();
+    let mut result = Vec::with_capacity(files.len());
+    for (path, key, perms) in files {
+        let mut contents = FileBuffer::new();
+        let mut redundant_edges = Vec::new();
+        let graph = retrieve(&branch, &key);
+        try!(output_file(&branch, &db_contents, &mut contents, graph, &mut redundant_edges));
+        let symlink = perms & SYMLINK_FLAG != 0;
+        result.push(OutputFile {
+            path: path,
+            symlink: symlink,
+            executable: !symlink && perms & 0o100 != 0,
+            contents: if symlink {
+                // On conflicts, keep the first target.
+                contents.versions.first().unwrap_or(&contents.inline).clone()
+            } else {
+                contents.inline
+            },
+        })
+    }
+    Ok(result)
+}
+
+/// Paths on branch `branch_name`, where patch `hash` is applied, of
+/// the files and directories whose nodes `patch` adds, or changes the
+/// edges of. Nodes it deletes are found at their last path.
+pub fn patch_paths<T, R: RawTxn>(repository: &Transaction<T, R>,
+                                 branch_name: &str,
+                                 hash: &[u8],
+                                 patch: &Patch)
+                                 -> Result<Vec<PathBuf>, Error> {
+    let branch = try!(repository.db_nodes(branch_name));
+    let db_contents = repository.db_contents();
+    let db_internal = repository.db_internal();
+    let internal = try!(internal_hash(&db_internal, hash)).contents.to_vec();
+    // Keys of patches are external, and relative to the patch itself
+    // when their hash is empty.
+    let path_of = |key: &[u8]| -> Option<PathBuf> {
+        if key.len() < LINE_SIZE {
+            return None;
+        }
+        let (h, line) = key.split_at(key.len() - LINE_SIZE);
+        let mut k = if h.is_empty() {
+            internal.clone()
+        } else {
+            match internal_hash(&db_internal, h) {
+                Ok(i) => i.contents.to_vec(),
+                Err(_) => return None,
+            }
+        };
+        k.extend(line);
+        file_of_key(&branch, &db_contents, &k)
+    };
+    let mut paths = BTreeSet::new();
+    for change in patch.changes.iter() {
+        match *change {
+            Change::NewNodes { ref up_context, ref flag, ref nodes, .. } => {
+                let up = up_context.get(0).and_then(|k| path_of(k));
+                if *flag & FOLDER_EDGE != 0 {
+                    // New names, below the directory of the context.
+                    let dir = up.unwrap_or(PathBuf::new());
+                    for n in nodes.iter().filter(|n| n.len() > 2) {
+                        paths.insert(dir.join(String::from_utf8_lossy(&n[2..]).as_ref()));
+                    }
+                } else if let Some(up) = up {
+                    paths.insert(up);
+                }
+            }
+            Change::Edges { ref flag, ref edges } => {
+                for e in edges.iter() {
+                    let target = if *flag & PARENT_EDGE == 0 { &e.to } else { &e.from };
+                    if let Some(path) = path_of(target) {
+                        paths.insert(path);
+                    }
+                }
+            }
+        }
+    }
+    Ok(paths.into_iter().collect())
+}
+
+/// How conflicts are written in files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictStyle {
+    /// Anonymous markers, see `graph::CONFLICT_START`.
+    Plain,
+    /// Markers labelled with the short hash and name of the patch
+    /// that introduced the first line of each side.
+    Labelled,
+    /// Labelled markers, followed after the first side by the lines
+    /// that the sides replaced, as in diff3.
+    Diff3,
+}
+
+impl Default for ConflictStyle {
+    fn default() -> Self {
+        ConflictStyle::Plain
+    }
+}
+
+impl ConflictStyle {
+    pub fn from_name(name: &str) -> Option<ConflictStyle> {
+        match name {
+            "plain" => Some(ConflictStyle::Plain),
+            "labelled" | "labeled" => Some(ConflictStyle::Labelled),
+            "diff3" => Some(ConflictStyle::Diff3),
+            _ => None,
+        }
+    }
+}
+
+/// Lines deleted right after `key` by one of the patches in
+/// `patches` (as internal hashes), following chains of deleted edges.
+/// When a conflict starts after `key`, and `patches` are the patches
+/// of its sides, these are the lines that the sides replaced, if any.
+fn deleted_after<T>(branch: &Branch<T>,
+                    db_contents: &Db<T>,
+                    key: &[u8],
+                    patches: &HashSet<Vec<u8>>)
+                    -> Vec<Vec<u8>> {
+    let mut lines = Vec::new();
+    let mut visited = HashSet::new();
+    let mut current = key.to_vec();
+    loop {
+        let next = branch.iter(&current, Some(&[DELETED_EDGE][..]))
+            .take_while(|&(k, v)| k == &current[..] && v[0] == DELETED_EDGE)
+            .filter(|&(_, v)| patches.contains(&v[(1 + KEY_SIZE)..]))
+            .map(|(_, v)| v[1..(1 + KEY_SIZE)].to_vec())
+            .next();
+        match next {
+            Some(next) => {
+                if !visited.insert(next.clone()) {
+                    break;
+                }
+                let mut line = Vec::new();
+                if let Some(contents) = db_contents.contents(&next) {
+                    for c in contents {
+                        line.extend(c)
+                    }
+                }
+                lines.push(line);
+                current = next
+            }
+            None => break,
+        }
+    }
+    lines
+}
+
+/// File buffer writing conflicts in the `Labelled` or `Diff3` style.
+/// Since markers are written before the side they introduce, the
+/// label is only added with the first line of that side. Similarly,
+/// the original version of a diff3 conflict is only known at the end
+/// of the conflict, once the patches of all sides have been seen, and
+/// is then inserted at `base`.
+struct LabelledBuffer<'c, 'name: 'c, 'b: 'c, 'a: 'b, T: 'c> {
+    file: FileBuffer,
+    branch: &'c Branch<'name, 'b, 'a, T>,
+    db_contents: &'c Db<'b, 'a, T>,
+    db_external: &'c Db<'b, 'a, T>,
+    working_copy: &'c Path,
+    diff3: bool,
+    marker: Option<&'static [u8]>,
+    last_key: Vec<u8>,
+    conflict_start: Vec<u8>,
+    side_patches: HashSet<Vec<u8>>,
+    base: Option<usize>,
+    names: HashMap<Vec<u8>, String>,
+}
+
+impl<'c, 'name, 'b, 'a, T> LabelledBuffer<'c, 'name, 'b, 'a, T> {
+    /// Short hash and name of the patch that introduced `key`.
+    fn label(&mut self, key: &[u8]) -> String {
+        if key.len() < HASH_SIZE {
+            return String::new();
+        }
+        let hash = match self.db_external.get(&key[0..HASH_SIZE]) {
+            Some(hash) => hash.to_vec(),
+            // Lines of the pending patch are not registered.
+            None => return "unrecorded changes".to_string(),
+        };
+        let working_copy = self.working_copy;
+        let name = self.names.entry(hash.clone()).or_insert_with(|| {
+            match Patch::from_repository(working_copy, &hash) {
+                Ok(patch) => patch.name.lines().next().unwrap_or("").to_string(),
+                Err(_) => String::new(),
+            }
+        });
+        format!("{} {}", &hash.to_hex()[0..10], name)
+    }
+}
+
+impl<'c, 'name, 'b, 'a, 'x, 'env: 'x, T: 'x, R: 'x + RawTxn> LineBuffer<'x, 'env, T, R>
+    for LabelledBuffer<'c, 'name, 'b, 'a, T> {
+    fn output_line(&mut self, key: &'x [u8], contents: Contents<'x, 'env, T, R>) -> Result<(), Error> {
+        let mut line = Vec::new();
+        for c in contents {
+            line.extend(c)
+        }
+        if let Some(marker) = self.marker.take() {
+            let label = self.label(key);
+            self.file.inline.extend(&marker[..(marker.len() - 1)]);
+            self.file.inline.push(b' ');
+            self.file.inline.extend(label.as_bytes());
+            self.file.inline.push(b'\n');
+        }
+        if self.file.side.is_none() && !key.is_empty() {
+            self.last_key.clear();
+            self.last_key.extend(key)
+        } else if self.file.side.is_some() && key.len() >= HASH_SIZE {
+            self.side_patches.insert(key[0..HASH_SIZE].to_vec());
+        }
+        self.file.is_binary = self.file.is_binary || is_binary(&line);
+        self.file.push(&line);
+        Ok(())
+    }
+    fn begin_conflict(&mut self) -> Result<(), Error> {
+        self.conflict_start = self.last_key.clone();
+        self.side_patches.clear();
+        self.marker = Some(CONFLICT_START);
+        self.file.next_side(0);
+        Ok(())
+    }
+    fn conflict_next(&mut self) -> Result<(), Error> {
+        if self.diff3 && self.file.side == Some(0) {
+            self.file.inline.extend(&CONFLICT_BASE[..(CONFLICT_BASE.len() - 1)]);
+            self.file.inline.extend(b" base\n");
+            self.base = Some(self.file.inline.len())
+        }
+        self.marker = Some(CONFLICT_NEXT);
+        let side = self.file.side.map(|s| s + 1).unwrap_or(0);
+        self.file.next_side(side);
+        Ok(())
+    }
+    fn end_conflict(&mut self) -> Result<(), Error> {
+        if let Some(base) = self.base.take() {
+            let rest = self.file.inline.split_off(base);
+            for line in deleted_after(self.branch, self.db_contents, &self.conflict_start, &self.side_patches) {
+                self.file.inline.extend(&line)
+            }
+            self.file.inline.extend(rest)
+        }
+        self.file.inline.extend(CONFLICT_END);
+        self.file.side = None;
+        Ok(())
+    }
+}
+
+/// Write the contents of a file. Conflicts on binary files are
+/// written as one copy of the file per side, named `file~1`,
+/// `file~2`, etc. for all sides but the first one, skipping the
+/// names other files use (see `ConflictCopies::new_copy`).
+fn write_file_buffer(path: &Path,
+                     buf: &FileBuffer,
+                     copies: &mut ConflictCopies,
+                     siblings: &HashSet<PathBuf>)
+                     -> Result<(), Error> {
+    if buf.is_binary && buf.versions.len() > 1 {
+        debug!("binary conflict on {:?}", path);
+        try!(write_if_changed(path, &buf.versions[0]));
+        for v in buf.versions.iter().skip(1) {
+            try!(write_if_changed(&copies.new_copy(path, siblings), v))
+        }
+        Ok(())
+    } else {
+        write_if_changed(path, &buf.inline)
+    }
+}
+
+/// Make `path` a symbolic link to `target`, unless it already is.
+#[cfg(not(windows))]
+fn write_symlink(path: &Path, target: &[u8]) -> Result<(), Error> {
+    use std::os::unix::ffi::OsStrExt;
+    let target = Path::new(std::ffi::OsStr::from_bytes(target));
+    if let Ok(current) = fs::read_link(path) {
+        if current == target {
+            debug!("unchanged link {:?}", path);
+            return Ok(());
+        }
+    }
+    if let Ok(attr) = fs::symlink_metadata(path) {
+        if !attr.is_dir() {
+            try!(fs::remove_file(path))
+        }
+    }
+    debug!("creating link {:?} -> {:?}", path, target);
+    try!(std::os::unix::fs::symlink(target, path));
+    Ok(())
+}
+#[cfg(windows)]
+fn write_symlink(path: &Path, target: &[u8]) -> Result<(), Error> {
+    write_if_changed(path, target)
+}
+
+fn output_visit_file<'a, 'b, 'c, 'name, T>(st: &mut OutputState<'a, 'b, 'c, T>,
+                                           c: &[u8],
+                                           file_path: &Path,
+                                           perms: usize,
+                                           file_inode: &Inode,
+                                           fileref: &[u8],
+                                           working_copy: &Path,
+                                           db_contents: &Db<'b, 'a, T>,
+                                           branch: &Branch<'name, 'b, 'a, T>,
+                                           do_output: bool,
+                                           recursive_calls: &mut Vec<(String, Vec<u8>, Inode)>,
+                                           basename: &str)
+                                           -> Result<(), Error> {
     let cv = &c[1..(1 + KEY_SIZE)];
     debug!("cv={}", cv.to_hex());
     let mut inode_v = fileref.to_vec();
@@ -232,7 +805,7 @@ fn output_visit_file<'a, 'b, 'name, T>(st: &mut OutputState<T>,
                         // move on filesystem
                         debug!("moving {:?} to {:?}", buf, file_path);
                         if fs::rename(&buf, &file_path).is_err() {
-                            create_conflicting_copy(&buf, &mut file_path);
+                            create_conflicting_copy(&buf, &mut file_path, &st.siblings);
                         }
                         debug!("done");
                         st.moves.push(Tree::Move {
@@ -246,11 +819,11 @@ fn output_visit_file<'a, 'b, 'name, T>(st: &mut OutputState<T>,
                         tree_key: inode_v,
                         tree_value: file_inode.as_ref().to_vec(),
                     });
-                    if perms & DIRECTORY_FLAG == 0 {
-                        debug!("creating the file");
-                        std::fs::File::create(&file_path).unwrap();
-                    } else {
+                    if perms & DIRECTORY_FLAG != 0 {
                         std::fs::create_dir_all(&file_path).unwrap();
+                    } else if perms & SYMLINK_FLAG == 0 {
+                        debug!("creating the file");
+                        try!(fs::OpenOptions::new().write(true).create(true).open(&file_path));
                     }
                 };
             }
@@ -258,11 +831,35 @@ fn output_visit_file<'a, 'b, 'name, T>(st: &mut OutputState<T>,
                 if do_output {
                     let mut redundant_edges = vec![];
                     let l = retrieve(branch, &cv);
-                    debug!("creating file {:?}", &file_path);
-                    let mut f = std::fs::File::create(&file_path).unwrap();
-                    debug!("done");
-
-                    try!(output_file(branch, db_contents, &mut f, l, &mut redundant_edges));
+                    let contents = if st.style == ConflictStyle::Plain {
+                        let mut contents = FileBuffer::new();
+                        try!(output_file(branch, db_contents, &mut contents, l, &mut redundant_edges));
+                        contents
+                    } else {
+                        let mut buf = LabelledBuffer {
+                            file: FileBuffer::new(),
+                            branch: branch,
+                            db_contents: db_contents,
+                            db_external: st.db_external,
+                            working_copy: working_copy,
+                            diff3: st.style == ConflictStyle::Diff3,
+                            marker: None,
+                            last_key: Vec::new(),
+                            conflict_start: Vec::new(),
+                            side_patches: HashSet::new(),
+                            base: None,
+                            names: HashMap::new(),
+                        };
+                        try!(output_file(branch, db_contents, &mut buf, l, &mut redundant_edges));
+                        buf.file
+                    };
+                    if perms & SYMLINK_FLAG != 0 {
+                        // On conflicts, keep the first target.
+                        let target = contents.versions.first().unwrap_or(&contents.inline);
+                        try!(write_symlink(&file_path, target));
+                    } else {
+                        try!(write_file_buffer(&file_path, &contents, st.copies, &st.siblings));
+                    }
                 }
             } else {
                 recursive_calls.push((basename.to_string(), cv.to_vec(), file_inode.clone()));
@@ -295,8 +892,32 @@ fn output_aux<'a, 'b, 'name, 'c, T>(st: &mut OutputState<'a, 'b, 'c, T>,
     //
     // This is because the database cannot be updated while being iterated over.
     let mut filename_buffer = Vec::new();
-    for (_, b) in branch.iter(key, Some(&[FOLDER_EDGE][..]))
-                        .take_while(|&(k, b)| k == key && b[0] <= FOLDER_EDGE | PSEUDO_EDGE) {
+    // Visit the names in the order of their external keys, so that
+    // all repositories output the same file on name conflicts. Names
+    // from the pending patch have no external key, and come first.
+    let mut names: Vec<(Option<Vec<u8>>, &[u8])> =
+        branch.iter(key, Some(&[FOLDER_EDGE][..]))
+              .take_while(|&(k, b)| k == key && b[0] <= FOLDER_EDGE | PSEUDO_EDGE)
+              .map(|(_, b)| {
+                  let ext = st.db_external.get(&b[1..(1 + HASH_SIZE)]).map(|h| {
+                      let mut ext = h.to_vec();
+                      ext.extend(&b[(1 + HASH_SIZE)..(1 + KEY_SIZE)]);
+                      ext
+                  });
+                  (ext, b)
+              })
+              .collect();
+    names.sort();
+    st.siblings = names.iter()
+        .map(|&(_, b)| {
+            let mut name = Vec::new();
+            for i in db_contents.contents(&b[1..(1 + KEY_SIZE)]).unwrap() {
+                name.extend(i)
+            }
+            path.join(String::from_utf8_lossy(&name[2..]).as_ref())
+        })
+        .collect();
+    for (_, b) in names {
 
         debug_assert!(b.len() == 1 + KEY_SIZE + HASH_SIZE);
         debug!("b={}", b.to_hex());
@@ -413,15 +1034,72 @@ fn output_aux<'a, 'b, 'name, 'c, T>(st: &mut OutputState<'a, 'b, 'c, T>,
     Ok(())
 }
 
+/// Remove the inodes in `dead`, given with their keys, from the
+/// inodes and tree tables (and their revs).
+fn kill_inodes<'b, 'a, T>(db_inodes: &mut Db<'b, 'a, T>,
+                          db_revinodes: &mut Db<'b, 'a, T>,
+                          db_tree: &mut Db<'b, 'a, T>,
+                          db_revtree: &mut Db<'b, 'a, T>,
+                          dead: Vec<(Vec<u8>, Vec<u8>)>)
+                          -> Result<(), Error> {
+    let mut uu = Vec::new();
+    let mut vv = Vec::new();
+    for (ref inode, ref key) in dead {
+        debug!("kill dead {:?}", inode.to_hex());
+        try!(db_inodes.del(inode, None));
+        try!(db_revinodes.del(key, None));
+        let mut kills = Vec::new();
+        // iterate through inode's relatives.
+        for (k, v) in db_revtree.iter(&inode, None).take_while(|&(k, _)| k == &inode[..]) {
+            kills.push((k.to_vec(), v.to_vec()));
+        }
+        for &(ref k, ref v) in kills.iter() {
+            try!(db_tree.del(&v, Some(&k[..])));
+            try!(db_revtree.del(&k, Some(&v[..])));
+        }
+
+        debug!("loop");
+        loop {
+            let mut found = false;
+            for (u, v) in db_tree.iter(inode, None) {
+                found = true;
+                uu.clear();
+                uu.extend(u);
+                vv.clear();
+                vv.extend(v);
+                break;
+            }
+            if found {
+                debug!("delete {:?} {:?}", uu.to_hex(), vv.to_hex());
+                try!(db_tree.del(&uu[..], Some(&vv[..])));
+                debug!("delete 0");
+                try!(db_revtree.del(&vv[..], Some(&uu[..])));
+                debug!("delete 1");
+            }
+            if !found {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Output `branch` (if `do_output`) and update the inodes and tree
+/// tables. Returns the paths, relative to `working_copy`, of the
+/// files of the working copy deleted in `branch` since the last
+/// output.
 fn unsafe_output_repository<'name, 'b, 'a, T>(branch: &Branch<'name, 'b, 'a, T>,
                                               db_contents: &Db<'b, 'a, T>,
                                               db_inodes: &mut Db<'b, 'a, T>,
                                               db_revinodes: &mut Db<'b, 'a, T>,
                                               db_tree: &mut Db<'b, 'a, T>,
                                               db_revtree: &mut Db<'b, 'a, T>,
+                                              db_external: &Db<'b, 'a, T>,
                                               working_copy: &Path,
-                                              do_output: bool)
-                                              -> Result<(), Error> {
+                                              do_output: bool,
+                                              style: ConflictStyle,
+                                              copies: &mut ConflictCopies)
+                                              -> Result<Vec<PathBuf>, Error> {
     let mut visited = HashMap::new();
     let mut p = PathBuf::from(working_copy);
 
@@ -432,8 +1110,12 @@ fn unsafe_output_repository<'name, 'b, 'a, T>(branch: &Branch<'name, 'b, 'a, T>,
             db_inodes: db_inodes,
             db_tree: db_tree,
             db_revtree: db_revtree,
+            db_external: db_external,
             moves: &mut moves,
             visited: &mut visited,
+            style: style,
+            copies: copies,
+            siblings: HashSet::new(),
         };
 
         try!(output_aux(&mut st,
@@ -448,12 +1130,21 @@ fn unsafe_output_repository<'name, 'b, 'a, T>(branch: &Branch<'name, 'b, 'a, T>,
 
     // Now, garbage collect dead inodes.
     let mut dead = Vec::new();
+    let mut deleted = Vec::new();
     {
         // let curs = try!(self.txn.cursor(self.dbi_inodes));
         for (u, v) in db_inodes.iter(b"", None) {
             if !has_edge(branch, &v[3..], PARENT_EDGE | FOLDER_EDGE, true) {
                 // v is dead.
                 debug!("dead:{:?} {:?}", u.to_hex(), v.to_hex());
+                // Files added and not recorded yet have no edges,
+                // and files removed locally are marked with 2.
+                if v[0] != 2 && has_edge(branch, &v[3..], PARENT_EDGE | FOLDER_EDGE | DELETED_EDGE, true) {
+                    let mut path = PathBuf::new();
+                    if filename_of_inode(db_revtree, &Inode::from_slice(u), &mut path) {
+                        deleted.push(path)
+                    }
+                }
                 dead.push((u.to_vec(), (&v[3..]).to_vec()))
             }
         }
@@ -461,82 +1152,110 @@ fn unsafe_output_repository<'name, 'b, 'a, T>(branch: &Branch<'name, 'b, 'a, T>,
 
 
     // Now, "kill the deads"
-    {
-        // let mut curs_tree= unsafe { &mut *try!(self.txn.unsafe_cursor(self.dbi_tree)) };
-        // let mut curs_revtree= unsafe { &mut *try!(self.txn.unsafe_cursor(self.dbi_revtree)) };
-
-        let mut uu = Vec::new();
-        let mut vv = Vec::new();
-        for (ref inode, ref key) in dead {
-            debug!("kill dead {:?}", inode.to_hex());
-            try!(db_inodes.del(inode, None));
-            try!(db_revinodes.del(key, None));
-            let mut kills = Vec::new();
-            // iterate through inode's relatives.
-            for (k, v) in db_revtree.iter(&inode, None).take_while(|&(k, _)| k == &inode[..]) {
-                kills.push((k.to_vec(), v.to_vec()));
-            }
-            for &(ref k, ref v) in kills.iter() {
-                try!(db_tree.del(&v, Some(&k[..])));
-                try!(db_revtree.del(&k, Some(&v[..])));
-            }
+    try!(kill_inodes(db_inodes, db_revinodes, db_tree, db_revtree, dead));
+    debug!("done unsafe_output_repository");
+    Ok(deleted)
+}
 
-            debug!("loop");
-            loop {
-                let mut found = false;
-                for (u, v) in db_tree.iter(inode, None) {
-                    found = true;
-                    uu.clear();
-                    uu.extend(u);
-                    vv.clear();
-                    vv.extend(v);
-                    break;
-                }
-                if found {
-                    debug!("delete {:?} {:?}", uu.to_hex(), vv.to_hex());
-                    try!(db_tree.del(&uu[..], Some(&vv[..])));
-                    debug!("delete 0");
-                    try!(db_revtree.del(&vv[..], Some(&uu[..])));
-                    debug!("delete 1");
-                }
-                if !found {
-                    break;
-                }
-            }
+
+/// Update the tree and inodes tables (and their revs) to match
+/// branch `branch_name`, without outputting the contents of files
+/// (`do_output = false`). Files missing from the working copy are
+/// created empty, and existing files are left untouched.
+pub fn update_inodes<T>(repository: &mut Transaction<T>,
+                        branch_name: &str,
+                        working_copy: &Path)
+                        -> Result<(), Error> {
+    update_tree(repository, branch_name, working_copy).map(|_| ())
+}
+
+/// Forget the inodes of the files removed locally whose removal is
+/// now in branch `branch_name`, so that they are not recorded again.
+pub fn forget_removed_files<T>(repository: &mut Transaction<T>, branch_name: &str) -> Result<(), Error> {
+    let branch = try!(repository.db_nodes(branch_name));
+    let mut db_inodes = repository.db_inodes();
+    let mut db_revinodes = repository.db_revinodes();
+    let mut db_tree = repository.db_tree();
+    let mut db_revtree = repository.db_revtree();
+    let dead: Vec<_> = db_inodes.iter(b"", None)
+        .filter(|&(_, v)| v[0] == 2 && !has_edge(&branch, &v[3..], PARENT_EDGE | FOLDER_EDGE, true))
+        .map(|(u, v)| (u.to_vec(), (&v[3..]).to_vec()))
+        .collect();
+    kill_inodes(&mut db_inodes, &mut db_revinodes, &mut db_tree, &mut db_revtree, dead)
+}
+
+/// Same as `update_inodes`, returning the files deleted in the
+/// branch since the last output.
+fn update_tree<T>(repository: &mut Transaction<T>,
+                  branch_name: &str,
+                  working_copy: &Path)
+                  -> Result<Vec<PathBuf>, Error> {
+    let branch = try!(repository.db_nodes(branch_name));
+    let db_contents = repository.db_contents();
+    let mut db_inodes = repository.db_inodes();
+    let mut db_revinodes = repository.db_revinodes();
+    let mut db_tree = repository.db_tree();
+    let mut db_revtree = repository.db_revtree();
+    let db_external = repository.db_external();
+
+    let deleted = try!(unsafe_output_repository(&branch,
+                                                &db_contents,
+                                                &mut db_inodes,
+                                                &mut db_revinodes,
+                                                &mut db_tree,
+                                                &mut db_revtree,
+                                                &db_external,
+                                                working_copy,
+                                                false,
+                                                ConflictStyle::Plain,
+                                                &mut ConflictCopies::default()));
+    try!(branch.commit_branch(branch_name));
+    Ok(deleted)
+}
+
+/// Remove the files at `deleted` (relative to `working_copy`),
+/// unless another file of the repository is now at the same path.
+fn remove_deleted_files<T>(repository: &Transaction<T>,
+                           working_copy: &Path,
+                           mut deleted: Vec<PathBuf>)
+                           -> Result<(), Error> {
+    let files: HashSet<PathBuf> = try!(list_files(repository)).into_iter().collect();
+    // Remove the contents of directories before the directories themselves.
+    deleted.sort();
+    for f in deleted.iter().rev() {
+        if files.contains(f) {
+            continue
+        }
+        let path = working_copy.join(f);
+        debug!("removing {:?}", path);
+        let result = match fs::symlink_metadata(&path) {
+            Ok(ref attr) if attr.is_dir() => fs::remove_dir(&path),
+            Ok(_) => fs::remove_file(&path),
+            Err(_) => Ok(()),
+        };
+        // Directories with untracked files in them are kept.
+        if let Err(e) = result {
+            debug!("could not remove {:?}: {:?}", path, e)
         }
     }
-    debug!("done unsafe_output_repository");
     Ok(())
 }
 
-
+/// Output branch `branch_name` to the working copy, along with the
+/// unrecorded changes in `pending`. Conflicts are written according
+/// to `style`.
 pub fn output_repository<T>(repository: &mut Transaction<T>,
                             branch_name: &str,
                             working_copy: &Path,
-                            pending: &Patch)
+                            pending: &Patch,
+                            style: ConflictStyle)
                             -> Result<(), Error> {
     debug!("begin output repository");
     // First output the repository to change the trees/inodes tables (and their revs).
-    // Do not output the files (do_output = false).
-    {
-        let branch = try!(repository.db_nodes(branch_name));
-        let db_contents = repository.db_contents();
-        let mut db_inodes = repository.db_inodes();
-        let mut db_revinodes = repository.db_inodes();
-        let mut db_tree = repository.db_tree();
-        let mut db_revtree = repository.db_revtree();
-
-        try!(unsafe_output_repository(&branch,
-                                      &db_contents,
-                                      &mut db_inodes,
-                                      &mut db_revinodes,
-                                      &mut db_tree,
-                                      &mut db_revtree,
-                                      working_copy,
-                                      false));
-        try!(branch.commit_branch(branch_name));
-    };
+    let deleted = try!(update_tree(repository, branch_name, working_copy));
+    try!(remove_deleted_files(repository, working_copy, deleted));
     // Then, apply pending and output in an aborted transaction.
+    let mut copies = try!(ConflictCopies::load(working_copy));
     let mut child_repository = try!(repository.child());
     let internal = new_internal(&mut child_repository);
     debug!("pending patch: {}", internal.to_hex());
@@ -550,19 +1269,24 @@ pub fn output_repository<T>(repository: &mut Transaction<T>,
         let branch = try!(child_repository.db_nodes(branch_name));
         let db_contents = child_repository.db_contents();
         let mut db_inodes = child_repository.db_inodes();
-        let mut db_revinodes = child_repository.db_inodes();
+        let mut db_revinodes = child_repository.db_revinodes();
         let mut db_tree = child_repository.db_tree();
         let mut db_revtree = child_repository.db_revtree();
+        let db_external = child_repository.db_external();
         try!(unsafe_output_repository(&branch,
                                       &db_contents,
                                       &mut db_inodes,
                                       &mut db_revinodes,
                                       &mut db_tree,
                                       &mut db_revtree,
+                                      &db_external,
                                       working_copy,
-                                      true));
+                                      true,
+                                      style,
+                                      &mut copies));
         try!(branch.commit_branch(branch_name));
     }
+    try!(copies.finish(&child_repository, working_copy));
     child_repository.abort();
     Ok(())
 }
@@ -49,7 +49,7 @@ extern crate flate2;
 
 use std::collections::BTreeMap;
 use super::fs_representation::{patch_path};
-use std::process::{Command};
+use super::signature::SigningKey;
 
 pub type FileIndex = HashMap<LocalKey, Inode>;
 
@@ -79,6 +79,24 @@ pub enum Change {
            edges:Vec<Edge> }
 }
 
+/// Patch files start with this magic number, followed by the version
+/// of their format (`PATCH_FORMAT_VERSION` when writing), as a
+/// big-endian 32-bit integer. The rest of the file is the patch,
+/// encoded in CBOR and compressed with gzip.
+///
+/// Files written before the header was introduced start directly with
+/// the gzip magic number, and are read as version 0. They are never
+/// rewritten, since that could change their hash (see `patch_hash`).
+///
+/// Versions 1 and 2 have the same contents, but the hash of version 2
+/// patches is computed by `Patch::hash`, instead of being the SHA-512
+/// of the file.
+pub const PATCH_MAGIC:&'static [u8]=b"pijulpatch";
+pub const PATCH_FORMAT_VERSION:u32=2;
+// PATCH_MAGIC, then the version.
+const PATCH_HEADER_SIZE:usize=10+4;
+const GZIP_MAGIC:[u8;2]=[0x1f,0x8b];
+
 #[derive(Debug,RustcEncodable,RustcDecodable)]
 pub struct Patch {
     pub authors:Vec<BTreeMap<String,Value>>,
@@ -113,24 +131,89 @@ impl Patch {
         let mut file=try!(File::open(&p));
         Patch::from_reader(&mut file,Some(&p))
     }
+    /// Read a patch file, in any version of the format (see
+    /// `PATCH_MAGIC`). `p` is only used in error messages.
     pub fn from_reader<R>(r:R,p:Option<&Path>)->Result<Patch,Error> where R:Read {
-        let d=try!(flate2::read::GzDecoder::new(r));
-        let mut d=cbor::Decoder::from_reader(d);
-        if let Some(d)=d.decode().next() {
-            Ok(try!(d))
-        } else {
-            Err(Error::NothingToDecode(p.and_then(|p| Some(p.to_path_buf()))))
+        Patch::from_reader_versioned(r,p).map(|(_,patch)| patch)
+    }
+
+    /// Like `from_reader`, but also returns the version of the format.
+    fn from_reader_versioned<R>(mut r:R,p:Option<&Path>)->Result<(u32,Patch),Error> where R:Read {
+        let invalid=|msg:&str| Error::InvalidPatch(p.map(|p| p.to_path_buf()),msg.to_string());
+        let mut start=[0;2];
+        try!(r.read_exact(&mut start).map_err(|_| invalid("file too short")));
+        if start==GZIP_MAGIC {
+            return Patch::decode_version(0,(&start[..]).chain(r),p)
+        }
+        let mut header=[0;PATCH_HEADER_SIZE-2];
+        try!(r.read_exact(&mut header).map_err(|_| invalid("file too short")));
+        if &start[..]!=&PATCH_MAGIC[..2] || &header[..PATCH_MAGIC.len()-2]!=&PATCH_MAGIC[2..] {
+            return Err(invalid("not a patch file"))
         }
+        let v=&header[PATCH_MAGIC.len()-2..];
+        let version=((v[0] as u32)<<24) | ((v[1] as u32)<<16) | ((v[2] as u32)<<8) | (v[3] as u32);
+        Patch::decode_version(version,r,p)
     }
 
+    /// Decode the contents of a patch file in format `version`, after
+    /// the header. This is where patches written in older versions
+    /// are converted to the current `Patch`: versions 1 and 2 only
+    /// changed the header and the hash, so version 0 needs no
+    /// conversion yet.
+    fn decode_version<R>(version:u32,r:R,p:Option<&Path>)->Result<(u32,Patch),Error> where R:Read {
+        match version {
+            0 | 1 | 2 => {
+                let d=try!(flate2::read::GzDecoder::new(r));
+                let mut d=cbor::Decoder::from_reader(d);
+                match d.decode::<Patch>().next() {
+                    Some(Ok(patch))=>Ok((version,patch)),
+                    Some(Err(e))=>Err(Error::InvalidPatch(p.map(|p| p.to_path_buf()),
+                                                          format!("version {}: {}",version,e))),
+                    None=>Err(Error::NothingToDecode(p.and_then(|p| Some(p.to_path_buf()))))
+                }
+            },
+            _ => Err(Error::UnknownPatchVersion(p.map(|p| p.to_path_buf()),version))
+        }
+    }
+
+    /// Write this patch in the current version of the format.
     pub fn to_writer<W>(&self,w:&mut W)->Result<(),Error> where W:Write {
+        try!(w.write_all(PATCH_MAGIC));
+        let v=PATCH_FORMAT_VERSION;
+        try!(w.write_all(&[(v>>24) as u8,(v>>16) as u8,(v>>8) as u8,v as u8]));
         let e = flate2::write::GzEncoder::new(w,flate2::Compression::Best);
         let mut e = cbor::Encoder::from_writer(e);
         try!(self.encode(&mut e));
         //try!(bincode::rustc_serialize::encode_into(self,w,SizeLimit::Infinite).map_err(Error::PatchEncoding));
         Ok(())
     }
-    pub fn save(&self,dir:&Path)->Result<Vec<u8>,Error>{
+    /// Hash of this patch, in the current version of the format: the
+    /// SHA-512 of the CBOR encoding of its fields, in the order of
+    /// their declaration, with the dependencies sorted. It does not
+    /// depend on how the patch file is compressed.
+    pub fn hash(&self)->Result<Vec<u8>,Error> {
+        let mut dependencies:Vec<&ExternalHash>=self.dependencies.iter().collect();
+        dependencies.sort();
+        let canonical=CanonicalPatch {
+            authors:&self.authors,
+            name:&self.name,
+            description:&self.description,
+            timestamp:&self.timestamp,
+            dependencies:dependencies,
+            changes:&self.changes
+        };
+        let mut hasher=HashWriter { hasher:Sha512::new() };
+        {
+            let mut e=cbor::Encoder::from_writer(&mut hasher);
+            try!(canonical.encode(&mut e));
+        }
+        let mut hash=vec![0;hasher.hasher.output_bytes()];
+        hasher.hasher.result(&mut hash);
+        Ok(hash)
+    }
+    /// Write this patch to directory `dir`, named after its hash, and
+    /// sign it with `key`, if any. Returns the hash.
+    pub fn save(&self,dir:&Path,key:Option<&SigningKey>)->Result<Vec<u8>,Error>{
         debug!("saving patch");
         let mut name:[u8;20]=[0;20]; // random name initially
         fn make_name(dir:&Path,name:&mut [u8])->std::path::PathBuf{
@@ -143,41 +226,12 @@ impl Patch {
             let mut buffer = BufWriter::new(try!(File::create(&tmp)));
             try!(self.to_writer(&mut buffer));
         }
+        let hash=try!(self.hash());
         // Sign
-        let tmp_gpg=tmp.with_extension("gpg");
-        let gpg=Command::new("gpg")
-            .arg("--batch")
-            .arg("--output")
-            .arg(&tmp_gpg)
-            .arg("--detach-sig")
-            .arg(&tmp)
-            .spawn();
-
-        // hash
-        let mut hasher = Sha512::new();
-        {
-            let mut buffer = BufReader::new(try!(File::open(&tmp)));
-            loop {
-                let len= {
-                    let buf=try!(buffer.fill_buf());
-                    if buf.len()==0 { break } else {
-                        hasher.input(buf);buf.len()
-                    }
-                };
-                buffer.consume(len)
-            }
+        if let Some(key)=key {
+            try!(key.sign_patch(dir,&hash));
         }
-        let mut hash=vec![0;hasher.output_bytes()];
-        hasher.result(&mut hash);
         let mut f=dir.join(hash.to_hex());
-        if let Ok(true)=gpg.and_then(|mut gpg| {
-            let stat=try!(gpg.wait());
-            Ok(stat.success())
-        }) {
-            f.set_extension("cbor.sig");
-            try!(std::fs::rename(&tmp_gpg,&f));
-        }
-        f.set_extension("");
         f.set_extension("cbor.gz");
         try!(std::fs::rename(&tmp,&f));
         debug!("/saving patch");
@@ -185,8 +239,90 @@ impl Patch {
     }
 }
 
+/// The fields of a `Patch`, in a deterministic order.
+#[derive(RustcEncodable)]
+struct CanonicalPatch<'a> {
+    authors:&'a Vec<BTreeMap<String,Value>>,
+    name:&'a String,
+    description:&'a Option<String>,
+    timestamp:&'a String,
+    dependencies:Vec<&'a ExternalHash>,
+    changes:&'a Vec<Change>
+}
+
+struct HashWriter {
+    hasher:Sha512
+}
+
+impl Write for HashWriter {
+    fn write(&mut self,buf:&[u8])->std::io::Result<usize> {
+        self.hasher.input(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self)->std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Read the patch in file `path`, and compute its hash: `Patch::hash`
+/// for patches in version 2 of the format or later, and the SHA-512 of
+/// the file for older ones, since that is how they were named.
+pub fn patch_hash(path:&Path)->Result<(Patch,Vec<u8>),Error> {
+    let (version,patch)={
+        let file=try!(File::open(path));
+        try!(Patch::from_reader_versioned(BufReader::new(file),Some(path)))
+    };
+    let hash=if version>=2 { try!(patch.hash()) } else { try!(file_hash(path)) };
+    Ok((patch,hash))
+}
+
+/// Version of the format of the patch file at `path`.
+pub fn patch_version(path:&Path)->Result<u32,Error> {
+    let file=try!(File::open(path));
+    Ok(try!(Patch::from_reader_versioned(BufReader::new(file),Some(path))).0)
+}
+
+/// Write `file`, the contents of a patch file in any known version
+/// of the format, unchanged to directory `dir`, named after its hash
+/// as in `patch_hash`. Returns the patch and its hash.
+pub fn save_file(dir:&Path,file:&[u8])->Result<(Patch,Vec<u8>),Error> {
+    let (version,patch)=try!(Patch::from_reader_versioned(file,None));
+    let hash=if version>=2 {
+        try!(patch.hash())
+    } else {
+        let mut hasher = Sha512::new();
+        hasher.input(file);
+        let mut hash=vec![0;hasher.output_bytes()];
+        hasher.result(&mut hash);
+        hash
+    };
+    let mut f=dir.join(hash.to_hex());
+    f.set_extension("cbor.gz");
+    try!(try!(File::create(&f)).write_all(file));
+    Ok((patch,hash))
+}
 
-pub fn write_changes(patches:&HashSet<&[u8]>,changes_file:&Path)->Result<(),Error>{
+/// SHA-512 of the file at `path`.
+fn file_hash(path:&Path)->Result<Vec<u8>,Error> {
+    let mut hasher = Sha512::new();
+    let mut buffer = BufReader::new(try!(File::open(path)));
+    loop {
+        let len= {
+            let buf=try!(buffer.fill_buf());
+            if buf.len()==0 { break } else {
+                hasher.input(buf);buf.len()
+            }
+        };
+        buffer.consume(len)
+    }
+    let mut hash=vec![0;hasher.output_bytes()];
+    hasher.result(&mut hash);
+    Ok(hash)
+}
+
+
+/// Write the hashes `patches` to `changes_file`, in this order.
+pub fn write_changes(patches:&[&[u8]],changes_file:&Path)->Result<(),Error>{
     let file=try!(File::create(changes_file));
     let mut buffer = BufWriter::new(file);
     let mut e = cbor::Encoder::from_writer(&mut buffer);
@@ -210,6 +346,17 @@ pub fn read_changes_from_file(changes_file:&Path)->Result<HashSet<Vec<u8>>,Error
     let r = BufReader::new(file);
     read_changes(r,Some(changes_file))
 }
+/// The hashes of `changes_file`, in the order in which they were
+/// written, which is the order in which the patches were applied.
+pub fn read_ordered_changes_from_file(changes_file:&Path)->Result<Vec<Vec<u8>>,Error> {
+    let file=try!(File::open(changes_file));
+    let mut d=cbor::Decoder::from_reader(BufReader::new(file));
+    if let Some(d)=d.decode().next() {
+        Ok(try!(d))
+    } else {
+        Err(Error::NothingToDecode(Some(changes_file.to_path_buf())))
+    }
+}
 
 pub fn dependencies(changes:&[Change])->HashSet<ExternalHash> {
     let mut deps=HashSet::new();
@@ -299,13 +446,13 @@ use super::backend::*;
 /// Gets the external key corresponding to the given key, returning an
 /// owned vector. If the key is just a patch id, it returns the
 /// corresponding external hash.
-pub fn external_key<T>(ext:&Db<T>,key:&[u8])->ExternalKey {
+pub fn external_key<T,R:RawTxn>(ext:&Db<T,R>,key:&[u8])->ExternalKey {
     let mut result= external_hash(ext, &key[0..HASH_SIZE]).to_vec();
     if key.len()==KEY_SIZE { result.extend(&key[HASH_SIZE..KEY_SIZE]) };
     result
 }
 
-pub fn external_hash<'a,'b,T>(ext:&'a Db<'a,'b,T>,key:&[u8])->&'a [u8] {
+pub fn external_hash<'a,'b,T,R:RawTxn>(ext:&'a Db<'a,'b,T,R>,key:&[u8])->&'a [u8] {
     //println!("internal key:{:?}",&key[0..HASH_SIZE]);
     if key.len()>=HASH_SIZE
         && unsafe {memcmp(key.as_ptr() as *const c_void,ROOT_KEY.as_ptr() as *const c_void,HASH_SIZE as size_t)}==0 {
@@ -326,7 +473,7 @@ pub fn external_hash<'a,'b,T>(ext:&'a Db<'a,'b,T>,key:&[u8])->&'a [u8] {
 }
 
 
-pub fn internal_hash<'a,T>(internal:&'a Db<T>,key:&[u8])->Result<&'a InternalKey,Error> {
+pub fn internal_hash<'a,T,R:RawTxn>(internal:&'a Db<T,R>,key:&[u8])->Result<&'a InternalKey,Error> {
     debug!("internal_hash: {}, {}",key.to_hex(), key.len());
     if key.len()==HASH_SIZE
         && unsafe { memcmp(key.as_ptr() as *const c_void,ROOT_KEY.as_ptr() as *const c_void,HASH_SIZE as size_t) }==0 {
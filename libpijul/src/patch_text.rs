@@ -0,0 +1,461 @@
+// Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+//
+// This file is part of Pijul.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! A plain-text representation of patches, to review them or send
+//! them by email. It holds exactly the fields of a `Patch`, so that
+//! reading it back gives the same patch, with the same `Patch::hash`.
+//!
+//! ```text
+//! pijul text patch 1
+//! name "Fix the frobnicator"
+//! description "First line\nSecond line"
+//! timestamp "2016-05-01T12:00:00+02:00"
+//! author
+//!     "name" "Jane Doe"
+//! dependency 0a1b...
+//! new-nodes flag none line-num 3
+//!     up 0a1b...00000001
+//!     down -00000002
+//!     node "a new line\n"
+//! edges flag parent|deleted
+//!     edge 0a1b...00000001 -00000003 0a1b...
+//! ```
+//!
+//! The first line is the version of the format. Then come the name,
+//! the description (absent if there is none), the timestamp, the
+//! authors, each followed by its indented fields, the dependencies,
+//! and the changes, in order. A `new-nodes` change lists its up and
+//! down contexts and then its nodes, an `edges` change its edges, as
+//! the keys of their origin, target and introducing patch.
+//!
+//! Strings are between double quotes, with escapes `\\`, `\"`, `\n`,
+//! `\r`, `\t`, and `\xHH` for other control characters and for bytes
+//! that are not valid UTF-8. Hashes and keys are in hexadecimal; keys
+//! of nodes introduced by the patch itself start with `-` instead of a
+//! hash. Flags are `none`, or names of edge flags separated by `|`.
+//! Empty lines and lines starting with `#` are ignored.
+//!
+//! Patches in versions of the format older than 2 are named after the
+//! hash of their file rather than of their fields, and that file
+//! cannot be rebuilt from the fields. For these patches,
+//! `to_text_with_file` writes the whole file as a `file` line in
+//! hexadecimal, right after the first line, so that importing the
+//! text gives back the same file, with the same hash.
+
+use super::error::Error;
+use super::patch::{Patch, Change, Edge, Value, Flag, LINE_SIZE};
+use super::graph::{PSEUDO_EDGE, FOLDER_EDGE, PARENT_EDGE, DELETED_EDGE};
+
+use std::io::{Read, Write};
+use std::collections::{BTreeMap, HashSet};
+use std::str::from_utf8;
+use rustc_serialize::hex::{ToHex, FromHex};
+
+const TEXT_HEADER: &'static str = "pijul text patch 1";
+
+const FLAG_NAMES: [(Flag, &'static str); 4] = [(PSEUDO_EDGE, "pseudo"),
+                                               (FOLDER_EDGE, "folder"),
+                                               (PARENT_EDGE, "parent"),
+                                               (DELETED_EDGE, "deleted")];
+
+fn quote(s: &[u8]) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    let mut rest = s;
+    while !rest.is_empty() {
+        let valid = match from_utf8(rest) {
+            Ok(_) => rest.len(),
+            Err(e) => e.valid_up_to(),
+        };
+        for c in from_utf8(&rest[..valid]).unwrap().chars() {
+            match c {
+                '"' => result.push_str("\\\""),
+                '\\' => result.push_str("\\\\"),
+                '\n' => result.push_str("\\n"),
+                '\r' => result.push_str("\\r"),
+                '\t' => result.push_str("\\t"),
+                c if c < ' ' || c == '\x7f' => result.push_str(&format!("\\x{:02x}", c as u8)),
+                c => result.push(c),
+            }
+        }
+        if valid < rest.len() {
+            result.push_str(&format!("\\x{:02x}", rest[valid]));
+            rest = &rest[valid + 1..]
+        } else {
+            break
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Hexadecimal representation of a key, where the hash of the patch
+/// itself (which is empty) is written `-`.
+fn key_to_text(key: &[u8]) -> String {
+    if key.len() <= LINE_SIZE {
+        format!("-{}", key.to_hex())
+    } else {
+        key.to_hex()
+    }
+}
+
+fn flag_to_text(flag: Flag) -> String {
+    let mut names = Vec::new();
+    let mut rest = flag;
+    for &(f, name) in FLAG_NAMES.iter() {
+        if flag & f != 0 {
+            names.push(name.to_string());
+            rest ^= f
+        }
+    }
+    if rest != 0 {
+        names.push(format!("{}", rest))
+    }
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join("|")
+    }
+}
+
+impl Patch {
+    /// Write this patch in the text format described in module
+    /// `patch_text`.
+    pub fn to_text<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        self.to_text_with_file(w, None)
+    }
+
+    /// Like `to_text`, but also write `file`, the patch file this
+    /// patch was read from, if any.
+    pub fn to_text_with_file<W: Write>(&self, w: &mut W, file: Option<&[u8]>) -> Result<(), Error> {
+        try!(writeln!(w, "{}", TEXT_HEADER));
+        if let Some(file) = file {
+            try!(writeln!(w, "file {}", file.to_hex()));
+        }
+        try!(writeln!(w, "name {}", quote(self.name.as_bytes())));
+        if let Some(ref description) = self.description {
+            try!(writeln!(w, "description {}", quote(description.as_bytes())));
+        }
+        try!(writeln!(w, "timestamp {}", quote(self.timestamp.as_bytes())));
+        for author in self.authors.iter() {
+            try!(writeln!(w, "author"));
+            for (k, v) in author.iter() {
+                let Value::String(ref v) = *v;
+                try!(writeln!(w, "    {} {}", quote(k.as_bytes()), quote(v.as_bytes())));
+            }
+        }
+        let mut dependencies: Vec<_> = self.dependencies.iter().collect();
+        dependencies.sort();
+        for d in dependencies {
+            try!(writeln!(w, "dependency {}", d.to_hex()));
+        }
+        for change in self.changes.iter() {
+            match *change {
+                Change::NewNodes { ref up_context, ref down_context, flag, line_num, ref nodes } => {
+                    try!(writeln!(w, "new-nodes flag {} line-num {}", flag_to_text(flag), line_num));
+                    for c in up_context.iter() {
+                        try!(writeln!(w, "    up {}", key_to_text(c)));
+                    }
+                    for c in down_context.iter() {
+                        try!(writeln!(w, "    down {}", key_to_text(c)));
+                    }
+                    for n in nodes.iter() {
+                        try!(writeln!(w, "    node {}", quote(n)));
+                    }
+                }
+                Change::Edges { flag, ref edges } => {
+                    try!(writeln!(w, "edges flag {}", flag_to_text(flag)));
+                    for e in edges.iter() {
+                        try!(writeln!(w,
+                                      "    edge {} {} {}",
+                                      key_to_text(&e.from),
+                                      key_to_text(&e.to),
+                                      key_to_text(&e.introduced_by)));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a patch written by `to_text`.
+    pub fn from_text<R: Read>(r: R) -> Result<Patch, Error> {
+        Patch::from_text_with_file(r).map(|(patch, _)| patch)
+    }
+
+    /// Read a patch written by `to_text_with_file`, along with its
+    /// file, if there is one.
+    pub fn from_text_with_file<R: Read>(mut r: R) -> Result<(Patch, Option<Vec<u8>>), Error> {
+        let mut contents = String::new();
+        try!(r.read_to_string(&mut contents));
+        parse(&contents).map_err(|(line, msg)| Error::InvalidPatch(None, format!("line {}: {}", line, msg)))
+    }
+}
+
+enum Token<'a> {
+    Word(&'a str),
+    Quoted(Vec<u8>),
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let bytes = line.as_bytes();
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b' ' || bytes[i] == b'\t' {
+            i += 1
+        } else if bytes[i] == b'"' {
+            let mut s = Vec::new();
+            i += 1;
+            loop {
+                if i >= bytes.len() {
+                    return Err("unterminated string".to_string());
+                }
+                match bytes[i] {
+                    b'"' => break,
+                    b'\\' if i + 1 < bytes.len() => {
+                        match bytes[i + 1] {
+                            b'\\' => s.push(b'\\'),
+                            b'"' => s.push(b'"'),
+                            b'n' => s.push(b'\n'),
+                            b'r' => s.push(b'\r'),
+                            b't' => s.push(b'\t'),
+                            b'x' if i + 3 < bytes.len() => {
+                                match from_utf8(&bytes[i + 2..i + 4]).ok().and_then(|h| h.from_hex().ok()) {
+                                    Some(b) => s.push(b[0]),
+                                    None => return Err("invalid escape".to_string()),
+                                }
+                                i += 2
+                            }
+                            _ => return Err("invalid escape".to_string()),
+                        }
+                        i += 2
+                    }
+                    b => {
+                        s.push(b);
+                        i += 1
+                    }
+                }
+            }
+            result.push(Token::Quoted(s));
+            i += 1
+        } else {
+            let start = i;
+            while i < bytes.len() && bytes[i] != b' ' && bytes[i] != b'\t' {
+                i += 1
+            }
+            result.push(Token::Word(&line[start..i]))
+        }
+    }
+    Ok(result)
+}
+
+fn bytes(t: &Token) -> Result<Vec<u8>, String> {
+    match *t {
+        Token::Quoted(ref s) => Ok(s.clone()),
+        Token::Word(w) => Err(format!("expected a string, found {:?}", w)),
+    }
+}
+
+fn string(t: &Token) -> Result<String, String> {
+    String::from_utf8(try!(bytes(t))).map_err(|_| "invalid UTF-8".to_string())
+}
+
+fn word<'a>(t: &Token<'a>) -> Result<&'a str, String> {
+    match *t {
+        Token::Word(w) => Ok(w),
+        Token::Quoted(_) => Err("unexpected string".to_string()),
+    }
+}
+
+fn key(t: &Token) -> Result<Vec<u8>, String> {
+    let w = try!(word(t));
+    let key = if w.starts_with("-") { &w[1..] } else { w };
+    key.from_hex().map_err(|_| format!("invalid key {:?}", w))
+}
+
+fn flag(t: &Token) -> Result<Flag, String> {
+    let w = try!(word(t));
+    let mut flag = 0;
+    if w == "none" {
+        return Ok(flag);
+    }
+    for name in w.split('|') {
+        match FLAG_NAMES.iter().find(|&&(_, n)| n == name) {
+            Some(&(f, _)) => flag |= f,
+            None => flag |= try!(name.parse::<Flag>().map_err(|_| format!("invalid flag {:?}", name))),
+        }
+    }
+    Ok(flag)
+}
+
+/// Check that `tokens` has `n` arguments after `keyword`.
+fn arguments<'a, 'b>(tokens: &'b [Token<'a>], keyword: &str, n: usize) -> Result<&'b [Token<'a>], String> {
+    if tokens.len() == n + 1 {
+        Ok(&tokens[1..])
+    } else {
+        Err(format!("{} takes {} arguments", keyword, n))
+    }
+}
+
+/// The fields of a patch, as they are read.
+#[derive(Default)]
+struct Parser {
+    name: Option<String>,
+    description: Option<String>,
+    timestamp: Option<String>,
+    authors: Vec<BTreeMap<String, Value>>,
+    dependencies: HashSet<Vec<u8>>,
+    changes: Vec<Change>,
+    file: Option<Vec<u8>>,
+    // Whether indented lines are fields of the last author (else
+    // parts of the last change).
+    in_author: bool,
+}
+
+fn parse(contents: &str) -> Result<(Patch, Option<Vec<u8>>), (usize, String)> {
+    let mut lines = contents.lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l))
+        .filter(|&(_, l)| !l.trim().is_empty() && !l.trim_left().starts_with("#"));
+    match lines.next() {
+        Some((_, l)) if l.trim() == TEXT_HEADER => {}
+        Some((i, _)) => return Err((i, format!("expected {:?}", TEXT_HEADER))),
+        None => return Err((0, "empty patch".to_string())),
+    }
+    let mut parser = Parser::default();
+    for (i, line) in lines {
+        let tokens = try!(tokenize(line).map_err(|e| (i, e)));
+        if tokens.is_empty() {
+            continue;
+        }
+        let indented = line.starts_with(" ") || line.starts_with("\t");
+        // Author fields start with a string, not a keyword.
+        let result = if indented && parser.in_author {
+            parse_author_field(&tokens, &mut parser.authors)
+        } else {
+            let keyword = try!(word(&tokens[0]).map_err(|e| (i, e)));
+            if indented {
+                parse_change_part(keyword, &tokens, &mut parser.changes)
+            } else {
+                parser.parse_line(keyword, &tokens)
+            }
+        };
+        try!(result.map_err(|e| (i, e)))
+    }
+    match (parser.name, parser.timestamp) {
+        (Some(name), Some(timestamp)) => {
+            Ok((Patch {
+                authors: parser.authors,
+                name: name,
+                description: parser.description,
+                timestamp: timestamp,
+                dependencies: parser.dependencies,
+                changes: parser.changes,
+            }, parser.file))
+        }
+        (None, _) => Err((0, "missing name".to_string())),
+        (_, None) => Err((0, "missing timestamp".to_string())),
+    }
+}
+
+impl Parser {
+    fn parse_line(&mut self, keyword: &str, tokens: &[Token]) -> Result<(), String> {
+        self.in_author = false;
+        match keyword {
+            "name" => self.name = Some(try!(string(&try!(arguments(tokens, keyword, 1))[0]))),
+            "description" => self.description = Some(try!(string(&try!(arguments(tokens, keyword, 1))[0]))),
+            "timestamp" => self.timestamp = Some(try!(string(&try!(arguments(tokens, keyword, 1))[0]))),
+            "file" => {
+                let file = try!(word(&try!(arguments(tokens, keyword, 1))[0]));
+                self.file = Some(try!(file.from_hex().map_err(|_| "invalid file".to_string())))
+            }
+            "author" => {
+                try!(arguments(tokens, keyword, 0));
+                self.authors.push(BTreeMap::new());
+                self.in_author = true
+            }
+            "dependency" => {
+                let hash = try!(key(&try!(arguments(tokens, keyword, 1))[0]));
+                self.dependencies.insert(hash);
+            }
+            "new-nodes" => {
+                let args = try!(arguments(tokens, keyword, 4));
+                if try!(word(&args[0])) != "flag" || try!(word(&args[2])) != "line-num" {
+                    return Err("expected new-nodes flag <flag> line-num <number>".to_string());
+                }
+                let line_num = try!(try!(word(&args[3])).parse().map_err(|_| "invalid line number".to_string()));
+                self.changes.push(Change::NewNodes {
+                    up_context: Vec::new(),
+                    down_context: Vec::new(),
+                    flag: try!(flag(&args[1])),
+                    line_num: line_num,
+                    nodes: Vec::new(),
+                })
+            }
+            "edges" => {
+                let args = try!(arguments(tokens, keyword, 2));
+                if try!(word(&args[0])) != "flag" {
+                    return Err("expected edges flag <flag>".to_string());
+                }
+                self.changes.push(Change::Edges {
+                    flag: try!(flag(&args[1])),
+                    edges: Vec::new(),
+                })
+            }
+            _ => return Err(format!("unknown keyword {:?}", keyword)),
+        }
+        Ok(())
+    }
+}
+
+fn parse_author_field(tokens: &[Token], authors: &mut Vec<BTreeMap<String, Value>>) -> Result<(), String> {
+    if tokens.len() != 2 {
+        return Err("expected an author field and its value".to_string());
+    }
+    let k = try!(string(&tokens[0]));
+    let v = try!(string(&tokens[1]));
+    // Parser::parse_line pushed the author before we got here.
+    authors.last_mut().unwrap().insert(k, Value::String(v));
+    Ok(())
+}
+
+fn parse_change_part(keyword: &str, tokens: &[Token], changes: &mut Vec<Change>) -> Result<(), String> {
+    match (changes.last_mut(), keyword) {
+        (Some(&mut Change::NewNodes { ref mut up_context, .. }), "up") => {
+            up_context.push(try!(key(&try!(arguments(tokens, keyword, 1))[0])))
+        }
+        (Some(&mut Change::NewNodes { ref mut down_context, .. }), "down") => {
+            down_context.push(try!(key(&try!(arguments(tokens, keyword, 1))[0])))
+        }
+        (Some(&mut Change::NewNodes { ref mut nodes, .. }), "node") => {
+            nodes.push(try!(bytes(&try!(arguments(tokens, keyword, 1))[0])))
+        }
+        (Some(&mut Change::Edges { ref mut edges, .. }), "edge") => {
+            let args = try!(arguments(tokens, keyword, 3));
+            edges.push(Edge {
+                from: try!(key(&args[0])),
+                to: try!(key(&args[1])),
+                introduced_by: try!(key(&args[2])),
+            })
+        }
+        (None, _) => return Err(format!("{:?} outside of a change", keyword)),
+        _ => return Err(format!("unexpected {:?} in this change", keyword)),
+    }
+    Ok(())
+}
@@ -26,15 +26,27 @@ use super::diff;
 
 use std::collections::HashMap;
 use std::path::{PathBuf};
-use std::fs::metadata;
+use std::fs::symlink_metadata;
 use std;
-use std::io::BufRead;
+use std::io::{BufRead,Read};
 use rustc_serialize::hex::ToHex;
 
 #[cfg(not(windows))]
 use std::os::unix::fs::PermissionsExt;
 
 
+/// Permissions and kind of a file, as stored in its name node.
+fn file_attr(attr:&std::fs::Metadata, default_perms:usize)->usize {
+    if attr.file_type().is_symlink() {
+        // The permissions of a link are not used.
+        0o777 | SYMLINK_FLAG
+    } else {
+        let p=permissions(attr).unwrap_or(default_perms) & 0o777;
+        let is_dir= if attr.is_dir() { DIRECTORY_FLAG } else { 0 };
+        p | is_dir
+    }
+}
+
 #[cfg(not(windows))]
 fn permissions(attr:&std::fs::Metadata)->Option<usize> {
     Some(attr.permissions().mode() as usize)
@@ -60,20 +72,27 @@ struct RecordState {
     line_num: usize,
     updatables: HashMap<Vec<u8>, Inode >,
     actions: Vec<Change>,
+    // Path of the file each action comes from.
+    paths: Vec<PathBuf>,
     redundant: Vec<u8>
 }
 
-fn record_file_addition<T>(st : &mut RecordState, current_inode: Inode, parent_node: &[u8], db_external: &Db<T>, realpath: &mut std::path::PathBuf, basename: &[u8]) -> Option< [u8; LINE_SIZE]> {
+/// What `record` found besides the changes: the inodes of the files
+/// the changes add, and the pseudo-edges of the branch that turned
+/// out to be redundant while comparing it with the working copy.
+/// `apply_local_patch` registers the former and removes the latter.
+pub struct RecordUpdates {
+    pub inodes: HashMap<LocalKey,Inode>,
+    pub redundant: Vec<u8>
+}
+
+fn record_file_addition<T,R:RawTxn>(st : &mut RecordState, current_inode: Inode, parent_node: &[u8], db_external: &Db<T,R>, realpath: &mut std::path::PathBuf, basename: &[u8]) -> Result<Option<[u8; LINE_SIZE]>, Error> {
     let mut l2=[0;LINE_SIZE];
     // File addition, create appropriate Newnodes.
     debug!("metadata for {:?}", realpath);
-    match metadata(&realpath) {
+    match symlink_metadata(&realpath) {
         Ok(attr) => {
-            let int_attr={
-                let p=permissions(&attr).unwrap_or(0o755);
-                let is_dir= if attr.is_dir() { DIRECTORY_FLAG } else { 0 };
-                p | is_dir
-            };
+            let int_attr=file_attr(&attr, 0o755);
             let mut nodes=Vec::new();
             unsafe {
                 *(l2.as_mut_ptr() as *mut u32) = ((st.line_num+1) as u32).to_le()
@@ -105,13 +124,25 @@ fn record_file_addition<T>(st : &mut RecordState, current_inode: Inode, parent_n
             // Reading the file
             if !attr.is_dir() {
                 nodes.clear();
-                let mut line=Vec::new();
-                let f = std::fs::File::open(realpath.as_path());
-                let mut f = std::io::BufReader::new(f.unwrap());
-                loop {
-                    match f.read_until('\n' as u8,&mut line) {
-                        Ok(l) => if l>0 { nodes.push(line.clone());line.clear() } else { break },
-                        Err(_) => break
+                if attr.file_type().is_symlink() {
+                    // The contents of a link is its target, as a single node.
+                    nodes.push(try!(read_link_target(&realpath)))
+                } else {
+                    let mut contents=Vec::new();
+                    let mut f = try!(std::fs::File::open(realpath.as_path()));
+                    try!(f.read_to_end(&mut contents));
+                    if super::is_binary(&contents) {
+                        // Binary files are a single opaque node.
+                        nodes.push(contents)
+                    } else {
+                        let mut f = &contents[..];
+                        let mut line=Vec::new();
+                        loop {
+                            match f.read_until('\n' as u8,&mut line) {
+                                Ok(l) => if l>0 { nodes.push(line.clone());line.clear() } else { break },
+                                Err(_) => break
+                            }
+                        }
                     }
                 }
                 let len=nodes.len();
@@ -125,20 +156,23 @@ fn record_file_addition<T>(st : &mut RecordState, current_inode: Inode, parent_n
                     );
                 }
                 st.line_num+=len;
-                None
+                Ok(None)
             } else {
-                Some(l2)
+                Ok(Some(l2))
             }
         },
-        Err(_)=>{
-            panic!("error adding file {:?} (metadata failed)",realpath);
+        // The file was added, and then deleted.
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e)=>{
+            debug!("error adding file {:?} (metadata failed)",realpath);
+            Err(Error::IO(e))
         }
     }
 }
 
-fn record_moved_file<'c, 'b, 'a, T>(branch:&Branch<'c,'b,'a,T>, repository: &Transaction<'a, T>,
-                                    realpath: &mut std::path::PathBuf, db_contents: &Db<T>,
-                                    db_external: &Db<T>, st: &mut RecordState,
+fn record_moved_file<'c, 'b, 'a, T, R:RawTxn>(branch:&Branch<'c,'b,'a,T,R>, repository: &Transaction<'a,T,R>,
+                                    realpath: &mut std::path::PathBuf, db_contents: &Db<T,R>,
+                                    db_external: &Db<T,R>, st: &mut RecordState,
                                     parent_node: &[u8], current_node: &[u8], basename: &[u8], int_attr: usize,
                                     old_attr: usize) -> Result <(), Error>{
     // Delete all former names.
@@ -151,8 +185,8 @@ fn record_moved_file<'c, 'b, 'a, T>(branch:&Branch<'c,'b,'a,T>, repository: &Tra
     name.extend(basename);
     for parent in iterate_parents!(branch, &current_node[3..]) {
         debug!("iterate_parents: {:?}", parent.to_hex());
-        let mut contents_name: Contents<T> = Contents::from_slice(&name[..]);
-        let mut previous_name: Contents<T> =
+        let mut contents_name: Contents<T,R> = Contents::from_slice(&name[..]);
+        let mut previous_name: Contents<T,R> =
             match db_contents.contents(&parent[1..(1+KEY_SIZE)]) {
                 None=>Contents::from_slice(b""),
                 Some(n)=>n
@@ -208,7 +242,7 @@ fn record_moved_file<'c, 'b, 'a, T>(branch:&Branch<'c,'b,'a,T>, repository: &Tra
     Ok(())
 }
 
-fn record_deleted_file<T>(st: &mut RecordState, branch: &Branch<T>, current_node: &[u8], db_external: &Db<T>)
+fn record_deleted_file<T,R:RawTxn>(st: &mut RecordState, branch: &Branch<T,R>, current_node: &[u8], db_external: &Db<T,R>)
                           -> Result<(), Error> {
     debug!("record_deleted_file");
     let mut edges=Vec::new();
@@ -232,9 +266,16 @@ fn record_deleted_file<T>(st: &mut RecordState, branch: &Branch<T>, current_node
             if l.key.len()>0 {
                 let ext_key = external_key(&db_external, l.key);
                 debug!("ext_key={}",ext_key.to_hex());
-                for v in iterate_parents!(branch, l.key) {
-                    
+                // Lines of the file have parent edges, not folder edges.
+                let parents = branch.iter(l.key, Some(&[PARENT_EDGE][..]))
+                    .take_while(|&(k,parent)| k == l.key && parent[0] >= PARENT_EDGE && parent[0] <= FOLDER_EDGE|PARENT_EDGE|PSEUDO_EDGE)
+                    .map(|(_,b)| b);
+                for v in parents {
                     debug!("v={}",v.to_hex());
+                    if v[0] & FOLDER_EDGE == 0 && v[0] & PSEUDO_EDGE != 0 {
+                        // Pseudo-edges between lines are not in patches.
+                        continue
+                    }
                     if v[0] & FOLDER_EDGE != 0 { &mut edges } else { &mut file_edges }
                     .push(Edge { from: ext_key.clone(),
                                  to: external_key(&db_external, &v[1..(1+KEY_SIZE)]),
@@ -251,9 +292,9 @@ fn record_deleted_file<T>(st: &mut RecordState, branch: &Branch<T>, current_node
     Ok(())
 }
 
-fn record_all<'a,'b,'c,T> (
-    repository:&Transaction<'a,T>,
-    branch:&Branch<'c,'b,'a,T>,
+fn record_all<'a,'b,'c,T,R:RawTxn> (
+    repository:&Transaction<'a,T,R>,
+    branch:&Branch<'c,'b,'a,T,R>,
     st: &mut RecordState,
     parent: Option< (&[u8], Inode) >,
     current_inode: Inode,
@@ -275,11 +316,9 @@ fn record_all<'a,'b,'c,T> (
                     let old_attr=((current_node[1] as usize) << 8) | (current_node[2] as usize);
                     // Add the new name.
                     let (int_attr,deleted)={
-                        match metadata(&realpath) {
+                        match symlink_metadata(&realpath) {
                             Ok(attr)=>{
-                                let p=(permissions(&attr).unwrap_or(old_attr)) & 0o777;
-                                let is_dir= if attr.is_dir() { DIRECTORY_FLAG } else { 0 };
-                                ((p | is_dir),false)
+                                (file_attr(&attr, old_attr),false)
                             },
                             Err(_)=>{
                                 (old_attr,true)
@@ -312,7 +351,7 @@ fn record_all<'a,'b,'c,T> (
                     Some(&current_node[3..])
                 },
                 None=>{
-                    record_file_addition(st, current_inode, parent_node, &db_external, realpath, basename)
+                    try!(record_file_addition(st, current_inode, parent_node, &db_external, realpath, basename))
                         .map(|ref p| {l2 = p.clone(); &l2[..]})
                 }
             }
@@ -320,6 +359,9 @@ fn record_all<'a,'b,'c,T> (
             Some(ROOT_KEY)
         };
     debug!("current_node={:?}",current_node);
+    while st.paths.len() < st.actions.len() {
+        st.paths.push(realpath.clone())
+    }
     match current_node {
         None => (), // we just added a file
         Some(current_node)=>{
@@ -350,11 +392,23 @@ fn record_all<'a,'b,'c,T> (
     Ok(())
 }
 
-pub fn record<T>(repository:&Transaction<T>,branch_name:&str, working_copy:&std::path::Path)->Result<(Vec<Change>,HashMap<LocalKey,Inode>),Error>{
-    let mut branch = try!(repository.db_nodes(branch_name));
+/// Compute the changes between branch `branch_name` and the working
+/// copy. This only reads the repository, and can be run in a
+/// read-only transaction. Redundant pseudo-edges found during the
+/// diff are only returned in the `RecordUpdates`.
+pub fn record<T,R:RawTxn>(repository:&Transaction<T,R>,branch_name:&str, working_copy:&std::path::Path)->Result<(Vec<Change>,RecordUpdates),Error>{
+    let (changes, updatables) = try!(record_with_paths(repository, branch_name, working_copy));
+    Ok((changes.into_iter().map(|(_,c)| c).collect(), updatables))
+}
+
+/// Same as `record`, but also returns the path of the file each
+/// change comes from, relative to the root of the working copy.
+pub fn record_with_paths<T,R:RawTxn>(repository:&Transaction<T,R>,branch_name:&str, working_copy:&std::path::Path)->Result<(Vec<(PathBuf,Change)>,RecordUpdates),Error>{
+    let branch = try!(repository.db_nodes(branch_name));
     let mut st = RecordState {
         line_num: 1,
         actions: Vec::new(),
+        paths: Vec::new(),
         updatables : HashMap::new(),
         redundant : Vec::new(),
     };
@@ -365,9 +419,12 @@ pub fn record<T>(repository:&Transaction<T>,branch_name:&str, working_copy:&std:
                         &[]));
         debug!("record done, {} changes", st.actions.len());
     }
-    try!(super::graph::remove_redundant_edges(&mut branch, &mut st.redundant));
-    try!(branch.commit_branch(branch_name));
-    //repository.set_db_nodes(branch_name, branch);
-    debug!("remove_redundant_edges done");
-    Ok((st.actions,st.updatables))
+    let paths = st.paths.into_iter().map(|p| {
+        match p.strip_prefix(working_copy) {
+            Ok(p) => p.to_path_buf(),
+            Err(_) => p.clone()
+        }
+    });
+    let updates = RecordUpdates { inodes: st.updatables, redundant: st.redundant };
+    Ok((paths.zip(st.actions.into_iter()).collect(), updates))
 }
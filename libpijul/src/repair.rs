@@ -0,0 +1,83 @@
+// Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+//
+// This file is part of Pijul.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+use super::backend::*;
+use super::error::Error;
+use super::patch::read_ordered_changes_from_file;
+use super::apply::apply_with_dependencies;
+use super::output::update_inodes;
+use super::fs_representation::repo_dir;
+
+use std::path::{Path, PathBuf};
+use std::collections::HashSet;
+use std::fs;
+use rustc_serialize::hex::FromHex;
+
+/// Branches that have a changes file in `location`, along with the
+/// path of that file, sorted by branch name.
+pub fn changes_files(location: &Path) -> Result<Vec<(String, PathBuf)>, Error> {
+    let mut result = Vec::new();
+    for entry in try!(fs::read_dir(repo_dir(location))) {
+        let entry = try!(entry);
+        let file_name = entry.file_name();
+        let file_name = match file_name.to_str() {
+            Some(f) => f,
+            None => continue,
+        };
+        if !file_name.starts_with("changes.") {
+            continue;
+        }
+        // Other files starting with "changes." are not ours.
+        let name = match file_name["changes.".len()..].from_hex().ok().and_then(|n| String::from_utf8(n).ok()) {
+            Some(name) => name,
+            None => continue,
+        };
+        result.push((name, entry.path()))
+    }
+    result.sort();
+    Ok(result)
+}
+
+/// Rebuild a pristine from the patches directory of `location`:
+/// every patch listed in a changes file is applied again to its
+/// branch, in the order of that file, after its dependencies. The
+/// tree and inodes tables are then derived from branch
+/// `current_branch` and the working copy, whose files are left
+/// untouched.
+///
+/// `repository` is expected to be empty.
+pub fn rebuild<T>(repository: &mut Transaction<T>,
+                  location: &Path,
+                  current_branch: &str)
+                  -> Result<(), Error> {
+    for (branch_name, changes_file) in try!(changes_files(location)) {
+        // Replaying in the order of the changes file keeps the order
+        // in which patches were applied (see `ReadTxn::applied_patches`).
+        let patches = try!(read_ordered_changes_from_file(&changes_file));
+        debug!("rebuilding branch {:?}, {} patches", branch_name, patches.len());
+        let mut patches_were_applied = false;
+        for hash in patches.iter() {
+            try!(apply_with_dependencies(repository,
+                                         &branch_name,
+                                         location,
+                                         hash,
+                                         &mut patches_were_applied,
+                                         &HashSet::new()))
+        }
+    }
+    update_inodes(repository, current_branch, location)
+}
@@ -30,8 +30,13 @@ pub mod backend {
     use std::cell::UnsafeCell;
     use std;
     
-    pub struct Transaction<'env,T> {
-        txn: UnsafeCell<sanakirja::MutTxn<'env,T>>,
+    /// A transaction on the repository. By default, this is a mutable
+    /// transaction, of which there can be only one at a time. Read-only
+    /// transactions (see `ReadTransaction`) use a `sanakirja::Txn`
+    /// instead, and can run concurrently with each other and with the
+    /// mutable transaction.
+    pub struct Transaction<'env,T,R=sanakirja::MutTxn<'env,T>> {
+        txn: UnsafeCell<R>,
         db_tree: UnsafeCell<sanakirja::Db>,
         db_revtree: UnsafeCell<sanakirja::Db>,
         db_inodes: UnsafeCell<sanakirja::Db>,
@@ -42,35 +47,67 @@ pub mod backend {
         db_branches: UnsafeCell<sanakirja::Db>,
         db_revdep: UnsafeCell<sanakirja::Db>,
         db_nodes: UnsafeCell<sanakirja::Db>,
+        db_applied: UnsafeCell<sanakirja::Db>,
+        marker: PhantomData<(&'env (),T)>,
+    }
+
+    /// A read-only transaction.
+    pub type ReadTransaction<'env> = Transaction<'env,(),sanakirja::Txn<'env>>;
+
+    /// What differs between the underlying read-only and mutable
+    /// sanakirja transactions.
+    pub trait RawTxn:Tra {
+        /// Open the graph of branch `name`. Mutable transactions create
+        /// it if it does not exist yet.
+        fn open_branch(&mut self, db_nodes:&sanakirja::Db, name:&str) -> Result<sanakirja::Db,Error>;
+    }
+
+    impl<'env,T> RawTxn for sanakirja::MutTxn<'env,T> {
+        fn open_branch(&mut self, db_nodes:&sanakirja::Db, name:&str) -> Result<sanakirja::Db,Error> {
+            if let Some(branch) = self.open_db(db_nodes, name.as_bytes()) {
+                Ok(branch)
+            } else {
+                Ok(try!(self.create_db()))
+            }
+        }
+    }
+
+    impl<'env> RawTxn for sanakirja::Txn<'env> {
+        fn open_branch(&mut self, db_nodes:&sanakirja::Db, name:&str) -> Result<sanakirja::Db,Error> {
+            self.open_db(db_nodes, name.as_bytes()).ok_or(Error::BranchNotFound(name.to_string()))
+        }
     }
 
     pub const DEFAULT_BRANCH:&'static str = "main";
 
-    pub struct Contents<'a,'env:'a,T:'a> {value:sanakirja::Value<'a,sanakirja::MutTxn<'env,T>>}
+    pub struct Contents<'a,'env:'a,T:'a,R:'a=sanakirja::MutTxn<'env,T>> {
+        value:sanakirja::Value<'a,R>,
+        marker:PhantomData<(&'env (),T)>
+    }
 
-    impl<'a,'env,T> Iterator for Contents<'a,'env,T> {
+    impl<'a,'env,T,R:RawTxn> Iterator for Contents<'a,'env,T,R> {
         type Item = &'a[u8];
         fn next(&mut self) -> Option<Self::Item> {
             self.value.next()
         }
     }
-    impl<'a,'env,T> Len for Contents<'a,'env,T> {
+    impl<'a,'env,T,R:RawTxn> Len for Contents<'a,'env,T,R> {
         fn len(&self) -> usize {
             self.value.len() as usize
         }
     }
 
-    impl<'a,'env,T> Contents<'a,'env,T> {
-        pub fn from_slice(x:&'a [u8])->Contents<'a,'env,T> {
-            Contents { value:sanakirja::Value::from_slice(x) }
+    impl<'a,'env,T,R:RawTxn> Contents<'a,'env,T,R> {
+        pub fn from_slice(x:&'a [u8])->Contents<'a,'env,T,R> {
+            Contents { value:sanakirja::Value::from_slice(x), marker:PhantomData }
         }
-        pub fn clone(&self) -> Contents<'a,'env,T> {
-            Contents { value:self.value.clone() }
+        pub fn clone(&self) -> Contents<'a,'env,T,R> {
+            Contents { value:self.value.clone(), marker:PhantomData }
         }
     }
 
     pub struct Repository { env:sanakirja::Env }
-    #[derive(Debug,PartialEq)]
+    #[derive(Debug,PartialEq,Clone,Copy)]
     enum Root {
         TREE,
         REVTREE,
@@ -81,7 +118,8 @@ pub mod backend {
         EXTERNAL,
         BRANCHES,
         REVDEP,
-        NODES
+        NODES,
+        APPLIED
     }
 
     fn open_db<T>(txn:&mut sanakirja::MutTxn<T>, num:Root) -> Result<sanakirja::Db, sanakirja::Error> {
@@ -91,9 +129,80 @@ pub mod backend {
             txn.create_db()
         }
     }
+    const ROOTS:[Root;11] = [Root::TREE, Root::REVTREE, Root::INODES, Root::REVINODES, Root::CONTENTS,
+                             Root::INTERNAL, Root::EXTERNAL, Root::BRANCHES, Root::REVDEP, Root::NODES,
+                             Root::APPLIED];
+
     impl Repository {
+        /// Open the pristine at `path`. If it was written by an older
+        /// version, without all the tables read-only transactions
+        /// need, the missing ones are created first, in a mutable
+        /// transaction. Other pristines are only read.
         pub fn open<P:AsRef<Path>>(path:P) -> Result<Self,Error> {
-            Ok(Repository { env: try!(sanakirja::Env::new(path, 1<<30)) })
+            let repo = Repository { env: try!(sanakirja::Env::new(path, 1<<30)) };
+            let initialized = {
+                let txn = try!(repo.env.txn_begin());
+                ROOTS.iter().all(|r| txn.root(*r as usize).is_some())
+            };
+            if !initialized {
+                let txn = try!(repo.mut_txn_begin());
+                try!(txn.commit())
+            }
+            Ok(repo)
+        }
+
+        /// Create the pristine of a new repository at `path`, with all
+        /// its tables and an empty default branch.
+        pub fn create<P:AsRef<Path>>(path:P) -> Result<Self,Error> {
+            let repo = try!(Repository::open(path));
+            {
+                let txn = try!(repo.mut_txn_begin());
+                {
+                    let branch = try!(txn.db_nodes(DEFAULT_BRANCH));
+                    try!(branch.commit_branch(DEFAULT_BRANCH));
+                }
+                try!(txn.commit())
+            }
+            Ok(repo)
+        }
+
+        /// Start a read-only transaction. This does not wait for the
+        /// mutable transaction, if any, and sees the repository as it
+        /// was at its last commit.
+        pub fn txn_begin<'env>(&'env self) -> Result<ReadTransaction<'env>,Error> {
+            let txn = try!(self.env.txn_begin());
+            // `open` made all the roots.
+            if !ROOTS.iter().all(|r| txn.root(*r as usize).is_some()) {
+                return Err(Error::PristineNotInitialized)
+            }
+            let db_tree = txn.root(Root::TREE as usize).unwrap();
+            let db_revtree = txn.root(Root::REVTREE as usize).unwrap();
+            let db_inodes = txn.root(Root::INODES as usize).unwrap();
+            let db_revinodes = txn.root(Root::REVINODES as usize).unwrap();
+            let db_contents = txn.root(Root::CONTENTS as usize).unwrap();
+            let db_internal = txn.root(Root::INTERNAL as usize).unwrap();
+            let db_external = txn.root(Root::EXTERNAL as usize).unwrap();
+            let db_branches = txn.root(Root::BRANCHES as usize).unwrap();
+            let db_revdep = txn.root(Root::REVDEP as usize).unwrap();
+            let db_nodes = txn.root(Root::NODES as usize).unwrap();
+            let db_applied = txn.root(Root::APPLIED as usize).unwrap();
+
+            let repo = Transaction {
+                txn: UnsafeCell::new(txn),
+                db_tree: UnsafeCell::new(db_tree),
+                db_revtree: UnsafeCell::new(db_revtree),
+                db_inodes: UnsafeCell::new(db_inodes),
+                db_revinodes: UnsafeCell::new(db_revinodes),
+                db_contents: UnsafeCell::new(db_contents),
+                db_internal: UnsafeCell::new(db_internal),
+                db_external: UnsafeCell::new(db_external),
+                db_branches: UnsafeCell::new(db_branches),
+                db_revdep: UnsafeCell::new(db_revdep),
+                db_nodes: UnsafeCell::new(db_nodes),
+                db_applied: UnsafeCell::new(db_applied),
+                marker: PhantomData
+            };
+            Ok(repo)
         }
 
         pub fn mut_txn_begin<'env>(&'env self) -> Result<Transaction<'env,()>,Error> {
@@ -108,6 +217,7 @@ pub mod backend {
             let db_branches = try!(open_db(&mut txn, Root::BRANCHES));
             let db_revdep = try!(open_db(&mut txn, Root::REVDEP));
             let db_nodes = try!(open_db(&mut txn, Root::NODES));
+            let db_applied = try!(open_db(&mut txn, Root::APPLIED));
             
             let repo = Transaction {
                 txn: UnsafeCell::new(txn),
@@ -120,30 +230,32 @@ pub mod backend {
                 db_external: UnsafeCell::new(db_external),
                 db_branches: UnsafeCell::new(db_branches),
                 db_revdep: UnsafeCell::new(db_revdep),
-                db_nodes: UnsafeCell::new(db_nodes)
+                db_nodes: UnsafeCell::new(db_nodes),
+                db_applied: UnsafeCell::new(db_applied),
+                marker: PhantomData
             };
             Ok(repo)
         }
     }
 
-    pub struct Db<'txn,'env,T> {
+    pub struct Db<'txn,'env,T,R=sanakirja::MutTxn<'env,T>> {
         db: *mut sanakirja::Db,
-        txn:*mut sanakirja::MutTxn<'env,T>,
-        marker:PhantomData<&'txn()>,
+        txn:*mut R,
+        marker:PhantomData<(&'txn(),&'env(),T)>,
         name: Option<&'static str>
     }
 
-    pub struct Branch<'name,'txn,'env,T> {
+    pub struct Branch<'name,'txn,'env,T,R=sanakirja::MutTxn<'env,T>> {
         db: sanakirja::Db,
         name: &'name str,
         parent: *mut sanakirja::Db,
-        txn:*mut sanakirja::MutTxn<'env,T>,
-        marker:PhantomData<&'txn()>,
+        txn:*mut R,
+        marker:PhantomData<(&'txn(),&'env(),T)>,
     }
 
-    impl<'env,T> Transaction<'env,T>{
+    impl<'env,T,R:RawTxn> Transaction<'env,T,R>{
         
-        pub fn db_tree<'txn>(&'txn self) -> Db<'txn,'env,T> {
+        pub fn db_tree<'txn>(&'txn self) -> Db<'txn,'env,T,R> {
             Db { db:self.db_tree.get(),
                  txn:self.txn.get(),
                  marker:PhantomData,
@@ -151,7 +263,7 @@ pub mod backend {
             }
         }
 
-        pub fn db_revtree<'txn>(&'txn self) -> Db<'txn,'env,T> {
+        pub fn db_revtree<'txn>(&'txn self) -> Db<'txn,'env,T,R> {
             Db { db:self.db_revtree.get(),
                  txn:self.txn.get(),
                  marker:PhantomData,
@@ -159,7 +271,7 @@ pub mod backend {
             }
         }
 
-        pub fn db_inodes<'txn>(&'txn self) -> Db<'txn,'env,T> {
+        pub fn db_inodes<'txn>(&'txn self) -> Db<'txn,'env,T,R> {
             Db { db: self.db_inodes.get(),
                  txn: self.txn.get(),
                  marker:PhantomData,
@@ -167,7 +279,7 @@ pub mod backend {
             }
         }
 
-        pub fn db_revinodes<'txn>(&'txn self) -> Db<'txn,'env,T> {
+        pub fn db_revinodes<'txn>(&'txn self) -> Db<'txn,'env,T,R> {
             Db { db:self.db_revinodes.get(),
                  txn: self.txn.get(),
                  marker:PhantomData,
@@ -175,7 +287,7 @@ pub mod backend {
             }
         }
 
-        pub fn db_contents<'txn>(&'txn self) -> Db<'txn,'env,T> {
+        pub fn db_contents<'txn>(&'txn self) -> Db<'txn,'env,T,R> {
             Db { db: self.db_contents.get(),
                  txn: self.txn.get(),
                  marker:PhantomData,
@@ -183,7 +295,7 @@ pub mod backend {
             }
         }
 
-        pub fn db_revdep<'txn>(&'txn self) -> Db<'txn,'env,T> {
+        pub fn db_revdep<'txn>(&'txn self) -> Db<'txn,'env,T,R> {
             Db { db: self.db_revdep.get(),
                  txn: self.txn.get(),
                  marker:PhantomData,
@@ -191,15 +303,10 @@ pub mod backend {
             }
         }
 
-        pub fn db_nodes<'name,'txn,'a>(&'txn self, name:&'name str) -> Result<Branch<'name,'txn,'env,T>, Error> {
+        pub fn db_nodes<'name,'txn,'a>(&'txn self, name:&'name str) -> Result<Branch<'name,'txn,'env,T,R>, Error> {
             let txn = unsafe { &mut *self.txn.get() };
             let db_nodes = unsafe { &mut *self.db_nodes.get() };
-            let branch =
-                if let Some(branch) = txn.open_db(&db_nodes, name.as_bytes()) {
-                    branch
-                } else {
-                    try!(txn.create_db())
-                };
+            let branch = try!(txn.open_branch(&db_nodes, name));
             Ok(Branch { db: branch,
                         name: name,
                         txn: txn,
@@ -208,7 +315,22 @@ pub mod backend {
             })
         }
 
-        pub fn db_branches<'txn>(&'txn self) -> Db<'txn,'env,T> {
+        /// Names of the branches in this repository.
+        pub fn branch_names(&self) -> Vec<String> {
+            let txn = unsafe { &*self.txn.get() };
+            let db_nodes = unsafe { &*self.db_nodes.get() };
+            txn.iter(db_nodes, b"", None)
+                .map(|(k,_)| String::from_utf8_lossy(k).into_owned())
+                .collect()
+        }
+
+        pub fn has_branch(&self, name:&str) -> bool {
+            let txn = unsafe { &mut *self.txn.get() };
+            let db_nodes = unsafe { &*self.db_nodes.get() };
+            txn.open_db(db_nodes, name.as_bytes()).is_some()
+        }
+
+        pub fn db_branches<'txn>(&'txn self) -> Db<'txn,'env,T,R> {
             Db { db: self.db_branches.get(),
                  txn: self.txn.get(),
                  marker:PhantomData,
@@ -216,7 +338,19 @@ pub mod backend {
             }
         }
 
-        pub fn db_internal<'txn>(&'txn self) -> Db<'txn,'env,T> {
+        /// Patches applied on each branch, in application order: the
+        /// values of a branch name are a big-endian sequence number
+        /// followed by the patch's internal hash. The empty key holds
+        /// the next sequence number.
+        pub fn db_applied<'txn>(&'txn self) -> Db<'txn,'env,T,R> {
+            Db { db: self.db_applied.get(),
+                 txn: self.txn.get(),
+                 marker:PhantomData,
+                 name: Some("db_applied")
+            }
+        }
+
+        pub fn db_internal<'txn>(&'txn self) -> Db<'txn,'env,T,R> {
             Db { db: self.db_internal.get(),
                  txn: self.txn.get(),
                  marker:PhantomData,
@@ -224,7 +358,7 @@ pub mod backend {
             }
         }
 
-        pub fn db_external<'txn>(&'txn self) -> Db<'txn,'env,T> {
+        pub fn db_external<'txn>(&'txn self) -> Db<'txn,'env,T,R> {
             Db { db: self.db_external.get(),
                  txn: self.txn.get(),
                  marker:PhantomData,
@@ -232,6 +366,33 @@ pub mod backend {
             }
         }
 
+    }
+
+    impl<'env,T> Transaction<'env,T> {
+        /// Remove a branch's graph. The patches applied on it are not
+        /// unregistered from `db_branches`.
+        pub fn drop_branch(&mut self, name:&str) -> Result<(),Error> {
+            debug!("dropping branch {:?}", name);
+            // Empty the graph first, so that its pages are freed.
+            {
+                let mut branch = try!(self.db_nodes(name));
+                let mut edges = Vec::new();
+                for (k,v) in branch.iter(b"", None) {
+                    edges.push((k.to_vec(), v.to_vec()))
+                }
+                for &(ref k, ref v) in edges.iter() {
+                    try!(branch.del(k, Some(v)))
+                }
+            }
+            let mut rng = rand::thread_rng();
+            unsafe {
+                let txn = &mut *self.txn.get();
+                let db_nodes = &mut *self.db_nodes.get();
+                try!(txn.del(&mut rng, db_nodes, name.as_bytes(), None));
+            }
+            Ok(())
+        }
+
         #[cfg(debug_assertions)]
         fn debug_db(&self,txn:&sanakirja::MutTxn<T>, db0:&sanakirja::Db) {
             txn.debug(&[db0], "/tmp/dump_debugging", false, true);
@@ -251,7 +412,8 @@ pub mod backend {
                              (Root::EXTERNAL, &self.db_external),
                              (Root::BRANCHES, &self.db_branches),
                              (Root::REVDEP, &self.db_revdep),
-                             (Root::NODES, &self.db_nodes)];
+                             (Root::NODES, &self.db_nodes),
+                             (Root::APPLIED, &self.db_applied)];
             let txn = unsafe {&mut *self.txn.get() };
             let mut v_=Vec::new();
             for &(ref name,ref i) in databases.iter() {
@@ -307,7 +469,9 @@ pub mod backend {
                     db_external: UnsafeCell::new((&*self.db_external.get()).clone()),
                     db_branches: UnsafeCell::new((&*self.db_branches.get()).clone()),
                     db_revdep: UnsafeCell::new((&*self.db_revdep.get()).clone()),
-                    db_nodes: UnsafeCell::new((&* self.db_nodes.get()).clone())
+                    db_nodes: UnsafeCell::new((&* self.db_nodes.get()).clone()),
+                    db_applied: UnsafeCell::new((&*self.db_applied.get()).clone()),
+                    marker: PhantomData
                 };
                 Ok(repo)
             }
@@ -329,6 +493,7 @@ pub mod backend {
                 txn.set_root(Root::BRANCHES as usize, self.db_branches.into_inner());
                 txn.set_root(Root::REVDEP as usize, self.db_revdep.into_inner());
                 txn.set_root(Root::NODES as usize, self.db_nodes.into_inner());
+                txn.set_root(Root::APPLIED as usize, self.db_applied.into_inner());
 
                 try!(txn.commit());
                 Ok(())
@@ -340,6 +505,27 @@ pub mod backend {
 
 
 
+    impl<'txn,'env,T,R:RawTxn> Db<'txn,'env,T,R> {
+        pub fn get<'a>(&'a self, key:&[u8]) -> Option<&'a[u8]> {
+            unsafe {
+                let txn = &*self.txn;
+                txn.get(&*self.db, key, None).and_then(|mut x| Some(x.next().unwrap_or(b"")))
+            }
+        }
+        pub fn iter<'a>(&'a self, starting_key:&[u8], starting_value:Option<&[u8]>) -> Iter<'a,R> {
+            unsafe {
+                let txn = &*self.txn;
+                Iter { iter: txn.iter(&*self.db, starting_key, starting_value) }
+            }
+        }
+        pub fn contents<'a>(&'a self, key:&[u8]) -> Option<Contents<'a,'env,T,R>> {
+            unsafe {
+                let txn = &*self.txn;
+                txn.get(&*self.db, key, None).and_then(|x| Some(Contents { value:x, marker:PhantomData }))
+            }
+        }
+    }
+
     impl<'txn,'env,T> Db<'txn,'env,T> {
         
         pub fn put(&mut self, key:&[u8], value:&[u8]) -> Result<(),Error> {
@@ -371,32 +557,35 @@ pub mod backend {
             }
             Ok(())
         }
+    }
+
+
+
+    impl<'name,'txn,'env,T,R:RawTxn> Branch<'name,'txn,'env,T,R> {
+        pub fn name(&self) -> &'name str {
+            self.name
+        }
         pub fn get<'a>(&'a self, key:&[u8]) -> Option<&'a[u8]> {
             unsafe {
                 let txn = &*self.txn;
-                txn.get(&*self.db, key, None).and_then(|mut x| Some(x.next().unwrap_or(b"")))
+                txn.get(&self.db, key, None).and_then(|mut x| x.next())
             }
         }
-        pub fn iter<'a>(&'a self, starting_key:&[u8], starting_value:Option<&[u8]>) -> Iter<'a,sanakirja::MutTxn<'env,T>> {
+        pub fn iter<'a>(&'a self, starting_key:&[u8], starting_value:Option<&[u8]>) -> Iter<'a,R> {
             unsafe {
                 let txn = &*self.txn;
-                Iter { iter: txn.iter(&*self.db, starting_key, starting_value) }
+                Iter { iter: txn.iter(&self.db, starting_key, starting_value) }
             }
         }
-        pub fn contents<'a>(&'a self, key:&[u8]) -> Option<Contents<'a,'env,T>> {
+        pub fn contents<'a>(&'a self, key:&[u8]) -> Option<Contents<'a,'env,T,R>> {
             unsafe {
                 let txn = &*self.txn;
-                txn.get(&*self.db, key, None).and_then(|x| Some(Contents { value:x }))
+                txn.get(&self.db, key, None).and_then(|x| Some(Contents { value:x, marker:PhantomData }))
             }
         }
     }
 
-
-
     impl<'name,'txn,'env,T> Branch<'name,'txn,'env,T> {
-        pub fn name(&self) -> &'name str {
-            self.name
-        }
         pub fn put(&mut self, key:&[u8], value:&[u8]) -> Result<(),Error> {
             debug!("put branch {:?} {:?} {:?}", self.name, key.to_hex(), value.to_hex());
             let mut rng = rand::thread_rng();
@@ -415,24 +604,6 @@ pub mod backend {
             }
             Ok(())
         }
-        pub fn get<'a>(&'a self, key:&[u8]) -> Option<&'a[u8]> {
-            unsafe {
-                let txn = &*self.txn;
-                txn.get(&self.db, key, None).and_then(|mut x| x.next())
-            }
-        }
-        pub fn iter<'a>(&'a self, starting_key:&[u8], starting_value:Option<&[u8]>) -> Iter<'a,sanakirja::MutTxn<'env,T>> {
-            unsafe {
-                let txn = &*self.txn;
-                Iter { iter: txn.iter(&self.db, starting_key, starting_value) }
-            }
-        }
-        pub fn contents<'a>(&'a self, key:&[u8]) -> Option<Contents<'a,'env,T>> {
-            unsafe {
-                let txn = &*self.txn;
-                txn.get(&self.db, key, None).and_then(|x| Some(Contents { value:x }))
-            }
-        }
         pub fn commit_branch(self, name:&str) -> Result<(),Error> {
             unsafe {
                 let mut rng = rand::thread_rng();
@@ -447,7 +618,7 @@ pub mod backend {
 
 
     pub struct Iter<'a,T:'a> {iter:sanakirja::Iter<'a,T>}
-    impl<'a,'env,T> Iterator for Iter<'a,sanakirja::MutTxn<'env,T>> {
+    impl<'a,R:RawTxn> Iterator for Iter<'a,R> {
         type Item=(&'a[u8],&'a[u8]);
         fn next(&mut self)->Option<Self::Item> {
             if let Some((a,mut b)) = self.iter.next() {
@@ -0,0 +1,299 @@
+// Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+//
+// This file is part of Pijul.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Signing patches with Ed25519 keys, and verifying their signatures.
+//!
+//! The signature of patch `h` is stored next to it, in file
+//! `signature_path(h)`, as a single line of ASCII text:
+//!
+//! ```text
+//! ed25519 <public key> <signature>
+//! ```
+//!
+//! where `<public key>` is the 32 bytes of the public key, and
+//! `<signature>` the 64 bytes of the Ed25519 signature of the hash of
+//! the patch (i.e. of the 64 bytes returned by `patch_hash`), both in
+//! hexadecimal. Signature files in any other format are taken to be
+//! OpenPGP detached signatures, as written by older versions, and are
+//! checked with `gpg --verify`.
+//!
+//! The public key of the signer is also recorded in the patch itself,
+//! in hexadecimal, as the `key` field of its first author.
+//!
+//! Secret keys are stored in files containing the line
+//! `ed25519 <seed>`, where `<seed>` is the 32 bytes of the seed of the
+//! key, in hexadecimal.
+
+use super::error::Error;
+use super::fs_representation::{patch_path, signature_path};
+use super::patch::patch_hash;
+
+use std::path::{Path, MAIN_SEPARATOR};
+use std::process::Command;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::fmt;
+
+extern crate crypto;
+use self::crypto::ed25519;
+use rand::{OsRng, Rng};
+use rustc_serialize::hex::{ToHex, FromHex};
+
+const ED25519_PREFIX: &'static str = "ed25519 ";
+
+/// A secret key, used to sign patches.
+#[derive(Clone)]
+pub struct SigningKey {
+    seed: Vec<u8>,
+    secret: Vec<u8>,
+    public: Vec<u8>,
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print the secret.
+        write!(f, "SigningKey {{ public: {} }}", self.public.to_hex())
+    }
+}
+
+impl SigningKey {
+    fn from_seed(seed: &[u8]) -> SigningKey {
+        let (secret, public) = ed25519::keypair(seed);
+        SigningKey {
+            seed: seed.to_vec(),
+            secret: secret.to_vec(),
+            public: public.to_vec(),
+        }
+    }
+
+    /// A new random key.
+    pub fn generate() -> Result<SigningKey, Error> {
+        let mut seed = [0; 32];
+        try!(OsRng::new()).fill_bytes(&mut seed);
+        Ok(SigningKey::from_seed(&seed))
+    }
+
+    /// Load the key stored in file `path`.
+    pub fn load(path: &Path) -> Result<SigningKey, Error> {
+        let mut contents = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut contents));
+        let contents = contents.trim();
+        if contents.starts_with(ED25519_PREFIX) {
+            if let Ok(seed) = contents[ED25519_PREFIX.len()..].from_hex() {
+                if seed.len() == 32 {
+                    return Ok(SigningKey::from_seed(&seed));
+                }
+            }
+        }
+        Err(Error::InvalidSigningKey(path.to_path_buf()))
+    }
+
+    /// Store this key in a new file at `path`, readable only by its
+    /// owner. Existing files are not overwritten.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        owner_only(&mut options);
+        let mut f = try!(options.open(path));
+        try!(writeln!(f, "{}{}", ED25519_PREFIX, self.seed.to_hex()));
+        Ok(())
+    }
+
+    /// The public key, which verifies the signatures of this key.
+    pub fn public_key(&self) -> &[u8] {
+        &self.public
+    }
+
+    /// Sign the patch with hash `hash`, in patches directory `dir`.
+    pub fn sign_patch(&self, dir: &Path, hash: &[u8]) -> Result<(), Error> {
+        let signature = ed25519::signature(hash, &self.secret);
+        let mut f = try!(File::create(dir.join(hash.to_hex() + ".cbor.sig")));
+        try!(writeln!(f, "{}{} {}", ED25519_PREFIX, self.public.to_hex(), signature.to_hex()));
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn owner_only(options: &mut OpenOptions) {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600);
+}
+
+#[cfg(not(unix))]
+fn owner_only(_: &mut OpenOptions) {}
+
+/// What to do with patches that are not signed by a trusted key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignaturePolicy {
+    /// Do not look at signatures at all.
+    Ignore,
+    /// Apply the patch anyway, but report it.
+    Warn(Vec<String>),
+    /// Refuse to apply the patch.
+    Require(Vec<String>),
+}
+
+impl Default for SignaturePolicy {
+    fn default() -> Self {
+        SignaturePolicy::Ignore
+    }
+}
+
+impl SignaturePolicy {
+    /// Policy called `name` ("ignore", "warn" or "require"), trusting
+    /// the keys in `trusted_keys`.
+    pub fn from_name(name: &str, trusted_keys: Vec<String>) -> Option<SignaturePolicy> {
+        match name {
+            "ignore" => Some(SignaturePolicy::Ignore),
+            "warn" => Some(SignaturePolicy::Warn(trusted_keys)),
+            "require" => Some(SignaturePolicy::Require(trusted_keys)),
+            _ => None,
+        }
+    }
+
+    /// Whether `signature` satisfies this policy. Trusted keys are
+    /// full Ed25519 public keys or full OpenPGP fingerprints, compared
+    /// ignoring case and whitespace. An empty list of keys trusts no
+    /// signature.
+    pub fn trusts(&self, signature: &Signature) -> bool {
+        let trusted_keys = match *self {
+            SignaturePolicy::Ignore => return true,
+            SignaturePolicy::Warn(ref keys) |
+            SignaturePolicy::Require(ref keys) => keys,
+        };
+        match *signature {
+            Signature::Valid { ref key } => {
+                trusted_keys.iter().any(|k| {
+                    let k: String = k.chars().filter(|c| !c.is_whitespace()).collect();
+                    !k.is_empty() && key.to_uppercase() == k.to_uppercase()
+                })
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Outcome of the verification of a patch's signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signature {
+    /// The patch has no signature file.
+    Missing,
+    /// The signature does not match the patch, or its key is unknown.
+    Invalid,
+    /// A good signature, by the key `key`: the public key in
+    /// hexadecimal for Ed25519 signatures, the fingerprint for
+    /// OpenPGP ones.
+    Valid { key: String },
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Signature::Missing => write!(f, "not signed"),
+            Signature::Invalid => write!(f, "invalid signature"),
+            Signature::Valid { ref key } => write!(f, "signed by {}", key),
+        }
+    }
+}
+
+/// Verify the signature of patch `hash`, in repository `repo_root`.
+pub fn verify_signature(repo_root: &Path, hash: &[u8]) -> Result<Signature, Error> {
+    let sig = repo_root.join(signature_path(hash, MAIN_SEPARATOR));
+    let mut contents = Vec::new();
+    match File::open(&sig) {
+        Ok(mut f) => try!(f.read_to_end(&mut contents)),
+        Err(_) => return Ok(Signature::Missing),
+    };
+    let patch = repo_root.join(patch_path(hash, MAIN_SEPARATOR));
+    // The signature is about the hash, make sure the file really has it.
+    if &try!(patch_hash(&patch)).1[..] != hash {
+        return Ok(Signature::Invalid);
+    }
+    if contents.starts_with(ED25519_PREFIX.as_bytes()) {
+        Ok(verify_ed25519(&contents[ED25519_PREFIX.len()..], hash))
+    } else {
+        verify_gpg(&sig, &patch)
+    }
+}
+
+fn verify_ed25519(contents: &[u8], hash: &[u8]) -> Signature {
+    let contents = String::from_utf8_lossy(contents);
+    let mut words = contents.split_whitespace().map(|w| w.from_hex());
+    match (words.next(), words.next(), words.next()) {
+        (Some(Ok(public)), Some(Ok(signature)), None) => {
+            if public.len() == 32 && signature.len() == 64 &&
+               ed25519::verify(hash, &public, &signature) {
+                Signature::Valid { key: public.to_hex() }
+            } else {
+                Signature::Invalid
+            }
+        }
+        _ => Signature::Invalid,
+    }
+}
+
+/// Signatures written by older versions, with `gpg --detach-sig`.
+fn verify_gpg(sig: &Path, patch: &Path) -> Result<Signature, Error> {
+    let output = try!(Command::new("gpg")
+        .arg("--batch")
+        .arg("--status-fd")
+        .arg("1")
+        .arg("--verify")
+        .arg(sig)
+        .arg(patch)
+        .output());
+    debug!("gpg --verify: {:?}", output);
+    if !output.status.success() {
+        return Ok(Signature::Invalid);
+    }
+    // See doc/DETAILS in GnuPG for the format of status lines.
+    let status = String::from_utf8_lossy(&output.stdout);
+    for line in status.lines() {
+        let mut words = line.split_whitespace();
+        if words.next() == Some("[GNUPG:]") && words.next() == Some("VALIDSIG") {
+            if let Some(key) = words.next() {
+                return Ok(Signature::Valid { key: key.to_string() });
+            }
+        }
+    }
+    Ok(Signature::Invalid)
+}
+
+/// Check the signatures of `patches` against `policy`. With
+/// `SignaturePolicy::Require`, the first untrusted patch is an
+/// `Error::UntrustedPatch`, with `SignaturePolicy::Warn`, the
+/// untrusted patches are returned, along with their signatures.
+pub fn check_signatures<'a, I: Iterator<Item = &'a Vec<u8>>>(repo_root: &Path,
+                                                             patches: I,
+                                                             policy: &SignaturePolicy)
+                                                             -> Result<Vec<(Vec<u8>, Signature)>, Error> {
+    let mut untrusted = Vec::new();
+    if *policy == SignaturePolicy::Ignore {
+        return Ok(untrusted);
+    }
+    for hash in patches {
+        let signature = try!(verify_signature(repo_root, hash));
+        if !policy.trusts(&signature) {
+            if let SignaturePolicy::Require(_) = *policy {
+                return Err(Error::UntrustedPatch(hash.clone(), signature));
+            }
+            untrusted.push((hash.clone(), signature))
+        }
+    }
+    Ok(untrusted)
+}
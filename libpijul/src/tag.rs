@@ -0,0 +1,90 @@
+// Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+//
+// This file is part of Pijul.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Tags: named, immutable sets of patches.
+//!
+//! Tag `name` is stored in file `tag_file(name)`, in the format of
+//! changes files (see `patch::write_changes`). Tags only record the
+//! hashes of their patches, which are kept in the patches directory
+//! like any other patch. Once created, a tag is never changed, it can
+//! only be deleted.
+
+use super::error::Error;
+use super::fs_representation::{tags_dir, tag_file};
+use super::patch::{read_changes_from_file, write_changes};
+
+use std::collections::HashSet;
+use std::fs::{create_dir_all, metadata, read_dir, remove_file};
+use std::io::ErrorKind;
+use std::path::Path;
+
+extern crate rustc_serialize;
+use self::rustc_serialize::hex::FromHex;
+
+/// The patches of tag `name` in repository `r`.
+pub fn read_tag(r: &Path, name: &str) -> Result<HashSet<Vec<u8>>, Error> {
+    let file = tag_file(r, name);
+    if metadata(&file).is_err() {
+        return Err(Error::TagNotFound(name.to_string()));
+    }
+    read_changes_from_file(&file)
+}
+
+/// Create tag `name` in repository `r`, with patches `patches`. Fails
+/// if the tag already exists.
+pub fn create_tag(r: &Path, name: &str, patches: &HashSet<Vec<u8>>) -> Result<(), Error> {
+    let file = tag_file(r, name);
+    if metadata(&file).is_ok() {
+        return Err(Error::TagAlreadyExists(name.to_string()));
+    }
+    // Repositories created by older versions have no tags directory.
+    try!(create_dir_all(tags_dir(r)));
+    let mut patches: Vec<&[u8]> = patches.iter().map(|p| &p[..]).collect();
+    patches.sort();
+    write_changes(&patches, &file)
+}
+
+/// Delete tag `name` from repository `r`. The patches are kept.
+pub fn delete_tag(r: &Path, name: &str) -> Result<(), Error> {
+    match remove_file(tag_file(r, name)) {
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Err(Error::TagNotFound(name.to_string())),
+        Err(e) => Err(Error::IO(e)),
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Names of the tags of repository `r`, sorted.
+pub fn list_tags(r: &Path) -> Result<Vec<String>, Error> {
+    let mut tags = Vec::new();
+    let entries = match read_dir(tags_dir(r)) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(tags),
+        Err(e) => return Err(Error::IO(e)),
+    };
+    for entry in entries {
+        let entry = try!(entry);
+        // Skip the files that are not tags.
+        if let Some(name) = entry.file_name().to_str().and_then(|n| n.from_hex().ok()) {
+            if let Ok(name) = String::from_utf8(name) {
+                tags.push(name)
+            }
+        }
+    }
+    tags.sort();
+    Ok(tags)
+}
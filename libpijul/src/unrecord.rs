@@ -0,0 +1,300 @@
+// Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+//
+// This file is part of Pijul.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+use super::backend::*;
+use super::error::Error;
+use super::patch::{Change, Patch, HASH_SIZE, KEY_SIZE, LINE_SIZE, InternalKey, internal_hash,
+                   external_hash};
+use super::graph::{FOLDER_EDGE, PARENT_EDGE, DELETED_EDGE};
+use super::apply::internal_edge;
+use super::file_operations::INODE_SIZE;
+
+use std::path::Path;
+use std::collections::HashSet;
+use rustc_serialize::hex::ToHex;
+
+/// Is the patch with internal id `internal` applied on branch `branch_name`?
+fn is_applied<T>(db_branches: &Db<T>, branch_name: &str, internal: &[u8]) -> bool {
+    for (k, v) in db_branches.iter(branch_name.as_bytes(), Some(internal)) {
+        return k == branch_name.as_bytes() && v == internal;
+    }
+    false
+}
+
+/// Is the patch applied on any branch other than `branch_name`? Node
+/// contents and hash registrations are shared between branches, and
+/// must be kept as long as one of them still uses the patch.
+fn is_applied_elsewhere<T>(db_branches: &Db<T>, branch_name: &str, internal: &[u8]) -> bool {
+    for (k, v) in db_branches.iter(b"", None) {
+        if k != branch_name.as_bytes() && v == internal {
+            return true;
+        }
+    }
+    false
+}
+
+/// External hashes of the patches applied on `branch_name` that
+/// depend on patch `internal`.
+fn applied_dependents<T>(repository: &Transaction<T>,
+                         branch_name: &str,
+                         internal: &[u8])
+                         -> Vec<Vec<u8>> {
+    let db_revdep = repository.db_revdep();
+    let db_branches = repository.db_branches();
+    let db_external = repository.db_external();
+    let mut dependents = Vec::new();
+    for (k, v) in db_revdep.iter(internal, None) {
+        if k != internal {
+            break;
+        }
+        if is_applied(&db_branches, branch_name, v) {
+            dependents.push(external_hash(&db_external, v).to_vec())
+        }
+    }
+    dependents
+}
+
+/// Convert an external key from a patch into an internal key. Keys of
+/// size `LINE_SIZE` refer to lines introduced by the patch itself.
+fn internal_key<T>(db_internal: &Db<T>,
+                   key: &[u8],
+                   internal: &InternalKey,
+                   result: &mut [u8])
+                   -> Result<(), Error> {
+    let u = if key.len() > LINE_SIZE {
+        try!(internal_hash(db_internal, &key[0..(key.len() - LINE_SIZE)])).contents
+    } else {
+        internal.contents
+    };
+    result[0..HASH_SIZE].clone_from_slice(&u);
+    result[HASH_SIZE..KEY_SIZE].clone_from_slice(&key[(key.len() - LINE_SIZE)..]);
+    Ok(())
+}
+
+/// Line number `l` of a patch, as the last `LINE_SIZE` bytes of a key.
+fn line_bytes(mut l: u32) -> [u8; LINE_SIZE] {
+    let mut line = [0; LINE_SIZE];
+    for b in line.iter_mut() {
+        *b = (l & 0xff) as u8;
+        l >>= 8
+    }
+    line
+}
+
+/// Remove patch `hash` from branch `branch_name`. This is the inverse
+/// of `apply::apply`: the edges introduced by the patch are removed,
+/// and the edges it deleted are put back. If `cascade` is false, this
+/// fails when another patch applied on the branch depends on this
+/// one; else, these dependent patches are unrecorded first.
+///
+/// The working copy is not touched: changes from the patch become
+/// unrecorded changes again. The edges repairing the missing
+/// contexts of the patch carry its hash, and are removed with it.
+/// Pseudo-edges added to reconnect the alive parts of the graph
+/// around lines it deleted carry the hash of the edges they replace,
+/// and are kept, since they are harmless.
+pub fn unrecord<T>(repository: &mut Transaction<T>,
+                   branch_name: &str,
+                   location: &Path,
+                   hash: &[u8],
+                   cascade: bool)
+                   -> Result<(), Error> {
+    let internal = {
+        let db_internal = repository.db_internal();
+        let db_branches = repository.db_branches();
+        let internal = match internal_hash(&db_internal, hash) {
+            Ok(internal) => InternalKey { contents: internal.contents },
+            Err(Error::InternalHashNotFound(_)) => {
+                return Err(Error::PatchNotOnBranch(hash.to_vec()))
+            }
+            Err(e) => return Err(e),
+        };
+        if !is_applied(&db_branches, branch_name, internal.as_slice()) {
+            return Err(Error::PatchNotOnBranch(hash.to_vec()));
+        }
+        internal
+    };
+    let dependents = applied_dependents(repository, branch_name, internal.as_slice());
+    if !dependents.is_empty() {
+        if cascade {
+            for dep in dependents.iter() {
+                // A dependent may already have been unrecorded as a
+                // dependent of a previous one.
+                match unrecord(repository, branch_name, location, dep, true) {
+                    Ok(()) | Err(Error::PatchNotOnBranch(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        } else {
+            return Err(Error::PatchIsDependedOn(hash.to_vec(), dependents[0].clone()));
+        }
+    }
+    debug!("unrecording patch {} ({})", hash.to_hex(), internal.to_hex());
+    let patch = try!(Patch::from_repository(location, hash));
+    let db_internal = repository.db_internal();
+    let mut db_branches = repository.db_branches();
+    let elsewhere = is_applied_elsewhere(&db_branches, branch_name, internal.as_slice());
+    let mut branch = try!(repository.db_nodes(branch_name));
+
+    // Remove all edges introduced by this patch, including zombie
+    // edges and the edges repairing missing contexts. Each of them
+    // touches a node mentioned in the patch (a new node, a context or
+    // an end of an edge), or another edge of the patch, so they are
+    // found by following them from these nodes.
+    let mut introduced: Vec<u8> = Vec::new();
+    {
+        let mut stack = Vec::new();
+        let mut key = [0; KEY_SIZE];
+        for ch in patch.changes.iter() {
+            match *ch {
+                Change::NewNodes { ref up_context, ref down_context, ref line_num, ref nodes, .. } => {
+                    for c in up_context.iter().chain(down_context.iter()) {
+                        try!(internal_key(&db_internal, c, &internal, &mut key));
+                        stack.push(key.to_vec())
+                    }
+                    for i in 0..nodes.len() {
+                        try!(internal_key(&db_internal, &line_bytes(*line_num + i as u32), &internal, &mut key));
+                        stack.push(key.to_vec())
+                    }
+                }
+                Change::Edges { ref edges, .. } => {
+                    for e in edges.iter() {
+                        try!(internal_key(&db_internal, &e.from, &internal, &mut key));
+                        stack.push(key.to_vec());
+                        try!(internal_key(&db_internal, &e.to, &internal, &mut key));
+                        stack.push(key.to_vec())
+                    }
+                }
+            }
+        }
+        let mut visited = HashSet::new();
+        while let Some(node) = stack.pop() {
+            if !visited.insert(node.clone()) {
+                continue;
+            }
+            for (k, v) in branch.iter(&node, None) {
+                if k != &node[..] {
+                    break;
+                }
+                if v.len() == 1 + KEY_SIZE + HASH_SIZE && &v[(1 + KEY_SIZE)..] == internal.as_slice() {
+                    introduced.extend(k);
+                    introduced.extend(v);
+                    stack.push(v[1..(1 + KEY_SIZE)].to_vec())
+                }
+            }
+        }
+    }
+    let mut i = 0;
+    while i < introduced.len() {
+        try!(branch.del(&introduced[i..(i + KEY_SIZE)],
+                        Some(&introduced[(i + KEY_SIZE)..(i + 2 * KEY_SIZE + 1 + HASH_SIZE)])));
+        i += 2 * KEY_SIZE + 1 + HASH_SIZE
+    }
+
+    // Put back the edges this patch deleted, in reverse order.
+    let mut pu: [u8; 1 + KEY_SIZE + HASH_SIZE] = [0; 1 + KEY_SIZE + HASH_SIZE];
+    let mut pv: [u8; 1 + KEY_SIZE + HASH_SIZE] = [0; 1 + KEY_SIZE + HASH_SIZE];
+    for ch in patch.changes.iter().rev() {
+        if let Change::Edges { ref flag, ref edges } = *ch {
+            for e in edges.iter().rev() {
+                let p = InternalKey {
+                    contents: try!(internal_hash(&db_internal, &e.introduced_by)).contents,
+                };
+                try!(internal_edge(&db_internal,
+                                   *flag ^ DELETED_EDGE ^ PARENT_EDGE,
+                                   &e.from,
+                                   &p,
+                                   &mut pu));
+                try!(internal_edge(&db_internal, *flag ^ DELETED_EDGE, &e.to, &p, &mut pv));
+                try!(branch.put(&pu[1..(1 + KEY_SIZE)], &pv));
+                try!(branch.put(&pv[1..(1 + KEY_SIZE)], &pu));
+            }
+        }
+    }
+
+    // Nodes introduced by this patch are now disconnected from the
+    // graph. Their contents are shared between branches.
+    let mut key = [0; KEY_SIZE];
+    if !elsewhere {
+        let mut db_contents = repository.db_contents();
+        for ch in patch.changes.iter() {
+            if let Change::NewNodes { ref line_num, ref nodes, .. } = *ch {
+                for i in 0..nodes.len() {
+                    try!(internal_key(&db_internal, &line_bytes(*line_num + i as u32), &internal, &mut key));
+                    try!(db_contents.del(&key, None));
+                }
+            }
+        }
+    }
+
+    // Files added by this patch are not in the repository anymore,
+    // but are still tracked: forget their inodes' keys, so that the
+    // next record sees them as additions.
+    if patch.changes.iter().any(|ch| match *ch {
+        Change::NewNodes { ref flag, .. } => flag & FOLDER_EDGE != 0,
+        _ => false,
+    }) {
+        let mut db_inodes = repository.db_inodes();
+        let mut db_revinodes = repository.db_revinodes();
+        let mut files: Vec<u8> = Vec::new();
+        for (k, v) in db_revinodes.iter(internal.as_slice(), None) {
+            if &k[0..HASH_SIZE] != internal.as_slice() {
+                break;
+            }
+            files.extend(k);
+            files.extend(v);
+        }
+        let mut i = 0;
+        while i < files.len() {
+            try!(db_inodes.del(&files[(i + KEY_SIZE)..(i + KEY_SIZE + INODE_SIZE)], None));
+            try!(db_revinodes.del(&files[i..(i + KEY_SIZE)], None));
+            i += KEY_SIZE + INODE_SIZE
+        }
+    }
+
+    try!(branch.commit_branch(branch_name));
+    try!(db_branches.del(branch_name.as_bytes(), Some(internal.as_slice())));
+    {
+        let mut db_applied = repository.db_applied();
+        let mut applied = None;
+        for (k, v) in db_applied.iter(branch_name.as_bytes(), None) {
+            if k != branch_name.as_bytes() {
+                break;
+            }
+            if &v[8..] == internal.as_slice() {
+                applied = Some(v.to_vec());
+                break;
+            }
+        }
+        if let Some(v) = applied {
+            try!(db_applied.del(branch_name.as_bytes(), Some(&v)));
+        }
+    }
+
+    if !elsewhere {
+        let mut db_revdep = repository.db_revdep();
+        for dep in patch.dependencies.iter() {
+            let dep_internal = try!(internal_hash(&db_internal, dep)).contents;
+            try!(db_revdep.del(&dep_internal, Some(internal.as_slice())));
+        }
+        let mut db_external = repository.db_external();
+        let mut db_internal = repository.db_internal();
+        try!(db_external.del(internal.as_slice(), None));
+        try!(db_internal.del(hash, None));
+    }
+    Ok(())
+}
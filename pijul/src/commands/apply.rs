@@ -22,11 +22,12 @@ use clap::{SubCommand, ArgMatches, Arg};
 extern crate libpijul;
 use libpijul::{Repository};
 use commands::StaticSubcommand;
-use self::libpijul::{DEFAULT_BRANCH};
 use self::libpijul::patch::{read_changes_from_file};
-use self::libpijul::fs_representation::{pristine_dir, find_repo_root, branch_changes_file};
+use self::libpijul::fs_representation::{pristine_dir, find_repo_root, branch_changes_file, read_current_branch};
 
 use commands::error::Error;
+use super::super::meta::Meta;
+use super::remote::verify_signatures;
 use std::collections::{HashSet};
 
 use std::path::{Path};
@@ -48,18 +49,25 @@ pub fn invocation() -> StaticSubcommand {
              .long("repository")
              .help("The repository where to record, defaults to the current directory.")
              .required(false))
+        .arg(Arg::with_name("branch")
+             .long("branch")
+             .help("The branch where to apply the patches, defaults to the current branch.")
+             .takes_value(true)
+             .required(false))
 }
 
 pub struct Params<'a> {
     pub repository : &'a Path,
-    pub hex_hash : Vec<&'a str>
+    pub hex_hash : Vec<&'a str>,
+    pub branch : Option<&'a str>
 }
 
 pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a>
 {
     Params {
         repository : Path::new(args.value_of("repository").unwrap_or(".")),
-        hex_hash : args.values_of("patch").map(|x| x.collect()).unwrap_or(Vec::new())
+        hex_hash : args.values_of("patch").map(|x| x.collect()).unwrap_or(Vec::new()),
+        branch : args.value_of("branch")
     }
 }
 
@@ -69,6 +77,7 @@ pub fn run(params : &Params) -> Result<Option<()>, Error> {
         Some(ref target) =>
         {
             debug!("applying");
+            let branch = params.branch.map(|x| x.to_string()).unwrap_or(read_current_branch(target));
             let remote:HashSet<Vec<u8>>={
                 let mut h=HashSet::new();
                 for i in params.hex_hash.iter() {
@@ -78,14 +87,15 @@ pub fn run(params : &Params) -> Result<Option<()>, Error> {
             };
             debug!("remote={:?}",remote);
             let local:HashSet<Vec<u8>>={
-                let changes_file=branch_changes_file(target,DEFAULT_BRANCH.as_bytes());
+                let changes_file=branch_changes_file(target,branch.as_bytes());
                 read_changes_from_file(&changes_file).unwrap_or(HashSet::new())
             };
             debug!("local={:?}",local);
+            try!(verify_signatures(target, remote.difference(&local)));
             let repo_dir=pristine_dir(target);
             let repo = try!(Repository::open(&repo_dir));
             let mut txn = try!(repo.mut_txn_begin());
-            try!(txn.apply_patches(DEFAULT_BRANCH, target,&remote,&local));
+            try!(txn.apply_patches(&branch, target,&remote,&local, Meta::conflict_style(target)));
             try!(txn.commit());
             Ok(Some(()))
         }
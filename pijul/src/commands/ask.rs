@@ -17,7 +17,7 @@ extern crate termios;
 use self::termios::{tcsetattr,ICANON,ECHO};
 
 use super::error::Error;
-use self::libpijul::{Transaction,internal_hash};
+use self::libpijul::{ReadTransaction,internal_hash,is_binary};
 use self::libpijul::graph::{FOLDER_EDGE,PARENT_EDGE};
 use std::io::stdin;
 use std::char::from_u32_unchecked;
@@ -236,7 +236,7 @@ fn change_deps(id:usize,c:&Change,provided_by:&mut HashMap<u32,usize>)->HashSet<
     s
 }
 
-fn print_change(repo:&Transaction,c:&Change)->Result<(),Error> {
+fn print_change(repo:&ReadTransaction,c:&Change)->Result<(),Error> {
     match *c {
         Change::NewNodes{/*ref up_context,ref down_context,ref line_num,*/ref flag,ref nodes,..}=>{
             for n in nodes {
@@ -244,6 +244,8 @@ fn print_change(repo:&Transaction,c:&Change)->Result<(),Error> {
                     if n.len()>=2 {
                         println!("new file {}",str::from_utf8(&n[2..]).unwrap_or(""));
                     }
+                } else if is_binary(n) {
+                    println!("binary file changed");
                 } else {
                     print!("+ {}",str::from_utf8(n).unwrap_or(""));
                 }
@@ -270,8 +272,14 @@ fn print_change(repo:&Transaction,c:&Change)->Result<(),Error> {
                                             LINE_SIZE)
                     };
                     let db_contents = repo.db_contents();
+                    let mut contents = Vec::new();
                     for l in db_contents.contents(&internal[..]).unwrap() {
-                        print!("- {}",str::from_utf8(l).unwrap_or(""))
+                        contents.extend(l)
+                    }
+                    if is_binary(&contents) {
+                        println!("binary file changed")
+                    } else {
+                        print!("- {}",str::from_utf8(&contents).unwrap_or(""))
                     }
                 }
             }
@@ -280,7 +288,7 @@ fn print_change(repo:&Transaction,c:&Change)->Result<(),Error> {
     }
 }
 
-pub fn ask_record(repository:&Transaction,changes:&[Change])->Result<HashMap<usize,bool>,Error> {
+pub fn ask_record(repository:&ReadTransaction,changes:&[Change])->Result<HashMap<usize,bool>,Error> {
     try!(init_getch());
     let mut i=0;
     let mut choices:HashMap<usize,bool>=HashMap::new();
@@ -0,0 +1,114 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg};
+
+extern crate libpijul;
+use commands::StaticSubcommand;
+use commands::error::Error;
+use commands::fs_operation::{absolute_path, iter_after};
+use self::libpijul::{Repository, ReadTxn, AnnotatedLine, is_binary};
+use self::libpijul::fs_representation::{pristine_dir, find_repo_root, read_current_branch};
+
+extern crate rustc_serialize;
+use self::rustc_serialize::hex::ToHex;
+
+use std::path::Path;
+use super::get_wd;
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("blame")
+        .about("show the patch that introduced each line of a file")
+        .arg(Arg::with_name("file")
+             .help("The file to annotate.")
+             .required(true))
+        .arg(Arg::with_name("branch")
+             .long("branch")
+             .help("The branch to look at, defaults to the current branch.")
+             .takes_value(true))
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("The repository to inspect, defaults to the current directory.")
+             .takes_value(true)
+             .required(false))
+}
+
+pub struct Params<'a> {
+    pub repository : Option<&'a Path>,
+    pub file : &'a Path,
+    pub branch : Option<&'a str>
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+    Params { repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }),
+             file : Path::new(args.value_of("file").unwrap()),
+             branch : args.value_of("branch")
+    }
+}
+
+/// Lines of the file in `args`, in repository `r`, with the patch
+/// that introduced each of them, its authors and its timestamp.
+pub fn annotate(r: &Path, args: &Params) -> Result<Vec<AnnotatedLine>, Error> {
+    let cwd = try!(get_wd(None));
+    let abs = try!(absolute_path(&cwd, args.file));
+    let path = match iter_after(abs.components(), r.components()) {
+        Some(rel) => rel.as_path().to_path_buf(),
+        None => return Err(Error::InvalidPath(args.file.to_string_lossy().into_owned()))
+    };
+    let branch = match args.branch {
+        Some(b) => b.to_string(),
+        None => read_current_branch(r)
+    };
+    let repo_dir=pristine_dir(r);
+    let repo = try!(Repository::open(&repo_dir).map_err(Error::Repository));
+    let txn = try!(repo.txn_begin());
+    if !txn.branch_exists(&branch) {
+        return Err(Error::Repository(libpijul::error::Error::BranchNotFound(branch)))
+    }
+    txn.annotate(&branch, r, &path).map_err(Error::Repository)
+}
+
+pub fn run(args : &Params) -> Result<(), Error> {
+    let wd=try!(get_wd(args.repository));
+    match find_repo_root(&wd){
+        None => return Err(Error::NotInARepository),
+        Some(ref r) =>
+        {
+            let lines = try!(annotate(r, args));
+            for line in lines.iter() {
+                let header = match line.patch {
+                    Some(ref hash) => {
+                        // Only the date part of the timestamp.
+                        let date = line.timestamp.as_ref().and_then(|t| t.split('T').next()).unwrap_or("");
+                        format!("{} {:<16.16} {:<10}", &hash.to_hex()[0..10], line.authors.join(", "), date)
+                    },
+                    None => String::new()
+                };
+                if is_binary(&line.contents) {
+                    println!("{:<39}| (binary contents)", header)
+                } else {
+                    let contents = String::from_utf8_lossy(&line.contents);
+                    println!("{:<39}| {}", header, contents.trim_right_matches('\n'))
+                }
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,146 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg};
+
+extern crate libpijul;
+use self::libpijul::{Repository,ReadTxn};
+use self::libpijul::fs_representation::{pristine_dir, find_repo_root, read_current_branch, set_current_branch};
+use commands::StaticSubcommand;
+use commands::error::Error;
+use super::get_wd;
+
+use std::path::Path;
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("branch")
+        .about("Manage branches")
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("The repository where to operate, defaults to the current directory.")
+             .takes_value(true)
+             .required(false))
+        .subcommand(SubCommand::with_name("new")
+                    .about("Create a new branch as a copy of an existing one")
+                    .arg(Arg::with_name("name")
+                         .help("Name of the new branch.")
+                         .required(true))
+                    .arg(Arg::with_name("from")
+                         .long("from")
+                         .help("Branch to fork, defaults to the current branch.")
+                         .takes_value(true)))
+        .subcommand(SubCommand::with_name("list")
+                    .about("List branches"))
+        .subcommand(SubCommand::with_name("rename")
+                    .about("Rename a branch")
+                    .arg(Arg::with_name("name")
+                         .help("Current name of the branch.")
+                         .required(true))
+                    .arg(Arg::with_name("new_name")
+                         .help("New name of the branch.")
+                         .required(true)))
+        .subcommand(SubCommand::with_name("delete")
+                    .about("Delete a branch")
+                    .arg(Arg::with_name("name")
+                         .help("Name of the branch to delete.")
+                         .required(true)))
+}
+
+#[derive(Debug)]
+pub enum Action<'a> {
+    New { name: &'a str, from: Option<&'a str> },
+    List,
+    Rename { name: &'a str, new_name: &'a str },
+    Delete { name: &'a str },
+}
+
+pub struct Params<'a> {
+    pub repository : Option<&'a Path>,
+    pub action : Action<'a>
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+    let action = match args.subcommand() {
+        ("new", Some(args)) => Action::New {
+            name: args.value_of("name").unwrap(),
+            from: args.value_of("from")
+        },
+        ("rename", Some(args)) => Action::Rename {
+            name: args.value_of("name").unwrap(),
+            new_name: args.value_of("new_name").unwrap()
+        },
+        ("delete", Some(args)) => Action::Delete {
+            name: args.value_of("name").unwrap()
+        },
+        _ => Action::List
+    };
+    Params {
+        repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }),
+        action : action
+    }
+}
+
+pub fn run(params : &Params) -> Result<(), Error> {
+    let wd = try!(get_wd(params.repository));
+    match find_repo_root(&wd){
+        None => return Err(Error::NotInARepository),
+        Some(ref target) =>
+        {
+            debug!("branch: {:?}", params.action);
+            let repo_dir=pristine_dir(target);
+            let repo = try!(Repository::open(&repo_dir));
+            let current = read_current_branch(target);
+            match params.action {
+                Action::List => {
+                    let txn = try!(repo.txn_begin());
+                    for name in txn.branches() {
+                        if name == current {
+                            println!("* {}", name)
+                        } else {
+                            println!("  {}", name)
+                        }
+                    }
+                },
+                Action::New { name, from } => {
+                    let mut txn = try!(repo.mut_txn_begin());
+                    try!(txn.fork_branch(target, from.unwrap_or(&current), name));
+                    try!(txn.commit())
+                },
+                Action::Rename { name, new_name } => {
+                    let mut txn = try!(repo.mut_txn_begin());
+                    try!(txn.rename_branch(target, name, new_name));
+                    try!(txn.commit());
+                    if name == current {
+                        try!(set_current_branch(target, new_name))
+                    }
+                },
+                Action::Delete { name } => {
+                    if name == current {
+                        return Err(Error::CurrentBranch(name.to_string()))
+                    }
+                    let mut txn = try!(repo.mut_txn_begin());
+                    try!(txn.delete_branch(target, name));
+                    try!(txn.commit())
+                }
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,203 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg};
+
+extern crate libpijul;
+use commands::StaticSubcommand;
+use commands::error::Error;
+use self::libpijul::{Repository, ReadTxn};
+use self::libpijul::patch::{Patch, Value};
+use self::libpijul::fs_representation::{pristine_dir, find_repo_root, read_current_branch};
+
+extern crate rustc_serialize;
+use self::rustc_serialize::hex::ToHex;
+use self::rustc_serialize::json::{Json, ToJson};
+
+extern crate time;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use super::get_wd;
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("changes")
+        .about("list the patches applied on a branch, oldest first")
+        .arg(Arg::with_name("branch")
+             .long("branch")
+             .help("The branch to list, defaults to the current branch.")
+             .takes_value(true))
+        .arg(Arg::with_name("sort")
+             .long("sort")
+             .help("Order of the patches, by application order (the default) or by timestamp.")
+             .possible_values(&["applied", "timestamp"])
+             .takes_value(true))
+        .arg(Arg::with_name("author")
+             .long("author")
+             .help("Only list the patches with an author containing this.")
+             .takes_value(true))
+        .arg(Arg::with_name("name")
+             .long("name")
+             .help("Only list the patches whose name contains this.")
+             .takes_value(true))
+        .arg(Arg::with_name("json")
+             .long("json")
+             .help("Output the patches as JSON."))
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("The repository to inspect, defaults to the current directory.")
+             .takes_value(true)
+             .required(false))
+}
+
+pub struct Params<'a> {
+    pub repository : Option<&'a Path>,
+    pub branch : Option<&'a str>,
+    pub by_timestamp : bool,
+    pub author : Option<&'a str>,
+    pub name : Option<&'a str>,
+    pub json : bool
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+    Params { repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }),
+             branch : args.value_of("branch"),
+             by_timestamp : args.value_of("sort") == Some("timestamp"),
+             author : args.value_of("author"),
+             name : args.value_of("name"),
+             json : args.is_present("json")
+    }
+}
+
+pub fn author_names(patch: &Patch) -> Vec<String> {
+    patch.authors.iter().map(|a| {
+        match a.get("name") {
+            Some(&Value::String(ref name)) => name.clone(),
+            None => String::new()
+        }
+    }).collect()
+}
+
+/// Seconds since the epoch of an RFC 3339 timestamp, as written in
+/// patches. Unparsable timestamps come first.
+fn seconds(timestamp: &str) -> i64 {
+    if timestamp.len() < 19 {
+        return 0
+    }
+    let t = match time::strptime(&timestamp[0..19], "%Y-%m-%dT%H:%M:%S") {
+        Ok(t) => t.to_timespec().sec,
+        Err(_) => return 0
+    };
+    // The rest is the offset: "Z", "+hh:mm" or "-hh:mm", possibly
+    // after fractional seconds.
+    let offset = timestamp[19..].trim_left_matches(|c: char| c == '.' || c.is_digit(10));
+    if offset.len() == 6 {
+        let h: i64 = offset[1..3].parse().unwrap_or(0);
+        let m: i64 = offset[4..6].parse().unwrap_or(0);
+        let off = h * 3600 + m * 60;
+        if offset.starts_with("-") { t + off } else { t - off }
+    } else {
+        t
+    }
+}
+
+/// Patches on the selected branch, with their hashes, filtered and
+/// sorted according to `args`.
+pub fn patches(r: &Path, args: &Params) -> Result<Vec<(Vec<u8>, Patch)>, Error> {
+    let repo_dir=pristine_dir(r);
+    let branch = match args.branch {
+        Some(b) => b.to_string(),
+        None => read_current_branch(r)
+    };
+    let repo = try!(Repository::open(&repo_dir).map_err(Error::Repository));
+    let txn = try!(repo.txn_begin());
+    if !txn.branch_exists(&branch) {
+        return Err(Error::Repository(libpijul::error::Error::BranchNotFound(branch)))
+    }
+    let mut patches = Vec::new();
+    for hash in try!(txn.applied_patches(&branch).map_err(Error::Repository)) {
+        let patch = try!(Patch::from_repository(r, &hash).map_err(Error::Repository));
+        if let Some(name) = args.name {
+            if !patch.name.contains(name) {
+                continue
+            }
+        }
+        if let Some(author) = args.author {
+            if !author_names(&patch).iter().any(|a| a.contains(author)) {
+                continue
+            }
+        }
+        patches.push((hash, patch))
+    }
+    if args.by_timestamp {
+        patches.sort_by(|a, b| seconds(&a.1.timestamp).cmp(&seconds(&b.1.timestamp)))
+    }
+    Ok(patches)
+}
+
+/// The header of `patch` shown by "pijul changes" and "pijul patch show".
+pub fn patch_header(hash: &[u8], patch: &Patch) -> String {
+    let mut header = format!("Hash: {}\n", hash.to_hex());
+    header.push_str(&format!("Authors: {}\n", author_names(patch).join(", ")));
+    header.push_str(&format!("Timestamp: {}\n", patch.timestamp));
+    header.push_str(&format!("\n    {}\n", patch.name));
+    if let Some(ref d) = patch.description {
+        header.push_str("\n");
+        for line in d.lines() {
+            header.push_str(&format!("    {}\n", line))
+        }
+    }
+    header
+}
+
+pub fn print_patch_header(hash: &[u8], patch: &Patch) {
+    print!("{}", patch_header(hash, patch))
+}
+
+fn patch_json(hash: &[u8], patch: &Patch) -> Json {
+    let mut d = BTreeMap::new();
+    d.insert("hash".to_string(), hash.to_hex().to_json());
+    d.insert("name".to_string(), patch.name.to_json());
+    d.insert("authors".to_string(), author_names(patch).to_json());
+    d.insert("timestamp".to_string(), patch.timestamp.to_json());
+    d.insert("description".to_string(), patch.description.to_json());
+    Json::Object(d)
+}
+
+pub fn run(args : &Params) -> Result<(), Error> {
+    let wd=try!(get_wd(args.repository));
+    match find_repo_root(&wd){
+        None => return Err(Error::NotInARepository),
+        Some(ref r) =>
+        {
+            let patches = try!(patches(r, args));
+            if args.json {
+                let patches = patches.iter().map(|&(ref h, ref p)| patch_json(h, p)).collect();
+                println!("{}", Json::Array(patches).pretty())
+            } else {
+                for &(ref hash, ref patch) in patches.iter() {
+                    print_patch_header(hash, patch);
+                    println!("");
+                }
+            }
+            Ok(())
+        }
+    }
+}
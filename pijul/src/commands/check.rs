@@ -19,40 +19,100 @@
 extern crate clap;
 use clap::{SubCommand, Arg, ArgMatches};
 use std::path::Path;
+use std::fs;
+use std::io;
 
 use commands::StaticSubcommand;
 extern crate libpijul;
-use self::libpijul::Repository;
-use self::libpijul::fs_representation::{pristine_dir,find_repo_root};
+use self::libpijul::{Repository,ReadTxn};
+use self::libpijul::fs_representation::{pristine_dir,find_repo_root,read_current_branch};
 use super::get_wd;
 use super::error::Error;
 
 pub struct Params<'a> {
-    pub repository : Option<&'a Path>
+    pub repository : Option<&'a Path>,
+    pub repair : bool
 }
 
 pub fn invocation() -> StaticSubcommand {
     return
         SubCommand::with_name("check")
-        .about("Check the sanity of a repository")
+        .about("Check the sanity of a repository, reporting each inconsistency")
         .arg(Arg::with_name("repository")
              .index(1)
              .help("The repository to check, defaults to the current directory.")
              .required(false)
+             )
+        .arg(Arg::with_name("repair")
+             .long("repair")
+             .help("Rebuild the pristine from the patches listed in the changes files, before checking.")
              );
 }
 
 pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
-    Params {repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }) }
+    Params {repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }),
+            repair : args.is_present("repair") }
+}
+
+/// Replace the pristine of `repo_base` with one rebuilt from its
+/// patches directory and changes files. The new pristine is built
+/// next to the current one, which is only replaced if that succeeds.
+fn repair(repo_base: &Path) -> Result<(),Error> {
+    let repo_dir = pristine_dir(repo_base);
+    let new_dir = repo_dir.with_file_name("pristine.repair");
+    let old_dir = repo_dir.with_file_name("pristine.old");
+    // Leftovers of an interrupted repair.
+    for dir in [&new_dir, &old_dir].iter() {
+        match fs::remove_dir_all(dir) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(Error::IO(e)),
+            Ok(()) => {}
+        }
+    }
+    try!(fs::create_dir_all(&new_dir));
+    {
+        let repository = try!(Repository::create(&new_dir));
+        let mut txn = try!(repository.mut_txn_begin());
+        try!(txn.rebuild(repo_base, &read_current_branch(repo_base)));
+        try!(txn.commit());
+    }
+    let had_pristine = match fs::rename(&repo_dir, &old_dir) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => false,
+        Err(e) => return Err(Error::IO(e)),
+        Ok(()) => true
+    };
+    if let Err(e) = fs::rename(&new_dir, &repo_dir) {
+        if had_pristine {
+            try!(fs::rename(&old_dir, &repo_dir))
+        }
+        return Err(Error::IO(e))
+    }
+    if had_pristine {
+        try!(fs::remove_dir_all(&old_dir))
+    }
+    Ok(())
 }
 
 pub fn run(args: &Params) -> Result<(),Error> {
     let wd=try!(get_wd(args.repository));
     match find_repo_root(&wd) {
         Some(ref repo_base) => {
-            let _repository = Repository::open(&pristine_dir(&repo_base)).unwrap(); //.expect("Repository error");
-            println!("Your repo looks alright Ma'am/Sir");
-            Ok(())
+            if args.repair {
+                try!(repair(repo_base))
+            }
+            let repository = try!(Repository::open(&pristine_dir(&repo_base)));
+            let txn = try!(repository.txn_begin());
+            try!(txn.list_files());
+            let inconsistencies = try!(txn.check(repo_base));
+            if inconsistencies.is_empty() {
+                println!("Your repo looks alright Ma'am/Sir");
+                Ok(())
+            } else {
+                for i in inconsistencies.iter() {
+                    println!("{}", i)
+                }
+                Err(Error::Inconsistencies(inconsistencies.len()))
+            }
         },
 
         None => {
@@ -0,0 +1,90 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg};
+
+extern crate libpijul;
+use self::libpijul::{Repository,ReadTxn};
+use self::libpijul::fs_representation::{pristine_dir, find_repo_root, read_current_branch, set_current_branch};
+use commands::StaticSubcommand;
+use commands::error::Error;
+use super::get_wd;
+use super::super::meta::Meta;
+
+use std::path::Path;
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("checkout")
+        .about("Switch the working copy to another branch")
+        .arg(Arg::with_name("branch")
+             .help("Branch to switch to.")
+             .required(true))
+        .arg(Arg::with_name("force")
+             .long("force")
+             .short("f")
+             .help("Switch even if there are unrecorded changes, losing them."))
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("The repository where to operate, defaults to the current directory.")
+             .takes_value(true)
+             .required(false))
+}
+
+pub struct Params<'a> {
+    pub repository : Option<&'a Path>,
+    pub branch : &'a str,
+    pub force : bool
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+    Params {
+        repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }),
+        branch : args.value_of("branch").unwrap(),
+        force : args.is_present("force")
+    }
+}
+
+pub fn run(params : &Params) -> Result<(), Error> {
+    let wd = try!(get_wd(params.repository));
+    match find_repo_root(&wd){
+        None => return Err(Error::NotInARepository),
+        Some(ref target) =>
+        {
+            let current = read_current_branch(target);
+            if current == params.branch {
+                return Ok(())
+            }
+            let repo_dir=pristine_dir(target);
+            let repo = try!(Repository::open(&repo_dir));
+            if !params.force {
+                let txn = try!(repo.txn_begin());
+                let (changes, _) = try!(txn.record(&current, target));
+                if !changes.is_empty() {
+                    return Err(Error::UnrecordedChanges)
+                }
+            }
+            let mut txn = try!(repo.mut_txn_begin());
+            try!(txn.checkout(params.branch, target, Meta::conflict_style(target)));
+            try!(txn.commit());
+            try!(set_current_branch(target, params.branch));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,117 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg};
+
+extern crate libpijul;
+use commands::StaticSubcommand;
+use commands::error::Error;
+use self::libpijul::{Repository, ReadTxn, Conflict, is_binary};
+use self::libpijul::fs_representation::{pristine_dir, find_repo_root, read_current_branch};
+
+extern crate rustc_serialize;
+use self::rustc_serialize::hex::ToHex;
+
+use std::path::Path;
+use super::get_wd;
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("conflicts")
+        .about("list the conflicts on a branch, exiting with an error if there are any")
+        .arg(Arg::with_name("branch")
+             .long("branch")
+             .help("The branch to look at, defaults to the current branch.")
+             .takes_value(true))
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("The repository to inspect, defaults to the current directory.")
+             .takes_value(true)
+             .required(false))
+}
+
+pub struct Params<'a> {
+    pub repository : Option<&'a Path>,
+    pub branch : Option<&'a str>
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+    Params { repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }),
+             branch : args.value_of("branch")
+    }
+}
+
+/// Conflicts on the branch selected by `args`, in repository `r`.
+pub fn conflicts(r: &Path, args: &Params) -> Result<Vec<Conflict>, Error> {
+    let branch = match args.branch {
+        Some(b) => b.to_string(),
+        None => read_current_branch(r)
+    };
+    let repo_dir=pristine_dir(r);
+    let repo = try!(Repository::open(&repo_dir).map_err(Error::Repository));
+    let txn = try!(repo.txn_begin());
+    if !txn.branch_exists(&branch) {
+        return Err(Error::Repository(libpijul::error::Error::BranchNotFound(branch)))
+    }
+    txn.conflicts(&branch).map_err(Error::Repository)
+}
+
+pub fn run(args : &Params) -> Result<(), Error> {
+    let wd=try!(get_wd(args.repository));
+    match find_repo_root(&wd){
+        None => return Err(Error::NotInARepository),
+        Some(ref r) =>
+        {
+            let conflicts = try!(conflicts(r, args));
+            for conflict in conflicts.iter() {
+                match *conflict {
+                    Conflict::Names { ref paths } => {
+                        println!("Name conflict between:");
+                        for p in paths.iter() {
+                            println!("        {}", p.display())
+                        }
+                    },
+                    Conflict::Files { ref path, count } => {
+                        println!("{} files named {}", count, path.display())
+                    },
+                    Conflict::Lines { ref path, ref sides } => {
+                        println!("Conflict in {}:", path.display());
+                        for (i, side) in sides.iter().enumerate() {
+                            let patches: Vec<String> = side.patches().iter().map(|p| p.to_hex()).collect();
+                            println!("    Side {} ({}):", i + 1, patches.join(", "));
+                            for line in side.lines.iter() {
+                                if is_binary(&line.contents) {
+                                    println!("        (binary contents)")
+                                } else {
+                                    let contents = String::from_utf8_lossy(&line.contents);
+                                    println!("        {}", contents.trim_right_matches('\n'))
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            if conflicts.is_empty() {
+                Ok(())
+            } else {
+                Err(Error::Conflicts(conflicts.len()))
+            }
+        }
+    }
+}
@@ -0,0 +1,189 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg};
+
+extern crate libpijul;
+use self::libpijul::{Repository, ReadTxn, ReadTransaction};
+use self::libpijul::patch::Patch;
+use self::libpijul::fs_representation::{pristine_dir, find_repo_root, read_current_branch};
+use commands::StaticSubcommand;
+use commands::error::Error;
+use commands::patch::find_patch;
+use super::get_wd;
+
+extern crate rustc_serialize;
+use self::rustc_serialize::hex::ToHex;
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::Path;
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("deps")
+        .about("show the dependencies between the patches of a branch")
+        .arg(Arg::with_name("hash")
+             .help("Hash of the patch, in hexadecimal. A prefix is enough if it is unique on the branch. Defaults to all the patches of the branch."))
+        .arg(Arg::with_name("branch")
+             .long("branch")
+             .help("The branch to inspect, defaults to the current branch.")
+             .takes_value(true))
+        .arg(Arg::with_name("reverse")
+             .long("reverse")
+             .help("Show the patches that depend on the patch, instead of its dependencies."))
+        .arg(Arg::with_name("transitive")
+             .long("transitive")
+             .help("Include indirect dependencies."))
+        .arg(Arg::with_name("dot")
+             .long("dot")
+             .help("Write the graph of dependencies in the format of Graphviz, restricted to the patch and its (transitive) dependencies if a hash is given."))
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("The repository to inspect, defaults to the current directory.")
+             .takes_value(true)
+             .required(false))
+}
+
+pub struct Params<'a> {
+    pub repository : Option<&'a Path>,
+    pub hash : Option<&'a str>,
+    pub branch : Option<&'a str>,
+    pub reverse : bool,
+    pub transitive : bool,
+    pub dot : bool
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+    Params { repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }),
+             hash : args.value_of("hash"),
+             branch : args.value_of("branch"),
+             reverse : args.is_present("reverse"),
+             transitive : args.is_present("transitive"),
+             dot : args.is_present("dot")
+    }
+}
+
+fn related_patches(r: &Path, txn: &ReadTransaction, branch: &str, hash: &[u8], reverse: bool, transitive: bool) -> Result<Vec<Vec<u8>>, Error> {
+    if reverse {
+        Ok(try!(txn.dependents(branch, hash, transitive)))
+    } else {
+        Ok(try!(txn.dependencies(branch, r, hash, transitive)))
+    }
+}
+
+/// Patches of branch `branch` that patch `hash` depends on, or that
+/// depend on it if `reverse` is true, sorted by hash.
+#[cfg(test)]
+pub fn related(r: &Path, branch: &str, hash: &[u8], reverse: bool, transitive: bool) -> Result<Vec<Vec<u8>>, Error> {
+    let repo = try!(Repository::open(&pristine_dir(r)));
+    let txn = try!(repo.txn_begin());
+    related_patches(r, &txn, branch, hash, reverse, transitive)
+}
+
+/// Name of patch `hash`, on one line, or nothing if the patch file
+/// cannot be read.
+fn patch_name(r: &Path, hash: &[u8]) -> String {
+    match Patch::from_repository(r, hash) {
+        Ok(patch) => patch.name.replace("\n", " "),
+        Err(_) => String::new()
+    }
+}
+
+/// Write the graph of dependencies between the patches of branch
+/// `branch` to `w`, in the dot format of Graphviz, with an edge from
+/// each patch to each of its direct dependencies. If `hash` is given,
+/// only this patch and its transitive dependencies (or dependents if
+/// `reverse` is true) are written.
+pub fn dot<W: Write>(r: &Path, branch: &str, hash: Option<&[u8]>, reverse: bool, w: &mut W) -> Result<(), Error> {
+    let repo = try!(Repository::open(&pristine_dir(r)));
+    let txn = try!(repo.txn_begin());
+    if !txn.branch_exists(branch) {
+        return Err(Error::Repository(libpijul::error::Error::BranchNotFound(branch.to_string())))
+    }
+    let nodes: Vec<Vec<u8>> = match hash {
+        Some(hash) => {
+            let mut nodes = try!(related_patches(r, &txn, branch, hash, reverse, true));
+            nodes.push(hash.to_vec());
+            nodes
+        },
+        None => try!(txn.applied_patches(branch))
+    };
+    let selected: HashSet<&Vec<u8>> = nodes.iter().collect();
+    try!(writeln!(w, "digraph dependencies {{"));
+    for node in nodes.iter() {
+        let hex = node.to_hex();
+        let label = format!("{} {}", &hex[..8], patch_name(r, node));
+        try!(writeln!(w, "    \"{}\" [label=\"{}\"];", hex,
+                      label.replace("\\", "\\\\").replace("\"", "\\\"")));
+    }
+    for (dependent, dependency) in try!(txn.dependency_graph(branch, r)) {
+        if selected.contains(&dependent) && selected.contains(&dependency) {
+            try!(writeln!(w, "    \"{}\" -> \"{}\";", dependent.to_hex(), dependency.to_hex()));
+        }
+    }
+    try!(writeln!(w, "}}"));
+    Ok(())
+}
+
+pub fn run(args : &Params) -> Result<(), Error> {
+    let wd=try!(get_wd(args.repository));
+    match find_repo_root(&wd){
+        None => return Err(Error::NotInARepository),
+        Some(ref r) =>
+        {
+            let branch = match args.branch {
+                Some(b) => b.to_string(),
+                None => read_current_branch(r)
+            };
+            if args.dot {
+                let hash = match args.hash {
+                    Some(prefix) => {
+                        let repo = try!(Repository::open(&pristine_dir(r)));
+                        let txn = try!(repo.txn_begin());
+                        Some(try!(find_patch(&txn, &branch, prefix)))
+                    },
+                    None => None
+                };
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                return dot(r, &branch, hash.as_ref().map(|h| &h[..]), args.reverse, &mut stdout)
+            }
+            let repo = try!(Repository::open(&pristine_dir(r)));
+            let txn = try!(repo.txn_begin());
+            match args.hash {
+                Some(prefix) => {
+                    let hash = try!(find_patch(&txn, &branch, prefix));
+                    for h in try!(related_patches(r, &txn, &branch, &hash, args.reverse, args.transitive)) {
+                        println!("{} {}", h.to_hex(), patch_name(r, &h))
+                    }
+                },
+                None => {
+                    for hash in try!(txn.applied_patches(&branch)) {
+                        println!("{} {}", hash.to_hex(), patch_name(r, &hash));
+                        for h in try!(related_patches(r, &txn, &branch, &hash, args.reverse, args.transitive)) {
+                            println!("    {} {}", h.to_hex(), patch_name(r, &h))
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,422 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg};
+
+extern crate libpijul;
+use commands::StaticSubcommand;
+use commands::error::Error;
+use commands::fs_operation::{absolute_path, iter_after};
+use self::libpijul::{Repository, ReadTxn, ReadTransaction, is_binary};
+use self::libpijul::patch::Change;
+use self::libpijul::graph::{FOLDER_EDGE, DELETED_EDGE};
+use self::libpijul::fs_representation::{pristine_dir, find_repo_root, read_current_branch};
+
+extern crate rustc_serialize;
+use self::rustc_serialize::json::{Json, ToJson};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::fs::symlink_metadata;
+use std::cmp::min;
+use super::get_wd;
+
+/// Number of unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("diff")
+        .about("show unrecorded changes")
+        .arg(Arg::with_name("paths")
+             .multiple(true)
+             .help("Only show changes to these files or directories.")
+             )
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("The repository to compare, defaults to the current directory.")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("json")
+             .long("json")
+             .help("Output the changes as JSON.")
+             .takes_value(false)
+             )
+}
+
+pub struct Params<'a> {
+    pub repository : Option<&'a Path>,
+    pub paths : Vec<&'a Path>,
+    pub json : bool
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+    Params { repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }),
+             paths : match args.values_of("paths") {
+                 Some(l) => l.map(|p| Path::new(p)).collect(),
+                 None => vec!()
+             },
+             json : args.is_present("json")
+    }
+}
+
+/// How a tracked file differs from the current branch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Added,
+    Deleted,
+    Moved,
+    Modified
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Status::Added => "added",
+            Status::Deleted => "deleted",
+            Status::Moved => "moved",
+            Status::Modified => "modified",
+        }
+    }
+}
+
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<(char, Vec<u8>)>
+}
+
+pub struct FileDiff {
+    pub path: PathBuf,
+    pub status: Status,
+    pub is_dir: bool,
+    pub is_binary: bool,
+    pub hunks: Vec<Hunk>
+}
+
+impl ToJson for Hunk {
+    fn to_json(&self) -> Json {
+        let mut d = BTreeMap::new();
+        d.insert("old_start".to_string(), self.old_start.to_json());
+        d.insert("old_lines".to_string(), self.old_len.to_json());
+        d.insert("new_start".to_string(), self.new_start.to_json());
+        d.insert("new_lines".to_string(), self.new_len.to_json());
+        d.insert("lines".to_string(), Json::Array(self.lines.iter().map(|&(tag, ref l)| {
+            let mut s = tag.to_string();
+            s.push_str(&String::from_utf8_lossy(l));
+            Json::String(s)
+        }).collect()));
+        Json::Object(d)
+    }
+}
+
+impl ToJson for FileDiff {
+    fn to_json(&self) -> Json {
+        let mut d = BTreeMap::new();
+        d.insert("path".to_string(), Json::String(self.path.to_string_lossy().into_owned()));
+        d.insert("status".to_string(), self.status.as_str().to_json());
+        d.insert("directory".to_string(), self.is_dir.to_json());
+        d.insert("binary".to_string(), self.is_binary.to_json());
+        d.insert("hunks".to_string(), Json::Array(self.hunks.iter().map(|h| h.to_json()).collect()));
+        Json::Object(d)
+    }
+}
+
+/// Files touched by `changes`, in the order in which they were
+/// recorded. A file whose name is only added is new, a file whose name
+/// is only deleted is removed, and a file whose name is replaced has
+/// been moved or had its permissions changed.
+pub fn changed_files(changes: &[(PathBuf, Change)]) -> Vec<(PathBuf, Status)> {
+    let mut files: Vec<(PathBuf, bool, bool)> = Vec::new();
+    for &(ref path, ref change) in changes {
+        let (new_name, deleted_name) = match *change {
+            Change::NewNodes { ref flag, .. } => (flag & FOLDER_EDGE != 0, false),
+            Change::Edges { ref flag, .. } => (false, flag & FOLDER_EDGE != 0 && flag & DELETED_EDGE != 0)
+        };
+        if let Some(f) = files.iter_mut().find(|f| &f.0 == path) {
+            f.1 |= new_name;
+            f.2 |= deleted_name;
+            continue
+        }
+        files.push((path.clone(), new_name, deleted_name))
+    }
+    files.into_iter().map(|(path, new_name, deleted_name)| {
+        let status = match (new_name, deleted_name) {
+            (true, false) => Status::Added,
+            (false, true) => Status::Deleted,
+            (true, true) => Status::Moved,
+            (false, false) => Status::Modified
+        };
+        (path, status)
+    }).collect()
+}
+
+/// Is `path` a directory, in the working copy or, if it was removed
+/// from there, in the tree?
+fn is_directory(txn: &ReadTransaction, r: &Path, path: &Path) -> Result<bool, Error> {
+    match symlink_metadata(&r.join(path)) {
+        Ok(attr) => Ok(attr.is_dir()),
+        Err(_) => {
+            let components: Vec<&[u8]> = path.iter().map(|c| c.to_str().unwrap_or("").as_bytes()).collect();
+            match try!(txn.follow_path(&components).map_err(Error::Repository)) {
+                Some(inode) => Ok(txn.is_directory(&inode)),
+                None => Ok(false)
+            }
+        }
+    }
+}
+
+/// Edit script of file `path`, rendered from `changes`, the changes
+/// `record` found in it. Lines deleted by `Edges` changes are marked
+/// '-', and lines of `NewNodes` changes are marked '+' and placed
+/// after their up context and the deleted lines following it, among
+/// the lines of the file on branch `branch_name` of repository `r`,
+/// marked ' '.
+fn edit_script(txn: &ReadTransaction, branch_name: &str, r: &Path, path: &Path, status: Status, changes: &[&Change])
+               -> Result<Vec<(char, Vec<u8>)>, Error> {
+    let mut deleted = HashSet::new();
+    let mut deleted_keys = Vec::new();
+    let mut added: Vec<(&[u8], &Vec<Vec<u8>>)> = Vec::new();
+    for ch in changes.iter() {
+        match **ch {
+            Change::NewNodes { ref flag, ref up_context, ref nodes, .. } if flag & FOLDER_EDGE == 0 => {
+                added.push((up_context.get(0).map(|k| &k[..]).unwrap_or(&[]), nodes))
+            },
+            Change::Edges { ref flag, ref edges } if flag & FOLDER_EDGE == 0 && flag & DELETED_EDGE != 0 => {
+                // A line with several parents has several edges.
+                for e in edges.iter() {
+                    if deleted.insert(&e.from[..]) {
+                        deleted_keys.push(&e.from[..])
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+    let mut script = Vec::new();
+    match status {
+        Status::Added => {
+            for &(_, nodes) in added.iter() {
+                script.extend(nodes.iter().map(|l| ('+', l.clone())))
+            }
+        },
+        Status::Deleted => {
+            // The file may not be in the working copy or in the tree
+            // anymore, its lines are found from the deleted edges.
+            for key in deleted_keys {
+                script.push(('-', try!(txn.node_contents(key).map_err(Error::Repository))))
+            }
+        },
+        Status::Moved | Status::Modified => {
+            let old = try!(txn.annotate(branch_name, r, path).map_err(Error::Repository));
+            let mut after: HashMap<&[u8], Vec<&Vec<Vec<u8>>>> = HashMap::new();
+            {
+                let keys: HashSet<&[u8]> = old.iter().filter_map(|l| l.key.as_ref().map(|k| &k[..])).collect();
+                for &(up_context, nodes) in added.iter() {
+                    if keys.contains(up_context) {
+                        after.entry(up_context).or_insert(Vec::new()).push(nodes)
+                    } else {
+                        // Lines added at the beginning of the file.
+                        script.extend(nodes.iter().map(|l| ('+', l.clone())))
+                    }
+                }
+            }
+            // Added lines go after the deleted lines that follow their
+            // up context, so that replaced lines read as '-' then '+'.
+            let mut pending = Vec::new();
+            for line in old.iter() {
+                // Conflict markers are not lines of the file.
+                let key = match line.key {
+                    Some(ref key) => &key[..],
+                    None => continue
+                };
+                let is_deleted = deleted.contains(key);
+                if !is_deleted {
+                    script.extend(pending.drain(..))
+                }
+                script.push((if is_deleted { '-' } else { ' ' }, line.contents.clone()));
+                if let Some(blocks) = after.get(key) {
+                    for nodes in blocks.iter() {
+                        pending.extend(nodes.iter().map(|l| ('+', l.clone())))
+                    }
+                }
+            }
+            script.extend(pending)
+        }
+    }
+    Ok(script)
+}
+
+/// Unified diff hunks of the edit script `script`, with `CONTEXT`
+/// lines of context, merging hunks whose contexts overlap.
+fn hunks(script: Vec<(char, Vec<u8>)>) -> Vec<Hunk> {
+    // Positions in the old and new versions before each line.
+    let mut ops = Vec::with_capacity(script.len());
+    let (mut i, mut j) = (0, 0);
+    for (tag, line) in script {
+        ops.push((tag, i, j, line));
+        if tag != '+' { i += 1 }
+        if tag != '-' { j += 1 }
+    }
+    let mut result = Vec::new();
+    let mut k = 0;
+    while k < ops.len() {
+        if ops[k].0 == ' ' {
+            k += 1;
+            continue
+        }
+        let start = if k > CONTEXT { k - CONTEXT } else { 0 };
+        let mut end = k;
+        let mut m = k;
+        while m < ops.len() && (ops[m].0 != ' ' || m < end + 2 * CONTEXT) {
+            if ops[m].0 != ' ' {
+                end = m + 1
+            }
+            m += 1
+        }
+        let stop = min(end + CONTEXT, ops.len());
+        let mut hunk = Hunk { old_start: ops[start].1, old_len: 0,
+                              new_start: ops[start].2, new_len: 0,
+                              lines: Vec::new() };
+        for &(tag, _, _, ref line) in &ops[start..stop] {
+            if tag != '+' { hunk.old_len += 1 }
+            if tag != '-' { hunk.new_len += 1 }
+            hunk.lines.push((tag, line.clone()))
+        }
+        // Line numbers start at 1, and an empty range is numbered
+        // after the line that precedes it.
+        if hunk.old_len > 0 { hunk.old_start += 1 }
+        if hunk.new_len > 0 { hunk.new_start += 1 }
+        result.push(hunk);
+        k = stop
+    }
+    result
+}
+
+fn file_diff(txn: &ReadTransaction, branch_name: &str, r: &Path, path: PathBuf, status: Status, changes: &[&Change])
+             -> Result<FileDiff, Error> {
+    let mut diff = FileDiff { path: path, status: status, is_dir: false, is_binary: false, hunks: Vec::new() };
+    if try!(is_directory(txn, r, &diff.path)) {
+        diff.is_dir = true;
+        return Ok(diff)
+    }
+    let script = try!(edit_script(txn, branch_name, r, &diff.path, status, changes));
+    if script.iter().any(|&(tag, ref line)| tag != ' ' && is_binary(line)) {
+        diff.is_binary = true
+    } else {
+        diff.hunks = hunks(script)
+    }
+    Ok(diff)
+}
+
+fn print_diff(diff: &FileDiff) {
+    let name = diff.path.to_string_lossy();
+    if diff.is_dir {
+        match diff.status {
+            Status::Added => println!("new directory {}", name),
+            Status::Deleted => println!("deleted directory {}", name),
+            _ => println!("moved directory {}", name),
+        }
+        return
+    }
+    if diff.status == Status::Moved {
+        println!("moved {}", name)
+    }
+    if diff.is_binary {
+        println!("binary file {} changed", name);
+        return
+    }
+    if diff.hunks.is_empty() {
+        return
+    }
+    if diff.status == Status::Added {
+        println!("--- /dev/null")
+    } else {
+        println!("--- a/{}", name)
+    }
+    if diff.status == Status::Deleted {
+        println!("+++ /dev/null")
+    } else {
+        println!("+++ b/{}", name)
+    }
+    for h in diff.hunks.iter() {
+        println!("@@ -{},{} +{},{} @@", h.old_start, h.old_len, h.new_start, h.new_len);
+        for &(tag, ref line) in h.lines.iter() {
+            print!("{}{}", tag, String::from_utf8_lossy(line));
+            if line.last() != Some(&b'\n') {
+                println!("");
+                println!("\\ No newline at end of file")
+            }
+        }
+    }
+}
+
+/// Changes between the current branch of the repository at `r` and
+/// its working copy, in the files under one of `prefixes` (relative
+/// to `r`), or in all files if `prefixes` is empty.
+pub fn diffs(r: &Path, prefixes: &[PathBuf]) -> Result<Vec<FileDiff>, Error> {
+    let repo_dir=pristine_dir(r);
+    let branch=read_current_branch(r);
+    let repo = try!(Repository::open(&repo_dir).map_err(Error::Repository));
+    let txn = try!(repo.txn_begin());
+    let (changes, _) = try!(txn.record_with_paths(&branch, &r).map_err(Error::Repository));
+    let mut diffs = Vec::new();
+    for (path, status) in changed_files(&changes) {
+        if prefixes.is_empty() || prefixes.iter().any(|p| path.starts_with(p)) {
+            let file_changes: Vec<&Change> =
+                changes.iter().filter(|c| c.0 == path).map(|c| &c.1).collect();
+            diffs.push(try!(file_diff(&txn, &branch, r, path, status, &file_changes)))
+        }
+    }
+    Ok(diffs)
+}
+
+/// Print the changes between the current branch and the working
+/// copy. Nothing is written to the repository.
+pub fn run(args : &Params) -> Result<Option<()>, Error> {
+    let wd=try!(get_wd(args.repository));
+    match find_repo_root(&wd){
+        None => return Err(Error::NotInARepository),
+        Some(ref r) =>
+        {
+            let mut prefixes = Vec::new();
+            if !args.paths.is_empty() {
+                let cwd = try!(get_wd(None));
+                for p in args.paths.iter() {
+                    let abs = try!(absolute_path(&cwd, p));
+                    match iter_after(abs.components(), r.components()) {
+                        Some(rel) => prefixes.push(rel.as_path().to_path_buf()),
+                        None => return Err(Error::InvalidPath(p.to_string_lossy().into_owned()))
+                    }
+                }
+            }
+            let diffs = try!(diffs(r, &prefixes));
+            if args.json {
+                println!("{}", Json::Array(diffs.iter().map(|d| d.to_json()).collect()).pretty())
+            } else {
+                for d in diffs.iter() {
+                    print_diff(d)
+                }
+            }
+            if diffs.is_empty() { Ok(None) } else { Ok(Some(())) }
+        }
+    }
+}
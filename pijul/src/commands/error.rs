@@ -44,6 +44,17 @@ pub enum Error{
     RemoteApplyFailed(String,i32,String),
     RemoteInitFailed(String,i32,String),
     InvalidPath(String),
+    CurrentBranch(String),
+    UnrecordedChanges,
+    AmbiguousPatch(String),
+    Conflicts(usize),
+    Inconsistencies(usize),
+    UnknownSignaturePolicy(String),
+    NoHomeDirectory,
+    WrongPatchHash(String,String),
+    InvalidGitStream(usize,String),
+    DirectoryNotEmpty(String),
+    CannotPushToUri(String),
 }
 
 impl fmt::Display for Error {
@@ -66,6 +77,17 @@ impl fmt::Display for Error {
             Error::RemoteApplyFailed(ref id,ref code,ref msg) => write!(f, "Remote apply to {} failed with code {} and message:\n{} ", id, code, msg),
             Error::RemoteInitFailed(ref id,ref code,ref msg) => write!(f, "Remote apply to {} failed with code {} and message:\n{} ", id, code, msg),
             Error::InvalidPath(ref p) => write!(f, "Invalid path {}", p),
+            Error::CurrentBranch(ref b) => write!(f, "Branch {} is the current branch", b),
+            Error::UnrecordedChanges => write!(f, "There are unrecorded changes, record or revert them first"),
+            Error::AmbiguousPatch(ref h) => write!(f, "Several patches start with {}", h),
+            Error::Conflicts(n) => write!(f, "{} unresolved conflict{}", n, if n > 1 { "s" } else { "" }),
+            Error::Inconsistencies(n) => write!(f, "{} inconsistenc{} found", n, if n > 1 { "ies" } else { "y" }),
+            Error::UnknownSignaturePolicy(ref p) => write!(f, "Unknown signature policy {:?}, expected \"ignore\", \"warn\" or \"require\"", p),
+            Error::NoHomeDirectory => write!(f, "Could not find the home directory"),
+            Error::WrongPatchHash(ref expected,ref actual) => write!(f, "Downloaded patch {} has hash {}", expected, actual),
+            Error::InvalidGitStream(line,ref msg) => write!(f, "Invalid git fast-export stream, line {}: {}", line, msg),
+            Error::DirectoryNotEmpty(ref p) => write!(f, "Directory {} is not empty", p),
+            Error::CannotPushToUri(ref uri) => write!(f, "Cannot push to {}, HTTP remotes are read-only", uri),
         }
     }
 }
@@ -90,6 +112,17 @@ impl error::Error for Error {
             Error::RemoteApplyFailed(_,_,_) => "Remote apply failed",
             Error::RemoteInitFailed(_,_,_) => "Remote init failed",
             Error::InvalidPath(_) => "Invalid path",
+            Error::CurrentBranch(_) => "Operation on the current branch",
+            Error::UnrecordedChanges => "Unrecorded changes in the working copy",
+            Error::AmbiguousPatch(_) => "Ambiguous patch hash prefix",
+            Error::Conflicts(_) => "Unresolved conflicts",
+            Error::Inconsistencies(_) => "Inconsistent repository",
+            Error::UnknownSignaturePolicy(_) => "Unknown signature policy",
+            Error::NoHomeDirectory => "No home directory",
+            Error::WrongPatchHash(_,_) => "Wrong patch hash",
+            Error::InvalidGitStream(_,_) => "Invalid git fast-export stream",
+            Error::DirectoryNotEmpty(_) => "Directory not empty",
+            Error::CannotPushToUri(_) => "Cannot push to a URI",
         }
     }
 
@@ -112,6 +145,17 @@ impl error::Error for Error {
             Error::RemoteApplyFailed(_,_,_) => None,
             Error::RemoteInitFailed(_,_,_) => None,
             Error::InvalidPath(_) => None,
+            Error::CurrentBranch(_) => None,
+            Error::UnrecordedChanges => None,
+            Error::AmbiguousPatch(_) => None,
+            Error::Conflicts(_) => None,
+            Error::Inconsistencies(_) => None,
+            Error::UnknownSignaturePolicy(_) => None,
+            Error::NoHomeDirectory => None,
+            Error::WrongPatchHash(_,_) => None,
+            Error::InvalidGitStream(_,_) => None,
+            Error::DirectoryNotEmpty(_) => None,
+            Error::CannotPushToUri(_) => None,
         }
     }
 }
@@ -0,0 +1,378 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg};
+
+extern crate libpijul;
+use self::libpijul::{Repository, ReadTxn, OutputFile};
+use self::libpijul::patch::{Patch, Value};
+use self::libpijul::fs_representation::{pristine_dir, find_repo_root, read_current_branch};
+use commands::StaticSubcommand;
+use commands::error::Error;
+use super::get_wd;
+
+extern crate rustc_serialize;
+use self::rustc_serialize::hex::{ToHex, FromHex};
+
+extern crate time;
+extern crate rand;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf, Component};
+
+/// Line of the messages of exported commits giving the hash of their
+/// patch.
+pub const PATCH_TRAILER: &'static str = "Pijul-Patch: ";
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("export-git")
+        .about("write the patches of a branch as a \"git fast-import\" stream, on the standard output")
+        .arg(Arg::with_name("branch")
+             .long("branch")
+             .help("The branch to export, defaults to the current branch.")
+             .takes_value(true))
+        .arg(Arg::with_name("ref")
+             .long("ref")
+             .help("The git reference to write, defaults to refs/heads/<branch>.")
+             .takes_value(true))
+        .arg(Arg::with_name("exported")
+             .long("exported")
+             .help("A file listing the patches exported before, in lines starting with \"Pijul-Patch: \", such as the output of \"git log --format=%B\". Only the other patches are exported, on top of the git reference.")
+             .takes_value(true))
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("The repository to export, defaults to the current directory.")
+             .takes_value(true)
+             .required(false))
+}
+
+pub struct Params<'a> {
+    pub repository : Option<&'a Path>,
+    pub branch : Option<&'a str>,
+    pub git_ref : Option<&'a str>,
+    pub exported : Option<&'a Path>
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+    Params { repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }),
+             branch : args.value_of("branch"),
+             git_ref : args.value_of("ref"),
+             exported : args.value_of("exported").map(Path::new)
+    }
+}
+
+/// Hashes of the patches mentioned in `text`, in lines starting with
+/// `PATCH_TRAILER`.
+pub fn exported_patches(text: &str) -> HashSet<Vec<u8>> {
+    text.lines()
+        .filter_map(|l| {
+            let l = l.trim();
+            if l.starts_with(PATCH_TRAILER) {
+                l[PATCH_TRAILER.len()..].trim().from_hex().ok()
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Seconds since the epoch and offset in seconds of an RFC 3339
+/// timestamp, such as `Patch::timestamp`.
+fn parse_timestamp(timestamp: &str) -> Option<(i64, i32)> {
+    if timestamp.len() < 19 || !timestamp.is_char_boundary(19) {
+        return None
+    }
+    let tm = match time::strptime(&timestamp[..19], "%Y-%m-%dT%H:%M:%S") {
+        Ok(tm) => tm,
+        Err(_) => return None
+    };
+    // Fractions of seconds are ignored.
+    let zone = timestamp[19..].trim_left_matches(|c: char| c == '.' || c.is_digit(10));
+    let offset = if zone == "Z" {
+        0
+    } else if zone.len() == 6 && (zone.starts_with("+") || zone.starts_with("-")) && zone.is_char_boundary(3) {
+        let hours: i32 = match zone[1..3].parse() { Ok(h) => h, Err(_) => return None };
+        let minutes: i32 = match zone[4..].parse() { Ok(m) => m, Err(_) => return None };
+        let offset = hours * 3600 + minutes * 60;
+        if zone.starts_with("-") { -offset } else { offset }
+    } else {
+        return None
+    };
+    Some((tm.to_timespec().sec - offset as i64, offset))
+}
+
+/// `patches` sorted so that each patch comes after its dependencies,
+/// and by timestamp (then hash) otherwise.
+fn order_patches(patches: &HashMap<Vec<u8>, Patch>) -> Vec<Vec<u8>> {
+    let date = |h: &Vec<u8>| parse_timestamp(&patches[h].timestamp).map(|(t, _)| t).unwrap_or(0);
+    // Dependencies on patches not on the branch are ignored.
+    let mut waiting: HashMap<&Vec<u8>, usize> = HashMap::new();
+    let mut dependents: HashMap<&Vec<u8>, Vec<&Vec<u8>>> = HashMap::new();
+    let mut ready = BTreeSet::new();
+    for (h, p) in patches.iter() {
+        let deps: Vec<&Vec<u8>> = p.dependencies.iter().filter(|d| patches.contains_key(*d)).collect();
+        if deps.is_empty() {
+            ready.insert((date(h), h));
+        } else {
+            waiting.insert(h, deps.len());
+            for d in deps {
+                dependents.entry(d).or_insert(Vec::new()).push(h)
+            }
+        }
+    }
+    let mut result = Vec::with_capacity(patches.len());
+    while let Some(next) = ready.iter().next().cloned() {
+        ready.remove(&next);
+        let (_, h) = next;
+        result.push(h.clone());
+        if let Some(dependents) = dependents.get(h) {
+            for d in dependents.iter() {
+                let n = waiting.get_mut(d).unwrap();
+                *n -= 1;
+                if *n == 0 {
+                    ready.insert((date(d), d));
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Path of `file` in git, with '/' as the separator, quoted if needed.
+fn git_path(file: &OutputFile) -> String {
+    let path: Vec<String> = file.path.components().filter_map(|c| match c {
+        Component::Normal(c) => Some(c.to_string_lossy().into_owned()),
+        _ => None
+    }).collect();
+    let path = path.join("/");
+    if !path.starts_with("\"") && !path.contains("\n") {
+        return path
+    }
+    let mut quoted = String::from("\"");
+    for c in path.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c < ' ' => quoted.push_str(&format!("\\{:03o}", c as u8)),
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Identity and date of the author of `patch`, as in git.
+fn git_author(patch: &Patch) -> String {
+    let name = match patch.authors.get(0).and_then(|a| a.get("name")) {
+        Some(&Value::String(ref name)) => name.replace("\n", " "),
+        _ => "Unknown".to_string()
+    };
+    let name = if name.ends_with(">") && name.contains("<") { name } else { format!("{} <>", name) };
+    let (seconds, offset) = parse_timestamp(&patch.timestamp).unwrap_or((0, 0));
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs() / 60;
+    format!("{} {} {}{:02}{:02}", name, seconds, sign, offset / 60, offset % 60)
+}
+
+fn write_data<W: Write>(w: &mut W, data: &[u8]) -> Result<(), Error> {
+    try!(writeln!(w, "data {}", data.len()));
+    try!(w.write_all(data));
+    try!(writeln!(w, ""));
+    Ok(())
+}
+
+/// Git mode of `file`.
+fn git_mode(file: &OutputFile) -> &'static str {
+    if file.symlink {
+        "120000"
+    } else if file.executable {
+        "100755"
+    } else {
+        "100644"
+    }
+}
+
+fn write_file<W: Write>(w: &mut W, path: &str, file: &OutputFile) -> Result<(), Error> {
+    try!(writeln!(w, "M {} inline {}", git_mode(file), path));
+    write_data(w, &file.contents)
+}
+
+/// Create a new directory next to the pristine of `r`, with a name
+/// no other directory has, so that concurrent exports do not share
+/// it.
+fn create_scratch_dir(r: &Path) -> Result<PathBuf, Error> {
+    loop {
+        let suffix: String = (0..8).map(|_| (b'a' + rand::random::<u8>() % 26) as char).collect();
+        let dir = pristine_dir(r).with_file_name(format!("pristine.export-git.{}", suffix));
+        match fs::create_dir(&dir) {
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+            Err(e) => return Err(Error::IO(e)),
+            Ok(()) => return Ok(dir)
+        }
+    }
+}
+
+/// Write the patches of branch `branch` of the repository at `r` to
+/// `w`, as a git fast-import stream updating `git_ref`, with one commit
+/// per patch, in the order of `order_patches`. The tree of each
+/// commit is the output of the patches up to that one.
+///
+/// The patches in `exported` are not written, and the first commit
+/// written is then a child of the current commit of `git_ref`. Returns
+/// the number of commits written.
+///
+/// The patches are replayed in a new scratch pristine in the `.pijul`
+/// directory, removed afterwards: the pristine and the working copy
+/// are left untouched.
+pub fn export<W: Write>(r: &Path,
+                        branch: &str,
+                        git_ref: &str,
+                        exported: &HashSet<Vec<u8>>,
+                        w: &mut W)
+                        -> Result<usize, Error> {
+    let mut patches = HashMap::new();
+    {
+        let repo = try!(Repository::open(&pristine_dir(r)));
+        let txn = try!(repo.txn_begin());
+        if !txn.branch_exists(branch) {
+            return Err(Error::Repository(libpijul::error::Error::BranchNotFound(branch.to_string())))
+        }
+        for hash in try!(txn.applied_patches(branch)) {
+            let patch = try!(Patch::from_repository(r, &hash));
+            patches.insert(hash, patch);
+        }
+    }
+    let scratch_dir = try!(create_scratch_dir(r));
+    let result = replay(r, &scratch_dir, branch, git_ref, &patches, exported, w);
+    try!(fs::remove_dir_all(&scratch_dir));
+    result
+}
+
+/// Apply `patches` in a new pristine in `scratch_dir`, writing a
+/// commit after each patch not in `exported`, with the files the
+/// patch changes.
+fn replay<W: Write>(r: &Path,
+                    scratch_dir: &Path,
+                    branch: &str,
+                    git_ref: &str,
+                    patches: &HashMap<Vec<u8>, Patch>,
+                    exported: &HashSet<Vec<u8>>,
+                    w: &mut W)
+                    -> Result<usize, Error> {
+    let repo = try!(Repository::create(scratch_dir));
+    let mut txn = try!(repo.mut_txn_begin());
+    let mut previous: Option<BTreeMap<String, OutputFile>> = None;
+    let mut written = 0;
+    for hash in order_patches(patches) {
+        try!(txn.apply_to_pristine(branch, r, &hash));
+        if exported.contains(&hash) {
+            continue
+        }
+        let patch = &patches[&hash];
+        let author = git_author(patch);
+        try!(writeln!(w, "commit {}", git_ref));
+        try!(writeln!(w, "author {}", author));
+        try!(writeln!(w, "committer {}", author));
+        let mut message = patch.name.clone();
+        if let Some(ref description) = patch.description {
+            message.push_str("\n\n");
+            message.push_str(description)
+        }
+        message.push_str(&format!("\n\n{}{}\n", PATCH_TRAILER, hash.to_hex()));
+        try!(write_data(w, message.as_bytes()));
+        let files = match previous {
+            Some(mut files) => {
+                // Only the files below the paths the patch touches
+                // can have changed.
+                let paths = try!(txn.patch_paths(branch, &hash, patch));
+                let changed: BTreeMap<String, OutputFile> = try!(txn.output_files_in(branch, &paths))
+                    .into_iter()
+                    .map(|f| (git_path(&f), f))
+                    .collect();
+                let removed: Vec<String> = files.iter()
+                    .filter(|&(p, f)| !changed.contains_key(p) && paths.iter().any(|q| f.path.starts_with(q)))
+                    .map(|(p, _)| p.clone())
+                    .collect();
+                for path in removed.iter() {
+                    try!(writeln!(w, "D {}", path));
+                    files.remove(path);
+                }
+                for (path, file) in changed.into_iter() {
+                    if files.get(&path) != Some(&file) {
+                        try!(write_file(w, &path, &file));
+                        files.insert(path, file);
+                    }
+                }
+                files
+            },
+            None => {
+                if !exported.is_empty() {
+                    try!(writeln!(w, "from {}^0", git_ref))
+                }
+                try!(writeln!(w, "deleteall"));
+                let files: BTreeMap<String, OutputFile> = try!(txn.output_files(branch))
+                    .into_iter()
+                    .map(|f| (git_path(&f), f))
+                    .collect();
+                for (path, file) in files.iter() {
+                    try!(write_file(w, path, file))
+                }
+                files
+            }
+        };
+        try!(writeln!(w, ""));
+        previous = Some(files);
+        written += 1
+    }
+    Ok(written)
+}
+
+pub fn run(args : &Params) -> Result<(), Error> {
+    let wd=try!(get_wd(args.repository));
+    match find_repo_root(&wd){
+        None => return Err(Error::NotInARepository),
+        Some(ref r) =>
+        {
+            let branch = match args.branch {
+                Some(b) => b.to_string(),
+                None => read_current_branch(r)
+            };
+            let git_ref = match args.git_ref {
+                Some(git_ref) => git_ref.to_string(),
+                None => format!("refs/heads/{}", branch)
+            };
+            let exported = match args.exported {
+                Some(path) => {
+                    let mut text = String::new();
+                    try!(try!(File::open(path)).read_to_string(&mut text));
+                    exported_patches(&text)
+                },
+                None => HashSet::new()
+            };
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            try!(export(r, &branch, &git_ref, &exported, &mut stdout));
+            Ok(())
+        }
+    }
+}
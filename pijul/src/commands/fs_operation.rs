@@ -4,8 +4,8 @@ extern crate libpijul;
 use clap::ArgMatches;
 use self::libpijul::{Repository};
 use self::libpijul::fs_representation::{repo_dir, pristine_dir, find_repo_root};
-use std::path::{Path};
-use std::fs::{metadata, canonicalize};
+use std::path::{Path, PathBuf};
+use std::fs::{symlink_metadata, canonicalize};
 use commands::error;
 use super::get_wd;
 #[derive(Debug)]
@@ -45,8 +45,8 @@ pub fn run<'a>(args : &Params<'a>, op : Operation)
             match op {
                 Operation::Add =>{
                     for file in &files[..] {
-                        let p=try!(canonicalize(wd.join(*file)));
-                        let m=try!(metadata(&p));
+                        let p=try!(absolute_path(&wd, file));
+                        let m=try!(symlink_metadata(&p));
                         if let Some(file)=iter_after(p.components(), r.components()) {
                             try!(txn.add_file(file.as_path(),m.is_dir()))
                         } else {
@@ -56,7 +56,7 @@ pub fn run<'a>(args : &Params<'a>, op : Operation)
                 },
                 Operation::Remove => {
                     for file in &files[..] {
-                        let p=try!(canonicalize(wd.join(*file)));
+                        let p=try!(absolute_path(&wd, file));
                         if let Some(file)=iter_after(p.components(), r.components()) {
                             try!(txn.remove_file(file.as_path()))
                         } else {
@@ -71,8 +71,18 @@ pub fn run<'a>(args : &Params<'a>, op : Operation)
     }
 }
 
+/// Absolute path of `file`. Symbolic links are tracked as links, so
+/// only the parent directory is canonicalized.
+pub fn absolute_path(wd:&Path, file:&Path) -> Result<PathBuf, error::Error> {
+    let p=wd.join(file);
+    match (p.parent(), p.file_name()) {
+        (Some(parent), Some(name)) => Ok(try!(canonicalize(parent)).join(name)),
+        _ => Ok(try!(canonicalize(&p)))
+    }
+}
+
 /// Ce morceau vient de path.rs du projet Rust, sous licence Apache/MIT.
-fn iter_after<A, I, J>(mut iter: I, mut prefix: J) -> Option<I> where
+pub fn iter_after<A, I, J>(mut iter: I, mut prefix: J) -> Option<I> where
     I: Iterator<Item=A> + Clone, J: Iterator<Item=A>, A: PartialEq
 {
     loop {
@@ -0,0 +1,677 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg};
+
+extern crate libpijul;
+use self::libpijul::{Repository, ReadTxn, Transaction};
+use self::libpijul::patch::{Patch, Value};
+use self::libpijul::fs_representation::{pristine_dir, read_current_branch, PIJUL_DIR_NAME};
+use commands::StaticSubcommand;
+use commands::error::Error;
+use super::init;
+
+extern crate time;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf, Component};
+use std::rc::Rc;
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("import-git")
+        .about("create a repository from git history, read from the output of \"git fast-export\" on the standard input")
+        .arg(Arg::with_name("directory")
+             .index(1)
+             .help("Where to create the repository, defaults to the current directory.")
+             .required(false))
+        .arg(Arg::with_name("ref")
+             .long("ref")
+             .help("The git reference to import, defaults to the one of the current branch (refs/heads/master in a new repository).")
+             .takes_value(true)
+             .required(false))
+}
+
+pub struct Params<'a> {
+    pub location : &'a Path,
+    pub reference : Option<&'a str>
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+    Params { location : Path::new(args.value_of("directory").unwrap_or(".")),
+             reference : args.value_of("ref") }
+}
+
+/// A file in a git tree.
+#[derive(Debug, Clone, PartialEq)]
+struct GitFile {
+    symlink: bool,
+    executable: bool,
+    contents: Rc<Vec<u8>>
+}
+
+type Tree = BTreeMap<PathBuf, GitFile>;
+
+/// A git fast-export stream, read line by line, except for the
+/// contents of "data" commands.
+struct Stream<R> {
+    reader: R,
+    line: usize,
+    unread: Option<String>
+}
+
+impl<R: BufRead> Stream<R> {
+    fn next_line(&mut self) -> Result<Option<String>, Error> {
+        if let Some(line) = self.unread.take() {
+            self.line += 1;
+            return Ok(Some(line))
+        }
+        let mut buf = Vec::new();
+        if try!(self.reader.read_until(b'\n', &mut buf)) == 0 {
+            return Ok(None)
+        }
+        self.line += 1;
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+        }
+        String::from_utf8(buf).map(Some).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    /// Put `line` back, to be returned by the next call to `next_line`.
+    fn unread(&mut self, line: String) {
+        self.line -= 1;
+        self.unread = Some(line)
+    }
+
+    fn error(&self, msg: &str) -> Error {
+        Error::InvalidGitStream(self.line, msg.to_string())
+    }
+
+    /// Read a "data <size>" line, followed by the data itself.
+    fn data(&mut self) -> Result<Vec<u8>, Error> {
+        let size = match try!(self.next_line()) {
+            Some(ref line) if line.starts_with("data ") => line["data ".len()..].parse().ok(),
+            _ => None
+        };
+        let size: usize = match size {
+            Some(size) => size,
+            None => return Err(self.error("expected \"data <size>\""))
+        };
+        let mut data = vec![0; size];
+        try!(self.reader.read_exact(&mut data));
+        self.line += data.iter().filter(|&&c| c == b'\n').count();
+        // The data may be followed by an optional newline.
+        let newline = try!(self.reader.fill_buf()).first() == Some(&b'\n');
+        if newline {
+            self.reader.consume(1);
+            self.line += 1
+        }
+        Ok(data)
+    }
+}
+
+/// Split `s` at its first space.
+fn first_word(s: &str) -> (&str, &str) {
+    match s.find(' ') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => (s, "")
+    }
+}
+
+/// Parse a path, quoted as a C string if it starts with a double
+/// quote, and return it along with the rest of `s`. Unquoted paths
+/// end at the first space, unless `last` is set.
+fn parse_path(s: &str, last: bool) -> Result<(PathBuf, &str), String> {
+    let (bytes, rest) = if s.starts_with("\"") {
+        let b = s.as_bytes();
+        let mut path = Vec::new();
+        let mut i = 1;
+        loop {
+            match b.get(i) {
+                None => return Err("unterminated path".to_string()),
+                Some(&b'"') => break,
+                Some(&b'\\') => {
+                    let c = match b.get(i + 1) {
+                        Some(&b'a') => 7,
+                        Some(&b'b') => 8,
+                        Some(&b'f') => 12,
+                        Some(&b'n') => b'\n',
+                        Some(&b'r') => b'\r',
+                        Some(&b't') => b'\t',
+                        Some(&b'v') => 11,
+                        Some(&c) if c >= b'0' && c <= b'3' && i + 3 < b.len() => {
+                            let octal = try!(::std::str::from_utf8(&b[i + 1..i + 4])
+                                             .ok()
+                                             .and_then(|o| u8::from_str_radix(o, 8).ok())
+                                             .ok_or("invalid escape in path".to_string()));
+                            i += 2;
+                            octal
+                        },
+                        Some(&c) => c,
+                        None => return Err("unterminated path".to_string())
+                    };
+                    path.push(c);
+                    i += 2
+                },
+                Some(&c) => {
+                    path.push(c);
+                    i += 1
+                }
+            }
+        }
+        (path, &s[i + 1..])
+    } else if last {
+        (s.as_bytes().to_vec(), "")
+    } else {
+        let (path, rest) = first_word(s);
+        (path.as_bytes().to_vec(), rest)
+    };
+    let path = PathBuf::from(try!(String::from_utf8(bytes).map_err(|_| "invalid UTF-8 in path".to_string())));
+    // Paths come from another repository, make sure they stay in ours.
+    let valid = {
+        let mut components = path.components();
+        let first = match components.next() {
+            Some(Component::Normal(c)) => c != PIJUL_DIR_NAME,
+            _ => false
+        };
+        first && components.all(|c| match c { Component::Normal(_) => true, _ => false })
+    };
+    if valid {
+        Ok((path, rest.trim_left()))
+    } else {
+        Err(format!("invalid path {:?}", path))
+    }
+}
+
+/// Parse the identity and date of an author or committer line, as
+/// "Name <email> <seconds> <offset>".
+fn parse_person(s: &str) -> Result<(String, time::Tm), String> {
+    let end = match s.rfind('>') {
+        Some(end) => end,
+        None => return Err("expected an email address".to_string())
+    };
+    let name = s[..end + 1].trim().to_string();
+    let mut date = s[end + 1..].split_whitespace();
+    let seconds = date.next().and_then(|d| d.parse::<i64>().ok());
+    let offset = date.next().and_then(|o| if o.len() == 5 && o.bytes().all(|b| b < 128) { Some(o) } else { None });
+    match (seconds, offset) {
+        (Some(seconds), Some(offset)) => {
+            let sign = if offset.starts_with("-") { -1 } else { 1 };
+            let hours: i32 = try!(offset[1..3].parse().map_err(|_| "invalid time zone".to_string()));
+            let minutes: i32 = try!(offset[3..].parse().map_err(|_| "invalid time zone".to_string()));
+            let offset = sign * (hours * 3600 + minutes * 60);
+            // Keep the time zone of the original commit.
+            let mut tm = time::at_utc(time::Timespec::new(seconds + offset as i64, 0));
+            tm.tm_utcoff = offset;
+            Ok((name, tm))
+        },
+        _ => Err("expected a date as \"<seconds> <offset>\"".to_string())
+    }
+}
+
+/// Apply the file operation `op`, with arguments `args`, to `tree`.
+fn file_operation<R: BufRead>(stream: &mut Stream<R>,
+                              blobs: &HashMap<String, Rc<Vec<u8>>>,
+                              tree: &mut Tree,
+                              op: &str,
+                              args: &str)
+                              -> Result<(), Error> {
+    match op {
+        "M" => {
+            let (mode, args) = first_word(args);
+            let (dataref, path) = first_word(args);
+            let (path, _) = try!(parse_path(path, true).map_err(|e| stream.error(&e)));
+            let (symlink, executable) = match mode {
+                "100644" | "644" => (false, false),
+                "100755" | "755" => (false, true),
+                "120000" => (true, false),
+                // Submodules are not imported.
+                "160000" => return Ok(()),
+                _ => return Err(stream.error(&format!("unsupported mode {}", mode)))
+            };
+            let contents = if dataref == "inline" {
+                Rc::new(try!(stream.data()))
+            } else {
+                match blobs.get(dataref) {
+                    Some(contents) => contents.clone(),
+                    None => return Err(stream.error(&format!("unknown blob {}", dataref)))
+                }
+            };
+            tree.insert(path, GitFile { symlink: symlink, executable: executable, contents: contents });
+        },
+        "D" => {
+            let (path, _) = try!(parse_path(args, true).map_err(|e| stream.error(&e)));
+            let removed: Vec<PathBuf> = tree.keys().filter(|p| p.starts_with(&path)).cloned().collect();
+            for p in removed.iter() {
+                tree.remove(p);
+            }
+        },
+        "R" | "C" => {
+            let (source, rest) = try!(parse_path(args, false).map_err(|e| stream.error(&e)));
+            let (dest, _) = try!(parse_path(rest, true).map_err(|e| stream.error(&e)));
+            // The source can be a directory.
+            let moved: Vec<(PathBuf, GitFile)> = tree.iter()
+                .filter(|&(p, _)| p.starts_with(&source))
+                .map(|(p, f)| (p.clone(), f.clone()))
+                .collect();
+            if moved.is_empty() {
+                return Err(stream.error(&format!("{} is not in the tree", source.display())))
+            }
+            for &(ref p, ref f) in moved.iter() {
+                if op == "R" {
+                    tree.remove(p);
+                }
+                let suffix = p.strip_prefix(&source).unwrap().to_path_buf();
+                let target = if suffix.as_os_str().is_empty() { dest.clone() } else { dest.join(suffix) };
+                tree.insert(target, f.clone());
+            }
+        },
+        "deleteall" => tree.clear(),
+        _ => unreachable!()
+    }
+    Ok(())
+}
+
+/// A commit, with its first parent, given by its position in
+/// `History::commits`.
+struct Commit {
+    mark: Option<String>,
+    parent: Option<usize>,
+    author: Option<(String, time::Tm)>,
+    committer: Option<(String, time::Tm)>,
+    message: Vec<u8>,
+    tree: Tree
+}
+
+/// The commits of a git fast-export stream, and their trees.
+struct History {
+    blobs: HashMap<String, Rc<Vec<u8>>>,
+    /// Commits, in the order of the stream.
+    commits: Vec<Commit>,
+    /// Positions of the commits in `commits`, by mark.
+    marks: HashMap<String, usize>,
+    /// Tips of the references, `None` for the empty commit.
+    refs: HashMap<String, Option<usize>>
+}
+
+impl History {
+    /// Commit `commit`, given as a mark or a reference.
+    fn commit_of<R: BufRead>(&self, stream: &Stream<R>, commit: &str) -> Result<Option<usize>, Error> {
+        if commit.starts_with(":") {
+            match self.marks.get(commit) {
+                Some(&i) => Ok(Some(i)),
+                None => Err(stream.error(&format!("unknown commit {}", commit)))
+            }
+        } else if commit.len() == 40 && commit.chars().all(|c| c == '0') {
+            Ok(None)
+        } else {
+            match self.refs.get(commit) {
+                Some(&tip) => Ok(tip),
+                None => Err(stream.error(&format!("unknown commit {}, only marks and references are supported", commit)))
+            }
+        }
+    }
+
+    /// Tip of `reference`, the empty commit if it does not exist yet.
+    fn tip(&self, reference: &str) -> Option<usize> {
+        self.refs.get(reference).cloned().unwrap_or(None)
+    }
+
+    /// Tree of `commit`.
+    fn tree(&self, commit: Option<usize>) -> Tree {
+        commit.map(|i| self.commits[i].tree.clone()).unwrap_or(Tree::new())
+    }
+
+    /// The commits from the first one to the tip of `reference`,
+    /// following first parents only.
+    fn first_parents(&self, reference: &str) -> Vec<usize> {
+        let mut chain = Vec::new();
+        let mut commit = self.tip(reference);
+        while let Some(i) = commit {
+            chain.push(i);
+            commit = self.commits[i].parent
+        }
+        chain.reverse();
+        chain
+    }
+
+    fn blob<R: BufRead>(&mut self, stream: &mut Stream<R>) -> Result<(), Error> {
+        let mut mark = None;
+        loop {
+            match try!(stream.next_line()) {
+                Some(ref line) if line.starts_with("mark ") => mark = Some(line["mark ".len()..].to_string()),
+                Some(ref line) if line.starts_with("original-oid ") => {},
+                Some(line) => {
+                    stream.unread(line);
+                    break
+                },
+                None => break
+            }
+        }
+        let data = Rc::new(try!(stream.data()));
+        if let Some(mark) = mark {
+            self.blobs.insert(mark, data);
+        }
+        Ok(())
+    }
+
+    fn reset<R: BufRead>(&mut self, stream: &mut Stream<R>, reference: &str) -> Result<(), Error> {
+        match try!(stream.next_line()) {
+            Some(ref line) if line.starts_with("from ") => {
+                let tip = try!(self.commit_of(stream, &line["from ".len()..]));
+                self.refs.insert(reference.to_string(), tip);
+            },
+            line => {
+                if let Some(line) = line {
+                    stream.unread(line)
+                }
+                self.refs.remove(reference);
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip an annotated tag, which has no equivalent here.
+    fn tag<R: BufRead>(&mut self, stream: &mut Stream<R>) -> Result<(), Error> {
+        loop {
+            match try!(stream.next_line()) {
+                Some(ref line) if line.starts_with("data ") => {
+                    stream.unread(line.clone());
+                    try!(stream.data());
+                    return Ok(())
+                },
+                Some(_) => {},
+                None => return Err(stream.error("unterminated tag"))
+            }
+        }
+    }
+
+    fn commit<R: BufRead>(&mut self, stream: &mut Stream<R>, reference: &str) -> Result<(), Error> {
+        let mut commit = Commit {
+            mark: None,
+            parent: None,
+            author: None,
+            committer: None,
+            message: Vec::new(),
+            tree: Tree::new()
+        };
+        // The tree starts from the first parent, or from the tip of
+        // the reference if there is no "from".
+        let mut base: Option<(Option<usize>, Tree)> = None;
+        loop {
+            let line = match try!(stream.next_line()) {
+                Some(line) => line,
+                None => break
+            };
+            if line.is_empty() {
+                break
+            }
+            let (command, args) = first_word(&line);
+            match command {
+                "mark" => commit.mark = Some(args.to_string()),
+                "original-oid" | "encoding" => {},
+                "author" => commit.author = Some(try!(parse_person(args).map_err(|e| stream.error(&e)))),
+                "committer" => commit.committer = Some(try!(parse_person(args).map_err(|e| stream.error(&e)))),
+                "data" => {
+                    stream.unread(line.clone());
+                    commit.message = try!(stream.data())
+                },
+                "from" => {
+                    let parent = try!(self.commit_of(stream, args));
+                    base = Some((parent, self.tree(parent)))
+                },
+                // The merged state is the result of the file
+                // operations, only check that the parent exists.
+                "merge" => { try!(self.commit_of(stream, args)); },
+                "M" | "D" | "R" | "C" | "deleteall" => {
+                    if base.is_none() {
+                        let tip = self.tip(reference);
+                        base = Some((tip, self.tree(tip)))
+                    }
+                    try!(file_operation(stream, &self.blobs, &mut base.as_mut().unwrap().1, command, args))
+                },
+                "N" => {
+                    // Notes are not imported.
+                    if first_word(args).0 == "inline" {
+                        try!(stream.data());
+                    }
+                },
+                _ => {
+                    stream.unread(line.clone());
+                    break
+                }
+            }
+        }
+        let (parent, tree) = match base {
+            Some(base) => base,
+            None => {
+                let tip = self.tip(reference);
+                (tip, self.tree(tip))
+            }
+        };
+        commit.parent = parent;
+        commit.tree = tree;
+        if commit.committer.is_none() && commit.author.is_none() {
+            return Err(stream.error("commit without a committer"))
+        }
+        let i = self.commits.len();
+        if let Some(ref mark) = commit.mark {
+            self.marks.insert(mark.clone(), i);
+        }
+        self.refs.insert(reference.to_string(), Some(i));
+        self.commits.push(commit);
+        Ok(())
+    }
+}
+
+/// Read the git fast-export stream `input`, which must have commits
+/// of `reference`.
+fn read_history<R: BufRead>(input: R, reference: &str) -> Result<History, Error> {
+    let mut history = History {
+        blobs: HashMap::new(),
+        commits: Vec::new(),
+        marks: HashMap::new(),
+        refs: HashMap::new()
+    };
+    let mut stream = Stream { reader: input, line: 0, unread: None };
+    loop {
+        let line = match try!(stream.next_line()) {
+            Some(line) => line,
+            None => break
+        };
+        let (command, args) = first_word(&line);
+        match command {
+            "" => {},
+            "blob" => try!(history.blob(&mut stream)),
+            "commit" => try!(history.commit(&mut stream, args)),
+            "reset" => try!(history.reset(&mut stream, args)),
+            "tag" => try!(history.tag(&mut stream)),
+            "progress" | "checkpoint" | "feature" | "option" => {},
+            "done" => break,
+            _ => return Err(stream.error(&format!("unknown command {:?}", command)))
+        }
+    }
+    if history.refs.contains_key(reference) {
+        Ok(history)
+    } else {
+        Err(stream.error(&format!("no commit of {}", reference)))
+    }
+}
+
+/// Records commits as patches in a pijul repository.
+struct Importer<'a> {
+    location: &'a Path,
+    branch: String,
+    /// Tree of the working copy.
+    current: Tree,
+    imported: usize
+}
+
+impl<'a> Importer<'a> {
+    /// Make the working copy and the tree of `txn` match `tree`.
+    fn update_working_copy(&self, txn: &mut Transaction, tree: &Tree) -> Result<(), Error> {
+        let mut dirs = HashSet::new();
+        for path in tree.keys() {
+            let mut path = path.as_path();
+            while let Some(parent) = path.parent() {
+                dirs.insert(parent.to_path_buf());
+                path = parent
+            }
+        }
+        // Remove files first, in case they are replaced by
+        // directories, and then the directories left empty.
+        let mut removed_dirs = Vec::new();
+        for path in self.current.keys().filter(|p| !tree.contains_key(*p)) {
+            try!(fs::remove_file(self.location.join(path)));
+            try!(txn.remove_file(path));
+            let mut path = path.as_path();
+            while let Some(parent) = path.parent() {
+                if !parent.as_os_str().is_empty() && !dirs.contains(parent) {
+                    removed_dirs.push(parent.to_path_buf())
+                }
+                path = parent
+            }
+        }
+        removed_dirs.sort();
+        removed_dirs.dedup();
+        for dir in removed_dirs.iter().rev() {
+            try!(fs::remove_dir(self.location.join(dir)));
+            try!(txn.remove_file(dir));
+        }
+        for (path, file) in tree.iter() {
+            let old = self.current.get(path);
+            if old == Some(file) {
+                continue
+            }
+            let full_path = self.location.join(path);
+            if old.is_some() {
+                try!(fs::remove_file(&full_path))
+            } else if let Some(parent) = full_path.parent() {
+                try!(fs::create_dir_all(parent))
+            }
+            if file.symlink {
+                try!(write_symlink(&full_path, &file.contents))
+            } else {
+                try!(fs::File::create(&full_path).and_then(|mut f| {
+                    use std::io::Write;
+                    f.write_all(&file.contents)
+                }));
+                try!(set_executable(&full_path, file.executable))
+            }
+            if old.is_none() {
+                try!(txn.add_file(path, false))
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the changes of `commit` as a patch, with the author,
+    /// date and message of the commit.
+    fn record(&mut self, commit: &Commit) -> Result<(), Error> {
+        let repo = try!(Repository::open(&pristine_dir(self.location)).map_err(Error::Repository));
+        let mut txn = try!(repo.mut_txn_begin());
+        try!(self.update_working_copy(&mut txn, &commit.tree));
+        self.current = commit.tree.clone();
+        let (changes, syncs) = try!(txn.record(&self.branch, self.location));
+        if changes.is_empty() {
+            debug!("nothing to record for {:?}", commit.mark);
+            return Ok(try!(txn.commit()))
+        }
+        let (author, timestamp) = commit.author.clone().or(commit.committer.clone()).unwrap();
+        let mut authors = BTreeMap::new();
+        authors.insert("name".to_string(), Value::String(author));
+        let message = String::from_utf8_lossy(&commit.message).into_owned();
+        let mut lines = message.splitn(2, '\n');
+        let name = lines.next().unwrap_or("").trim().to_string();
+        let description = lines.next().map(|d| d.trim().to_string()).and_then(|d| if d.is_empty() { None } else { Some(d) });
+        let patch = Patch::new(vec![authors], name, description, timestamp, changes);
+        try!(txn.apply_local_patch(&self.branch, self.location, patch, &syncs, None));
+        try!(txn.commit());
+        self.imported += 1;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn write_symlink(path: &Path, target: &[u8]) -> Result<(), Error> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::ffi::OsStr;
+    Ok(try!(::std::os::unix::fs::symlink(OsStr::from_bytes(target), path)))
+}
+
+#[cfg(not(unix))]
+fn write_symlink(path: &Path, target: &[u8]) -> Result<(), Error> {
+    use std::io::Write;
+    Ok(try!(try!(fs::File::create(path)).write_all(target)))
+}
+
+/// Give `path` the permissions of git modes 100755 and 100644.
+#[cfg(unix)]
+fn set_executable(path: &Path, executable: bool) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = if executable { 0o755 } else { 0o644 };
+    Ok(try!(fs::set_permissions(path, fs::Permissions::from_mode(mode))))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_: &Path, _: bool) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Replay the commits of `reference` in the git fast-export stream
+/// `input` in the repository at `location`, each of them becoming a
+/// patch on the current branch. Returns the number of patches
+/// recorded.
+///
+/// Only the commits on the first-parent chain of the tip of
+/// `reference` are recorded, whatever reference the stream labels
+/// them with: "git fast-export <ref>" labels all the commits it
+/// writes with `<ref>`, including those of merged branches. The
+/// other commits are only read, for their trees. The working copy
+/// goes from the tree of each recorded commit to the tree of the
+/// next one, so that merge commits record the merged state, and the
+/// working copy ends up as the tip of `reference`.
+pub fn import<R: BufRead>(location: &Path, reference: &str, input: R) -> Result<usize, Error> {
+    let history = try!(read_history(input, reference));
+    let mut importer = Importer {
+        location: location,
+        branch: read_current_branch(location),
+        current: Tree::new(),
+        imported: 0
+    };
+    for i in history.first_parents(reference) {
+        try!(importer.record(&history.commits[i]))
+    }
+    Ok(importer.imported)
+}
+
+pub fn run(params : &Params) -> Result<(), Error> {
+    try!(fs::create_dir_all(params.location));
+    try!(init::run(&init::Params { location: params.location, allow_nested: false }));
+    let reference = match params.reference {
+        Some(reference) => reference.to_string(),
+        None => format!("refs/heads/{}", read_current_branch(params.location))
+    };
+    let stdin = io::stdin();
+    let imported = try!(import(params.location, &reference, stdin.lock()));
+    println!("Imported {} commits", imported);
+    Ok(())
+}
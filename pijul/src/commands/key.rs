@@ -0,0 +1,159 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg};
+
+extern crate libpijul;
+use self::libpijul::signature::SigningKey;
+use commands::StaticSubcommand;
+use commands::error::Error;
+use super::super::meta::Meta;
+
+extern crate rustc_serialize;
+use self::rustc_serialize::hex::ToHex;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_KEY_NAME: &'static str = "default";
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("key")
+        .about("Manage the keys used to sign patches")
+        .arg(Arg::with_name("keys-dir")
+             .long("keys-dir")
+             .help("The directory where keys are stored, defaults to ~/.pijul/keys.")
+             .takes_value(true)
+             .required(false))
+        .subcommand(SubCommand::with_name("generate")
+                    .about("Generate a new Ed25519 key")
+                    .arg(Arg::with_name("name")
+                         .help("Name of the new key, defaults to \"default\".")))
+        .subcommand(SubCommand::with_name("list")
+                    .about("List keys, with their public keys"))
+        .subcommand(SubCommand::with_name("export")
+                    .about("Print the public key of a key, as listed in trusted_keys")
+                    .arg(Arg::with_name("name")
+                         .help("Name of the key, defaults to \"default\".")))
+}
+
+#[derive(Debug)]
+pub enum Action<'a> {
+    Generate { name: &'a str },
+    List,
+    Export { name: &'a str },
+}
+
+pub struct Params<'a> {
+    pub keys_dir : Option<&'a Path>,
+    pub action : Action<'a>
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+    let action = match args.subcommand() {
+        ("generate", Some(args)) => Action::Generate {
+            name: args.value_of("name").unwrap_or(DEFAULT_KEY_NAME)
+        },
+        ("export", Some(args)) => Action::Export {
+            name: args.value_of("name").unwrap_or(DEFAULT_KEY_NAME)
+        },
+        _ => Action::List
+    };
+    Params {
+        keys_dir : args.value_of("keys-dir").and_then(|x| { Some(Path::new(x)) }),
+        action : action
+    }
+}
+
+/// Directory where keys are stored by default.
+pub fn default_keys_dir() -> Result<PathBuf, Error> {
+    match env::home_dir() {
+        Some(home) => Ok(home.join(".pijul").join("keys")),
+        None => Err(Error::NoHomeDirectory)
+    }
+}
+
+/// Key used to sign the patches recorded in a repository with meta
+/// data `meta`: the key at `signing_key` if set, else the default
+/// key of `keys_dir` (or of `default_keys_dir()`), if it exists.
+pub fn signing_key(meta: &Meta, keys_dir: Option<&Path>) -> Result<Option<SigningKey>, Error> {
+    match meta.signing_key {
+        Some(ref path) => Ok(Some(try!(SigningKey::load(Path::new(path))))),
+        None => {
+            let path = match keys_dir {
+                Some(dir) => dir.join(DEFAULT_KEY_NAME),
+                None => match default_keys_dir() {
+                    Ok(dir) => dir.join(DEFAULT_KEY_NAME),
+                    Err(_) => return Ok(None)
+                }
+            };
+            if fs::metadata(&path).is_ok() {
+                Ok(Some(try!(SigningKey::load(&path))))
+            } else {
+                Ok(None)
+            }
+        }
+    }
+}
+
+pub fn run(params : &Params) -> Result<(), Error> {
+    let keys_dir = match params.keys_dir {
+        Some(dir) => dir.to_path_buf(),
+        None => try!(default_keys_dir())
+    };
+    debug!("key: {:?} in {:?}", params.action, keys_dir);
+    match params.action {
+        Action::Generate { name } => {
+            try!(fs::create_dir_all(&keys_dir));
+            let key = try!(SigningKey::generate());
+            let path = keys_dir.join(name);
+            try!(key.save(&path));
+            println!("Generated key {} in {}", key.public_key().to_hex(), path.display());
+            Ok(())
+        },
+        Action::List => {
+            let mut names = Vec::new();
+            match fs::read_dir(&keys_dir) {
+                Ok(entries) => {
+                    for entry in entries {
+                        names.push(try!(entry).path())
+                    }
+                },
+                // No key was ever generated.
+                Err(_) => {}
+            }
+            names.sort();
+            for path in names.iter() {
+                let name = path.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or(String::new());
+                match SigningKey::load(path) {
+                    Ok(key) => println!("{} {}", name, key.public_key().to_hex()),
+                    Err(_) => println!("{} (not a key)", name)
+                }
+            }
+            Ok(())
+        },
+        Action::Export { name } => {
+            let key = try!(SigningKey::load(&keys_dir.join(name)));
+            println!("{}", key.public_key().to_hex());
+            Ok(())
+        }
+    }
+}
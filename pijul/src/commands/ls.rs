@@ -20,7 +20,7 @@ use commands::StaticSubcommand;
 use clap::{SubCommand, ArgMatches,Arg};
 extern crate libpijul;
 use commands::error;
-use self::libpijul::{Repository};
+use self::libpijul::{Repository,ReadTxn};
 use self::libpijul::fs_representation::{repo_dir, pristine_dir, find_repo_root};
 use std::path::Path;
 use super::get_wd;
@@ -55,7 +55,7 @@ pub fn run<'a>(args : &Params<'a>) -> Result<(), error::Error> {
         {
             let repo_dir=pristine_dir(r);
             let repo = try!(Repository::open(&repo_dir).map_err(error::Error::Repository));
-            let txn = try!(repo.mut_txn_begin());
+            let txn = try!(repo.txn_begin());
             let files = txn.list_files();
             for f in files {
                 println!("{:?}",f)
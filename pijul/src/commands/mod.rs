@@ -33,12 +33,26 @@ pub mod add;
 pub mod pull;
 pub mod push;
 pub mod apply;
+pub mod unrecord;
+pub mod branch;
+pub mod checkout;
 pub mod clone;
 pub mod remove;
 pub mod mv;
 pub mod ls;
 pub mod revert;
 pub mod login;
+pub mod diff;
+pub mod status;
+pub mod changes;
+pub mod patch;
+pub mod blame;
+pub mod conflicts;
+pub mod key;
+pub mod import_git;
+pub mod export_git;
+pub mod tag;
+pub mod deps;
 #[cfg(test)]
 mod test;
 pub mod error;
@@ -59,12 +73,26 @@ pub fn all_command_invocations() -> Vec<StaticSubcommand> {
         pull::invocation(),
         push::invocation(),
         apply::invocation(),
+        unrecord::invocation(),
+        branch::invocation(),
+        checkout::invocation(),
         clone::invocation(),
         remove::invocation(),
         mv::invocation(),
         ls::invocation(),
         revert::invocation(),
-        login::invocation()
+        login::invocation(),
+        diff::invocation(),
+        status::invocation(),
+        changes::invocation(),
+        patch::invocation(),
+        blame::invocation(),
+        conflicts::invocation(),
+        key::invocation(),
+        import_git::invocation(),
+        export_git::invocation(),
+        tag::invocation(),
+        deps::invocation()
         ];
 }
 
@@ -21,7 +21,7 @@ use commands::StaticSubcommand;
 use clap::{SubCommand, ArgMatches,Arg};
 use commands::error;
 use std::path::{PathBuf, Path};
-use std::fs::{rename, metadata};
+use std::fs::{rename, symlink_metadata};
 
 extern crate libpijul;
 use self::libpijul::fs_representation::{pristine_dir, find_repo_root};
@@ -71,11 +71,11 @@ pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
         let mut path = std::env::current_dir().unwrap();
         path.push(fname);
         debug!("path = {:?}", path);
+        // Do not follow symbolic links: they are moved as links.
         let path =
-            if let Ok(f) = std::fs::canonicalize(&path) {
-                f
-            } else {
-                std::fs::canonicalize(&path.parent().unwrap()).unwrap().join(&path.file_name().unwrap())
+            match (path.parent(), path.file_name()) {
+                (Some(parent), Some(name)) => std::fs::canonicalize(parent).unwrap().join(name),
+                _ => std::fs::canonicalize(&path).unwrap()
             };
         debug!("path = {:?}", path);
         let path = path.strip_prefix(&repo_root).unwrap();
@@ -131,7 +131,7 @@ pub fn run<'a>(args : &Params<'a>) -> Result<(), error::Error> {
                                                .ok_or(error::Error::InvalidPath(f.to_string_lossy().into_owned())));
                     dest_dir.as_path().join(&target_basename)
                 };
-                let is_dir = try!(metadata(&repo_root.join(f))).is_dir();
+                let is_dir = try!(symlink_metadata(&repo_root.join(f))).is_dir();
                 try!(txn.move_file(f, &repo_target_name.as_path(), is_dir));
             };
             for file in orig_paths {
@@ -0,0 +1,389 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg, AppSettings};
+
+extern crate libpijul;
+use commands::StaticSubcommand;
+use commands::error::Error;
+use commands::changes::print_patch_header;
+use self::libpijul::{Repository, ReadTxn, ReadTransaction, internal_hash, is_binary};
+use self::libpijul::patch::{Patch, Change, LINE_SIZE, patch_version, save_file};
+use self::libpijul::graph::{FOLDER_EDGE, PARENT_EDGE, DELETED_EDGE};
+use self::libpijul::fs_representation::{pristine_dir, patches_dir, patch_path, find_repo_root, read_current_branch};
+
+extern crate rustc_serialize;
+use self::rustc_serialize::hex::{ToHex, FromHex};
+use self::rustc_serialize::json;
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use super::get_wd;
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("patch")
+        .about("inspect patches")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("show")
+                    .about("show the contents of a patch")
+                    .arg(Arg::with_name("hash")
+                         .help("Hash of the patch, in hexadecimal. A prefix is enough if it is unique on the branch.")
+                         .required(true))
+                    .arg(Arg::with_name("branch")
+                         .long("branch")
+                         .help("The branch used to resolve file names, defaults to the current branch.")
+                         .takes_value(true))
+                    .arg(Arg::with_name("json")
+                         .long("json")
+                         .help("Dump the patch as JSON, without resolving anything."))
+                    .arg(Arg::with_name("repository")
+                         .long("repository")
+                         .help("The repository to inspect, defaults to the current directory.")
+                         .takes_value(true)
+                         .required(false)))
+        .subcommand(SubCommand::with_name("export")
+                    .about("write a patch in text form, to review it or send it by email")
+                    .arg(Arg::with_name("hash")
+                         .help("Hash of the patch, in hexadecimal. A prefix is enough if it is unique on the branch.")
+                         .required(true))
+                    .arg(Arg::with_name("branch")
+                         .long("branch")
+                         .help("The branch used to resolve hash prefixes, defaults to the current branch.")
+                         .takes_value(true))
+                    .arg(Arg::with_name("output")
+                         .long("output")
+                         .short("o")
+                         .help("The file to write, defaults to the standard output.")
+                         .takes_value(true))
+                    .arg(Arg::with_name("repository")
+                         .long("repository")
+                         .help("The repository to export from, defaults to the current directory.")
+                         .takes_value(true)
+                         .required(false)))
+        .subcommand(SubCommand::with_name("import")
+                    .about("add a patch written by \"pijul patch export\" to the repository, and print its hash")
+                    .arg(Arg::with_name("file")
+                         .help("The file to read, defaults to the standard input."))
+                    .arg(Arg::with_name("repository")
+                         .long("repository")
+                         .help("The repository to import into, defaults to the current directory.")
+                         .takes_value(true)
+                         .required(false)))
+}
+
+#[derive(Debug)]
+pub enum Action<'a> {
+    Show { hash: &'a str, branch: Option<&'a str>, json: bool },
+    Export { hash: &'a str, branch: Option<&'a str>, output: Option<&'a Path> },
+    Import { file: Option<&'a Path> },
+}
+
+pub struct Params<'a> {
+    pub repository : Option<&'a Path>,
+    pub action : Action<'a>
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+    // clap makes sure there is a subcommand.
+    let (action, args) = match args.subcommand() {
+        ("export", Some(args)) => (Action::Export {
+            hash: args.value_of("hash").unwrap(),
+            branch: args.value_of("branch"),
+            output: args.value_of("output").map(Path::new)
+        }, args),
+        ("import", Some(args)) => (Action::Import {
+            file: args.value_of("file").map(Path::new)
+        }, args),
+        (_, args) => {
+            let args = args.unwrap();
+            (Action::Show {
+                hash: args.value_of("hash").unwrap(),
+                branch: args.value_of("branch"),
+                json: args.is_present("json")
+            }, args)
+        }
+    };
+    Params { repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }),
+             action : action
+    }
+}
+
+/// Full hash of the patch whose hexadecimal hash starts with
+/// `prefix`, looked up among the patches applied on `branch`. Other
+/// patches of the repository must be given in full.
+pub fn find_patch(txn: &ReadTransaction, branch: &str, prefix: &str) -> Result<Vec<u8>, Error> {
+    let mut found = None;
+    for hash in try!(txn.applied_patches(branch).map_err(Error::Repository)) {
+        if hash.to_hex().starts_with(prefix) {
+            if found.is_some() {
+                return Err(Error::AmbiguousPatch(prefix.to_string()))
+            }
+            found = Some(hash)
+        }
+    }
+    match found {
+        Some(hash) => Ok(hash),
+        None => Ok(try!(prefix.from_hex()))
+    }
+}
+
+/// Internal key corresponding to external key `key` of the patch
+/// with internal id `internal`, if that patch is applied.
+fn internal_key(txn: &ReadTransaction, internal: &Option<Vec<u8>>, key: &[u8]) -> Option<Vec<u8>> {
+    if key.len() < LINE_SIZE {
+        return None
+    }
+    let (hash, line) = key.split_at(key.len() - LINE_SIZE);
+    let mut result = if hash.is_empty() {
+        match *internal {
+            Some(ref i) => i.clone(),
+            None => return None
+        }
+    } else {
+        let db_internal = txn.db_internal();
+        match internal_hash(&db_internal, hash) {
+            Ok(i) => i.contents.to_vec(),
+            Err(_) => return None
+        }
+    };
+    result.extend(line);
+    Some(result)
+}
+
+fn node_contents(txn: &ReadTransaction, key: &Option<Vec<u8>>) -> Option<Vec<u8>> {
+    let db_contents = txn.db_contents();
+    key.as_ref().and_then(|key| {
+        db_contents.contents(key).map(|c| {
+            let mut v = Vec::new();
+            for chunk in c {
+                v.extend(chunk)
+            }
+            v
+        })
+    })
+}
+
+fn file_name(txn: &ReadTransaction, branch: &str, key: &Option<Vec<u8>>) -> Option<PathBuf> {
+    key.as_ref().and_then(|key| txn.file_of_key(branch, key))
+}
+
+/// Print "In <file>:" when moving on to the lines of another file.
+fn enter_file(current: &mut Option<String>, file: Option<PathBuf>) {
+    let file = match file {
+        Some(f) => f.to_string_lossy().into_owned(),
+        None => "(unknown file)".to_string()
+    };
+    if current.as_ref() != Some(&file) {
+        println!("In {}:", file);
+        *current = Some(file)
+    }
+}
+
+fn print_line(sign: char, line: &[u8]) {
+    if is_binary(line) {
+        println!("{} (binary contents)", sign)
+    } else {
+        let line = String::from_utf8_lossy(line);
+        if line.ends_with("\n") {
+            print!("{} {}", sign, line)
+        } else {
+            println!("{} {}", sign, line)
+        }
+    }
+}
+
+fn print_changes(txn: &ReadTransaction, branch: &str, internal: &Option<Vec<u8>>, patch: &Patch) {
+    let mut current = None;
+    for change in patch.changes.iter() {
+        match *change {
+            Change::NewNodes { ref up_context, ref down_context, ref flag, ref nodes, .. } => {
+                let up = up_context.get(0).and_then(|c| internal_key(txn, internal, c));
+                if *flag & FOLDER_EDGE != 0 {
+                    current = None;
+                    let dir = file_name(txn, branch, &up).unwrap_or(PathBuf::new());
+                    for n in nodes.iter().filter(|n| n.len() > 2) {
+                        let path = dir.join(String::from_utf8_lossy(&n[2..]).as_ref());
+                        if down_context.is_empty() {
+                            println!("New file {}", path.display())
+                        } else {
+                            println!("New name {}", path.display())
+                        }
+                    }
+                } else {
+                    enter_file(&mut current, file_name(txn, branch, &up));
+                    for n in nodes.iter() {
+                        print_line('+', n)
+                    }
+                }
+            },
+            Change::Edges { ref flag, ref edges } => {
+                let mut targets = HashSet::with_capacity(edges.len());
+                for e in edges.iter() {
+                    let target = if *flag & PARENT_EDGE == 0 { &e.to } else { &e.from };
+                    if !targets.insert(target) {
+                        continue
+                    }
+                    let key = internal_key(txn, internal, target);
+                    let contents = node_contents(txn, &key);
+                    let sign = if *flag & DELETED_EDGE != 0 { '-' } else { '+' };
+                    if *flag & FOLDER_EDGE != 0 {
+                        // Only name nodes have contents here.
+                        if contents.as_ref().map(|c| c.len() > 2).unwrap_or(false) {
+                            current = None;
+                            let name = file_name(txn, branch, &key)
+                                .map(|p| p.to_string_lossy().into_owned())
+                                .unwrap_or("(unknown file)".to_string());
+                            if sign == '-' {
+                                println!("Removed name {}", name)
+                            } else {
+                                println!("Restored name {}", name)
+                            }
+                        }
+                    } else {
+                        enter_file(&mut current, file_name(txn, branch, &key));
+                        match contents {
+                            Some(ref c) => print_line(sign, c),
+                            None => println!("{} (unknown line)", sign)
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The patch of `r` whose hash starts with `prefix`, along with its
+/// full hash. A missing patch file is reported as `PatchNotFound`,
+/// any other error is returned as is.
+pub fn load_patch(r: &Path, txn: &ReadTransaction, branch: &str, prefix: &str) -> Result<(Vec<u8>, Patch), Error> {
+    let hash = try!(find_patch(txn, branch, prefix));
+    match Patch::from_repository(r, &hash) {
+        Ok(patch) => Ok((hash, patch)),
+        Err(libpijul::error::Error::IO(ref e)) if e.kind() == io::ErrorKind::NotFound =>
+            Err(Error::PatchNotFound(r.to_string_lossy().into_owned(), prefix.to_string())),
+        Err(e) => Err(Error::from(e))
+    }
+}
+
+fn show(r: &Path, txn: &ReadTransaction, branch: &str, prefix: &str, json: bool) -> Result<(), Error> {
+    let (hash, patch) = try!(load_patch(r, txn, branch, prefix));
+    if json {
+        println!("{}", json::as_pretty_json(&patch));
+        return Ok(())
+    }
+    print_patch_header(&hash, &patch);
+    if !patch.dependencies.is_empty() {
+        println!("");
+        println!("Dependencies:");
+        let mut deps: Vec<String> = patch.dependencies.iter().map(|d| d.to_hex()).collect();
+        deps.sort();
+        for d in deps.iter() {
+            println!("    {}", d)
+        }
+    }
+    println!("");
+    let internal = {
+        let db_internal = txn.db_internal();
+        internal_hash(&db_internal, &hash).ok().map(|i| i.contents.to_vec())
+    };
+    print_changes(txn, branch, &internal, &patch);
+    Ok(())
+}
+
+/// Write patch `hash` of `r` in text form to `w`. Patches in versions
+/// of the format older than 2 are written along with their file, so
+/// that importing them keeps their hash.
+fn export<W: Write>(r: &Path, hash: &[u8], patch: &Patch, w: &mut W) -> Result<(), Error> {
+    let path = r.join(patch_path(hash, MAIN_SEPARATOR));
+    if try!(patch_version(&path)) < 2 {
+        let mut file = Vec::new();
+        try!(try!(File::open(&path)).read_to_end(&mut file));
+        try!(patch.to_text_with_file(w, Some(&file)))
+    } else {
+        try!(patch.to_text(w))
+    }
+    Ok(())
+}
+
+/// Add the patch written in text form in `file` (or the standard
+/// input) to the patches of `r`, and return its hash. The patch is
+/// not applied. If the text has the patch file, that file is kept
+/// as it is, after checking that it is the same patch.
+pub fn import(r: &Path, file: Option<&Path>) -> Result<Vec<u8>, Error> {
+    let (patch, original) = match file {
+        Some(file) => try!(Patch::from_text_with_file(try!(File::open(file)))),
+        None => try!(Patch::from_text_with_file(io::stdin()))
+    };
+    match original {
+        Some(original) => {
+            let (mut text, mut original_text) = (Vec::new(), Vec::new());
+            try!(patch.to_text(&mut text));
+            try!(try!(Patch::from_reader(&original[..], None)).to_text(&mut original_text));
+            if text != original_text {
+                return Err(Error::Repository(libpijul::error::Error::InvalidPatch(
+                    None, "the patch file does not match the text".to_string())))
+            }
+            let (_, hash) = try!(save_file(&patches_dir(r), &original));
+            Ok(hash)
+        },
+        None => Ok(try!(patch.save(&patches_dir(r), None)))
+    }
+}
+
+fn branch_name(r: &Path, branch: Option<&str>) -> String {
+    match branch {
+        Some(b) => b.to_string(),
+        None => read_current_branch(r)
+    }
+}
+
+pub fn run(args : &Params) -> Result<(), Error> {
+    let wd=try!(get_wd(args.repository));
+    match find_repo_root(&wd){
+        None => return Err(Error::NotInARepository),
+        Some(ref r) =>
+        {
+            let repo_dir=pristine_dir(r);
+            match args.action {
+                Action::Show { hash, branch, json } => {
+                    let repo = try!(Repository::open(&repo_dir).map_err(Error::Repository));
+                    let txn = try!(repo.txn_begin());
+                    show(r, &txn, &branch_name(r, branch), hash, json)
+                },
+                Action::Export { hash, branch, output } => {
+                    let repo = try!(Repository::open(&repo_dir).map_err(Error::Repository));
+                    let txn = try!(repo.txn_begin());
+                    let (hash, patch) = try!(load_patch(r, &txn, &branch_name(r, branch), hash));
+                    match output {
+                        Some(output) => export(r, &hash, &patch, &mut try!(File::create(output))),
+                        None => export(r, &hash, &patch, &mut io::stdout())
+                    }
+                },
+                Action::Import { file } => {
+                    let hash = try!(import(r, file));
+                    println!("{}", hash.to_hex());
+                    Ok(())
+                }
+            }
+        }
+    }
+}
@@ -53,6 +53,11 @@ pub fn invocation() -> StaticSubcommand {
         .arg(Arg::with_name("set-default")
              .long("set-default")
              )
+        .arg(Arg::with_name("tag")
+             .long("tag")
+             .help("Pull this tag and its patches, instead of the patches of the current branch.")
+             .takes_value(true)
+             )
         .arg(Arg::with_name("port")
              .short("p")
              .long("port")
@@ -71,7 +76,8 @@ pub struct Params<'a> {
     pub remote_id : Option<&'a str>,
     pub yes_to_all : bool,
     pub set_default : bool,
-    pub port : Option<u64>
+    pub port : Option<u64>,
+    pub tag : Option<&'a str>
 }
 
 pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
@@ -82,7 +88,8 @@ pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
              remote_id : remote_id,
              yes_to_all : args.is_present("all"),
              set_default : args.is_present("set-default"),
-             port : args.value_of("port").and_then(|x| { Some(x.parse().unwrap()) }) }
+             port : args.value_of("port").and_then(|x| { Some(x.parse().unwrap()) }),
+             tag : args.value_of("tag") }
 }
 
 pub fn run<'a>(args : &Params<'a>) -> Result<(), Error> {
@@ -108,26 +115,30 @@ pub fn run<'a>(args : &Params<'a>) -> Result<(), Error> {
                 }
             };
             let mut session=try!(remote.session());
-            let mut pullable=try!(session.pullable_patches(r));
-            // Loading a patch's dependencies
-            if !args.yes_to_all {
-                let selected={
-                    let mut patches=Vec::new();
-                    for i in pullable.iter() {
-                        let patch={
-                            let filename=try!(session.download_patch(r,i));
-                            let mut file=try!(File::open(&filename));
-                            try!(Patch::from_reader(&mut file,Some(&filename)))
-                        };
-                        patches.push((&i[..],patch));
-                    }
-                    try!(ask_apply(Command::Pull,&patches))
-                };
-                pullable.remote=selected;
+            if let Some(tag)=args.tag {
+                try!(session.pull_tag(r,tag));
+            } else {
+                let mut pullable=try!(session.pullable_patches(r));
+                // Loading a patch's dependencies
+                if !args.yes_to_all {
+                    let selected={
+                        let mut patches=Vec::new();
+                        for i in pullable.iter() {
+                            let patch={
+                                let filename=try!(session.download_patch(r,i));
+                                let mut file=try!(File::open(&filename));
+                                try!(Patch::from_reader(&mut file,Some(&filename)))
+                            };
+                            patches.push((&i[..],patch));
+                        }
+                        try!(ask_apply(Command::Pull,&patches))
+                    };
+                    pullable.remote=selected;
+                }
+                // Pulling and applying
+                info!("Pulling patch {:?}", pullable);
+                try!(session.pull(r,&pullable));
             }
-            // Pulling and applying
-            info!("Pulling patch {:?}", pullable);
-            try!(session.pull(r,&pullable));
             info!("Saving meta");
             if args.set_default && savable {
                 let mut meta = match Meta::load(r) { Ok(m)=>m, Err(_)=> { Meta::new() } };
@@ -49,6 +49,11 @@ pub fn invocation() -> StaticSubcommand {
         .arg(Arg::with_name("set-default")
              .long("set-default")
              )
+        .arg(Arg::with_name("tag")
+             .long("tag")
+             .help("Push this tag and its patches, instead of the patches of the current branch.")
+             .takes_value(true)
+             )
         .arg(Arg::with_name("port")
              .short("p")
              .long("port")
@@ -67,7 +72,8 @@ pub struct Params<'a> {
     pub remote_id : Option<&'a str>,
     pub yes_to_all : bool,
     pub set_default : bool,
-    pub port : Option<u64>
+    pub port : Option<u64>,
+    pub tag : Option<&'a str>
 }
 
 pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
@@ -77,7 +83,8 @@ pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
              remote_id : remote_id,
              yes_to_all : args.is_present("all"),
              set_default : args.is_present("set-default"),
-             port : args.value_of("port").and_then(|x| { Some(x.parse().unwrap()) }) }
+             port : args.value_of("port").and_then(|x| { Some(x.parse().unwrap()) }),
+             tag : args.value_of("tag") }
 }
 
 pub fn run<'a>(args : &Params<'a>) -> Result<(), Error> {
@@ -102,20 +109,24 @@ pub fn run<'a>(args : &Params<'a>) -> Result<(), Error> {
                 }
             };
             let mut session=try!(remote.session());
-            let mut pushable=try!(session.pushable_patches(r));
-            if !args.yes_to_all {
-                let selected={
-                    let mut patches=Vec::new();
-                    for i in pushable.iter() {
-                        let patch=try!(Patch::from_repository(r,i));
-                        patches.push((&i[..],patch));
-                    }
-                    try!(super::ask::ask_apply(super::ask::Command::Push,&patches))
-                };
-                pushable=selected;
-            }
+            if let Some(tag)=args.tag {
+                try!(session.push_tag(r,tag));
+            } else {
+                let mut pushable=try!(session.pushable_patches(r));
+                if !args.yes_to_all {
+                    let selected={
+                        let mut patches=Vec::new();
+                        for i in pushable.iter() {
+                            let patch=try!(Patch::from_repository(r,i));
+                            patches.push((&i[..],patch));
+                        }
+                        try!(super::ask::ask_apply(super::ask::Command::Push,&patches))
+                    };
+                    pushable=selected;
+                }
 
-            try!(session.push(r,&pushable));
+                try!(session.push(r,&pushable));
+            }
             if args.set_default && savable {
                 let mut meta = match Meta::load(r) { Ok(m)=>m, Err(_)=> { Meta::new() } };
                 if let Some(remote_id)=args.remote_id {
@@ -21,9 +21,9 @@ use clap::{SubCommand, ArgMatches, Arg};
 
 extern crate libpijul;
 use commands::StaticSubcommand;
-use self::libpijul::{Repository,DEFAULT_BRANCH};
+use self::libpijul::{Repository,ReadTxn};
 use self::libpijul::patch::{Patch,Value};
-use self::libpijul::fs_representation::{repo_dir, pristine_dir, find_repo_root};
+use self::libpijul::fs_representation::{repo_dir, pristine_dir, find_repo_root, read_current_branch};
 
 extern crate time;
 use commands::error::Error;
@@ -36,6 +36,9 @@ use super::ask;
 use super::get_wd;
 use std::collections::BTreeMap;
 
+extern crate rustc_serialize;
+use self::rustc_serialize::hex::ToHex;
+
 pub fn invocation() -> StaticSubcommand {
     return
         SubCommand::with_name("record")
@@ -63,13 +66,19 @@ pub fn invocation() -> StaticSubcommand {
              .multiple(true)
              .takes_value(true)
              )
+        .arg(Arg::with_name("keys-dir")
+             .long("keys-dir")
+             .help("The directory of the default signing key, defaults to ~/.pijul/keys.")
+             .takes_value(true)
+             .required(false))
 }
 
 pub struct Params<'a> {
     pub repository : Option<&'a Path>,
     pub patch_name : Option<&'a str>,
     pub authors : Option<Vec<&'a str>>,
-    pub yes_to_all : bool
+    pub yes_to_all : bool,
+    pub keys_dir : Option<&'a Path>
 }
 
 pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a>
@@ -77,7 +86,8 @@ pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a>
     Params { repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }),
              yes_to_all : args.is_present("all"),
              authors : args.values_of("author").map(|x| x.collect()),
-             patch_name : args.value_of("message")
+             patch_name : args.value_of("message"),
+             keys_dir : args.value_of("keys-dir").map(Path::new)
     }
 }
 
@@ -88,11 +98,12 @@ pub fn run(args : &Params) -> Result<Option<()>, Error> {
         Some(ref r) =>
         {
             let repo_dir=pristine_dir(r);
+            let branch=read_current_branch(r);
             let t0=time::precise_time_s();
             let (changes,syncs)= {
                 let repo = try!(Repository::open(&repo_dir).map_err(Error::Repository));
-                let txn = try!(repo.mut_txn_begin());
-                let (changes,syncs)=try!(txn.record(DEFAULT_BRANCH, &r).map_err(Error::Repository));
+                let txn = try!(repo.txn_begin());
+                let (changes,syncs)=try!(txn.record(&branch, &r).map_err(Error::Repository));
                 if !args.yes_to_all {
                     let c=try!(super::ask::ask_record(&txn,&changes));
                     let selected =
@@ -115,7 +126,7 @@ pub fn run(args : &Params) -> Result<Option<()>, Error> {
                 Ok(None)
             } else {
                 //println!("patch: {:?}",changes);
-                let patch={
+                let (patch,key)={
                     let mut save_meta=false;
                     let mut meta = match Meta::load(r) { Ok(m)=>m, Err(_)=> { save_meta=true; Meta::new() } };
                     debug!("meta:{:?}",meta);
@@ -143,6 +154,15 @@ pub fn run(args : &Params) -> Result<Option<()>, Error> {
                                 authors
                             }
                         };
+                    let key=try!(super::key::signing_key(&meta, args.keys_dir));
+                    // Record the public key of the signer with the first author.
+                    let mut authors=authors;
+                    if let Some(ref key)=key {
+                        if authors.is_empty() {
+                            authors.push(BTreeMap::new())
+                        }
+                        authors[0].insert("key".to_string(),Value::String(key.public_key().to_hex()));
+                    }
                     debug!("authors:{:?}",authors);
                     let patch_name=
                         if let Some(ref m)=args.patch_name {
@@ -155,17 +175,18 @@ pub fn run(args : &Params) -> Result<Option<()>, Error> {
                         try!(meta.save(r))
                     }
                     debug!("new");
-                    Patch::new(authors,
-                               patch_name,
-                               None,
-                               self::time::now(),
-                               changes)
+                    (Patch::new(authors,
+                                patch_name,
+                                None,
+                                self::time::now(),
+                                changes),
+                     key)
                 };
                 debug!("register_patch");
                 // save patch
                 let repo = try!(Repository::open(&repo_dir).map_err(Error::Repository));
                 let mut txn = try!(repo.mut_txn_begin());
-                try!(txn.apply_local_patch(DEFAULT_BRANCH, r, patch, &syncs).map_err(Error::Repository));
+                try!(txn.apply_local_patch(&branch, r, patch, &syncs, key.as_ref()).map_err(Error::Repository));
                 try!(txn.commit());
                 Ok(Some(()))
             }
@@ -19,15 +19,18 @@
 extern crate clap;
 
 extern crate libpijul;
-use self::libpijul::{Repository,DEFAULT_BRANCH};
-use self::libpijul::patch::{read_changes_from_file,read_changes};
-use self::libpijul::fs_representation::{repo_dir, pristine_dir, branch_changes_base_path,branch_changes_file,PIJUL_DIR_NAME,PATCHES_DIR_NAME,patch_path};
+use self::libpijul::{Repository,ReadTxn};
+use self::libpijul::patch::{read_changes_from_file,read_changes,patch_hash};
+use self::libpijul::signature::check_signatures;
+use self::libpijul::tag::{read_tag,create_tag};
+use self::libpijul::fs_representation::{repo_dir, pristine_dir, branch_changes_base_path,branch_changes_file,PIJUL_DIR_NAME,PATCHES_DIR_NAME,TAGS_DIR_NAME,patch_path,signature_path,tag_path,tag_file,tags_dir,read_current_branch};
 use std::path::{Path,PathBuf,MAIN_SEPARATOR};
 use std::io::{BufWriter};
 use std::collections::hash_set::{HashSet};
-use std::fs::{File,hard_link,copy,metadata};
+use std::fs::{File,hard_link,copy,metadata,remove_file,create_dir_all};
 
 use super::error::Error;
+use super::super::meta::Meta;
 use std::str::{from_utf8,from_utf8_unchecked};
 extern crate ssh;
 use self::ssh::Channel;
@@ -94,6 +97,25 @@ fn ssh_recv_file<'b,P:AsRef<Path>>(s:&'b mut ssh::Session,p:P)->Result<Option<ss
     }
 }
 
+/// Check that the patch downloaded to `local_file` really has hash
+/// `hash`, removing it (and its signature) if it does not.
+fn check_patch_hash(local_file:PathBuf,hash:&[u8])->Result<PathBuf,Error> {
+    let actual=match patch_hash(&local_file) {
+        Ok((_,actual))=>actual,
+        Err(e)=>{
+            let _=remove_file(&local_file);
+            return Err(Error::Repository(e))
+        }
+    };
+    if &actual[..]==hash {
+        Ok(local_file)
+    } else {
+        let _=remove_file(&local_file);
+        let _=remove_file(local_file.with_extension("sig"));
+        Err(Error::WrongPatchHash(hash.to_hex(),actual.to_hex()))
+    }
+}
+
 impl <'a> Drop for Session<'a> {
     fn drop(&mut self){
         match *self {
@@ -124,7 +146,7 @@ impl<'a> Session<'a> {
             },
             Session::Uri {uri,ref mut client} =>{
                 let mut uri=uri.to_string();
-                uri = uri + "/" + PIJUL_DIR_NAME + "/" + &branch_changes_base_path(DEFAULT_BRANCH.as_bytes());
+                uri = uri + "/" + PIJUL_DIR_NAME + "/" + &branch_changes_base_path(branch);
                 let mut res = try!(client.get(&uri)
                                    .header(hyper::header::Connection::close())
                                    .send());
@@ -134,12 +156,95 @@ impl<'a> Session<'a> {
             },
         }
     }
+    /// The patches of tag `name` in the remote repository, or `None`
+    /// if it has no such tag.
+    pub fn tag(&mut self, name:&str) -> Result<Option<HashSet<Vec<u8>>>,Error> {
+        match *self {
+            Session::Ssh{ref path,ref mut session,..}=>{
+                let remote_file=path.join(&tag_path(name,'/'));
+                // scp does not tell missing files from other errors.
+                let exists={
+                    let mut s=try!(session.channel_new());
+                    try!(s.open_session());
+                    let esc_file=escape(Cow::Borrowed(remote_file.to_str().unwrap()));
+                    try!(s.request_exec(format!("test -f \"{}\"",esc_file).as_bytes()));
+                    try!(s.send_eof());
+                    s.get_exit_status()==Some(0)
+                };
+                if !exists {
+                    return Ok(None)
+                }
+                match try!(ssh_recv_file(session,&remote_file)) {
+                    Some(r)=>Ok(Some(try!(read_changes(r,None)))),
+                    None=>Ok(None)
+                }
+            },
+            Session::Local{path}=>{
+                match read_tag(path,name) {
+                    Ok(patches)=>Ok(Some(patches)),
+                    Err(libpijul::error::Error::TagNotFound(_))=>Ok(None),
+                    Err(e)=>Err(Error::Repository(e))
+                }
+            },
+            Session::Uri{uri,ref mut client}=>{
+                let uri=uri.to_string() + "/" + &tag_path(name,'/');
+                let mut res = try!(client.get(&uri)
+                                   .header(hyper::header::Connection::close())
+                                   .send());
+                if res.status==hyper::status::StatusCode::Ok {
+                    Ok(Some(try!(read_changes(&mut res,None))))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+    /// Download the detached signature of a patch, if the remote
+    /// repository has one. Missing signatures are not errors, the
+    /// signature policy decides what to do with unsigned patches.
+    fn download_signature(&mut self, repo_root:&Path, patch_hash:&[u8])->Result<(),Error>{
+        let local_file=repo_root.join(&signature_path(patch_hash,MAIN_SEPARATOR));
+        if metadata(&local_file).is_ok() {
+            return Ok(())
+        }
+        match *self {
+            Session::Local{path}=>{
+                let remote_file=path.join(&signature_path(patch_hash,MAIN_SEPARATOR));
+                if metadata(&remote_file).is_ok() {
+                    try!(copy(&remote_file, &local_file));
+                }
+            },
+            Session::Ssh{ref path,ref mut session,..}=>{
+                let remote_file=path.join(&signature_path(patch_hash,'/'));
+                if let Ok(Some(mut rem))=ssh_recv_file(session,&remote_file) {
+                    let mut contents = Vec::new();
+                    try!(rem.read_to_end(&mut contents));
+                    let mut w=BufWriter::new(try!(File::create(&local_file)));
+                    try!(w.write_all(&contents));
+                }
+            },
+            Session::Uri{ref mut client,uri}=>{
+                let uri=uri.to_string() + "/" + &signature_path(patch_hash,'/');
+                debug!("downloading uri {:?}",uri);
+                if let Ok(ref mut res)=client.get(&uri).header(hyper::header::Connection::close()).send() {
+                    if res.status==hyper::status::StatusCode::Ok {
+                        let mut body=Vec::new();
+                        try!(res.read_to_end(&mut body));
+                        let mut f=try!(File::create(&local_file));
+                        try!(f.write_all(&body));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
     pub fn download_patch(&mut self, repo_root:&Path, patch_hash:&[u8])->Result<PathBuf,Error>{
         let s=patch_path(patch_hash,MAIN_SEPARATOR);
         let local_file=repo_root.join(&s);
         if metadata(&local_file).is_ok() {
             Ok(local_file)
         } else {
+            try!(self.download_signature(repo_root, patch_hash));
             match *self {
                 Session::Local{path}=>{
                     debug!("local downloading {:?}",patch_hash.to_hex());
@@ -149,7 +254,7 @@ impl<'a> Session<'a> {
                     try!(hard_link(&remote_file,&local_file).or_else(|_|{
                         copy(&remote_file, &local_file).and_then(|_| Ok(()))
                     }));
-                    Ok(local_file)
+                    check_patch_hash(local_file,patch_hash)
                 },
                 Session::Ssh{ref path,ref mut session,..}=>{
                     let s_=patch_path(patch_hash,'/');
@@ -160,7 +265,8 @@ impl<'a> Session<'a> {
                         try!(rem.read_to_end(&mut contents));
                         let mut w=BufWriter::new(try!(File::create(&local_file)));
                         try!(w.write_all(&contents));
-                        Ok(local_file)
+                        try!(w.flush());
+                        check_patch_hash(local_file,patch_hash)
                     } else {
                         Err(Error::PatchNotFound(path.to_path_buf().to_string_lossy().into_owned(),
                                                  patch_hash.to_hex()))
@@ -180,7 +286,7 @@ impl<'a> Session<'a> {
                                 let mut f=try!(File::create(&local_file));
                                 try!(f.write_all(&body));
                                 debug!("patch downloaded through http: {:?}",body);
-                                return Ok(local_file)
+                                return check_patch_hash(local_file,patch_hash)
                             },
                             Ok(_) => {
                                 break
@@ -229,6 +335,17 @@ impl<'a> Session<'a> {
                     }
                     try!(scp.push_file(&remote_path,buf.len(),0o644));
                     try!(scp.write(&buf));
+
+                    let sig=repo_root.join(&signature_path(hash,MAIN_SEPARATOR));
+                    if let Ok(mut f)=File::open(&sig) {
+                        buf.clear();
+                        try!(f.read_to_end(&mut buf));
+                        remote_path.truncate(remote_len);
+                        remote_path.push_str(&hash.to_hex());
+                        remote_path.push_str(".cbor.sig");
+                        try!(scp.push_file(&remote_path,buf.len(),0o644));
+                        try!(scp.write(&buf));
+                    }
                 }
                 Ok(())
             },
@@ -263,6 +380,11 @@ impl<'a> Session<'a> {
                             copy(&local_path, &remote_path).and_then(|_| Ok(()))
                         }))
                     }
+                    let sig=repo_root.join(&signature_path(hash,MAIN_SEPARATOR));
+                    let remote_sig=path.join(&signature_path(hash,MAIN_SEPARATOR));
+                    if metadata(&sig).is_ok() && metadata(&remote_sig).is_err() {
+                        try!(copy(&sig, &remote_sig));
+                    }
                 }
                 Ok(())
             },
@@ -272,7 +394,7 @@ impl<'a> Session<'a> {
 
     // patch hash in binary
     /// Apply patches that have been uploaded.
-    pub fn remote_apply(&mut self, patch_hashes:&HashSet<Vec<u8>>)->Result<(),Error> {
+    pub fn remote_apply(&mut self, patch_hashes:&HashSet<Vec<u8>>, branch:&str)->Result<(),Error> {
         match *self {
             Session::Ssh { ref mut session, ref path, ref id, .. }=> {
                 debug!("ssh: remote_apply");
@@ -284,7 +406,8 @@ impl<'a> Session<'a> {
                     patches=patches + " " + &(i.to_hex());
                 }
                 if patches.len()>0 {
-                    let cmd=format!("cd \"{}\"; pijul apply{}",esc_path, &patches);
+                    let esc_branch=escape(Cow::Borrowed(branch));
+                    let cmd=format!("cd \"{}\"; pijul apply --branch \"{}\"{}",esc_path, esc_branch, &patches);
                     debug!("command line:{:?}",cmd);
                     try!(s.request_exec(cmd.as_bytes()));
                 }
@@ -308,11 +431,12 @@ impl<'a> Session<'a> {
                 }
             },
             Session::Local{path} =>{
-                let applied_patches:HashSet<Vec<u8>>=try!(self.changes(DEFAULT_BRANCH.as_bytes()));
+                let applied_patches:HashSet<Vec<u8>>=try!(self.changes(branch.as_bytes()));
+                try!(verify_signatures(path, patch_hashes.difference(&applied_patches)));
                 let repo_dir=pristine_dir(path);
                 let repo = try!(Repository::open(&repo_dir).map_err(Error::Repository));
                 let mut txn = try!(repo.mut_txn_begin());
-                try!(txn.apply_patches(DEFAULT_BRANCH, path, &patch_hashes,&applied_patches));
+                try!(txn.apply_patches(branch, path, &patch_hashes,&applied_patches, Meta::conflict_style(path)));
                 try!(txn.commit());
                 Ok(())
             }
@@ -352,9 +476,10 @@ impl<'a> Session<'a> {
     }
 
     pub fn pullable_patches(&mut self,target:&Path) -> Result<Pullable, Error> {
-        let remote_patches:HashSet<Vec<u8>>=try!(self.changes(DEFAULT_BRANCH.as_bytes()));
+        let branch=read_current_branch(target);
+        let remote_patches:HashSet<Vec<u8>>=try!(self.changes(branch.as_bytes()));
         let local_patches:HashSet<Vec<u8>>={
-            let changes_file=branch_changes_file(target,DEFAULT_BRANCH.as_bytes());
+            let changes_file=branch_changes_file(target,branch.as_bytes());
             read_changes_from_file(&changes_file).unwrap_or(HashSet::new())
         };
         Ok(Pullable { local:local_patches, remote: remote_patches })
@@ -364,28 +489,52 @@ impl<'a> Session<'a> {
         for i in pullable.iter() {
             try!(self.download_patch(&target,i));
         }
+        try!(verify_signatures(target, pullable.iter()));
+        let branch=read_current_branch(target);
         let repo_dir=pristine_dir(target);
         let repo = try!(Repository::open(&repo_dir).map_err(Error::Repository));
         let mut txn = try!(repo.mut_txn_begin());
         info!("Calling apply_patches");
-        try!(txn.apply_patches(DEFAULT_BRANCH, target,&pullable.remote,&pullable.local));
+        try!(txn.apply_patches(&branch, target,&pullable.remote,&pullable.local, Meta::conflict_style(target)));
         debug!("pull: committing");
         let mut f = File::create("/tmp/debug").unwrap();
-        txn.debug(DEFAULT_BRANCH, &mut f);
+        txn.debug(&branch, &mut f);
         try!(txn.commit());
         debug!("pull: commited");
         Ok(())
     }
 
+    /// Download tag `name` and its patches, and create the tag in
+    /// `target`. Pulling a tag that `target` already has is a no-op.
+    pub fn pull_tag(&mut self,target:&Path,name:&str) -> Result<(), Error> {
+        let patches=match try!(self.tag(name)) {
+            Some(patches)=>patches,
+            None=>return Err(Error::Repository(libpijul::error::Error::TagNotFound(name.to_string())))
+        };
+        if let Ok(local)=read_tag(target,name) {
+            if local==patches {
+                return Ok(())
+            }
+            return Err(Error::Repository(libpijul::error::Error::TagAlreadyExists(name.to_string())))
+        }
+        for i in patches.iter() {
+            try!(self.download_patch(&target,i));
+        }
+        try!(verify_signatures(target, patches.iter()));
+        try!(create_tag(target,name,&patches));
+        Ok(())
+    }
+
     pub fn pushable_patches(&mut self, source:&Path) -> Result<HashSet<Vec<u8>>,Error> {
         debug!("source: {:?}",source);
+        let branch=read_current_branch(source);
         let mut from_changes:HashSet<Vec<u8>>={
-            let changes_file=branch_changes_file(source,DEFAULT_BRANCH.as_bytes());
+            let changes_file=branch_changes_file(source,branch.as_bytes());
             debug!("changes_file: {:?}",changes_file);
             read_changes_from_file(&changes_file).unwrap_or(HashSet::new()) // empty repositories don't have this file
         };
         debug!("pushing: {:?}",from_changes);
-        let to_changes=try!(self.changes(DEFAULT_BRANCH.as_bytes()));
+        let to_changes=try!(self.changes(branch.as_bytes()));
         for i in to_changes.iter() {
             from_changes.remove(i);
         }
@@ -394,9 +543,56 @@ impl<'a> Session<'a> {
 
     pub fn push(&mut self, source:&Path,pushable:&HashSet<Vec<u8>>) -> Result<(), Error> {
         try!(self.upload_patches(source,pushable));
-        try!(self.remote_apply(pushable));
+        try!(self.remote_apply(pushable, &read_current_branch(source)));
         Ok(())
     }
+
+    /// Upload tag `name` of `source` and its patches. Pushing a tag
+    /// that the remote repository already has is a no-op.
+    pub fn push_tag(&mut self, source:&Path, name:&str) -> Result<(), Error> {
+        if let Session::Uri{uri,..}=*self {
+            return Err(Error::CannotPushToUri(uri.to_string()))
+        }
+        let patches=try!(read_tag(source,name));
+        match try!(self.tag(name)) {
+            Some(ref remote) if *remote==patches => return Ok(()),
+            Some(_)=>return Err(Error::Repository(libpijul::error::Error::TagAlreadyExists(name.to_string()))),
+            None=>{}
+        }
+        try!(self.upload_patches(source,&patches));
+        let local_file=tag_file(source,name);
+        match *self {
+            Session::Ssh { ref mut session, ref path, .. }=> {
+                // Repositories created by older versions have no tags directory.
+                {
+                    let mut s=try!(session.channel_new());
+                    try!(s.open_session());
+                    let esc_path=escape(Cow::Borrowed(path.to_str().unwrap()));
+                    try!(s.request_exec(format!("mkdir -p \"{}/{}/{}\"",esc_path,PIJUL_DIR_NAME,TAGS_DIR_NAME).as_bytes()));
+                    try!(s.send_eof());
+                    let _=s.get_exit_status();
+                }
+                let mut buf=Vec::new();
+                {
+                    let mut f=try!(File::open(&local_file));
+                    try!(f.read_to_end(&mut buf));
+                }
+                let remote_dir=path.to_str().unwrap().to_string()+"/"+PIJUL_DIR_NAME+"/"+TAGS_DIR_NAME+"/";
+                let remote_path=path.to_str().unwrap().to_string()+"/"+&tag_path(name,'/');
+                let mut scp=try!(session.scp_new(ssh::WRITE,&remote_dir));
+                try!(scp.init());
+                try!(scp.push_file(&remote_path,buf.len(),0o644));
+                try!(scp.write(&buf));
+                Ok(())
+            },
+            Session::Local{path} =>{
+                try!(create_dir_all(tags_dir(path)));
+                try!(copy(&local_file,tag_file(path,name)));
+                Ok(())
+            },
+            Session::Uri{uri,..}=>Err(Error::CannotPushToUri(uri.to_string()))
+        }
+    }
 }
 
 
@@ -480,6 +676,17 @@ pub fn parse_remote<'a>(remote_id:&'a str,port:Option<u64>,base_path:Option<&'a
     }
 }
 
+/// Check the signatures of `patches`, about to be applied to
+/// repository `r`, against its signature policy, printing a warning
+/// for each untrusted patch if the policy only warns.
+pub fn verify_signatures<'a,I:Iterator<Item=&'a Vec<u8>>>(r:&Path, patches:I) -> Result<(),Error> {
+    let policy = try!(Meta::signature_policy(r));
+    for (hash, signature) in try!(check_signatures(r, patches, &policy)) {
+        println!("Warning: patch {} is not signed by a trusted key ({})", hash.to_hex(), signature)
+    }
+    Ok(())
+}
+
 // Reimplementation of hash_set::Difference (because of unstable features used there)
 #[derive(Debug)]
 pub struct Pullable {
@@ -21,12 +21,13 @@ use clap::{SubCommand, ArgMatches,Arg};
 
 use commands::StaticSubcommand;
 extern crate libpijul;
-use self::libpijul::{Repository,DEFAULT_BRANCH};
+use self::libpijul::{Repository};
 use self::libpijul::patch::{Patch};
-use self::libpijul::fs_representation::{repo_dir, pristine_dir, find_repo_root};
+use self::libpijul::fs_representation::{repo_dir, pristine_dir, find_repo_root, read_current_branch};
 use std::path::{Path};
 
 use commands::error;
+use super::super::meta::Meta;
 use super::get_wd;
 pub fn invocation() -> StaticSubcommand {
     return
@@ -55,7 +56,7 @@ pub fn run<'a>(args : &Params<'a>) -> Result<(), error::Error> {
             let repo_dir=pristine_dir(r);
             let repo = try!(Repository::open(&repo_dir));
             let mut txn = try!(repo.mut_txn_begin());
-            try!(txn.output_repository(DEFAULT_BRANCH, &r,&Patch::empty()));
+            try!(txn.output_repository(&read_current_branch(r), &r,&Patch::empty(), Meta::conflict_style(r)));
             Ok(())
         }
     }
@@ -0,0 +1,125 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg};
+
+extern crate libpijul;
+use commands::StaticSubcommand;
+use commands::error::Error;
+use commands::diff::{Status, changed_files};
+use self::libpijul::{Repository, ReadTxn};
+use self::libpijul::fs_representation::{pristine_dir, find_repo_root, read_current_branch, PIJUL_DIR_NAME};
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::fs::read_dir;
+use super::get_wd;
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("status")
+        .about("show the state of the working copy")
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("The repository to inspect, defaults to the current directory.")
+             .takes_value(true)
+             .required(false))
+}
+
+pub struct Params<'a> {
+    pub repository : Option<&'a Path>
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+    Params { repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }) }
+}
+
+/// State of a working copy, with paths relative to its root.
+pub struct Summary {
+    pub branch: String,
+    pub changes: Vec<(PathBuf, Status)>,
+    pub untracked: Vec<PathBuf>
+}
+
+/// Push to `untracked` the files of directory `dir` (relative to
+/// `root`) that are not in `tracked`. Untracked directories are
+/// listed, but not their contents.
+fn untracked_files(root: &Path, dir: &Path, tracked: &HashSet<PathBuf>, untracked: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let mut entries = Vec::new();
+    for entry in try!(read_dir(root.join(dir))) {
+        entries.push(try!(entry))
+    }
+    entries.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    for entry in entries {
+        if dir.as_os_str().is_empty() && entry.file_name().to_str() == Some(PIJUL_DIR_NAME) {
+            continue
+        }
+        let path = dir.join(entry.file_name());
+        if tracked.contains(&path) {
+            if try!(entry.file_type()).is_dir() {
+                try!(untracked_files(root, &path, tracked, untracked))
+            }
+        } else {
+            untracked.push(path)
+        }
+    }
+    Ok(())
+}
+
+pub fn summary(r: &Path) -> Result<Summary, Error> {
+    let repo_dir=pristine_dir(r);
+    let branch=read_current_branch(r);
+    let repo = try!(Repository::open(&repo_dir).map_err(Error::Repository));
+    let txn = try!(repo.txn_begin());
+    let (changes, _) = try!(txn.record_with_paths(&branch, r).map_err(Error::Repository));
+    let tracked: HashSet<PathBuf> = try!(txn.list_files().map_err(Error::Repository)).into_iter().collect();
+    let mut untracked = Vec::new();
+    try!(untracked_files(r, Path::new(""), &tracked, &mut untracked));
+    Ok(Summary { branch: branch, changes: changed_files(&changes), untracked: untracked })
+}
+
+pub fn run(args : &Params) -> Result<(), Error> {
+    let wd=try!(get_wd(args.repository));
+    match find_repo_root(&wd){
+        None => return Err(Error::NotInARepository),
+        Some(ref r) =>
+        {
+            let summary = try!(summary(r));
+            println!("On branch {}", summary.branch);
+            if summary.changes.is_empty() && summary.untracked.is_empty() {
+                println!("Nothing to record, working copy clean");
+            }
+            if !summary.changes.is_empty() {
+                println!("");
+                println!("Changes not yet recorded:");
+                for &(ref path, status) in summary.changes.iter() {
+                    println!("        {:<10}{}", format!("{}:", status.as_str()), path.display())
+                }
+            }
+            if !summary.untracked.is_empty() {
+                println!("");
+                println!("Untracked files:");
+                for path in summary.untracked.iter() {
+                    println!("        {}", path.display())
+                }
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,205 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg};
+
+extern crate libpijul;
+use self::libpijul::{Repository, ReadTxn};
+use self::libpijul::patch::Patch;
+use self::libpijul::tag::{read_tag, create_tag, delete_tag, list_tags};
+use self::libpijul::fs_representation::{pristine_dir, find_repo_root, read_current_branch};
+use commands::StaticSubcommand;
+use commands::error::Error;
+use super::get_wd;
+
+extern crate rustc_serialize;
+use self::rustc_serialize::hex::ToHex;
+
+use std::collections::HashSet;
+use std::fs::{create_dir_all, read_dir};
+use std::path::Path;
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("tag")
+        .about("Manage tags, i.e. named sets of patches")
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("The repository where to operate, defaults to the current directory.")
+             .takes_value(true)
+             .required(false))
+        .subcommand(SubCommand::with_name("new")
+                    .about("Create a tag with the patches of a branch")
+                    .arg(Arg::with_name("name")
+                         .help("Name of the new tag.")
+                         .required(true))
+                    .arg(Arg::with_name("branch")
+                         .long("branch")
+                         .help("Branch to tag, defaults to the current branch.")
+                         .takes_value(true)))
+        .subcommand(SubCommand::with_name("list")
+                    .about("List tags"))
+        .subcommand(SubCommand::with_name("delete")
+                    .about("Delete a tag")
+                    .arg(Arg::with_name("name")
+                         .help("Name of the tag to delete.")
+                         .required(true)))
+        .subcommand(SubCommand::with_name("diff")
+                    .about("List the patches of a branch that are not in a tag (+), and conversely (-)")
+                    .arg(Arg::with_name("name")
+                         .help("Name of the tag.")
+                         .required(true))
+                    .arg(Arg::with_name("branch")
+                         .long("branch")
+                         .help("Branch to compare, defaults to the current branch.")
+                         .takes_value(true)))
+        .subcommand(SubCommand::with_name("output")
+                    .about("Write the files of a tag to a new directory")
+                    .arg(Arg::with_name("name")
+                         .help("Name of the tag.")
+                         .required(true))
+                    .arg(Arg::with_name("directory")
+                         .help("Directory to write, which must be empty or not exist.")
+                         .required(true)))
+}
+
+#[derive(Debug)]
+pub enum Action<'a> {
+    New { name: &'a str, branch: Option<&'a str> },
+    List,
+    Delete { name: &'a str },
+    Diff { name: &'a str, branch: Option<&'a str> },
+    Output { name: &'a str, directory: &'a Path },
+}
+
+pub struct Params<'a> {
+    pub repository : Option<&'a Path>,
+    pub action : Action<'a>
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+    let action = match args.subcommand() {
+        ("new", Some(args)) => Action::New {
+            name: args.value_of("name").unwrap(),
+            branch: args.value_of("branch")
+        },
+        ("delete", Some(args)) => Action::Delete {
+            name: args.value_of("name").unwrap()
+        },
+        ("diff", Some(args)) => Action::Diff {
+            name: args.value_of("name").unwrap(),
+            branch: args.value_of("branch")
+        },
+        ("output", Some(args)) => Action::Output {
+            name: args.value_of("name").unwrap(),
+            directory: Path::new(args.value_of("directory").unwrap())
+        },
+        _ => Action::List
+    };
+    Params {
+        repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }),
+        action : action
+    }
+}
+
+/// Patches of branch `branch` that are not in tag `name`, and patches
+/// of the tag that are not on the branch, both sorted by hash.
+pub fn diff(r: &Path, name: &str, branch: &str) -> Result<(Vec<Vec<u8>>, Vec<Vec<u8>>), Error> {
+    let tag = try!(read_tag(r, name));
+    let repo = try!(Repository::open(&pristine_dir(r)));
+    let txn = try!(repo.txn_begin());
+    if !txn.branch_exists(branch) {
+        return Err(Error::Repository(libpijul::error::Error::BranchNotFound(branch.to_string())))
+    }
+    let on_branch: HashSet<Vec<u8>> = try!(txn.applied_patches(branch)).into_iter().collect();
+    let mut added: Vec<Vec<u8>> = on_branch.difference(&tag).cloned().collect();
+    let mut removed: Vec<Vec<u8>> = tag.difference(&on_branch).cloned().collect();
+    added.sort();
+    removed.sort();
+    Ok((added, removed))
+}
+
+/// Write the files of tag `name` to `directory`, which must be empty
+/// or not exist. The tag's patches are replayed on a scratch branch,
+/// in a transaction that is never committed.
+pub fn output(r: &Path, name: &str, directory: &Path) -> Result<(), Error> {
+    let mut patches: Vec<Vec<u8>> = try!(read_tag(r, name)).into_iter().collect();
+    patches.sort();
+    if let Ok(mut entries) = read_dir(directory) {
+        if entries.next().is_some() {
+            return Err(Error::DirectoryNotEmpty(directory.to_string_lossy().into_owned()))
+        }
+    }
+    let repo = try!(Repository::open(&pristine_dir(r)));
+    let mut txn = try!(repo.mut_txn_begin());
+    let scratch = txn.scratch_branch(&format!("{} (tag)", name));
+    for hash in patches.iter() {
+        try!(txn.apply_to_pristine(&scratch, r, hash))
+    }
+    try!(create_dir_all(directory));
+    for file in try!(txn.output_files(&scratch)) {
+        try!(file.write(directory))
+    }
+    Ok(())
+}
+
+pub fn run(params : &Params) -> Result<(), Error> {
+    let wd = try!(get_wd(params.repository));
+    match find_repo_root(&wd){
+        None => return Err(Error::NotInARepository),
+        Some(ref target) =>
+        {
+            debug!("tag: {:?}", params.action);
+            match params.action {
+                Action::List => {
+                    for name in try!(list_tags(target)) {
+                        println!("{}", name)
+                    }
+                },
+                Action::New { name, branch } => {
+                    let branch = branch.map(|b| b.to_string()).unwrap_or(read_current_branch(target));
+                    let repo = try!(Repository::open(&pristine_dir(target)));
+                    let txn = try!(repo.txn_begin());
+                    if !txn.branch_exists(&branch) {
+                        return Err(Error::Repository(libpijul::error::Error::BranchNotFound(branch)))
+                    }
+                    let patches = try!(txn.applied_patches(&branch)).into_iter().collect();
+                    try!(create_tag(target, name, &patches))
+                },
+                Action::Delete { name } => {
+                    try!(delete_tag(target, name))
+                },
+                Action::Diff { name, branch } => {
+                    let branch = branch.map(|b| b.to_string()).unwrap_or(read_current_branch(target));
+                    let (added, removed) = try!(diff(target, name, &branch));
+                    for (sign, patches) in vec![('+', added), ('-', removed)] {
+                        for hash in patches {
+                            let patch = try!(Patch::from_repository(target, &hash));
+                            println!("{} {} {}", sign, hash.to_hex(), patch.name)
+                        }
+                    }
+                },
+                Action::Output { name, directory } => {
+                    try!(output(target, name, directory))
+                }
+            }
+            Ok(())
+        }
+    }
+}
@@ -2,7 +2,7 @@ extern crate tempdir;
 extern crate env_logger;
 extern crate rand;
 use self::rand::Rng;
-use commands::{init, info, record, add, remove, pull, mv};
+use commands::{init, info, record, add, remove, pull, mv, unrecord, branch, checkout, diff, status, changes, patch, blame, conflicts, check, key, apply, import_git, export_git, tag, push, deps, revert};
 use commands::error;
 use std::fs;
 use std::path::PathBuf;
@@ -57,9 +57,14 @@ fn add_one_file(repo: &std::path::Path, file: &std::path::Path) -> Result< Optio
     add::run(&add_params)
 }
 
+/// Keys directory given to record, so that tests never sign patches
+/// with the default key of the user running them.
+const NO_KEYS_DIR: &'static str = "/nonexistent/pijul/keys";
+
 fn record_all(repo: &std::path::Path, name: Option<&str>) -> Result<Option<()>, error::Error> {
     let record_params = record::Params {
         repository: Some(repo),
+        keys_dir: Some(std::path::Path::new(NO_KEYS_DIR)),
         yes_to_all: true,
         authors: Some(vec![]),
         patch_name: name,
@@ -68,6 +73,26 @@ fn record_all(repo: &std::path::Path, name: Option<&str>) -> Result<Option<()>,
     record::run(&record_params)
 }
 
+/// Check that all the patches of `repo` are read back identical from
+/// their text form.
+fn check_text_round_trip(repo: &std::path::Path) {
+    let patches_dir = libpijul::fs_representation::patches_dir(repo);
+    for entry in fs::read_dir(&patches_dir).unwrap() {
+        let path = entry.unwrap().path();
+        if !path.to_string_lossy().ends_with(".cbor.gz") {
+            continue
+        }
+        let (patch, _) = libpijul::patch::patch_hash(&path).unwrap();
+        let mut text = Vec::new();
+        patch.to_text(&mut text).unwrap();
+        let read = libpijul::patch::Patch::from_text(&text[..]).unwrap();
+        let mut read_text = Vec::new();
+        read.to_text(&mut read_text).unwrap();
+        assert_eq!(String::from_utf8(read_text).unwrap(), String::from_utf8(text).unwrap());
+        assert_eq!(read.hash().unwrap(), patch.hash().unwrap());
+    }
+}
+
 fn pull_all(from: &std::path::Path, to: &std::path::Path) -> Result<(), error::Error> {
 
     let pull_params = pull::Params {
@@ -75,6 +100,7 @@ fn pull_all(from: &std::path::Path, to: &std::path::Path) -> Result<(), error::E
         remote_id: Some(from.to_str().unwrap()),
         set_default: true,
         port: None,
+        tag: None,
         yes_to_all: true,
     };
     pull::run(&pull_params)
@@ -245,6 +271,18 @@ fn add_remove_nothing_to_record() {
     }
 }
 
+#[test]
+fn add_delete_nothing_to_record() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let file = &dir.join("toto");
+    create_file_random_content(&file, "");
+    add_one_file(&dir, &file).unwrap();
+    // Deleted from the working copy without "pijul remove".
+    fs::remove_file(&file).unwrap();
+    assert!(record_all(&dir, Some("")).unwrap().is_none());
+}
+
 #[test]
 fn no_remove_without_add() {
     let dir = mk_tmp_repo();
@@ -375,6 +413,7 @@ fn add_record_pull_edit_record_pull_(empty_file: bool, really_edit: bool) {
     };
     let record_params = record::Params {
         repository: Some(&dir_a),
+        keys_dir: Some(std::path::Path::new(NO_KEYS_DIR)),
         yes_to_all: true,
         authors: Some(vec![]),
         patch_name: Some("nothing"),
@@ -388,6 +427,7 @@ fn add_record_pull_edit_record_pull_(empty_file: bool, really_edit: bool) {
         remote_id: Some(dir_a.to_str().unwrap()),
         set_default: false,
         port: None,
+        tag: None,
         yes_to_all: true,
     };
     pull::run(&pull_params).unwrap();
@@ -404,6 +444,7 @@ fn add_record_pull_edit_record_pull_(empty_file: bool, really_edit: bool) {
     }
     let record_params = record::Params {
         repository: Some(&dir_a),
+        keys_dir: Some(std::path::Path::new(NO_KEYS_DIR)),
         yes_to_all: true,
         authors: Some(vec![]),
         patch_name: Some("edit"),
@@ -418,6 +459,7 @@ fn add_record_pull_edit_record_pull_(empty_file: bool, really_edit: bool) {
         remote_id: Some(dir_a.to_str().unwrap()),
         set_default: false,
         port: None,
+        tag: None,
         yes_to_all: true,
     };
     pull::run(&pull_params).unwrap();
@@ -558,6 +600,7 @@ fn move_to_file_(edit_file: bool) {
         remote_id: Some(dir_a.to_str().unwrap()),
         set_default: false,
         port: None,
+        tag: None,
         yes_to_all: true,
     };
     pull_all(&dir_a, &dir_b).unwrap();
@@ -629,6 +672,7 @@ fn move_to_dir_editing_(empty_file: bool, edit_file: bool) {
     };
     let record_params = record::Params {
         repository: Some(&dir_a),
+        keys_dir: Some(std::path::Path::new(NO_KEYS_DIR)),
         yes_to_all: true,
         authors: Some(vec![]),
         patch_name: Some("file add"),
@@ -651,6 +695,7 @@ fn move_to_dir_editing_(empty_file: bool, edit_file: bool) {
 
     let record_params = record::Params {
         repository: Some(&dir_a),
+        keys_dir: Some(std::path::Path::new(NO_KEYS_DIR)),
         yes_to_all: true,
         authors: Some(vec![]),
         patch_name: Some("dir add"),
@@ -700,6 +745,7 @@ fn move_to_dir_editing_(empty_file: bool, edit_file: bool) {
         remote_id: Some(dir_a.to_str().unwrap()),
         set_default: false,
         port: None,
+        tag: None,
         yes_to_all: true,
     };
     pull::run(&pull_params).unwrap();
@@ -742,6 +788,7 @@ fn add_edit_remove_pull() {
     };
     let record_params = record::Params {
         repository: Some(&dir_a),
+        keys_dir: Some(std::path::Path::new(NO_KEYS_DIR)),
         yes_to_all: true,
         authors: Some(vec![]),
         patch_name: Some("file add"),
@@ -757,6 +804,7 @@ fn add_edit_remove_pull() {
         remote_id: Some(dir_a.to_str().unwrap()),
         set_default: true,
         port: None,
+        tag: None,
         yes_to_all: true,
     };
     pull::run(&pull_params).unwrap();
@@ -769,6 +817,7 @@ fn add_edit_remove_pull() {
 
     let record_params = record::Params {
         repository: Some(&dir_b),
+        keys_dir: Some(std::path::Path::new(NO_KEYS_DIR)),
         yes_to_all: true,
         authors: Some(vec![]),
         patch_name: Some("file remove"),
@@ -779,12 +828,15 @@ fn add_edit_remove_pull() {
         Some(()) => (),
     };
     println!("done recording add of toto");
+    // The removal is recorded once.
+    assert!(record_all(&dir_b, Some("nothing")).unwrap().is_none());
 
     let pull_params = pull::Params {
         repository: Some(&dir_a),
         remote_id: Some(dir_b.to_str().unwrap()),
         set_default: true,
         port: None,
+        tag: None,
         yes_to_all: true,
     };
     pull::run(&pull_params).unwrap();
@@ -952,3 +1004,1379 @@ fn add_record_edit_record() {
     }
 
 }
+
+fn branch_patches(repo: &std::path::Path) -> std::collections::HashSet<Vec<u8>> {
+    let changes_file = libpijul::fs_representation::branch_changes_file(repo, libpijul::DEFAULT_BRANCH.as_bytes());
+    libpijul::patch::read_changes_from_file(&changes_file).unwrap()
+}
+
+fn unrecord_one(repo: &std::path::Path, hash: &[u8], cascade: bool) -> Result<Option<()>, error::Error> {
+    use rustc_serialize::hex::ToHex;
+    let hex = hash.to_hex();
+    let unrecord_params = unrecord::Params {
+        repository: Some(repo),
+        hex_hash: vec![&hex],
+        cascade: cascade,
+    };
+    unrecord::run(&unrecord_params)
+}
+
+#[test]
+fn add_record_edit_record_unrecord() {
+    let dir = mk_tmp_repo();
+    let fpath = &dir.path().join("toto");
+    create_file_random_content(&fpath, "toto > ");
+    match add_one_file(&dir.path(), &fpath).unwrap() {
+        Some(()) => (),
+        None => panic!("no file added"),
+    };
+    match record_all(&dir.path(), Some("add toto")).unwrap() {
+        None => panic!("file add is not going to be recorded"),
+        Some(()) => (),
+    }
+    let first = branch_patches(&dir.path());
+    assert_eq!(first.len(), 1);
+    let first = first.into_iter().next().unwrap();
+
+    create_file_random_content(&fpath, "toto > ");
+    match record_all(&dir.path(), Some("edit toto")).unwrap() {
+        None => panic!("file editing will not be recorded"),
+        Some(()) => (),
+    }
+    let second = branch_patches(&dir.path());
+    assert_eq!(second.len(), 2);
+    let second = second.into_iter().filter(|h| *h != first).next().unwrap();
+
+    match unrecord_one(&dir.path(), &first, false) {
+        Err(error::Error::Repository(libpijul::error::Error::PatchIsDependedOn(_, _))) => (),
+        Ok(_) => panic!("unrecorded a patch other patches depend on"),
+        Err(e) => panic!("funky failure {} while unrecording", e),
+    }
+    unrecord_one(&dir.path(), &second, false).unwrap();
+    assert!(branch_patches(&dir.path()).len() == 1);
+
+    match record_all(&dir.path(), Some("edit toto again")).unwrap() {
+        None => panic!("unrecorded edit is not going to be recorded again"),
+        Some(()) => (),
+    }
+}
+
+fn branch_action(repo: &std::path::Path, action: branch::Action) -> Result<(), error::Error> {
+    let branch_params = branch::Params {
+        repository: Some(repo),
+        action: action,
+    };
+    branch::run(&branch_params)
+}
+
+#[test]
+fn fork_rename_delete_branch() {
+    let dir = mk_tmp_repo();
+    let fpath = &dir.path().join("toto");
+    create_file_random_content(&fpath, "toto > ");
+    add_one_file(&dir.path(), &fpath).unwrap();
+    record_all(&dir.path(), Some("add toto")).unwrap();
+    let main_patches = branch_patches(&dir.path());
+
+    branch_action(&dir.path(), branch::Action::New { name: "exp", from: None }).unwrap();
+    let exp_file = libpijul::fs_representation::branch_changes_file(&dir.path(), b"exp");
+    assert_eq!(libpijul::patch::read_changes_from_file(&exp_file).unwrap(), main_patches);
+    match branch_action(&dir.path(), branch::Action::New { name: "exp", from: None }) {
+        Err(error::Error::Repository(libpijul::error::Error::BranchAlreadyExists(_))) => (),
+        Ok(_) => panic!("created the same branch twice"),
+        Err(e) => panic!("funky failure {} while creating a branch", e),
+    }
+
+    branch_action(&dir.path(), branch::Action::Rename { name: "exp", new_name: "exp2" }).unwrap();
+    assert!(fs::metadata(&exp_file).is_err());
+    let exp2_file = libpijul::fs_representation::branch_changes_file(&dir.path(), b"exp2");
+    assert_eq!(libpijul::patch::read_changes_from_file(&exp2_file).unwrap(), main_patches);
+
+    branch_action(&dir.path(), branch::Action::Delete { name: "exp2" }).unwrap();
+    assert!(fs::metadata(&exp2_file).is_err());
+    match branch_action(&dir.path(), branch::Action::Delete { name: "exp2" }) {
+        Err(error::Error::Repository(libpijul::error::Error::BranchNotFound(_))) => (),
+        Ok(_) => panic!("deleted a branch twice"),
+        Err(e) => panic!("funky failure {} while deleting a branch", e),
+    }
+    assert_eq!(branch_patches(&dir.path()), main_patches);
+}
+
+fn branch_names(repo: &std::path::Path) -> Vec<String> {
+    use libpijul::ReadTxn;
+    let repository = libpijul::Repository::open(&libpijul::fs_representation::pristine_dir(repo)).unwrap();
+    let txn = repository.txn_begin().unwrap();
+    txn.branches()
+}
+
+#[test]
+fn delete_default_branch() {
+    let dir = mk_tmp_repo();
+    assert_eq!(branch_names(&dir.path()), vec![libpijul::DEFAULT_BRANCH.to_string()]);
+    branch_action(&dir.path(), branch::Action::New { name: "exp", from: None }).unwrap();
+    checkout_branch(&dir.path(), "exp", false).unwrap();
+    branch_action(&dir.path(), branch::Action::Rename { name: libpijul::DEFAULT_BRANCH, new_name: "old" }).unwrap();
+    assert_eq!(branch_names(&dir.path()), vec!["exp".to_string(), "old".to_string()]);
+    branch_action(&dir.path(), branch::Action::Delete { name: "old" }).unwrap();
+    assert_eq!(branch_names(&dir.path()), vec!["exp".to_string()]);
+}
+
+fn checkout_branch(repo: &std::path::Path, name: &str, force: bool) -> Result<(), error::Error> {
+    let checkout_params = checkout::Params {
+        repository: Some(repo),
+        branch: name,
+        force: force,
+    };
+    checkout::run(&checkout_params)
+}
+
+#[test]
+fn checkout_between_branches() {
+    let dir = mk_tmp_repo();
+    let toto = &dir.path().join("toto");
+    let text_toto = create_file_random_content(&toto, "toto > ");
+    add_one_file(&dir.path(), &toto).unwrap();
+    record_all(&dir.path(), Some("add toto")).unwrap();
+
+    branch_action(&dir.path(), branch::Action::New { name: "exp", from: None }).unwrap();
+    checkout_branch(&dir.path(), "exp", false).unwrap();
+    assert_eq!(libpijul::fs_representation::read_current_branch(&dir.path()), "exp");
+
+    let titi = &dir.path().join("titi");
+    let text_titi = create_file_random_content(&titi, "titi > ");
+    add_one_file(&dir.path(), &titi).unwrap();
+    record_all(&dir.path(), Some("add titi")).unwrap();
+
+    checkout_branch(&dir.path(), "main", false).unwrap();
+    assert!(fs::metadata(&titi).is_err());
+    assert!(file_eq(&toto, &text_toto));
+
+    checkout_branch(&dir.path(), "exp", false).unwrap();
+    assert!(file_eq(&titi, &text_titi));
+    assert!(file_eq(&toto, &text_toto));
+
+    create_file_random_content(&toto, "toto > ");
+    match checkout_branch(&dir.path(), "main", false) {
+        Err(error::Error::UnrecordedChanges) => (),
+        Ok(_) => panic!("switched branches with unrecorded changes"),
+        Err(e) => panic!("funky failure {} while switching branches", e),
+    }
+    match branch_action(&dir.path(), branch::Action::Delete { name: "exp" }) {
+        Err(error::Error::CurrentBranch(_)) => (),
+        Ok(_) => panic!("deleted the current branch"),
+        Err(e) => panic!("funky failure {} while deleting a branch", e),
+    }
+    checkout_branch(&dir.path(), "main", true).unwrap();
+    assert!(file_eq(&toto, &text_toto));
+}
+
+fn create_file_binary_content(path: &std::path::Path) -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+    let mut contents: Vec<u8> = (0..4096).map(|_| rng.gen()).collect();
+    contents[0] = 0;
+    contents[2048] = b'\n';
+    let mut file = fs::File::create(path).unwrap();
+    file.write_all(&contents).unwrap();
+    contents
+}
+
+fn read_file(path: &std::path::Path) -> Vec<u8> {
+    let mut contents = Vec::new();
+    fs::File::open(path).unwrap().read_to_end(&mut contents).unwrap();
+    contents
+}
+
+#[test]
+fn binary_edit_and_conflict() {
+    let (tmp_dir, dir_a, dir_b) = mk_tmp_repo_pair();
+    let _ = tmp_dir.into_path();
+
+    let blob_a = &dir_a.join("blob");
+    create_file_binary_content(&blob_a);
+    add_one_file(&dir_a, &blob_a).unwrap();
+    record_all(&dir_a, Some("add blob")).unwrap();
+    let edited = create_file_binary_content(&blob_a);
+    record_all(&dir_a, Some("edit blob")).unwrap();
+
+    pull_all(&dir_a, &dir_b).unwrap();
+    let blob_b = &dir_b.join("blob");
+    assert_eq!(read_file(&blob_b), edited);
+
+    // Concurrent replacements produce one copy per side.
+    let contents_a = create_file_binary_content(&blob_a);
+    record_all(&dir_a, Some("replace blob in a")).unwrap();
+    let contents_b = create_file_binary_content(&blob_b);
+    record_all(&dir_b, Some("replace blob in b")).unwrap();
+    // Untracked files are never overwritten by the copies.
+    fs::File::create(&dir_b.join("blob~1")).unwrap().write_all(b"untracked\n").unwrap();
+    pull_all(&dir_a, &dir_b).unwrap();
+
+    let side_0 = read_file(&blob_b);
+    let side_1 = read_file(&dir_b.join("blob~2"));
+    assert!((side_0 == contents_a && side_1 == contents_b) ||
+            (side_0 == contents_b && side_1 == contents_a));
+    assert_eq!(read_file(&dir_b.join("blob~1")), b"untracked\n");
+
+    // Outputting again reuses the same copy.
+    revert::run(&revert::Params { repository: Some(&dir_b) }).unwrap();
+    assert_eq!(read_file(&dir_b.join("blob~2")), side_1);
+    assert!(fs::metadata(&dir_b.join("blob~3")).is_err());
+
+    // Once the conflict is solved, the copy is removed.
+    create_file_binary_content(&blob_b);
+    record_all(&dir_b, Some("solve the conflict")).unwrap();
+    revert::run(&revert::Params { repository: Some(&dir_b) }).unwrap();
+    assert!(fs::metadata(&dir_b.join("blob~2")).is_err());
+    assert_eq!(read_file(&dir_b.join("blob~1")), b"untracked\n");
+}
+
+#[cfg(not(windows))]
+#[test]
+fn record_and_pull_symlink() {
+    use std::os::unix::fs::symlink;
+    let (tmp_dir, dir_a, dir_b) = mk_tmp_repo_pair();
+    let _ = tmp_dir.into_path();
+
+    let toto = &dir_a.join("toto");
+    create_file_random_content(&toto, "toto > ");
+    let link_a = &dir_a.join("link");
+    symlink("toto", &link_a).unwrap();
+    add_one_file(&dir_a, &toto).unwrap();
+    add_one_file(&dir_a, &link_a).unwrap();
+    record_all(&dir_a, Some("add toto and link")).unwrap();
+
+    pull_all(&dir_a, &dir_b).unwrap();
+    let link_b = &dir_b.join("link");
+    assert!(fs::symlink_metadata(&link_b).unwrap().file_type().is_symlink());
+    assert_eq!(fs::read_link(&link_b).unwrap(), PathBuf::from("toto"));
+
+    // Editing the target does not change the link.
+    create_file_random_content(&toto, "toto > ");
+    record_all(&dir_a, Some("edit toto")).unwrap();
+    fs::remove_file(&link_a).unwrap();
+    symlink("titi", &link_a).unwrap();
+    record_all(&dir_a, Some("retarget link")).unwrap();
+
+    pull_all(&dir_a, &dir_b).unwrap();
+    assert!(files_eq(&toto, &dir_b.join("toto")));
+    assert_eq!(fs::read_link(&link_b).unwrap(), PathBuf::from("titi"));
+}
+
+fn write_lines(path: &std::path::Path, text: &[String]) {
+    let mut file = fs::File::create(path).unwrap();
+    for line in text.iter() {
+        file.write_all(line.as_bytes()).unwrap();
+    }
+}
+
+#[test]
+fn record_small_edit_in_large_file() {
+    let (tmp_dir, dir_a, dir_b) = mk_tmp_repo_pair();
+    let _ = tmp_dir.into_path();
+
+    let big = &dir_a.join("big");
+    let mut text: Vec<String> = (0..10000).map(|i| format!("line {}\n", i)).collect();
+    write_lines(&big, &text);
+    add_one_file(&dir_a, &big).unwrap();
+    record_all(&dir_a, Some("add big")).unwrap();
+
+    // A diff computing the full table of the longest common
+    // subsequence takes the same time for all edits of this file,
+    // whereas it should depend on the size of the edit.
+    text[100] = "edited\n".to_string();
+    text.insert(7500, "inserted\n".to_string());
+    text.remove(9000);
+    write_lines(&big, &text);
+    let start = std::time::Instant::now();
+    record_all(&dir_a, Some("small edit")).unwrap();
+    let small = start.elapsed();
+
+    for (i, line) in text.iter_mut().enumerate() {
+        if i % 4 == 0 {
+            *line = format!("edited {}\n", i)
+        }
+    }
+    write_lines(&big, &text);
+    let start = std::time::Instant::now();
+    record_all(&dir_a, Some("large edit")).unwrap();
+    let large = start.elapsed();
+    assert!(small * 2 < large, "small edit: {:?}, large edit: {:?}", small, large);
+
+    pull_all(&dir_a, &dir_b).unwrap();
+    assert!(file_eq(&dir_b.join("big"), &text));
+}
+
+#[test]
+fn diff_does_not_record() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let toto = &dir.join("toto");
+    let text = create_file_random_content(&toto, "");
+    add_one_file(&dir, &toto).unwrap();
+    let diff_params = diff::Params { repository: Some(&dir), paths: vec!(), json: false };
+    assert!(diff::run(&diff_params).unwrap().is_some());
+    record_all(&dir, Some("add toto")).unwrap();
+    assert!(diff::run(&diff_params).unwrap().is_none());
+
+    let mut edited = edit(&text, 10, 10);
+    edited.push("one more line\n".to_string());
+    {
+        let mut file = fs::File::create(&toto).unwrap();
+        for line in edited.iter() {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+    }
+    let json_params = diff::Params { repository: Some(&dir), paths: vec!(), json: true };
+    assert!(diff::run(&json_params).unwrap().is_some());
+    assert!(diff::run(&diff_params).unwrap().is_some());
+    // The changes are still unrecorded.
+    assert!(record_all(&dir, Some("edit toto")).unwrap().is_some());
+    assert!(diff::run(&diff_params).unwrap().is_none());
+}
+
+fn hunk_lines(hunk: &diff::Hunk) -> Vec<String> {
+    hunk.lines.iter().map(|&(tag, ref l)| format!("{}{}", tag, String::from_utf8_lossy(l))).collect()
+}
+
+#[test]
+fn diff_renders_recorded_changes() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let toto = &dir.join("toto");
+    {
+        let mut file = fs::File::create(&toto).unwrap();
+        file.write_all(b"a\nb\nc\nd\ne\n").unwrap();
+    }
+    add_one_file(&dir, &toto).unwrap();
+    record_all(&dir, Some("add toto")).unwrap();
+
+    {
+        let mut file = fs::File::create(&toto).unwrap();
+        file.write_all(b"a\nb\nC\nd\ne\nf\n").unwrap();
+    }
+    let diffs = diff::diffs(&dir, &[]).unwrap();
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(diffs[0].status, diff::Status::Modified);
+    assert_eq!(diffs[0].hunks.len(), 1);
+    assert_eq!(hunk_lines(&diffs[0].hunks[0]),
+               vec![" a\n", " b\n", "-c\n", "+C\n", " d\n", " e\n", "+f\n"]);
+    assert_eq!((diffs[0].hunks[0].old_start, diffs[0].hunks[0].old_len), (1, 5));
+    assert_eq!((diffs[0].hunks[0].new_start, diffs[0].hunks[0].new_len), (1, 6));
+    record_all(&dir, Some("edit toto")).unwrap();
+
+    // The lines of a removed file are shown too.
+    let rem_params = remove::Params {
+        repository: Some(&dir),
+        touched_files: vec![&toto],
+    };
+    remove::run(&rem_params).unwrap();
+    fs::remove_file(&toto).unwrap();
+    let diffs = diff::diffs(&dir, &[]).unwrap();
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(diffs[0].status, diff::Status::Deleted);
+    assert!(!diffs[0].is_dir);
+    assert_eq!(hunk_lines(&diffs[0].hunks[0]),
+               vec!["-a\n", "-b\n", "-C\n", "-d\n", "-e\n", "-f\n"]);
+}
+
+#[test]
+fn status_lists_changes_and_untracked_files() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let recorded = &dir.join("recorded");
+    create_file_random_content(&recorded, "");
+    add_one_file(&dir, &recorded).unwrap();
+    record_all(&dir, Some("add recorded")).unwrap();
+
+    let summary = status::summary(&dir).unwrap();
+    assert!(summary.changes.is_empty());
+    assert!(summary.untracked.is_empty());
+
+    {
+        let mut file = fs::OpenOptions::new().append(true).open(&recorded).unwrap();
+        file.write_all(b"one more line\n").unwrap();
+    }
+    let staged = &dir.join("staged");
+    create_file_random_content(&staged, "");
+    add_one_file(&dir, &staged).unwrap();
+    fs::create_dir(&dir.join("subdir")).unwrap();
+    create_file_random_content(&dir.join("subdir").join("untracked"), "");
+
+    let mut summary = status::summary(&dir).unwrap();
+    summary.changes.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(summary.changes,
+               vec!((PathBuf::from("recorded"), diff::Status::Modified),
+                    (PathBuf::from("staged"), diff::Status::Added)));
+    assert_eq!(summary.untracked, vec!(PathBuf::from("subdir")));
+}
+
+/// Remove the table of applied patches from the pristine of `dir`,
+/// as in pristines written before it existed.
+fn remove_applied_root(dir: &std::path::Path) {
+    // Sanakirja keeps the root of table n at offset 32 + 8 * n, and
+    // the table of applied patches is table 10.
+    let db = libpijul::fs_representation::pristine_dir(dir).join("db");
+    let mut file = fs::OpenOptions::new().write(true).open(&db).unwrap();
+    file.seek(std::io::SeekFrom::Start(112)).unwrap();
+    file.write_all(&[0; 8]).unwrap();
+}
+
+#[test]
+fn read_pristine_without_applied_patches_table() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let file = &dir.join("file");
+    create_file_random_content(&file, "");
+    add_one_file(&dir, &file).unwrap();
+    record_all(&dir, Some("add file")).unwrap();
+    remove_applied_root(&dir);
+
+    // Read-only commands work, the missing table is created.
+    assert!(status::summary(&dir).unwrap().changes.is_empty());
+    assert_eq!(branch_names(&dir), vec![libpijul::DEFAULT_BRANCH.to_string()]);
+}
+
+#[test]
+fn changes_of_pristine_without_applied_patches_table() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    for name in ["first", "second"].iter() {
+        let file = &dir.join(name);
+        create_file_random_content(&file, "");
+        add_one_file(&dir, &file).unwrap();
+        record_all(&dir, Some(*name)).unwrap();
+    }
+    remove_applied_root(&dir);
+    let file = &dir.join("third");
+    create_file_random_content(&file, "");
+    add_one_file(&dir, &file).unwrap();
+    record_all(&dir, Some("third")).unwrap();
+
+    // The patches applied before come first, in no particular order.
+    let params = changes::Params { repository: Some(&dir), branch: None, by_timestamp: false,
+                                   author: None, name: None, json: false };
+    let mut names: Vec<String> = changes::patches(&dir, &params).unwrap().into_iter().map(|(_, p)| p.name).collect();
+    assert_eq!(names.pop(), Some("third".to_string()));
+    names.sort();
+    assert_eq!(names, vec!("first", "second"));
+}
+
+#[test]
+fn changes_in_application_order() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    for name in ["first", "second", "third"].iter() {
+        let file = &dir.join(name);
+        create_file_random_content(&file, "");
+        add_one_file(&dir, &file).unwrap();
+        record_all(&dir, Some(*name)).unwrap();
+    }
+    let mut params = changes::Params { repository: Some(&dir), branch: None, by_timestamp: false,
+                                       author: None, name: None, json: false };
+    let names: Vec<String> = changes::patches(&dir, &params).unwrap().into_iter().map(|(_, p)| p.name).collect();
+    assert_eq!(names, vec!("first", "second", "third"));
+
+    params.name = Some("ir");
+    let names: Vec<String> = changes::patches(&dir, &params).unwrap().into_iter().map(|(_, p)| p.name).collect();
+    assert_eq!(names, vec!("first", "third"));
+
+    params.name = None;
+    params.author = Some("nobody");
+    assert!(changes::patches(&dir, &params).unwrap().is_empty());
+}
+
+#[test]
+fn patch_show_by_prefix() {
+    use rustc_serialize::hex::ToHex;
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let file = &dir.join("file");
+    create_file_random_content(&file, "");
+    add_one_file(&dir, &file).unwrap();
+    record_all(&dir, Some("add file")).unwrap();
+    let params = changes::Params { repository: Some(&dir), branch: None, by_timestamp: false,
+                                   author: None, name: None, json: false };
+    let hash = changes::patches(&dir, &params).unwrap()[0].0.to_hex();
+    let show = patch::Params { repository: Some(&dir),
+                               action: patch::Action::Show { hash: &hash[0..8], branch: None, json: false } };
+    patch::run(&show).unwrap();
+    let show = patch::Params { repository: Some(&dir),
+                               action: patch::Action::Show { hash: &hash, branch: None, json: true } };
+    patch::run(&show).unwrap();
+
+    let repo = libpijul::Repository::open(&libpijul::fs_representation::pristine_dir(&dir)).unwrap();
+    let txn = repo.txn_begin().unwrap();
+    let branch = libpijul::fs_representation::read_current_branch(&dir);
+    let (resolved, p) = patch::load_patch(&dir, &txn, &branch, &hash[0..8]).unwrap();
+    assert_eq!(resolved.to_hex(), hash);
+    let header = changes::patch_header(&resolved, &p);
+    assert!(header.starts_with(&format!("Hash: {}\n", hash)));
+    assert!(header.contains("\n    add file\n"));
+
+    // Only a missing patch file is reported as an unknown patch.
+    let patch_file = dir.join(libpijul::fs_representation::patch_path(&resolved, std::path::MAIN_SEPARATOR));
+    fs::File::create(&patch_file).unwrap().write_all(b"not a patch").unwrap();
+    match patch::load_patch(&dir, &txn, &branch, &hash[0..8]) {
+        Err(error::Error::PatchNotFound(_, _)) => panic!("a corrupted patch is not a missing patch"),
+        Err(_) => (),
+        Ok(_) => panic!("loaded a corrupted patch")
+    }
+    fs::remove_file(&patch_file).unwrap();
+    match patch::load_patch(&dir, &txn, &branch, &hash[0..8]) {
+        Err(error::Error::PatchNotFound(_, _)) => (),
+        Err(e) => panic!("funky failure {} while loading a missing patch", e),
+        Ok(_) => panic!("loaded a missing patch")
+    }
+}
+
+#[test]
+fn blame_finds_the_patch_of_each_line() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let file = &dir.join("file");
+    fs::File::create(&file).unwrap().write_all(b"a\nb\n").unwrap();
+    add_one_file(&dir, &file).unwrap();
+    record_all(&dir, Some("first")).unwrap();
+    fs::File::create(&file).unwrap().write_all(b"a\nc\nb\n").unwrap();
+    let record_params = record::Params {
+        repository: Some(&dir),
+        keys_dir: Some(std::path::Path::new(NO_KEYS_DIR)),
+        yes_to_all: true,
+        authors: Some(vec!["Jane Doe"]),
+        patch_name: Some("second"),
+    };
+    record::run(&record_params).unwrap();
+
+    let params = changes::Params { repository: Some(&dir), branch: None, by_timestamp: false,
+                                   author: None, name: None, json: false };
+    let patches = changes::patches(&dir, &params).unwrap();
+    let hashes: Vec<Vec<u8>> = patches.iter().map(|&(ref h, _)| h.clone()).collect();
+    let params = blame::Params { repository: Some(&dir), file: &file, branch: None };
+    let r = std::fs::canonicalize(&dir).unwrap();
+    let lines = blame::annotate(&r, &params).unwrap();
+    let lines: Vec<(Vec<u8>, Option<Vec<u8>>, Vec<String>, Option<String>)> =
+        lines.into_iter().map(|l| (l.contents, l.patch, l.authors, l.timestamp)).collect();
+    let first = (Some(hashes[0].clone()), Vec::new(), Some(patches[0].1.timestamp.clone()));
+    let second = (Some(hashes[1].clone()), vec!("Jane Doe".to_string()), Some(patches[1].1.timestamp.clone()));
+    assert_eq!(lines, vec!((b"a\n".to_vec(), first.0.clone(), first.1.clone(), first.2.clone()),
+                           (b"c\n".to_vec(), second.0, second.1, second.2),
+                           (b"b\n".to_vec(), first.0, first.1, first.2)));
+    assert!(blame::run(&params).is_ok());
+}
+
+#[test]
+fn conflicts_lists_both_sides() {
+    let (tmp_dir, dir_a, dir_b) = mk_tmp_repo_pair();
+    let _ = tmp_dir.into_path();
+
+    let file_a = &dir_a.join("file");
+    fs::File::create(&file_a).unwrap().write_all(b"a\nb\n").unwrap();
+    add_one_file(&dir_a, &file_a).unwrap();
+    record_all(&dir_a, Some("add file")).unwrap();
+    pull_all(&dir_a, &dir_b).unwrap();
+
+    let params_b = conflicts::Params { repository: Some(&dir_b), branch: None };
+    let r_b = std::fs::canonicalize(&dir_b).unwrap();
+    assert!(conflicts::conflicts(&r_b, &params_b).unwrap().is_empty());
+    assert!(conflicts::run(&params_b).is_ok());
+
+    fs::File::create(&file_a).unwrap().write_all(b"a\nx\nb\n").unwrap();
+    record_all(&dir_a, Some("insert x")).unwrap();
+    let file_b = &dir_b.join("file");
+    fs::File::create(&file_b).unwrap().write_all(b"a\ny\nb\n").unwrap();
+    record_all(&dir_b, Some("insert y")).unwrap();
+    pull_all(&dir_a, &dir_b).unwrap();
+
+    let found = conflicts::conflicts(&r_b, &params_b).unwrap();
+    assert_eq!(found.len(), 1);
+    match found[0] {
+        libpijul::Conflict::Lines { ref path, ref sides } => {
+            assert_eq!(path, &PathBuf::from("file"));
+            let mut sides: Vec<Vec<Vec<u8>>> = sides.iter()
+                .map(|s| s.lines.iter().map(|l| l.contents.clone()).collect())
+                .collect();
+            sides.sort();
+            assert_eq!(sides, vec!(vec!(b"x\n".to_vec()), vec!(b"y\n".to_vec())));
+        },
+        ref c => panic!("unexpected conflict {:?}", c)
+    }
+    match conflicts::run(&params_b) {
+        Err(error::Error::Conflicts(1)) => {},
+        _ => panic!("conflicts should fail")
+    }
+}
+
+#[test]
+fn conflicts_lists_files_with_the_same_name() {
+    let (tmp_dir, dir_a, dir_b) = mk_tmp_repo_pair();
+    let _ = tmp_dir.into_path();
+
+    let file_a = &dir_a.join("file");
+    fs::File::create(&file_a).unwrap().write_all(b"a\n").unwrap();
+    add_one_file(&dir_a, &file_a).unwrap();
+    record_all(&dir_a, Some("add file in a")).unwrap();
+    let file_b = &dir_b.join("file");
+    fs::File::create(&file_b).unwrap().write_all(b"b\n").unwrap();
+    add_one_file(&dir_b, &file_b).unwrap();
+    record_all(&dir_b, Some("add file in b")).unwrap();
+    pull_all(&dir_a, &dir_b).unwrap();
+
+    let params_b = conflicts::Params { repository: Some(&dir_b), branch: None };
+    let r_b = std::fs::canonicalize(&dir_b).unwrap();
+    let found = conflicts::conflicts(&r_b, &params_b).unwrap();
+    assert_eq!(found.len(), 1);
+    match found[0] {
+        libpijul::Conflict::Files { ref path, count } => {
+            assert_eq!(path, &PathBuf::from("file"));
+            assert_eq!(count, 2)
+        },
+        ref c => panic!("unexpected conflict {:?}", c)
+    }
+}
+
+#[test]
+fn diff3_conflict_markers() {
+    let (tmp_dir, dir_a, dir_b) = mk_tmp_repo_pair();
+    let _ = tmp_dir.into_path();
+
+    let file_a = &dir_a.join("file");
+    fs::File::create(&file_a).unwrap().write_all(b"a\nb\nc\n").unwrap();
+    add_one_file(&dir_a, &file_a).unwrap();
+    record_all(&dir_a, Some("add file")).unwrap();
+    pull_all(&dir_a, &dir_b).unwrap();
+
+    let mut meta = ::meta::Meta::new();
+    meta.conflict_style = Some("diff3".to_string());
+    meta.save(&dir_b).unwrap();
+
+    fs::File::create(&file_a).unwrap().write_all(b"a\nx\nc\n").unwrap();
+    record_all(&dir_a, Some("b to x")).unwrap();
+    let file_b = &dir_b.join("file");
+    fs::File::create(&file_b).unwrap().write_all(b"a\ny\nc\n").unwrap();
+    record_all(&dir_b, Some("b to y")).unwrap();
+    pull_all(&dir_a, &dir_b).unwrap();
+
+    let contents = String::from_utf8(read_file(&file_b)).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 9);
+    assert!(lines[1].starts_with(">>>>>>>>") && (lines[1].ends_with(" b to x") || lines[1].ends_with(" b to y")));
+    assert!(lines[3].starts_with("||||||||") && lines[3].ends_with(" base"));
+    assert_eq!(lines[4], "b");
+    assert!(lines[5].starts_with("========") && (lines[5].ends_with(" b to x") || lines[5].ends_with(" b to y")));
+    assert!(lines[7].starts_with("<<<<<<<<"));
+
+    // The labels and the original version are not recorded.
+    assert!(record_all(&dir_b, Some("nothing")).unwrap().is_none());
+
+    // Lines of the user that look like markers are recorded.
+    let mut contents = read_file(&file_b);
+    contents.extend(b"|||||||||||||||||||||||||||||||| base\nz\n".iter());
+    fs::File::create(&file_b).unwrap().write_all(&contents).unwrap();
+    assert!(record_all(&dir_b, Some("marker-like line")).unwrap().is_some());
+    let params = changes::Params { repository: Some(&dir_b), branch: None, by_timestamp: false,
+                                   author: None, name: None, json: false };
+    let r_b = std::fs::canonicalize(&dir_b).unwrap();
+    let last = changes::patches(&r_b, &params).unwrap().into_iter()
+        .find(|&(_, ref p)| p.name == "marker-like line")
+        .unwrap();
+    let added: usize = last.1.changes.iter().map(|c| match *c {
+        libpijul::patch::Change::NewNodes { ref nodes, .. } => nodes.len(),
+        _ => 0
+    }).sum();
+    assert_eq!(added, 2);
+}
+
+#[test]
+fn check_detects_tampered_patches() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let file = &dir.join("file");
+    create_file_random_content(&file, "");
+    add_one_file(&dir, &file).unwrap();
+    record_all(&dir, Some("add file")).unwrap();
+
+    let params = check::Params { repository: Some(&dir), repair: false };
+    check::run(&params).unwrap();
+
+    let hash = branch_patches(&dir).into_iter().next().unwrap();
+    let patch_file = dir.join(libpijul::fs_representation::patch_path(&hash, std::path::MAIN_SEPARATOR));
+    // Recompressing a patch does not change its hash, changing it does.
+    let (mut patch, _) = libpijul::patch::patch_hash(&patch_file).unwrap();
+    patch.to_writer(&mut fs::File::create(&patch_file).unwrap()).unwrap();
+    check::run(&params).unwrap();
+    patch.name = "tampered".to_string();
+    patch.to_writer(&mut fs::File::create(&patch_file).unwrap()).unwrap();
+    match check::run(&params) {
+        Err(error::Error::Inconsistencies(1)) => {},
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(()) => panic!("the tampered patch was not detected")
+    }
+}
+
+#[test]
+fn check_repair_rebuilds_the_pristine() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let file = &dir.join("file");
+    create_file_random_content(&file, "");
+    add_one_file(&dir, &file).unwrap();
+    record_all(&dir, Some("add file")).unwrap();
+    let patches = branch_patches(&dir);
+
+    // An unrecorded change, which must survive the repair.
+    fs::OpenOptions::new().append(true).open(&file).unwrap().write_all(b"unrecorded\n").unwrap();
+    let contents = read_file(&file);
+
+    fs::remove_dir_all(libpijul::fs_representation::pristine_dir(&dir)).unwrap();
+    let params = check::Params { repository: Some(&dir), repair: true };
+    check::run(&params).unwrap();
+
+    assert_eq!(branch_patches(&dir), patches);
+    assert_eq!(read_file(&file), contents);
+    // The file is still tracked, and only the unrecorded change is left to record.
+    assert!(record_all(&dir, Some("unrecorded")).unwrap().is_some());
+    assert!(record_all(&dir, Some("nothing")).unwrap().is_none());
+}
+
+/// Patches of the current branch of `repo`, in the order in which
+/// they were applied.
+fn applied_patches(repo: &std::path::Path) -> Vec<Vec<u8>> {
+    use libpijul::ReadTxn;
+    let repository = libpijul::Repository::open(&libpijul::fs_representation::pristine_dir(repo)).unwrap();
+    let txn = repository.txn_begin().unwrap();
+    txn.applied_patches(&libpijul::fs_representation::read_current_branch(repo)).unwrap()
+}
+
+#[test]
+fn check_repair_keeps_the_order_and_the_old_pristine() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    for name in ["b", "a", "c"].iter() {
+        let file = &dir.join(name);
+        create_file_random_content(&file, "");
+        add_one_file(&dir, &file).unwrap();
+        record_all(&dir, Some(*name)).unwrap();
+    }
+    let order = applied_patches(&dir);
+    assert_eq!(order.len(), 3);
+
+    let params = check::Params { repository: Some(&dir), repair: true };
+    check::run(&params).unwrap();
+    assert_eq!(applied_patches(&dir), order);
+
+    // A failed repair leaves the pristine alone.
+    let branch = libpijul::fs_representation::read_current_branch(&dir);
+    let changes_file = libpijul::fs_representation::branch_changes_file(&dir, branch.as_bytes());
+    fs::File::create(&changes_file).unwrap().write_all(b"not cbor").unwrap();
+    assert!(check::run(&params).is_err());
+    assert_eq!(applied_patches(&dir), order);
+}
+
+#[test]
+fn pull_checks_signatures() {
+    let (tmp_dir, dir_a, dir_b) = mk_tmp_repo_pair();
+    let _ = tmp_dir.into_path();
+    let file = &dir_a.join("file");
+    create_file_random_content(&file, "");
+    add_one_file(&dir_a, &file).unwrap();
+    record_all(&dir_a, Some("add file")).unwrap();
+
+    // Whether or not the patch was signed by a default key, this key did not sign it.
+    let mut meta = ::meta::Meta::new();
+    meta.signatures = Some("require".to_string());
+    meta.trusted_keys = Some(vec!["0123456789ABCDEF".to_string()]);
+    meta.save(&dir_b).unwrap();
+    match pull_all(&dir_a, &dir_b) {
+        Err(error::Error::Repository(libpijul::error::Error::UntrustedPatch(_, _))) => {},
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(()) => panic!("an untrusted patch was pulled")
+    }
+    assert!(fs::metadata(dir_b.join("file")).is_err());
+
+    // An unreadable meta file does not disable the policy.
+    fs::File::create(libpijul::fs_representation::meta_file(&dir_b)).unwrap().write_all(b"signatures = [").unwrap();
+    match pull_all(&dir_a, &dir_b) {
+        Err(error::Error::MetaDecoding) => {},
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(()) => panic!("pulled despite an unreadable meta file")
+    }
+    assert!(fs::metadata(dir_b.join("file")).is_err());
+
+    let mut meta = ::meta::Meta::new();
+    meta.signatures = Some("warn".to_string());
+    meta.trusted_keys = Some(vec!["0123456789ABCDEF".to_string()]);
+    meta.save(&dir_b).unwrap();
+    pull_all(&dir_a, &dir_b).unwrap();
+    assert_eq!(branch_patches(&dir_b), branch_patches(&dir_a));
+}
+
+#[test]
+fn signature_policy_compares_full_keys() {
+    use libpijul::signature::{SignaturePolicy, Signature};
+    let signed = |key: &str| Signature::Valid { key: key.to_string() };
+    // No trusted key trusts no signature.
+    assert!(!SignaturePolicy::Require(vec![]).trusts(&signed("abcd")));
+    assert!(!SignaturePolicy::Warn(vec![]).trusts(&signed("abcd")));
+    let policy = SignaturePolicy::Require(vec!["AB CD".to_string()]);
+    assert!(policy.trusts(&signed("abcd")));
+    assert!(!policy.trusts(&signed("01abcd")));
+    assert!(!policy.trusts(&Signature::Missing));
+    assert!(SignaturePolicy::Ignore.trusts(&Signature::Missing));
+}
+
+#[test]
+fn record_signs_with_ed25519_keys() {
+    let (tmp_dir, dir_a, dir_b) = mk_tmp_repo_pair();
+    let keys_dir = tmp_dir.path().join("keys");
+    key::run(&key::Params { keys_dir: Some(&keys_dir), action: key::Action::Generate { name: "test" } }).unwrap();
+    let signing_key = libpijul::signature::SigningKey::load(&keys_dir.join("test")).unwrap();
+    let public_key = {
+        use rustc_serialize::hex::ToHex;
+        signing_key.public_key().to_hex()
+    };
+
+    let mut meta = ::meta::Meta::new();
+    meta.signing_key = Some(keys_dir.join("test").to_str().unwrap().to_string());
+    meta.save(&dir_a).unwrap();
+    let file = &dir_a.join("file");
+    create_file_random_content(&file, "");
+    add_one_file(&dir_a, &file).unwrap();
+    record_all(&dir_a, Some("add file")).unwrap();
+
+    let hash = branch_patches(&dir_a).into_iter().next().unwrap();
+    assert_eq!(libpijul::signature::verify_signature(&dir_a, &hash).unwrap(),
+               libpijul::signature::Signature::Valid { key: public_key.clone() });
+    // The patch names its signer.
+    let patch = libpijul::patch::Patch::from_repository(&dir_a, &hash).unwrap();
+    assert_eq!(patch.authors[0].get("key"), Some(&libpijul::patch::Value::String(public_key.clone())));
+
+    // The signature is pulled along with the patch, and trusted.
+    let mut meta = ::meta::Meta::new();
+    meta.signatures = Some("require".to_string());
+    meta.trusted_keys = Some(vec![public_key]);
+    meta.save(&dir_b).unwrap();
+    pull_all(&dir_a, &dir_b).unwrap();
+    assert_eq!(branch_patches(&dir_b), branch_patches(&dir_a));
+}
+
+fn golden_patch(contents: &[u8]) -> Result<libpijul::patch::Patch, libpijul::error::Error> {
+    libpijul::patch::Patch::from_reader(contents, None)
+}
+
+#[test]
+fn patch_format_golden_files() {
+    let dependency: Vec<u8> = (0..64).map(|i| 2 * i).collect();
+    for contents in [&include_bytes!("../../tests/fixtures/patch-v0.cbor.gz")[..],
+                     &include_bytes!("../../tests/fixtures/patch-v1.cbor.gz")[..],
+                     &include_bytes!("../../tests/fixtures/patch-v2.cbor.gz")[..]].iter() {
+        let patch = golden_patch(contents).unwrap();
+        assert_eq!(patch.name, "golden patch");
+        assert!(patch.authors.is_empty());
+        assert!(patch.description.is_none());
+        assert_eq!(patch.timestamp, "2016-05-01T12:00:00+02:00");
+        assert!(patch.changes.is_empty());
+        assert_eq!(patch.dependencies.len(), 1);
+        assert!(patch.dependencies.contains(&dependency));
+    }
+    let key = |line: u8| {
+        let mut key = dependency.clone();
+        key.extend(&[line, 0, 0, 0]);
+        key
+    };
+    for contents in [&include_bytes!("../../tests/fixtures/patch-changes-v0.cbor.gz")[..],
+                     &include_bytes!("../../tests/fixtures/patch-changes-v1.cbor.gz")[..],
+                     &include_bytes!("../../tests/fixtures/patch-changes-v2.cbor.gz")[..]].iter() {
+        let patch = golden_patch(contents).unwrap();
+        assert_eq!(patch.name, "golden changes");
+        assert!(patch.dependencies.contains(&dependency));
+        assert_eq!(patch.changes.len(), 2);
+        match patch.changes[0] {
+            libpijul::patch::Change::NewNodes { ref up_context, ref down_context, flag, line_num, ref nodes } => {
+                assert_eq!(up_context, &vec![key(1)]);
+                assert_eq!(down_context, &vec![key(2)]);
+                assert_eq!(flag, 0);
+                assert_eq!(line_num, 1);
+                assert_eq!(nodes, &vec![b"a\n".to_vec(), b"b\n".to_vec()]);
+            },
+            ref c => panic!("unexpected change {:?}", c)
+        }
+        match patch.changes[1] {
+            libpijul::patch::Change::Edges { flag, ref edges } => {
+                assert_eq!(flag, libpijul::graph::DELETED_EDGE);
+                assert_eq!(edges.len(), 1);
+                assert_eq!(edges[0].from, key(1));
+                assert_eq!(edges[0].to, key(2));
+                assert_eq!(edges[0].introduced_by, dependency);
+            },
+            ref c => panic!("unexpected change {:?}", c)
+        }
+    }
+    match golden_patch(include_bytes!("../../tests/fixtures/patch-v99.cbor.gz")) {
+        Err(libpijul::error::Error::UnknownPatchVersion(_, 99)) => {},
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("decoded a patch from the future")
+    }
+    match golden_patch(b"not a patch") {
+        Err(libpijul::error::Error::InvalidPatch(_, _)) => {},
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("decoded garbage")
+    }
+
+    // New patches are written in the current version, and read back.
+    let mut patch = golden_patch(include_bytes!("../../tests/fixtures/patch-v0.cbor.gz")).unwrap();
+    patch.name = "new patch".to_string();
+    let mut buffer = Vec::new();
+    patch.to_writer(&mut buffer).unwrap();
+    let header_len = libpijul::patch::PATCH_MAGIC.len();
+    assert_eq!(&buffer[..header_len], libpijul::patch::PATCH_MAGIC);
+    assert_eq!(&buffer[header_len..header_len + 4], &[0, 0, 0, libpijul::patch::PATCH_FORMAT_VERSION as u8]);
+    let read = golden_patch(&buffer).unwrap();
+    assert_eq!(read.name, "new patch");
+    assert!(read.dependencies.contains(&dependency));
+}
+
+#[test]
+fn patches_are_named_after_their_contents() {
+    let tmp_dir = tempdir::TempDir::new("pijul").unwrap();
+    let v1 = tmp_dir.path().join("v1");
+    let v2 = tmp_dir.path().join("v2");
+    fs::File::create(&v1).unwrap().write_all(include_bytes!("../../tests/fixtures/patch-v1.cbor.gz")).unwrap();
+    fs::File::create(&v2).unwrap().write_all(include_bytes!("../../tests/fixtures/patch-v2.cbor.gz")).unwrap();
+
+    // Version 1 patches keep the hash of their file, version 2 ones
+    // have the hash of their contents.
+    let (patch, hash_v1) = libpijul::patch::patch_hash(&v1).unwrap();
+    let (_, hash_v2) = libpijul::patch::patch_hash(&v2).unwrap();
+    assert!(hash_v1 != hash_v2);
+    assert_eq!(hash_v2, patch.hash().unwrap());
+
+    // Recorded patches are named after the hash of their contents.
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let file = &dir.join("file");
+    create_file_random_content(&file, "");
+    add_one_file(&dir, &file).unwrap();
+    record_all(&dir, Some("add file")).unwrap();
+    let hash = branch_patches(&dir).into_iter().next().unwrap();
+    let patch_file = dir.join(libpijul::fs_representation::patch_path(&hash, std::path::MAIN_SEPARATOR));
+    let (patch, actual) = libpijul::patch::patch_hash(&patch_file).unwrap();
+    assert_eq!(actual, hash);
+    assert_eq!(patch.hash().unwrap(), hash);
+}
+
+#[test]
+fn patch_export_import() {
+    use rustc_serialize::hex::ToHex;
+    let (_tmp_dir, dir_a, dir_b) = mk_tmp_repo_pair();
+    let file = &dir_a.join("file");
+    create_file_random_content(&file, "");
+    add_one_file(&dir_a, &file).unwrap();
+    record_all(&dir_a, Some("add \"file\"\nwith a description")).unwrap();
+    let hash = branch_patches(&dir_a).into_iter().next().unwrap();
+
+    let hex = hash.to_hex();
+    let text_file = dir_a.join("patch.txt");
+    let export = patch::Params { repository: Some(&dir_a),
+                                 action: patch::Action::Export { hash: &hex, branch: None,
+                                                                 output: Some(&text_file) } };
+    patch::run(&export).unwrap();
+    let text = String::from_utf8(read_file(&text_file)).unwrap();
+    assert!(text.starts_with("pijul text patch 1\n"));
+    assert!(text.contains("name \"add \\\"file\\\"\\nwith a description\"\n"));
+
+    // The patch imported into another repository has the same hash,
+    // and can be applied there.
+    assert_eq!(patch::import(&dir_b, Some(&text_file)).unwrap(), hash);
+    let apply = apply::Params { repository: &dir_b, hex_hash: vec![&hex], branch: None };
+    apply::run(&apply).unwrap();
+    assert_eq!(branch_patches(&dir_b), branch_patches(&dir_a));
+
+    match libpijul::patch::Patch::from_text(&b"pijul text patch 1\nname \"unterminated\n"[..]) {
+        Err(libpijul::error::Error::InvalidPatch(_, ref msg)) => assert!(msg.starts_with("line 2:")),
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("parsed an invalid patch")
+    }
+}
+
+#[test]
+fn text_round_trip_of_recorded_patches() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let file = &dir.join("toto");
+    let text0 = create_file_random_content(&file, "");
+    add_one_file(&dir, &file).unwrap();
+    record_all(&dir, Some("add toto")).unwrap();
+    {
+        let mut f = fs::File::create(&file).unwrap();
+        for line in edit(&text0, 20, 20).iter() {
+            f.write_all(line.as_bytes()).unwrap();
+        }
+    }
+    record_all(&dir, Some("edit toto")).unwrap();
+    let mv_params = mv::Params {
+        repository: Some(&dir),
+        movement: mv::Movement::FileToFile {
+            from: PathBuf::from("toto"),
+            to: PathBuf::from("titi"),
+        },
+    };
+    mv::run(&mv_params).unwrap();
+    record_all(&dir, Some("move toto to titi")).unwrap();
+    let remove_params = remove::Params {
+        repository: Some(&dir),
+        touched_files: vec![std::path::Path::new("titi")],
+    };
+    remove::run(&remove_params).unwrap();
+    record_all(&dir, Some("remove titi")).unwrap();
+
+    // A binary file, recorded with an author.
+    let binary = &dir.join("binary");
+    fs::File::create(&binary).unwrap().write_all(&[0, 255, 10, 1, 0, 10, 127]).unwrap();
+    add_one_file(&dir, &binary).unwrap();
+    let record_params = record::Params {
+        repository: Some(&dir),
+        keys_dir: Some(std::path::Path::new(NO_KEYS_DIR)),
+        yes_to_all: true,
+        authors: Some(vec!["Jane Doe <jane@example.com>"]),
+        patch_name: Some("add binary"),
+    };
+    record::run(&record_params).unwrap();
+    assert_eq!(branch_patches(&dir).len(), 5);
+
+    // The command line cannot give descriptions or other author
+    // fields, save such a patch directly.
+    let patches_dir = libpijul::fs_representation::patches_dir(&dir);
+    let hash = branch_patches(&dir).into_iter().next().unwrap();
+    let path = dir.join(libpijul::fs_representation::patch_path(&hash, std::path::MAIN_SEPARATOR));
+    let (mut described, _) = libpijul::patch::patch_hash(&path).unwrap();
+    described.description = Some("A description\n\n  over \"several\" lines.".to_string());
+    let mut author = std::collections::BTreeMap::new();
+    author.insert("name".to_string(), libpijul::patch::Value::String("Jane Doe".to_string()));
+    author.insert("email".to_string(), libpijul::patch::Value::String("jane@example.com".to_string()));
+    described.authors = vec![author.clone(), author];
+    described.save(&patches_dir, None).unwrap();
+    check_text_round_trip(&dir);
+}
+
+#[test]
+fn patch_import_keeps_old_patch_files() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let contents = &include_bytes!("../../tests/fixtures/patch-changes-v1.cbor.gz")[..];
+    let patch = libpijul::patch::Patch::from_reader(contents, None).unwrap();
+
+    // The version 1 file is imported unchanged, with its hash.
+    let text_file = dir.join("patch.txt");
+    patch.to_text_with_file(&mut fs::File::create(&text_file).unwrap(), Some(contents)).unwrap();
+    let hash = patch::import(&dir, Some(&text_file)).unwrap();
+    let imported = dir.join(libpijul::fs_representation::patch_path(&hash, std::path::MAIN_SEPARATOR));
+    assert_eq!(read_file(&imported), contents.to_vec());
+    assert_eq!(libpijul::patch::patch_version(&imported).unwrap(), 1);
+    assert_eq!(libpijul::patch::patch_hash(&imported).unwrap().1, hash);
+
+    // The file must be the patch of the text.
+    let mut other = libpijul::patch::Patch::from_reader(contents, None).unwrap();
+    other.name = "other changes".to_string();
+    other.to_text_with_file(&mut fs::File::create(&text_file).unwrap(), Some(contents)).unwrap();
+    assert!(patch::import(&dir, Some(&text_file)).is_err());
+}
+
+#[test]
+fn import_git_replays_commits() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let stream = include_bytes!("../../tests/fixtures/git-fast-export.txt");
+    assert_eq!(import_git::import(&dir, "refs/heads/master", &stream[..]).unwrap(), 4);
+
+    // The working copy is the merged state, and matches the pristine.
+    assert_eq!(read_file(&dir.join("README.md")), b"hello\nworld\n");
+    assert_eq!(read_file(&dir.join("docs").join("a file.txt")), b"some docs\n");
+    assert!(fs::metadata(dir.join("README")).is_err());
+    assert!(fs::metadata(dir.join("src")).is_err());
+    assert!(record_all(&dir, Some("nothing")).unwrap().is_none());
+    check::run(&check::Params { repository: Some(&dir), repair: false }).unwrap();
+
+    let params = changes::Params { repository: Some(&dir), branch: None, by_timestamp: false,
+                                   author: None, name: None, json: false };
+    let patches = changes::patches(&dir, &params).unwrap();
+    // The commit of the merged branch is not on the first-parent
+    // chain of master, even though the stream labels it as master.
+    assert_eq!(patches.len(), 4);
+    assert!(patches.iter().all(|&(_, ref p)| p.name != "Add documentation"));
+    let initial = &patches.iter().find(|&&(_, ref p)| p.name == "Initial commit").unwrap().1;
+    assert_eq!(initial.authors[0].get("name"),
+               Some(&libpijul::patch::Value::String("Alice <alice@example.com>".to_string())));
+    assert_eq!(initial.timestamp, "2016-05-01T12:00:00+02:00");
+    assert!(initial.description.is_none());
+    let hello = &patches.iter().find(|&&(_, ref p)| p.name == "Say hello to the world").unwrap().1;
+    assert_eq!(hello.description, Some("The README was too short.".to_string()));
+
+    match import_git::import(&dir, "refs/heads/master", &b"frobnicate\n"[..]) {
+        Err(error::Error::InvalidGitStream(1, _)) => {},
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("imported an invalid stream")
+    }
+
+    // Only the commits of refs/heads/master are in the stream.
+    let tmp_dir_b = mk_tmp_repo();
+    match import_git::import(tmp_dir_b.path(), "refs/heads/topic", &stream[..]) {
+        Err(error::Error::InvalidGitStream(_, _)) => {},
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("imported a reference not in the stream")
+    }
+}
+
+#[test]
+fn import_git_modes() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let stream = b"commit refs/heads/master\n\
+                   mark :1\n\
+                   committer Carol <carol@example.com> 1462104000 -0130\n\
+                   data 5\n\
+                   Init\n\
+                   M 100755 inline run.sh\n\
+                   data 5\n\
+                   true\n\
+                   M 100644 inline README\n\
+                   data 3\n\
+                   hi\n\
+                   \n\
+                   commit refs/heads/master\n\
+                   mark :2\n\
+                   committer Carol <carol@example.com> 1462104060 -0130\n\
+                   data 6\n\
+                   Chmod\n\
+                   from :1\n\
+                   M 100644 inline run.sh\n\
+                   data 5\n\
+                   true\n\
+                   M 100755 inline README\n\
+                   data 3\n\
+                   hi\n\
+                   \n";
+    assert_eq!(import_git::import(&dir, "refs/heads/master", &stream[..]).unwrap(), 2);
+    let params = changes::Params { repository: Some(&dir), branch: None, by_timestamp: false,
+                                   author: None, name: None, json: false };
+    let patches = changes::patches(&dir, &params).unwrap();
+    let init = &patches.iter().find(|&&(_, ref p)| p.name == "Init").unwrap().1;
+    assert_eq!(init.timestamp, "2016-05-01T10:30:00-01:30");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |f: &str| fs::metadata(dir.join(f)).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode("run.sh"), 0o644);
+        assert_eq!(mode("README"), 0o755);
+    }
+    assert!(record_all(&dir, Some("nothing")).unwrap().is_none());
+}
+
+#[test]
+fn export_git_round_trip() {
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let stream = include_bytes!("../../tests/fixtures/git-fast-export.txt");
+    assert_eq!(import_git::import(&dir, "refs/heads/master", &stream[..]).unwrap(), 4);
+
+    let branch = libpijul::fs_representation::read_current_branch(&dir);
+    let mut exported = Vec::new();
+    let none = std::collections::HashSet::new();
+    assert_eq!(export_git::export(&dir, &branch, "refs/heads/master", &none, &mut exported).unwrap(), 4);
+    let text = String::from_utf8_lossy(&exported).into_owned();
+    let hashes = export_git::exported_patches(&text);
+    assert_eq!(hashes, branch_patches(&dir));
+    assert!(text.contains("author Alice <alice@example.com> 1462096800 +0200\n"));
+    // Each commit only has the files its patch changes.
+    assert_eq!(text.matches("M 100644 inline README.md\n").count(), 1);
+    assert_eq!(text.matches("M 100644 inline docs/a file.txt\n").count(), 1);
+    assert!(text.contains("D README\n"));
+    // Exporting leaves no trace in the repository.
+    check::run(&check::Params { repository: Some(&dir), repair: false }).unwrap();
+    let pristine = libpijul::fs_representation::pristine_dir(&dir);
+    assert!(fs::read_dir(pristine.parent().unwrap()).unwrap()
+            .all(|e| !e.unwrap().file_name().to_string_lossy().starts_with("pristine.export-git")));
+
+    // Importing the export gives back the same files.
+    let tmp_dir_b = mk_tmp_repo();
+    let dir_b = tmp_dir_b.path();
+    assert_eq!(import_git::import(&dir_b, "refs/heads/master", &exported[..]).unwrap(), 4);
+    assert_eq!(read_file(&dir_b.join("README.md")), b"hello\nworld\n");
+    assert_eq!(read_file(&dir_b.join("docs").join("a file.txt")), b"some docs\n");
+    assert!(fs::metadata(dir_b.join("README")).is_err());
+
+    // Only new patches are exported, on top of the reference.
+    let mut again = Vec::new();
+    assert_eq!(export_git::export(&dir, &branch, "refs/heads/master", &hashes, &mut again).unwrap(), 0);
+    assert!(again.is_empty());
+    {
+        let mut f = fs::File::create(&dir.join("README.md")).unwrap();
+        f.write_all(b"hello\nworld\nagain\n").unwrap();
+    }
+    record_all(&dir, Some("Once more")).unwrap();
+    let mut more = Vec::new();
+    assert_eq!(export_git::export(&dir, &branch, "refs/heads/master", &hashes, &mut more).unwrap(), 1);
+    let more = String::from_utf8(more).unwrap();
+    assert!(more.contains("from refs/heads/master^0\ndeleteall\n"));
+    assert!(more.contains("M 100644 inline README.md\ndata 18\nhello\nworld\nagain\n"));
+
+    // Executable files have mode 100755.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&dir.join("README.md"), fs::Permissions::from_mode(0o755)).unwrap();
+        record_all(&dir, Some("Executable")).unwrap();
+        let mut chmod = Vec::new();
+        assert_eq!(export_git::export(&dir, &branch, "refs/heads/master", &hashes, &mut chmod).unwrap(), 2);
+        let chmod = String::from_utf8(chmod).unwrap();
+        let last = &chmod[chmod.rfind("commit refs/heads/master\n").unwrap()..];
+        assert!(last.contains("M 100755 inline README.md\n"));
+        assert!(!last.contains("a file.txt"));
+    }
+}
+
+fn tag_action(repo: &std::path::Path, action: tag::Action) -> Result<(), error::Error> {
+    tag::run(&tag::Params { repository: Some(repo), action: action })
+}
+
+#[test]
+fn tag_new_diff_output_push_pull() {
+    let (tmp_dir, dir_a, dir_b) = mk_tmp_repo_pair();
+    let file = dir_a.join("file");
+    {
+        let mut f = fs::File::create(&file).unwrap();
+        f.write_all(b"one\n").unwrap();
+    }
+    add_one_file(&dir_a, &file).unwrap();
+    record_all(&dir_a, Some("one")).unwrap();
+    tag_action(&dir_a, tag::Action::New { name: "v1", branch: None }).unwrap();
+    assert_eq!(libpijul::tag::list_tags(&dir_a).unwrap(), vec!["v1".to_string()]);
+    assert_eq!(libpijul::tag::read_tag(&dir_a, "v1").unwrap(), branch_patches(&dir_a));
+    // Tags are immutable.
+    match tag_action(&dir_a, tag::Action::New { name: "v1", branch: None }) {
+        Err(error::Error::Repository(libpijul::error::Error::TagAlreadyExists(_))) => {},
+        _ => panic!("a tag was overwritten")
+    }
+
+    {
+        let mut f = fs::File::create(&file).unwrap();
+        f.write_all(b"one\ntwo\n").unwrap();
+    }
+    record_all(&dir_a, Some("two")).unwrap();
+    let branch = libpijul::fs_representation::read_current_branch(&dir_a);
+    let (added, removed) = tag::diff(&dir_a, "v1", &branch).unwrap();
+    assert_eq!(added.len(), 1);
+    assert!(removed.is_empty());
+    assert_eq!(libpijul::tag::read_tag(&dir_a, "v1").unwrap().len(), 1);
+
+    let out = tmp_dir.path().join("v1");
+    tag::output(&dir_a, "v1", &out).unwrap();
+    assert_eq!(read_file(&out.join("file")), b"one\n");
+    match tag::output(&dir_a, "v1", &out) {
+        Err(error::Error::DirectoryNotEmpty(_)) => {},
+        _ => panic!("output to a non-empty directory")
+    }
+    // Outputting a tag leaves the repository alone.
+    assert_eq!(read_file(&file), b"one\ntwo\n");
+    check::run(&check::Params { repository: Some(&dir_a), repair: false }).unwrap();
+
+    // Pushing a tag transfers its patches, without applying them.
+    push::run(&push::Params {
+        repository: Some(&dir_a),
+        remote_id: Some(dir_b.to_str().unwrap()),
+        yes_to_all: true,
+        set_default: false,
+        port: None,
+        tag: Some("v1"),
+    }).unwrap();
+    assert_eq!(libpijul::tag::read_tag(&dir_b, "v1").unwrap(),
+               libpijul::tag::read_tag(&dir_a, "v1").unwrap());
+    assert!(fs::metadata(dir_b.join("file")).is_err());
+    // HTTP remotes are read-only.
+    let remote = super::remote::parse_remote("http://localhost/repository", None, None);
+    match remote.session().unwrap().push_tag(&dir_a, "v1") {
+        Err(error::Error::CannotPushToUri(_)) => {},
+        _ => panic!("pushed a tag to a URI")
+    }
+    let out_b = tmp_dir.path().join("v1_b");
+    tag::output(&dir_b, "v1", &out_b).unwrap();
+    assert_eq!(read_file(&out_b.join("file")), b"one\n");
+
+    let tmp_dir_c = mk_tmp_repo();
+    let dir_c = tmp_dir_c.path();
+    let pull_params = pull::Params {
+        repository: Some(dir_c),
+        remote_id: Some(dir_b.to_str().unwrap()),
+        set_default: false,
+        port: None,
+        tag: Some("v1"),
+        yes_to_all: true,
+    };
+    pull::run(&pull_params).unwrap();
+    // Pulling the same tag again does nothing.
+    pull::run(&pull_params).unwrap();
+    assert_eq!(libpijul::tag::read_tag(&dir_c, "v1").unwrap(),
+               libpijul::tag::read_tag(&dir_a, "v1").unwrap());
+
+    tag_action(&dir_a, tag::Action::Delete { name: "v1" }).unwrap();
+    assert!(libpijul::tag::list_tags(&dir_a).unwrap().is_empty());
+    match tag_action(&dir_a, tag::Action::Delete { name: "v1" }) {
+        Err(error::Error::Repository(libpijul::error::Error::TagNotFound(_))) => {},
+        _ => panic!("deleted a tag that does not exist")
+    }
+}
+
+#[test]
+fn deps_between_patches() {
+    use rustc_serialize::hex::ToHex;
+    let tmp_dir = mk_tmp_repo();
+    let dir = tmp_dir.path();
+    let write = |name: &str, contents: &[u8]| {
+        let mut f = fs::File::create(&dir.join(name)).unwrap();
+        f.write_all(contents).unwrap();
+    };
+    write("file", b"a\n");
+    add_one_file(&dir, &dir.join("file")).unwrap();
+    record_all(&dir, Some("A")).unwrap();
+    write("file", b"a\nb\n");
+    record_all(&dir, Some("B")).unwrap();
+    write("file", b"a\nb\nd\n");
+    record_all(&dir, Some("D")).unwrap();
+    write("other", b"c\n");
+    add_one_file(&dir, &dir.join("other")).unwrap();
+    record_all(&dir, Some("C")).unwrap();
+
+    let params = changes::Params { repository: Some(&dir), branch: None, by_timestamp: false,
+                                   author: None, name: None, json: false };
+    let patches = changes::patches(&dir, &params).unwrap();
+    let hash = |name: &str| patches.iter().find(|&&(_, ref p)| p.name == name).unwrap().0.clone();
+    let (a, b, c, d) = (hash("A"), hash("B"), hash("C"), hash("D"));
+    let sorted = |mut v: Vec<Vec<u8>>| { v.sort(); v };
+    let branch = libpijul::fs_representation::read_current_branch(&dir);
+
+    assert_eq!(deps::related(&dir, &branch, &b, false, false).unwrap(), vec![a.clone()]);
+    assert_eq!(deps::related(&dir, &branch, &d, false, true).unwrap(), sorted(vec![a.clone(), b.clone()]));
+    assert_eq!(deps::related(&dir, &branch, &a, true, true).unwrap(), sorted(vec![b.clone(), d.clone()]));
+    // C is independent of the others.
+    assert!(deps::related(&dir, &branch, &c, false, true).unwrap().is_empty());
+    assert!(deps::related(&dir, &branch, &c, true, true).unwrap().is_empty());
+    match deps::related(&dir, &branch, &[0; 64], false, false) {
+        Err(error::Error::Repository(libpijul::error::Error::PatchNotOnBranch(_))) => {},
+        _ => panic!("found the dependencies of an unknown patch")
+    }
+
+    let mut out = Vec::new();
+    deps::dot(&dir, &branch, None, false, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("digraph dependencies {\n"));
+    assert!(out.contains(&format!("\"{}\" -> \"{}\";", b.to_hex(), a.to_hex())));
+    assert!(out.contains(&format!("\"{}\" [label=\"{} C\"];", c.to_hex(), &c.to_hex()[..8])));
+    assert!(!out.contains(&format!("\"{}\" ->", c.to_hex())));
+    assert!(!out.contains(&format!("-> \"{}\"", c.to_hex())));
+
+    let mut out = Vec::new();
+    deps::dot(&dir, &branch, Some(&b), false, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains(&format!("\"{}\" -> \"{}\";", b.to_hex(), a.to_hex())));
+    assert!(!out.contains(&d.to_hex()));
+    assert!(!out.contains(&c.to_hex()));
+}
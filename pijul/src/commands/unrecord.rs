@@ -0,0 +1,90 @@
+/*
+  Copyright Florent Becker and Pierre-Etienne Meunier 2015.
+
+  This file is part of Pijul.
+
+  This program is free software: you can redistribute it and/or modify
+  it under the terms of the GNU Affero General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  This program is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU Affero General Public License for more details.
+
+  You should have received a copy of the GNU Affero General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+extern crate clap;
+use clap::{SubCommand, ArgMatches, Arg};
+
+extern crate libpijul;
+use libpijul::{Repository};
+use commands::StaticSubcommand;
+use self::libpijul::fs_representation::{pristine_dir, find_repo_root, read_current_branch};
+
+use commands::error::Error;
+use super::get_wd;
+
+use std::path::{Path};
+
+extern crate rustc_serialize;
+use self::rustc_serialize::hex::{FromHex};
+
+pub fn invocation() -> StaticSubcommand {
+    return
+        SubCommand::with_name("unrecord")
+        .about("remove a patch from the current branch, keeping its changes in the working copy")
+        .arg(Arg::with_name("patch")
+             .help("Hash of the patch to unrecord, in hexadecimal.")
+             .multiple(true)
+             .required(true))
+        .arg(Arg::with_name("cascade")
+             .long("cascade")
+             .help("Also unrecord the patches depending on these ones."))
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("The repository where to unrecord, defaults to the current directory.")
+             .takes_value(true)
+             .required(false))
+}
+
+pub struct Params<'a> {
+    pub repository : Option<&'a Path>,
+    pub hex_hash : Vec<&'a str>,
+    pub cascade : bool
+}
+
+pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a>
+{
+    Params {
+        repository : args.value_of("repository").and_then(|x| { Some(Path::new(x)) }),
+        hex_hash : args.values_of("patch").map(|x| x.collect()).unwrap_or(Vec::new()),
+        cascade : args.is_present("cascade")
+    }
+}
+
+pub fn run(params : &Params) -> Result<Option<()>, Error> {
+    let wd = try!(get_wd(params.repository));
+    match find_repo_root(&wd){
+        None => return Err(Error::NotInARepository),
+        Some(ref target) =>
+        {
+            let mut hashes = Vec::new();
+            for h in params.hex_hash.iter() {
+                hashes.push(try!(h.from_hex()))
+            }
+            let branch=read_current_branch(target);
+            let repo_dir=pristine_dir(target);
+            let repo = try!(Repository::open(&repo_dir));
+            let mut txn = try!(repo.mut_txn_begin());
+            for hash in hashes.iter() {
+                debug!("unrecording {:?}", hash);
+                try!(txn.unrecord(&branch, target, hash, params.cascade));
+            }
+            try!(txn.commit());
+            Ok(Some(()))
+        }
+    }
+}
@@ -71,12 +71,26 @@ fn main() {
                                "pull" => pull,
                                "push" => push,
                                "apply" => apply,
+                               "unrecord" => unrecord,
+                               "branch" => branch,
+                               "checkout" => checkout,
                                "clone" => clone,
                                "remove" => remove,
                                "mv" => mv,
                                "ls" => ls,
                                "revert" => revert,
-                               "login" => login
+                               "login" => login,
+                               "diff" => diff,
+                               "status" => status,
+                               "changes" => changes,
+                               "patch" => patch,
+                               "blame" => blame,
+                               "conflicts" => conflicts,
+                               "key" => key,
+                               "import-git" => import_git,
+                               "export-git" => export_git,
+                               "tag" => tag,
+                               "deps" => deps
                                );
     let time1=time::precise_time_s();
     info!(target:"pijul","whole command took: {}", time1-time0);
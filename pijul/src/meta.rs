@@ -20,11 +20,13 @@ extern crate toml;
 extern crate libpijul;
 use self::libpijul::fs_representation::{meta_file};
 use self::libpijul::patch::Value;
+use self::libpijul::ConflictStyle;
+use self::libpijul::signature::SignaturePolicy;
 use std::path::Path;
 use std::collections::BTreeMap;
 use commands::error::Error;
 use std::fs::File;
-use std::io::{Read,Write};
+use std::io::{self,Read,Write};
 extern crate rustc_serialize;
 use self::rustc_serialize::Encodable;
 #[derive(Debug,RustcEncodable,RustcDecodable)]
@@ -38,7 +40,18 @@ pub enum Repository {
 pub struct Meta {
     pub default_authors:Vec<BTreeMap<String,Value>>,
     pub pull:Option<Repository>,
-    pub push:Option<Repository>
+    pub push:Option<Repository>,
+    /// How conflicts are written in files: "plain", "labelled" or "diff3".
+    pub conflict_style:Option<String>,
+    /// What to do with patches that are not signed by a trusted key,
+    /// when pulling or applying them: "ignore", "warn" or "require".
+    pub signatures:Option<String>,
+    /// Full Ed25519 public keys or OpenPGP fingerprints of the
+    /// trusted keys. If empty, no signature is trusted.
+    pub trusted_keys:Option<Vec<String>>,
+    /// Path of the key used to sign recorded patches. Defaults to
+    /// the key called "default" in ~/.pijul/keys, if any.
+    pub signing_key:Option<String>
 }
 
 impl Meta {
@@ -54,7 +67,31 @@ impl Meta {
         }
     }
     pub fn new()->Meta {
-        Meta { default_authors:Vec::new(),pull:None,push:None }
+        Meta { default_authors:Vec::new(),pull:None,push:None,conflict_style:None,signatures:None,trusted_keys:None,signing_key:None }
+    }
+    /// Conflict style of the repository at `r`. Unknown styles and
+    /// missing or invalid meta files give the default style.
+    pub fn conflict_style(r:&Path) -> ConflictStyle {
+        match Meta::load(r) {
+            Ok(Meta { conflict_style:Some(ref s), .. }) => ConflictStyle::from_name(s).unwrap_or(ConflictStyle::default()),
+            _ => ConflictStyle::default()
+        }
+    }
+    /// Signature policy of the repository at `r`. Missing meta files
+    /// give the default policy, but unreadable ones and unknown
+    /// policies are errors, so that a typo does not silently disable
+    /// verification.
+    pub fn signature_policy(r:&Path) -> Result<SignaturePolicy,Error> {
+        let meta = match Meta::load(r) {
+            Ok(m)=>m,
+            Err(Error::IO(ref e)) if e.kind()==io::ErrorKind::NotFound => Meta::new(),
+            Err(e)=>return Err(e)
+        };
+        match meta.signatures {
+            Some(ref s) => SignaturePolicy::from_name(s, meta.trusted_keys.clone().unwrap_or(Vec::new()))
+                .ok_or(Error::UnknownSignaturePolicy(s.clone())),
+            None => Ok(SignaturePolicy::default())
+        }
     }
     pub fn save(self,r:&Path)->Result<(),Error> {
         let mut f=try!(File::create(meta_file(r)));